@@ -1,9 +1,11 @@
 use crate::assembler::assembler_util::InstructionValue::{Literal, Slot};
 use crate::assembler::binary::AddressLabel::{Constant, Label};
-use crate::assembler::binary::{AddressLabel, NamedLabel, RawRegion};
+use crate::assembler::binary::{AddressLabel, BinarySection, NamedLabel};
+use crate::assembler::binary_builder::BinaryBuilderRegion;
 use crate::assembler::cursor::{is_adjacent_kind, LexerCursor};
+use crate::assembler::instructions::OperandKind;
 use crate::assembler::lexer::TokenKind::{
-    IntegerLiteral, LeftBrace, NewLine, Plus, Register, RightBrace, StringLiteral, Symbol,
+    IntegerLiteral, LeftBrace, NewLine, Plus, Register, RightBrace, Star, StringLiteral, Symbol,
 };
 use crate::assembler::lexer::{Location, StrippedKind, Token, TokenKind};
 use crate::assembler::registers::RegisterSlot;
@@ -23,14 +25,49 @@ pub enum AssemblerReason {
     ExpectedLeftBrace(StrippedKind),
     ExpectedRightBrace(StrippedKind),
     ConstantOutOfRange(i64, i64),    // start, end
-    OverwriteEdge(u32, Option<u64>), // pc, count
+    DivideByZero,
+    OverwriteEdge(BinarySection, u32, u64), // section, region address, bytes the region would hold
+    RelaxedOverlap(BinarySection, u32, u32), // section, region address, start of the region it grew into
+    OutputTooLarge(usize), // limit in bytes
     UnknownLabel(String),
     UnknownDirective(String),
     UnknownInstruction(String),
+    FloatingPointInstruction(String),
     JumpOutOfRange(u32, u32), // to, from
+    LabelOutOfRange(String, u32, usize, Option<Location>), // name, address, bytes available, where the label is defined
+    TemporaryUnavailable(String), // instruction
+    TemporaryClobbered(String, RegisterSlot), // instruction, the assembler's temporary register
+    MissingOperand { instruction: String, expected: OperandKind, position: usize }, // position from 1
+    MissingComma, // at the operand that needs one before it
+    UnexpectedComma,
     MissingRegion,
     MissingInstruction,
-    DuplicateLabel(String),
+    DuplicateLabel(String, Location), // name, location of the first definition
+    MisalignedTarget(String, u32, Option<Location>), // label, address, where the label is defined
+    TargetNotCode(String, u32), // label, address
+    DuplicateAlias(String),
+}
+
+// -0x80 rather than 0xffffffffffffff80.
+fn signed_hex(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#x}", value.unsigned_abs())
+    } else {
+        format!("{value:#x}")
+    }
+}
+
+// 1st, 2nd, 3rd...
+fn ordinal(value: usize) -> String {
+    let suffix = match (value % 10, value % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+
+    format!("{value}{suffix}")
 }
 
 impl Display for AssemblerReason {
@@ -45,26 +82,99 @@ impl Display for AssemblerReason {
             AssemblerReason::ExpectedNewline(kind) => write!(f, "Expected a newline, but found {kind}"),
             AssemblerReason::ExpectedLeftBrace(kind) => write!(f, "Expected a left brace, but found {kind}"),
             AssemblerReason::ExpectedRightBrace(kind) => write!(f, "Expected a right brace, but found {kind}"),
-            AssemblerReason::ConstantOutOfRange(min, max) => write!(f, "Constant must be between {min:#x} and {max:#x}"),
-            AssemblerReason::OverwriteEdge(pc, count) => write!(
-                f, "Instruction pushes cursor out of boundary (from {:#x}{})",
-                pc, count.map(|v| format!(" with 0x{v:x} bytes")).unwrap_or("".into())
+            AssemblerReason::DivideByZero => write!(f, "Constant expression divides by zero"),
+            AssemblerReason::ConstantOutOfRange(min, max) => write!(
+                f, "Constant must be between {} and {}", signed_hex(*min), signed_hex(*max)),
+            AssemblerReason::OverwriteEdge(section, address, size) => write!(
+                f, "{} region at 0x{address:08x} would hold 0x{size:x} bytes, past the end of memory at 0xffffffff",
+                section.directive()
             ),
+            AssemblerReason::RelaxedOverlap(section, address, next) => write!(
+                f, "Relaxing branches grew the {} region at 0x{address:08x} into the region at 0x{next:08x}, \
+                    move the second region further along", section.directive()
+            ),
+            AssemblerReason::OutputTooLarge(limit) => write!(
+                f, "This directive takes the assembled output past the limit of {limit} bytes"),
             AssemblerReason::UnknownLabel(name) => write!(f, "Could not find a label named \"{name}\", check for typos"),
             AssemblerReason::UnknownDirective(name) => write!(f, "There's no current support for any {name} directive"),
             AssemblerReason::UnknownInstruction(name) => write!(f, "Unknown instruction named \"{name}\", check for typos"),
+            AssemblerReason::FloatingPointInstruction(name) => write!(
+                f, "Instruction \"{name}\" belongs to the floating point unit, which titan does not support"),
             AssemblerReason::JumpOutOfRange(to, from) => write!(
                 f, "Trying to jump to 0x{to:08x} from 0x{from:08x}, but this jump is too distant for this instruction"),
+            AssemblerReason::LabelOutOfRange(name, address, bytes, _) => write!(
+                f, "Label \"{name}\" is at 0x{address:08x}, which does not fit in {bytes} byte(s)"),
+            AssemblerReason::TemporaryUnavailable(name) => write!(
+                f, "Instruction \"{name}\" needs $at to expand, but $at is reserved by .set noat"),
+            AssemblerReason::TemporaryClobbered(name, register) => write!(
+                f, "Instruction \"{name}\" expands using {register}, which is also one of its operands and would be overwritten"),
+            AssemblerReason::MissingOperand { instruction, expected, position } => write!(
+                f, "Instruction \"{instruction}\" is missing its {} operand, expected {expected}", ordinal(*position)),
+            AssemblerReason::MissingComma => write!(f, "Expected a comma before this operand"),
+            AssemblerReason::UnexpectedComma => write!(
+                f, "Found a comma that does not separate two operands, check for a doubled, leading or trailing comma"),
             AssemblerReason::MissingRegion => write!(
                 f, "Assembler did not mount a binary region. Please file an issue at https://github.com/1whatleytay/titan/issues"),
             AssemblerReason::MissingInstruction => write!(
                 f, "Assembler marked an instruction that does not exist. Please file an issue at https://github.com/1whatleytay/titan/issues"),
-            AssemblerReason::DuplicateLabel(label) => write!(
-                f, "Found duplicate label with the name \"{label}\", only one label with each name is allowed")
+            AssemblerReason::MisalignedTarget(name, address, _) => write!(
+                f, "Label \"{name}\" is at 0x{address:08x}, which is not word aligned, so a branch or jump cannot land on it"),
+            AssemblerReason::TargetNotCode(name, address) => write!(
+                f, "Label \"{name}\" is at 0x{address:08x}, which is not in an executable section (ex. .data), \
+                    so a branch or jump cannot land on it"),
+            AssemblerReason::DuplicateLabel(label, _) => write!(
+                f, "Found duplicate label with the name \"{label}\", only one label with each name is allowed"),
+            AssemblerReason::DuplicateAlias(label) => write!(
+                f, "Alias \"{label}\" has the same name as another label or alias")
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum AssemblerWarningReason {
+    TemporaryUsed(RegisterSlot), // the assembler's temporary ($at) written explicitly while the assembler may use it
+    ConstantTruncated(u64, usize), // value, bytes kept
+    UnusedLabel(String),
+    TargetNotCode(String, u32), // label, address
+    ColonlessLabel(String),
+    ImplicitPadding(usize, usize), // bytes inserted, alignment
+}
+
+impl Display for AssemblerWarningReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssemblerWarningReason::TemporaryUsed(RegisterSlot::AssemblerTemporary) => write!(
+                f, "$at is used by pseudo instructions and may be overwritten, use .set noat to reserve it"),
+            AssemblerWarningReason::TemporaryUsed(register) => write!(
+                f, "{register} is used by pseudo instructions and may be overwritten, it's reserved for the assembler"),
+            AssemblerWarningReason::ConstantTruncated(value, bytes) => write!(
+                f, "Constant {:#x} does not fit in {bytes} byte(s) and was truncated", *value as i64),
+            AssemblerWarningReason::UnusedLabel(name) => write!(
+                f, "Label \"{name}\" is never referenced"),
+            AssemblerWarningReason::TargetNotCode(name, address) => write!(
+                f, "Label \"{name}\" is at 0x{address:08x}, which is not in an executable section (ex. .data), \
+                    so running there will fail"),
+            AssemblerWarningReason::ColonlessLabel(name) => write!(
+                f, "Label \"{name}\" has no colon, write \"{name}:\" so MARS accepts it too"),
+            AssemblerWarningReason::ImplicitPadding(bytes, alignment) => write!(
+                f, "{bytes} byte(s) of padding were inserted to align this data to {alignment} bytes, \
+                    add .align {} before it to make that explicit", alignment.trailing_zeros()),
         }
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct AssemblerWarning {
+    pub location: Location,
+    pub reason: AssemblerWarningReason,
+}
+
+impl Display for AssemblerWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.reason.fmt(f)
+    }
+}
+
 #[derive(Debug)]
 pub struct AssemblerError {
     pub location: Option<Location>,
@@ -77,21 +187,64 @@ impl Display for AssemblerError {
     }
 }
 
-pub fn pc_for_region(region: &RawRegion, location: Option<Location>) -> Result<u32, AssemblerError> {
-    region.pc().ok_or_else(|| {
-        let reason = AssemblerReason::OverwriteEdge(region.address, Some(region.data.len() as u64));
+// True if value can be stored in this many bytes, read either signed or unsigned.
+pub fn fits_width(value: u64, bytes: usize) -> bool {
+    let bits = 8 * bytes as u32;
+    let value = value as i64;
 
-        AssemblerError { location, reason }
-    })
+    bits >= 64 || (value >= -(1 << (bits - 1)) && value < (1 << bits))
+}
+
+pub fn overwrite_edge(region: &BinaryBuilderRegion, size: u64, location: Option<Location>) -> AssemblerError {
+    AssemblerError {
+        location,
+        reason: AssemblerReason::OverwriteEdge(region.section, region.raw.address, size),
+    }
+}
+
+// The address of the next byte in region, an error if the region already reaches the end of memory.
+pub fn pc_for_region(region: &BinaryBuilderRegion, location: Option<Location>) -> Result<u32, AssemblerError> {
+    region.raw.pc().ok_or_else(|| overwrite_edge(region, region.raw.data.len() as u64 + 1, location))
+}
+
+// Call before appending count bytes to region, so it doesn't run past 0xFFFFFFFF (and wrap around to 0).
+pub fn check_region_edge(region: &BinaryBuilderRegion, count: u64, location: Option<Location>) -> Result<(), AssemblerError> {
+    let size = region.raw.data.len() as u64 + count;
+
+    if region.raw.address as u64 + size > 1 << 32 {
+        return Err(overwrite_edge(region, size, location))
+    }
+
+    Ok(())
 }
 
 impl Error for AssemblerError {}
 
 pub fn get_token<'a, 'b>(iter: &mut LexerCursor<'a, 'b>) -> Result<&'b Token<'a>, AssemblerError> {
-    iter.next_adjacent().ok_or(AssemblerError {
+    let token = iter.next_adjacent().ok_or(AssemblerError {
         location: None,
         reason: AssemblerReason::EndOfFile,
-    })
+    })?;
+
+    // A newline ends the statement, so it's left for the next one rather than read as an operand.
+    if token.kind == NewLine {
+        iter.set_position(iter.get_position() - 1)
+    }
+
+    Ok(token)
+}
+
+// True if the error came from running out of tokens in the statement, not from a wrong one.
+pub fn is_end_of_statement(error: &AssemblerError) -> bool {
+    match &error.reason {
+        AssemblerReason::EndOfFile => true,
+        AssemblerReason::ExpectedRegister(kind)
+        | AssemblerReason::ExpectedConstant(kind)
+        | AssemblerReason::ExpectedLabel(kind)
+        | AssemblerReason::ExpectedLeftBrace(kind)
+        | AssemblerReason::ExpectedRightBrace(kind) => *kind == StrippedKind::NewLine,
+        _ => false,
+    }
 }
 
 fn default_error(reason: AssemblerReason, token: &Token) -> AssemblerError {
@@ -179,7 +332,7 @@ pub fn get_value(iter: &mut LexerCursor) -> Result<InstructionValue, AssemblerEr
 }
 
 pub fn maybe_get_value(iter: &mut LexerCursor) -> Option<InstructionValue> {
-    let Some(value) = iter.seek_without(is_adjacent_kind) else { return None };
+    let value = iter.seek_without(is_adjacent_kind)?;
 
     if let Some(value) = get_integer(value, iter, true) {
         Some(Literal(value))
@@ -208,7 +361,7 @@ pub fn get_constant(iter: &mut LexerCursor) -> Result<u64, AssemblerError> {
     }
 }
 
-pub fn get_string(iter: &mut LexerCursor) -> Result<String, AssemblerError> {
+pub fn get_string(iter: &mut LexerCursor) -> Result<Vec<u8>, AssemblerError> {
     let token = get_token(iter)?;
 
     match &token.kind {
@@ -220,22 +373,82 @@ pub fn get_string(iter: &mut LexerCursor) -> Result<String, AssemblerError> {
     }
 }
 
+fn expression_factor(iter: &mut LexerCursor) -> Result<i64, AssemblerError> {
+    let token = get_token(iter)?;
+
+    match &token.kind {
+        IntegerLiteral(value) => Ok(*value as i64),
+        Plus => expression_factor(iter),
+        Minus => Ok(expression_factor(iter)?.wrapping_neg()),
+        LeftBrace => {
+            let value = expression_sum(iter)?;
+            let right = get_token(iter)?;
+
+            if right.kind != RightBrace {
+                return Err(default_error(AssemblerReason::ExpectedRightBrace(right.kind.strip()), right))
+            }
+
+            Ok(value)
+        }
+        _ => Err(default_error(AssemblerReason::ExpectedConstant(token.kind.strip()), token)),
+    }
+}
+
+// Folds `operand (op operand)*` for the operators in ops, left to right.
+fn expression_chain(
+    iter: &mut LexerCursor,
+    ops: &[StrippedKind],
+    operand: fn(&mut LexerCursor) -> Result<i64, AssemblerError>,
+) -> Result<i64, AssemblerError> {
+    let mut value = operand(iter)?;
+
+    loop {
+        let (position, token) = iter.peek_adjacent();
+
+        let Some(token) = token.filter(|token| ops.contains(&token.kind.strip())) else {
+            return Ok(value)
+        };
+
+        iter.set_position(position + 1);
+
+        let right = operand(iter)?;
+
+        value = match token.kind {
+            Plus => value.wrapping_add(right),
+            Minus => value.wrapping_sub(right),
+            Star => value.wrapping_mul(right),
+            _ if right == 0 => return Err(default_error(AssemblerReason::DivideByZero, token)),
+            _ => value.wrapping_div(right),
+        }
+    }
+}
+
+fn expression_product(iter: &mut LexerCursor) -> Result<i64, AssemblerError> {
+    expression_chain(iter, &[StrippedKind::Star, StrippedKind::Slash], expression_factor)
+}
+
+// A constant expression: integers, + - * / and parentheses, with the usual precedence.
+// Used for label offsets (`table + 4 * INDEX`). Arithmetic wraps, only dividing by zero fails.
+pub fn expression_sum(iter: &mut LexerCursor) -> Result<i64, AssemblerError> {
+    expression_chain(iter, &[StrippedKind::Plus, StrippedKind::Minus], expression_product)
+}
+
 fn to_label(token: &Token, iter: &mut LexerCursor) -> Result<AddressLabel, AssemblerError> {
     if let Some(value) = get_integer(token, iter, false) {
         Ok(Constant(value))
     } else {
         match &token.kind {
             Symbol(value) => {
-                let (position, plus) = iter.peek_adjacent();
-                let follows_plus = plus.map(|token| token.kind == Plus).unwrap_or(false);
+                let (position, sign) = iter.peek_adjacent();
+                let follows_sign = sign.is_some_and(|token| matches!(token.kind, Plus | Minus));
 
-                let offset = if follows_plus {
+                // The sign is read as part of the expression, so `label - 4 + 2` is label - 2.
+                let offset = if follows_sign {
                     iter.set_position(position);
-                    iter.next(); // consume +
 
-                    get_constant(iter)?
+                    expression_sum(iter)?
                 } else {
-                    0u64
+                    0
                 };
 
                 Ok(Label(NamedLabel {
@@ -1,10 +1,11 @@
 use crate::assembler::binary::BinarySection::{Data, KernelData, KernelText, Text};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use bitflags::bitflags;
 use crate::assembler::lexer::Location;
+use crate::assembler::assembler_util::AssemblerWarning;
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BinarySection {
     Text,
     Data,
@@ -21,6 +22,15 @@ impl BinarySection {
         matches!(self, Text | KernelText)
     }
 
+    pub fn directive(&self) -> &'static str {
+        match self {
+            Text => ".text",
+            Data => ".data",
+            KernelText => ".ktext",
+            KernelData => ".kdata",
+        }
+    }
+
     pub fn default_address(&self) -> u32 {
         match self {
             Text => 0x00400000,
@@ -35,13 +45,21 @@ impl BinarySection {
 pub struct NamedLabel {
     pub name: String,
     pub location: Location,
-    pub offset: u64,
+    pub offset: i64, // bytes added to the label's address
+}
+
+// `end - start`, a constant once both labels have an address.
+#[derive(Clone, Debug)]
+pub struct DifferenceLabel {
+    pub left: NamedLabel,
+    pub right: NamedLabel,
 }
 
 #[derive(Clone, Debug)]
 pub enum AddressLabel {
     Constant(u64),
     Label(NamedLabel), // usize -> start, offset
+    Difference(DifferenceLabel), // only from data directives
 }
 
 bitflags! {
@@ -76,23 +94,98 @@ impl RawRegion {
         self.address.checked_add(self.data.len() as u32)
     }
 
+    // Which directive would have made it, going by flags and whether it's in kernel space.
+    pub fn section(&self) -> BinarySection {
+        let kernel = self.address >= KernelText.default_address();
+
+        match (self.flags.contains(RegionFlags::EXECUTABLE), kernel) {
+            (true, false) => Text,
+            (false, false) => Data,
+            (true, true) => KernelText,
+            (false, true) => KernelData,
+        }
+    }
+
     pub fn wrapping_pc(&self) -> u32 {
         self.address.wrapping_add(self.data.len() as u32)
     }
+
+    pub fn contains(&self, address: u32) -> bool {
+        address.wrapping_sub(self.address) < self.data.len() as u32
+    }
+
+    // CRC-32 (IEEE, same as zlib) of the data, for cache keys and fingerprints.
+    // Address and flags aren't included, compare those separately.
+    pub fn crc32(&self) -> u32 {
+        let mut crc = !0u32;
+
+        for byte in &self.data {
+            crc ^= *byte as u32;
+
+            for _ in 0 .. 8 {
+                crc = (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg());
+            }
+        }
+
+        !crc
+    }
 }
 
+// One statement: where it was written and the words it became.
 #[derive(Clone, Debug)]
 pub struct BinaryBreakpoint {
     pub location: Location,
     pub pcs: Vec<u32>,
+    pub mnemonic: String, // lowercase, empty if read from a compiled binary older than version 4
+    pub pseudo: bool, // not a real instruction (la, bge, move)
+}
+
+impl BinaryBreakpoint {
+    // Expansion overhead: a pseudo-instruction, or a real one that took more than one word
+    // (addi with a large immediate, lw from a label, a relaxed branch).
+    pub fn is_expansion(&self) -> bool {
+        self.pseudo || self.pcs.len() > 1
+    }
+}
+
+// The fixup kinds an external linker can patch, named after InstructionLabelKind.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RelocationKind {
+    Upper,
+    Lower,
+    Jump,
+    Branch,
+    Full,
+}
+
+// A reference left for a linker. The field at offset holds the addend (label + offset), not an address.
+#[derive(Clone, Debug)]
+pub struct Relocation {
+    pub region: usize, // index into regions
+    pub offset: usize, // bytes from the start of the region
+    pub kind: RelocationKind,
+    pub symbol: String,
+}
+
+// How Binary::entry was picked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntrySource {
+    Directive, // .entry, or the entry of an ELF header
+    MainLabel, // no .entry, but main was defined
+    TextStart, // neither, the first text region (or the default text address if there is none)
 }
 
 #[derive(Clone, Debug)]
 pub struct Binary {
     pub entry: u32,
+    pub entry_source: EntrySource,
     pub regions: Vec<RawRegion>,
     pub breakpoints: Vec<BinaryBreakpoint>, // pc -> offset
-    pub labels: HashMap<String, u32>
+    pub labels: HashMap<String, u32>, // includes aliases, numeric labels (1:) only if a relocation names one
+    pub aliases: HashMap<String, String>, // alias -> label it was defined from
+    pub globals: HashSet<String>, // names given to .globl
+    pub relocations: Vec<Relocation>,
+    pub warnings: Vec<AssemblerWarning>,
 }
 
 fn build_breakpoint_map(
@@ -156,12 +249,97 @@ impl Binary {
         source_breakpoints(&self.breakpoints, source, id)
     }
 
+    // Index of the region holding address, or else one ending right at it (a label after the last item).
+    pub fn region_for(&self, address: u32) -> Option<usize> {
+        let regions = || self.regions.iter().enumerate().filter(|(_, region)| !region.data.is_empty());
+
+        regions().find(|(_, region)| address >= region.address && address < region.wrapping_pc())
+            .or_else(|| regions().find(|(_, region)| address == region.wrapping_pc()))
+            .map(|(index, _)| index)
+    }
+
+    // Entry for a program without .entry: main if it was defined, otherwise the start of its code.
+    pub fn default_entry(&mut self) {
+        if let Some(main) = self.labels.get("main") {
+            self.entry = *main;
+            self.entry_source = EntrySource::MainLabel;
+        } else {
+            let start = self.text_regions()
+                .find(|region| !region.data.is_empty())
+                .map_or(Text.default_address(), |region| region.address);
+
+            self.entry = start;
+            self.entry_source = EntrySource::TextStart;
+        }
+    }
+
+    // A name for the entry point, if any label sits there (main first, then alphabetically).
+    pub fn entry_label(&self) -> Option<&str> {
+        if self.entry_source == EntrySource::MainLabel || self.labels.get("main") == Some(&self.entry) {
+            return Some("main")
+        }
+
+        self.labels.iter()
+            .filter(|(_, address)| **address == self.entry)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+
+    pub fn text_regions(&self) -> impl Iterator<Item = &RawRegion> {
+        self.regions.iter().filter(|region| region.flags.contains(RegionFlags::EXECUTABLE))
+    }
+
+    pub fn data_regions(&self) -> impl Iterator<Item = &RawRegion> {
+        self.regions.iter().filter(|region| !region.flags.contains(RegionFlags::EXECUTABLE))
+    }
+
+    // Where the pc goes after the last word of each text region, running into one finishes the program.
+    // Data regions don't count, even if one ends where text starts.
+    pub fn end_pcs(&self) -> Vec<u32> {
+        let mut result: Vec<u32> = self.text_regions()
+            .filter(|region| !region.data.is_empty())
+            .map(|region| region.wrapping_pc())
+            .filter(|pc| self.text_regions().all(|region| !region.contains(*pc)))
+            .collect();
+
+        result.sort();
+        result.dedup();
+
+        result
+    }
+
+    // Unlike region_for, only a region with a byte at address.
+    pub fn region_containing(&self, address: u32) -> Option<&RawRegion> {
+        self.regions.iter().find(|region| region.contains(address))
+    }
+
+    // Little endian, None if any of the four bytes is outside the region holding address
+    // (words split between regions aren't stitched together).
+    pub fn word_at(&self, address: u32) -> Option<u32> {
+        let region = self.region_containing(address)?;
+        let start = address.wrapping_sub(region.address) as usize;
+
+        let bytes = region.data.get(start .. start.checked_add(4)?)?;
+
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    // Index of the breakpoint (source statement) that emitted the word at pc.
+    pub fn statement_for(&self, pc: u32) -> Option<usize> {
+        self.breakpoints.iter().position(|breakpoint| breakpoint.pcs.contains(&pc))
+    }
+
     pub fn new() -> Binary {
         Binary {
             entry: Text.default_address(),
+            entry_source: EntrySource::TextStart,
             regions: vec![],
             breakpoints: vec![],
-            labels: HashMap::new()
+            labels: HashMap::new(),
+            aliases: HashMap::new(),
+            globals: HashSet::new(),
+            relocations: vec![],
+            warnings: vec![],
         }
     }
 }
@@ -171,3 +349,76 @@ impl Default for Binary {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::binary::{Binary, RawRegion, RegionFlags};
+    use crate::assembler::string::assemble_from;
+
+    fn region(address: u32, data: &[u8]) -> RawRegion {
+        RawRegion { flags: RegionFlags::READABLE, address, data: data.to_vec() }
+    }
+
+    #[test]
+    fn checksums_match_zlib() {
+        assert_eq!(region(0, b"123456789").crc32(), 0xCBF43926);
+        assert_eq!(region(0, b"").crc32(), 0);
+
+        // Only the bytes count.
+        assert_eq!(region(0x400000, b"abc").crc32(), region(0x10010000, b"abc").crc32());
+        assert_ne!(region(0, b"abc").crc32(), region(0, b"abd").crc32());
+    }
+
+    #[test]
+    fn regions_split_into_text_and_data() {
+        let binary = assemble_from("
+            .text
+            main: nop
+            .data
+            value: .word 0x11223344
+            .byte 5, 6
+            .ktext
+            handler: nop
+            .kdata
+            .word 7
+        ").unwrap();
+
+        let text: Vec<u32> = binary.text_regions().map(|region| region.address).collect();
+        let data: Vec<u32> = binary.data_regions().map(|region| region.address).collect();
+
+        assert_eq!(text, [binary.labels["main"], binary.labels["handler"]]);
+        assert_eq!(data.len(), 2);
+        assert!(data.contains(&binary.labels["value"]));
+
+        let value = binary.labels["value"];
+
+        assert_eq!(binary.word_at(value), Some(0x11223344));
+        assert_eq!(binary.word_at(binary.labels["main"]), Some(0));
+        assert_eq!(binary.region_containing(value + 5).map(|region| region.address), Some(value));
+
+        // Past the last byte of the region, and a word that runs off its end.
+        assert!(binary.region_containing(value + 6).is_none());
+        assert_eq!(binary.word_at(value + 4), None);
+    }
+
+    #[test]
+    fn words_never_span_regions() {
+        let mut binary = Binary::new();
+
+        binary.regions.push(region(0x1000, &[1, 2, 3, 4, 5, 6]));
+        binary.regions.push(region(0x1006, &[7, 8]));
+        binary.regions.push(region(0xFFFFFFFE, &[9, 10]));
+
+        assert_eq!(binary.word_at(0x1000), Some(0x04030201));
+        assert_eq!(binary.word_at(0x1002), Some(0x06050403));
+
+        // Four bytes exist from 0x1004, but in two regions.
+        assert_eq!(binary.word_at(0x1004), None);
+        assert_eq!(binary.word_at(0x1006), None);
+
+        // Near the top of memory, nothing wraps around.
+        assert_eq!(binary.region_containing(0xFFFFFFFF).map(|region| region.address), Some(0xFFFFFFFE));
+        assert_eq!(binary.word_at(0xFFFFFFFE), None);
+        assert_eq!(binary.word_at(0x2000), None);
+    }
+}
@@ -1,57 +1,153 @@
-use crate::assembler::assembler_util::AssemblerError;
+use crate::assembler::assembler_util::{check_region_edge, fits_width, AssemblerError, AssemblerWarning, AssemblerWarningReason};
 use crate::assembler::assembler_util::AssemblerReason::{
-    JumpOutOfRange, MissingInstruction, UnknownLabel,
+    DuplicateAlias, JumpOutOfRange, LabelOutOfRange, MisalignedTarget, MissingInstruction, OutputTooLarge, RelaxedOverlap,
+    TargetNotCode, UnknownLabel,
+};
+use crate::assembler::binary::AddressLabel::{Constant, Difference, Label};
+use crate::assembler::binary::{
+    AddressLabel, Binary, BinaryBreakpoint, BinarySection, EntrySource, NamedLabel, RawRegion, RegionFlags, Relocation,
+    RelocationKind,
 };
-use crate::assembler::binary::AddressLabel::{Constant, Label};
-use crate::assembler::binary::{AddressLabel, Binary, BinaryBreakpoint, BinarySection, RawRegion, RegionFlags};
 use crate::assembler::binary_builder::BinarySection::Text;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::collections::HashMap;
-use std::io::Cursor;
+use std::collections::{HashMap, HashSet};
 use crate::assembler::lexer::Location;
+use crate::assembler::preprocessor::is_numeric_label;
+use crate::assembler::registers::RegisterSlot;
+
+fn named_address<F: FnMut(&str) -> Option<u32>>(name: &NamedLabel, mut lookup: F) -> Result<u32, AssemblerError> {
+    lookup(&name.name)
+        .map(|value| value.wrapping_add(name.offset as u32))
+        .ok_or_else(|| AssemblerError {
+            location: Some(name.location),
+            reason: UnknownLabel(name.name.clone()),
+        })
+}
 
-fn get_address(label: AddressLabel, map: &HashMap<String, u32>) -> Result<u32, AssemblerError> {
+fn get_address<F: FnMut(&str) -> Option<u32>>(label: &AddressLabel, mut lookup: F) -> Result<u32, AssemblerError> {
     match label {
-        Constant(value) => Ok(value as u32),
-        Label(name) => map
-            .get(&name.name)
-            .copied()
-            .map(|value| value.wrapping_add(name.offset as u32))
-            .ok_or(AssemblerError {
-                location: Some(name.location),
-                reason: UnknownLabel(name.name),
-            }),
+        Constant(value) => Ok(*value as u32),
+        Label(name) => named_address(name, lookup),
+        Difference(difference) => {
+            let left = named_address(&difference.left, &mut lookup)?;
+            let right = named_address(&difference.right, &mut lookup)?;
+
+            Ok(left.wrapping_sub(right))
+        }
+    }
+}
+
+// What branch and jump destinations are checked against.
+struct Targets<'a> {
+    definitions: &'a HashMap<String, Location>,
+    spans: Vec<(u32, u32, bool)>, // start, end, executable; for every region with data
+    strict: bool, // a destination outside of code is an error, not a warning
+}
+
+impl Targets<'_> {
+    // Where the label (the first one of a difference) is defined, not known for constants or aliases.
+    fn definition(&self, label: &AddressLabel) -> Option<Location> {
+        match label {
+            Label(name) => self.definitions.get(&name.name).copied(),
+            Difference(difference) => self.definitions.get(&difference.left.name).copied(),
+            Constant(_) => None,
+        }
+    }
+
+    fn in_data(&self, address: u32) -> bool {
+        self.spans.iter()
+            .any(|(start, end, executable)| !executable && (*start..*end).contains(&address))
+    }
+
+    fn check(
+        &self, label: &AddressLabel, destination: u32, location: Location, warnings: &mut Vec<AssemblerWarning>
+    ) -> Result<(), AssemblerError> {
+        let name = match label {
+            Label(name) => name.name.clone(),
+            Constant(value) => format!("{value:#x}"),
+            Difference(difference) => format!("{}-{}", difference.left.name, difference.right.name),
+        };
+
+        if destination % 4 != 0 {
+            return Err(AssemblerError {
+                location: Some(location),
+                reason: MisalignedTarget(name, destination, self.definition(label)),
+            })
+        }
+
+        if self.in_data(destination) {
+            if self.strict {
+                return Err(AssemblerError {
+                    location: Some(location),
+                    reason: TargetNotCode(name, destination),
+                })
+            }
+
+            warnings.push(AssemblerWarning {
+                location,
+                reason: AssemblerWarningReason::TargetNotCode(name, destination),
+            })
+        }
+
+        Ok(())
     }
 }
 
-fn add_label(
+fn add_label<F: FnMut(&str) -> Option<u32>>(
     instruction: u32,
     pc: u32,
     location: Location,
-    label: InstructionLabel,
-    map: &HashMap<String, u32>,
+    label: &InstructionLabel,
+    lookup: F,
+    targets: &Targets,
+    warnings: &mut Vec<AssemblerWarning>,
 ) -> Result<u32, AssemblerError> {
+    // Only needed for messages, so not built for every reference.
+    let name = || match &label.label {
+        Label(name) => name.name.clone(),
+        Constant(value) => format!("{value:#x}"),
+        Difference(difference) => format!("{}-{}", difference.left.name, difference.right.name),
+    };
+
+    // Data is usually too far to reach anyway, which says less about what went wrong.
     let make_out_of_range = |destination: u32| AssemblerError {
         location: Some(location),
-        reason: JumpOutOfRange(destination, pc),
+        reason: if targets.in_data(destination) {
+            TargetNotCode(name(), destination)
+        } else {
+            JumpOutOfRange(destination, pc)
+        },
     };
 
-    let destination = get_address(label.label, map)?;
+    let destination = get_address(&label.label, lookup)?;
+
+    if matches!(label.kind, InstructionLabelKind::Branch | InstructionLabelKind::Jump) {
+        targets.check(&label.label, destination, location, warnings)?
+    }
+
+    // Data fixups accept anything that fits the width either signed or unsigned.
+    let check_width = |bytes: usize| {
+        if !fits_width(destination as i32 as i64 as u64, bytes) {
+            Err(AssemblerError {
+                location: Some(location),
+                reason: LabelOutOfRange(name(), destination, bytes, targets.definition(&label.label)),
+            })
+        } else {
+            Ok(destination)
+        }
+    };
 
     Ok(match label.kind {
         InstructionLabelKind::Branch => {
-            let immediate = (destination >> 2) as i32 - ((pc + 4) >> 2) as i32;
-
-            if !(-0x10000..=0xFFFF).contains(&immediate) {
+            if !branch_in_range(destination, pc) {
                 return Err(make_out_of_range(destination));
             }
 
+            let immediate = (destination >> 2) as i32 - ((pc + 4) >> 2) as i32;
+
             instruction & 0xFFFF0000 | (immediate as u32 & 0xFFFF)
         }
         InstructionLabelKind::Jump => {
-            let lossy_mask = 0xF0000000u32;
-
-            if destination & lossy_mask != (pc + 4) & lossy_mask {
+            if !jump_in_range(destination, pc) {
                 return Err(make_out_of_range(destination));
             }
 
@@ -71,27 +167,227 @@ fn add_label(
             instruction & 0xFFFF0000 | top
         }
         InstructionLabelKind::Full => destination,
+        InstructionLabelKind::Half => check_width(2)? & 0xFFFF,
+        InstructionLabelKind::Byte => check_width(1)? & 0xFF,
     })
 }
 
+// What a linker is told to patch. Half and byte data have no matching kind, so they always resolve here.
+fn relocation_kind(kind: InstructionLabelKind) -> Option<RelocationKind> {
+    match kind {
+        InstructionLabelKind::Branch => Some(RelocationKind::Branch),
+        InstructionLabelKind::Jump => Some(RelocationKind::Jump),
+        InstructionLabelKind::Lower => Some(RelocationKind::Lower),
+        InstructionLabelKind::Upper => Some(RelocationKind::Upper),
+        InstructionLabelKind::Full => Some(RelocationKind::Full),
+        InstructionLabelKind::Half | InstructionLabelKind::Byte => None,
+    }
+}
+
+// REL relocations keep the addend in the field itself, the linker adds the symbol's address to it.
+// Branches are relative to the instruction after them, so their addend is 4 bytes short.
+fn addend_field(instruction: u32, kind: RelocationKind, addend: u32) -> u32 {
+    match kind {
+        RelocationKind::Branch => instruction & 0xFFFF0000 | ((addend.wrapping_sub(4) as i32 >> 2) as u32 & 0xFFFF),
+        RelocationKind::Jump => instruction & (!0u32 << 26) | ((addend >> 2) & (!0u32 >> 6)),
+        RelocationKind::Lower => instruction & 0xFFFF0000 | (addend & 0xFFFF),
+        RelocationKind::Upper => instruction & 0xFFFF0000 | (addend >> 16),
+        RelocationKind::Full => addend,
+    }
+}
+
+// Bytes of output all regions together may hold, so a hostile source fails before it can allocate gigabytes.
+pub const DEFAULT_OUTPUT_LIMIT: usize = 256 << 20;
+
+// Relaxation goes through the assembler's temporary register ($at unless BinaryBuilder::temporary says otherwise).
+fn load_upper(at: RegisterSlot) -> u32 {
+    0x3C000000 | (at as u32) << 16 // lui at, 0
+}
+
+fn or_lower(at: RegisterSlot) -> u32 {
+    0x34000000 | (at as u32) << 21 | (at as u32) << 16 // ori at, at, 0
+}
+
+fn jump_register(at: RegisterSlot) -> u32 {
+    (at as u32) << 21 | 0x08 // jr at
+}
+
+fn jump_link_register(at: RegisterSlot) -> u32 {
+    (at as u32) << 21 | 0xF809 // jalr $ra, at
+}
+
+// j and jal keep the top 4 bits of pc + 4, so they only reach their own 256MB block.
+fn jump_in_range(destination: u32, pc: u32) -> bool {
+    let mask = 0xF0000000u32;
+
+    destination & mask == pc.wrapping_add(4) & mask
+}
+
+fn branch_in_range(destination: u32, pc: u32) -> bool {
+    let immediate = (destination >> 2) as i32 - ((pc.wrapping_add(4)) >> 2) as i32;
+
+    (-0x8000..=0x7FFF).contains(&immediate)
+}
+
+// Words were inserted (count > 0) or removed (count < 0) at offset. Skips past it move with it,
+// and a skip whose words include offset grows or shrinks with them, so it still lands on the same instruction.
+fn adjust_skips(data: &mut [u8], skips: &mut [usize], offset: usize, count: i32) {
+    let moved = |position: usize| if count > 0 {
+        position + 4 * count as usize
+    } else {
+        position - 4 * count.unsigned_abs() as usize
+    };
+
+    for skip in skips.iter_mut() {
+        if *skip >= offset {
+            *skip = moved(*skip);
+
+            continue
+        }
+
+        let bytes = &mut data[*skip..*skip + 4];
+        let branch = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let words = branch & 0xFFFF;
+
+        // Words go in after an instruction, so an insert right at the target belongs to the last skipped word.
+        let target = *skip + 4 + 4 * words as usize;
+        let covers = if count > 0 { offset <= target } else { offset < target };
+
+        if covers {
+            let words = (words as i32 + count) as u32 & 0xFFFF;
+
+            bytes.copy_from_slice(&(branch & 0xFFFF0000 | words).to_le_bytes());
+        }
+    }
+}
+
+// Same branch with the opposite condition. Linking branches (bltzal, bgezal) have no inverse.
+fn invert_branch(instruction: u32) -> Option<u32> {
+    let op = instruction >> 26;
+    let rt = (instruction >> 16) & 0x1F;
+
+    match op {
+        4 | 6 => Some(instruction + (1 << 26)), // beq -> bne, blez -> bgtz
+        5 | 7 => Some(instruction - (1 << 26)), // bne -> beq, bgtz -> blez
+        1 if rt <= 1 => Some(instruction ^ (1 << 16)), // bltz <-> bgez
+        _ => None,
+    }
+}
+
+// Labels nothing in the program (fixups, .entry, .alias, .globl) refers to.
+// Entry point main is exempt, and so are numeric labels (`1:`), which are often just markers.
+fn unused_labels(
+    definitions: &HashMap<String, Location>,
+    table: &HashMap<&str, (u32, bool)>,
+    globals: &HashSet<String>,
+    aliases: &HashMap<String, String>,
+) -> Vec<AssemblerWarning> {
+    let targets: HashSet<&str> = aliases.values().map(|name| name.as_str()).collect();
+
+    let mut result: Vec<AssemblerWarning> = definitions.iter()
+        .filter(|(name, _)| {
+            let used = table.get(name.as_str()).is_some_and(|(_, used)| *used);
+
+            !used && !is_numeric_label(name) && name.as_str() != "main" && !globals.contains(*name) && !targets.contains(name.as_str())
+        })
+        .map(|(name, location)| AssemblerWarning {
+            location: *location,
+            reason: AssemblerWarningReason::UnusedLabel(name.clone()),
+        })
+        .collect();
+
+    result.sort_by_key(|warning| (warning.location.source, warning.location.index));
+
+    result
+}
+
+// Aliases resolve in passes so they can name labels (or other aliases) defined later on.
+fn resolve_aliases(
+    aliases: Vec<BinaryBuilderAlias>,
+    map: &mut HashMap<String, u32>,
+) -> Result<HashMap<String, String>, AssemblerError> {
+    let mut names = HashSet::new();
+
+    for alias in &aliases {
+        if map.contains_key(&alias.name) || !names.insert(alias.name.clone()) {
+            return Err(AssemblerError {
+                location: Some(alias.location),
+                reason: DuplicateAlias(alias.name.clone()),
+            });
+        }
+    }
+
+    let mut result = HashMap::new();
+    let mut pending = aliases;
+
+    while !pending.is_empty() {
+        let (ready, waiting): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|alias| map.contains_key(&alias.target.name));
+
+        if ready.is_empty() {
+            // Whatever is left names a label that doesn't exist, or only other waiting aliases.
+            let target = &waiting[0].target;
+
+            return Err(AssemblerError {
+                location: Some(target.location),
+                reason: UnknownLabel(target.name.clone()),
+            });
+        }
+
+        for alias in ready {
+            let address = map[&alias.target.name].wrapping_add(alias.target.offset as u32);
+
+            map.insert(alias.name.clone(), address);
+            result.insert(alias.name, alias.target.name);
+        }
+
+        pending = waiting;
+    }
+
+    Ok(result)
+}
+
+pub struct BinaryBuilderAlias {
+    pub name: String,
+    pub location: Location,
+    pub target: NamedLabel,
+}
+
 pub struct BinaryBuilderLabel {
     pub offset: usize,
     pub location: Location,
     pub label: InstructionLabel,
+    pub temporary: bool, // $at was available here, so relaxation may expand through it
 }
 
 pub struct BinaryBuilderRegion {
+    pub section: BinarySection,
     pub raw: RawRegion,
     pub labels: Vec<BinaryBuilderLabel>, // start
+    pub skips: Vec<usize>, // offsets of branches over a fixed number of words (relaxation's inverted branch)
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub enum InstructionLabelKind {
     Branch,
     Jump,
     Lower,
     Upper,
     Full,
+    Half, // .half data
+    Byte, // .byte data
+}
+
+impl InstructionLabelKind {
+    // Bytes patched at the label's offset.
+    pub fn size(&self) -> usize {
+        match self {
+            InstructionLabelKind::Half => 2,
+            InstructionLabelKind::Byte => 1,
+            _ => 4,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -103,6 +399,7 @@ pub struct InstructionLabel {
 pub struct BinaryBuilderState {
     pub mode: BinarySection,
     pub indices: HashMap<BinarySection, usize>,
+    pub at: bool, // pseudo instructions may use $at (.set at), false after .set noat, see temporary_available
 }
 
 pub struct BinaryBuilder {
@@ -110,11 +407,26 @@ pub struct BinaryBuilder {
     pub state: BinaryBuilderState,
     pub regions: Vec<BinaryBuilderRegion>,
     pub labels: HashMap<String, u32>,
+    pub definitions: HashMap<String, Location>, // where each label (not alias) was written
+    pub globals: HashSet<String>, // named by .globl, so not expected to be referenced here
+    pub offsets: HashMap<String, (usize, usize)>, // label -> region index, byte offset
+    pub relax_branches: bool, // rewrite out of range branches instead of failing, changes layout
+    pub optimize: bool, // shrink pseudo expansions once addresses are known, changes layout
+    pub colonless_labels: bool, // `count .word 0` defines count in data sections
+    pub padding_warnings: bool, // warn when .half or .word data is padded to its alignment
+    pub output_limit: usize, // bytes, across all regions
+    pub relocatable: bool, // leave label references to a linker, undefined labels are not an error
+    pub reject_data_targets: bool, // a branch or jump into a data section fails instead of warning
+    pub strict_commas: bool, // instruction operands need exactly one comma between each
+    pub temporary: RegisterSlot, // what pseudo instructions and relaxation expand through, $at by default
+    pub origins: HashMap<BinarySection, u32>, // where a section starts, if not its default address
+    pub aliases: Vec<BinaryBuilderAlias>,
     pub breakpoints: Vec<BinaryBreakpoint>,
+    pub warnings: Vec<AssemblerWarning>,
 }
 
 impl BinaryBuilderState {
-    fn index(&self) -> Option<usize> {
+    pub fn index(&self) -> Option<usize> {
         self.indices.get(&self.mode).cloned()
     }
 
@@ -122,6 +434,7 @@ impl BinaryBuilderState {
         BinaryBuilderState {
             mode: Text,
             indices: HashMap::new(),
+            at: true,
         }
     }
 }
@@ -133,20 +446,37 @@ impl BinaryBuilder {
             state: BinaryBuilderState::new(),
             regions: vec![],
             labels: HashMap::new(),
+            definitions: HashMap::new(),
+            globals: HashSet::new(),
+            offsets: HashMap::new(),
+            relax_branches: false,
+            optimize: false,
+            colonless_labels: false,
+            padding_warnings: true,
+            output_limit: DEFAULT_OUTPUT_LIMIT,
+            relocatable: false,
+            reject_data_targets: false,
+            strict_commas: false,
+            temporary: RegisterSlot::AssemblerTemporary,
+            origins: HashMap::new(),
+            aliases: vec![],
             breakpoints: vec![],
+            warnings: vec![],
         }
     }
 
-    fn seek(&mut self, address: u32, flags: RegionFlags) -> usize {
+    fn seek(&mut self, address: u32, section: BinarySection) -> usize {
         let index = self.regions.len();
 
         self.regions.push(BinaryBuilderRegion {
+            section,
             raw: RawRegion {
-                flags,
+                flags: section.into(),
                 address,
                 data: vec![],
             },
             labels: vec![],
+            skips: vec![],
         });
 
         index
@@ -155,19 +485,75 @@ impl BinaryBuilder {
     pub fn seek_mode(&mut self, mode: BinarySection) {
         self.state.mode = mode;
 
+        let origin = self.origins.get(&mode).copied().unwrap_or(mode.default_address());
+
         let index = self
             .state
             .index()
-            .unwrap_or_else(|| self.seek(mode.default_address(), mode.into()));
+            .unwrap_or_else(|| self.seek(origin, mode));
 
         self.state.indices.insert(mode, index);
     }
 
     pub fn seek_mode_address(&mut self, mode: BinarySection, address: u32) {
+        let previous = self.state.indices.get(&mode).copied();
+
         self.state.mode = mode;
 
-        let index = self.seek(address, mode.into());
+        let index = self.seek(address, mode);
         self.state.indices.insert(mode, index);
+
+        if let Some(previous) = previous {
+            self.carry_labels(previous, index);
+        }
+    }
+
+    // Regions left empty are dropped in build, so labels waiting at the start of one
+    // follow their section to wherever it emits next (the end of the region at index).
+    fn carry_labels(&mut self, from: usize, index: usize) {
+        if from == index || !self.regions[from].raw.data.is_empty() {
+            return
+        }
+
+        let region = &self.regions[index].raw;
+        let offset = region.data.len();
+        let address = region.address.wrapping_add(offset as u32);
+
+        for (name, position) in &mut self.offsets {
+            if position.0 == from {
+                *position = (index, offset);
+
+                self.labels.insert(name.clone(), address);
+            }
+        }
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.regions.iter().map(|region| region.raw.data.len()).sum()
+    }
+
+    // Call before writing bytes more output, so nothing is allocated past the limit.
+    pub fn reserve_output(&self, bytes: usize) -> Result<(), AssemblerError> {
+        if self.output_size().saturating_add(bytes) > self.output_limit {
+            return Err(AssemblerError {
+                location: None,
+                reason: OutputTooLarge(self.output_limit),
+            })
+        }
+
+        Ok(())
+    }
+
+    // Where each section would emit next, for the unit assembled after this one.
+    pub fn ends(&self) -> HashMap<BinarySection, u32> {
+        self.state.indices.iter()
+            .map(|(mode, index)| (*mode, self.regions[*index].raw.wrapping_pc()))
+            .collect()
+    }
+
+    // .set noat only reserves $at, a register picked in its place always belongs to the assembler.
+    pub fn temporary_available(&self) -> bool {
+        self.state.at || self.temporary != RegisterSlot::AssemblerTemporary
     }
 
     pub fn region(&mut self) -> Option<&mut BinaryBuilderRegion> {
@@ -176,54 +562,764 @@ impl BinaryBuilder {
         Some(&mut self.regions[index])
     }
 
-    pub fn build(self) -> Result<Binary, AssemblerError> {
+    // Inserts words right after the instruction at offset, moving everything behind it down
+    // (fixups, labels, breakpoints). The new words join that instruction's breakpoint.
+    fn insert_after(&mut self, index: usize, offset: usize, words: &[u32]) {
+        let region = &mut self.regions[index];
+        let pc = region.raw.address.wrapping_add(offset as u32);
+        let end = region.raw.address.wrapping_add(region.raw.data.len() as u32);
+        let size = 4 * words.len();
+
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        region.raw.data.splice(offset + 4..offset + 4, bytes);
+
+        for other in &mut region.labels {
+            if other.offset > offset {
+                other.offset += size;
+            }
+        }
+
+        adjust_skips(&mut region.raw.data, &mut region.skips, offset + 4, words.len() as i32);
+
+        for breakpoint in &mut self.breakpoints {
+            let anchor = breakpoint.pcs.iter().position(|address| *address == pc);
+
+            for address in &mut breakpoint.pcs {
+                if *address > pc && *address < end {
+                    *address += size as u32;
+                }
+            }
+
+            if let Some(position) = anchor {
+                for i in 0..words.len() {
+                    breakpoint.pcs.insert(position + 1 + i, pc + 4 * (i as u32 + 1));
+                }
+            }
+        }
+
+        for (region_index, label_offset) in self.offsets.values_mut() {
+            if *region_index == index && *label_offset > offset {
+                *label_offset += size;
+            }
+        }
+
+        for (name, (region_index, label_offset)) in &self.offsets {
+            let address = self.regions[*region_index].raw.address.wrapping_add(*label_offset as u32);
+
+            self.labels.insert(name.clone(), address);
+        }
+    }
+
+    // Drops the word right after the instruction at offset, moving everything behind it up.
+    // Opposite of insert_after, the dropped word leaves its breakpoint.
+    fn remove_after(&mut self, index: usize, offset: usize) {
+        let region = &mut self.regions[index];
+        let removed = region.raw.address.wrapping_add(offset as u32 + 4);
+        let end = region.raw.address.wrapping_add(region.raw.data.len() as u32);
+
+        region.raw.data.drain(offset + 4..offset + 8);
+
+        adjust_skips(&mut region.raw.data, &mut region.skips, offset + 4, -1);
+
+        region.labels.retain(|other| other.offset != offset + 4);
+
+        for other in &mut region.labels {
+            if other.offset > offset + 4 {
+                other.offset -= 4;
+            }
+        }
+
+        for breakpoint in &mut self.breakpoints {
+            breakpoint.pcs.retain(|address| *address != removed);
+
+            for address in &mut breakpoint.pcs {
+                if *address > removed && *address < end {
+                    *address -= 4;
+                }
+            }
+        }
+
+        for (region_index, label_offset) in self.offsets.values_mut() {
+            if *region_index == index && *label_offset > offset + 4 {
+                *label_offset -= 4;
+            }
+        }
+
+        for (name, (region_index, label_offset)) in &self.offsets {
+            let address = self.regions[*region_index].raw.address.wrapping_add(*label_offset as u32);
+
+            self.labels.insert(name.clone(), address);
+        }
+    }
+
+    // The fixup at regions[index].labels[label] if it's the lui of
+    //   lui rd, upper
+    //   ori rd, rd, lower
+    // for a label whose upper half is zero, so the pair can be just ori rd, $zero, lower.
+    fn shrinkable_load(&self, index: usize, label: usize) -> bool {
+        let region = &self.regions[index];
+        let upper = &region.labels[label];
+
+        if !matches!(upper.label.kind, InstructionLabelKind::Upper) {
+            return false
+        }
+
+        // Only plain labels, they can only move down as code shrinks, differences might not fit later.
+        let AddressLabel::Label(name) = &upper.label.label else { return false };
+
+        let lower_offset = upper.offset + 4;
+
+        let has_lower = region.labels.iter().any(|other| {
+            other.offset == lower_offset
+                && matches!(other.label.kind, InstructionLabelKind::Lower)
+                && matches!(&other.label.label, AddressLabel::Label(other) if other.name == name.name && other.offset == name.offset)
+        });
+
+        // A label between the two would lose its instruction.
+        let split = self.offsets.values()
+            .any(|(region_index, offset)| *region_index == index && *offset == lower_offset);
+
+        if !has_lower || split {
+            return false
+        }
+
+        let Some(bytes) = region.raw.data.get(upper.offset..upper.offset + 8) else { return false };
+
+        let lui = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let ori = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+
+        let dest = (lui >> 16) & 0x1F;
+
+        if lui >> 26 != 15 || ori >> 26 != 13 || (ori >> 16) & 0x1F != dest || (ori >> 21) & 0x1F != dest {
+            return false
+        }
+
+        get_address(&upper.label.label, |name| self.labels.get(name).copied())
+            .is_ok_and(|address| address & 0xFFFF0000 == 0)
+    }
+
+    // Shrinking moves labels down, which can make more of them fit, so repeat until nothing changes.
+    // Runs after relax, so nothing grows again behind it.
+    fn optimize(&mut self) {
+        loop {
+            let mut changed = false;
+
+            for index in 0..self.regions.len() {
+                let mut label = 0;
+
+                while label < self.regions[index].labels.len() {
+                    if !self.shrinkable_load(index, label) {
+                        label += 1;
+
+                        continue
+                    }
+
+                    let offset = self.regions[index].labels[label].offset;
+
+                    let region = &mut self.regions[index];
+                    let ori = u32::from_le_bytes([
+                        region.raw.data[offset + 4], region.raw.data[offset + 5],
+                        region.raw.data[offset + 6], region.raw.data[offset + 7],
+                    ]);
+
+                    // ori rd, $zero, lower in place of the lui.
+                    region.raw.data[offset..offset + 4].copy_from_slice(&(ori & !(0x1F << 21)).to_le_bytes());
+                    region.labels[label].label.kind = InstructionLabelKind::Lower;
+
+                    self.remove_after(index, offset);
+
+                    changed = true;
+                    label = 0; // the lower fixup is gone, indices behind it moved
+                }
+            }
+
+            if !changed {
+                break
+            }
+        }
+    }
+
+    // Turns the fixup at regions[index].labels[label] (currently at offset) into
+    //   lui $at, upper
+    //   ori $at, $at, lower
+    // with the fixup now patching the lui, and a second one added for the ori.
+    fn load_target(&mut self, index: usize, label: usize, offset: usize) {
+        let region = &mut self.regions[index];
+
+        region.raw.data[offset..offset + 4].copy_from_slice(&load_upper(self.temporary).to_le_bytes());
+        region.raw.data[offset + 4..offset + 8].copy_from_slice(&or_lower(self.temporary).to_le_bytes());
+
+        let fixup = &mut region.labels[label];
+        fixup.offset = offset;
+        fixup.label.kind = InstructionLabelKind::Upper;
+
+        let lower = BinaryBuilderLabel {
+            offset: offset + 4,
+            location: fixup.location,
+            label: InstructionLabel {
+                kind: InstructionLabelKind::Lower,
+                label: fixup.label.label.clone(),
+            },
+            temporary: fixup.temporary,
+        };
+
+        region.labels.push(lower);
+    }
+
+    // Turns the branch fixup at regions[index].labels[label] into
+    //   (inverted branch) +1
+    //   j target
+    // or, when j can't reach the target either and $at is free,
+    //   (inverted branch) +3
+    //   lui $at, upper
+    //   ori $at, $at, lower
+    //   jr $at
+    fn relax_branch(&mut self, index: usize, label: usize, inverted: u32, far: bool) {
+        let offset = self.regions[index].labels[label].offset;
+        let skip = if far { 3 } else { 1 };
+
+        self.regions[index].raw.data[offset..offset + 4]
+            .copy_from_slice(&(inverted & 0xFFFF0000 | skip).to_le_bytes());
+
+        if far {
+            self.insert_after(index, offset, &[0, 0, jump_register(self.temporary)]);
+            self.load_target(index, label, offset + 4);
+        } else {
+            self.insert_after(index, offset, &[2u32 << 26]);
+        }
+
+        // Added after the words it skips, so inserting them didn't stretch it.
+        self.regions[index].skips.push(offset);
+
+        if !far {
+            let fixup = &mut self.regions[index].labels[label];
+            fixup.offset += 4;
+            fixup.label.kind = InstructionLabelKind::Jump;
+            fixup.temporary = false; // the +1 skip above can't grow with it
+        }
+    }
+
+    // Turns j/jal at regions[index].labels[label] into a register jump through $at.
+    fn relax_jump(&mut self, index: usize, label: usize, instruction: u32) {
+        let offset = self.regions[index].labels[label].offset;
+        let link = instruction >> 26 == 3;
+
+        let jump = if link { jump_link_register(self.temporary) } else { jump_register(self.temporary) };
+
+        self.insert_after(index, offset, &[0, jump]);
+        self.load_target(index, label, offset);
+    }
+
+    // Relaxing moves code, which can push other branches out of range, so repeat until nothing changes.
+    // Fixups that can't be relaxed are left for build to report.
+    fn relax(&mut self) {
+        loop {
+            let mut changed = false;
+
+            for index in 0..self.regions.len() {
+                for label in 0..self.regions[index].labels.len() {
+                    let region = &self.regions[index];
+                    let fixup = &region.labels[label];
+
+                    let kind = fixup.label.kind;
+
+                    if !matches!(kind, InstructionLabelKind::Branch | InstructionLabelKind::Jump) {
+                        continue
+                    }
+
+                    let pc = region.raw.address.wrapping_add(fixup.offset as u32);
+
+                    let Ok(destination) = get_address(&fixup.label.label, |name| self.labels.get(name).copied()) else {
+                        continue
+                    };
+
+                    let Some(bytes) = region.raw.data.get(fixup.offset..fixup.offset + 4) else {
+                        continue
+                    };
+
+                    let instruction = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+                    match kind {
+                        InstructionLabelKind::Branch if !branch_in_range(destination, pc) => {
+                            let Some(inverted) = invert_branch(instruction) else { continue };
+
+                            // The j would sit one instruction further along.
+                            let far = !jump_in_range(destination, pc + 4) && fixup.temporary;
+
+                            self.relax_branch(index, label, inverted, far);
+                        }
+                        InstructionLabelKind::Jump if !jump_in_range(destination, pc) && fixup.temporary => {
+                            self.relax_jump(index, label, instruction);
+                        }
+                        _ => continue,
+                    }
+
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break
+            }
+        }
+    }
+
+    // A region that grew during relax can run into one that started right after it (ends are from before relax).
+    // Regions that already overlapped were written that way, those are left alone.
+    fn check_relaxed_overlap(&self, ends: &[u32]) -> Result<(), AssemblerError> {
+        for (region, end) in self.regions.iter().zip(ends) {
+            let start = region.raw.address;
+            let grown = region.raw.wrapping_pc();
+
+            let next = self.regions.iter()
+                .filter(|other| !other.raw.data.is_empty())
+                .map(|other| other.raw.address)
+                .find(|address| address.wrapping_sub(start) >= end.wrapping_sub(start)
+                    && address.wrapping_sub(start) < grown.wrapping_sub(start));
+
+            if let Some(next) = next {
+                return Err(AssemblerError {
+                    location: None,
+                    reason: RelaxedOverlap(region.section, start, next),
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build(mut self) -> Result<Binary, AssemblerError> {
         let mut binary = Binary::new();
 
+        if self.relax_branches {
+            let ends: Vec<u32> = self.regions.iter().map(|region| region.raw.wrapping_pc()).collect();
+
+            self.relax();
+
+            self.check_relaxed_overlap(&ends)?;
+        }
+
+        // Relaxing grows regions, which can push one past the end of memory.
+        for region in &self.regions {
+            check_region_edge(region, 0, None)?;
+        }
+
+        if self.optimize && !self.relocatable {
+            self.optimize();
+        }
+
+        binary.aliases = resolve_aliases(self.aliases, &mut self.labels)?;
+
         const MISSING: AssemblerError = AssemblerError {
             location: None,
             reason: MissingInstruction,
         };
 
-        if let Some(entry) = self.entry {
-            let address = get_address(entry, &self.labels)?;
+        // Every reference goes through here once, which also marks its label as used.
+        let mut table: HashMap<&str, (u32, bool)> = self.labels.iter()
+            .map(|(name, address)| (name.as_str(), (*address, false)))
+            .collect();
+
+        let mut lookup = |name: &str| table.get_mut(name).map(|(address, used)| {
+            *used = true;
+
+            *address
+        });
 
-            binary.entry = address;
+        if let Some(entry) = &self.entry {
+            match get_address(entry, &mut lookup) {
+                Ok(address) => {
+                    binary.entry = address;
+                    binary.entry_source = EntrySource::Directive;
+                }
+                Err(_) if self.relocatable => {} // defined elsewhere, up to the linker
+                Err(error) => return Err(error),
+            }
         }
 
+        let targets = Targets {
+            definitions: &self.definitions,
+            spans: self.regions.iter()
+                .filter(|region| !region.raw.data.is_empty())
+                .map(|region| {
+                    let executable = region.raw.flags.contains(RegionFlags::EXECUTABLE);
+
+                    (region.raw.address, region.raw.address.wrapping_add(region.raw.data.len() as u32), executable)
+                })
+                .collect(),
+            strict: self.reject_data_targets,
+        };
+
         for region in self.regions {
             let mut raw = region.raw;
+            let index = binary.regions.len();
 
             for label in region.labels {
-                let pc = raw.address + label.offset as u32;
+                let pc = raw.address.wrapping_add(label.offset as u32);
                 let size = raw.data.len();
+                let width = label.label.kind.size();
 
-                let bytes = &raw.data[label.offset..label.offset + 4];
-
-                let instruction = Cursor::new(bytes).read_u32::<LittleEndian>();
-                let Ok(instruction) = instruction else {
+                let Some(bytes) = raw.data.get(label.offset..label.offset + width) else {
                     return Err(MISSING)
                 };
 
-                let result = add_label(instruction, pc, label.location, label.label, &self.labels)?;
+                let mut word = [0u8; 4];
+                word[..width].copy_from_slice(bytes);
+
+                let instruction = u32::from_le_bytes(word);
+
+                let relocation = match (&label.label.label, relocation_kind(label.label.kind)) {
+                    (Label(name), Some(kind)) if self.relocatable => Some((name, kind)),
+                    _ => None,
+                };
+
+                if let Some((name, kind)) = relocation {
+                    lookup(&name.name); // only marks it as used, the address is the linker's to fill in
 
-                let mut_bytes = &mut raw.data[label.offset..label.offset + 4];
+                    let result = addend_field(instruction, kind, name.offset as u32);
 
-                if Cursor::new(mut_bytes)
-                    .write_u32::<LittleEndian>(result)
-                    .is_err()
-                {
-                    return Err(MISSING);
+                    raw.data[label.offset..label.offset + width].copy_from_slice(&result.to_le_bytes()[..width]);
+
+                    binary.relocations.push(Relocation {
+                        region: index,
+                        offset: label.offset,
+                        kind,
+                        symbol: name.name.clone(),
+                    });
+
+                    continue
                 }
 
+                let result = add_label(
+                    instruction, pc, label.location, &label.label, &mut lookup, &targets, &mut self.warnings
+                )?;
+
+                raw.data[label.offset..label.offset + width]
+                    .copy_from_slice(&result.to_le_bytes()[..width]);
+
                 assert_eq!(size, raw.data.len());
             }
 
-            binary.regions.push(raw)
+            // Nothing to load for an empty region, its labels were carried along by seek_mode_address.
+            // A label with no later emission in its section keeps its address, like one at the end of a region.
+            if !raw.data.is_empty() {
+                binary.regions.push(raw)
+            }
         }
 
+        let mut unused = unused_labels(&self.definitions, &table, &self.globals, &binary.aliases);
+        self.warnings.append(&mut unused);
+
         binary.breakpoints = self.breakpoints;
-        binary.labels = self.labels;
+
+        // Numeric labels are only there for 1b and 1f, so they stay out of symbol tables and the debugger,
+        // unless a relocation still needs one as its symbol.
+        let relocated: HashSet<&str> = binary.relocations.iter()
+            .map(|relocation| relocation.symbol.as_str())
+            .collect();
+
+        binary.labels = self.labels.into_iter()
+            .filter(|(name, _)| !is_numeric_label(name) || relocated.contains(name.as_str()))
+            .collect();
+
+        if binary.entry_source != EntrySource::Directive {
+            binary.default_entry();
+        }
+        binary.globals = self.globals;
+        binary.warnings = self.warnings;
 
         Ok(binary)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_util::AssemblerReason::{
+        JumpOutOfRange, MisalignedTarget, RelaxedOverlap, TargetNotCode,
+    };
+    use crate::assembler::assembler_util::{AssemblerError, AssemblerWarningReason};
+    use crate::assembler::binary::Binary;
+    use crate::assembler::binary::BinarySection::Text;
+    use crate::assembler::binary_builder::adjust_skips;
+    use crate::assembler::core::AssembleOptions;
+    use crate::assembler::project::file_error;
+    use crate::assembler::source::FileProviderPool;
+    use crate::assembler::string::{assemble_from_with_options, assemble_with_provider, SourceError};
+    use crate::unit::device::UnitDevice;
+    use crate::unit::device::UnitDeviceError::ProgramCompleted;
+    use crate::unit::register::RegisterName::T2;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    // Kernel code branching to user code 2 GiB away, so relaxation has to go through lui/ori/jr,
+    // and the target's upper half is zero, so -O shrinks that lui/ori pair inside the relaxed skip.
+    const FAR_BRANCHES: &str = "
+        .ktext
+        main:
+            li $t0, 1
+            li $t1, 2
+            beq $t0, $t1, far
+            addi $t2, $t2, 1
+            beq $t0, $t0, far
+            addi $t2, $t2, 10
+        .text 0x1000
+        far:
+            addi $t2, $t2, 100
+    ";
+
+    fn assemble(source: &str, optimize: bool) -> Binary {
+        let options = AssembleOptions { relax_branches: true, optimize, ..AssembleOptions::default() };
+
+        assemble_from_with_options(source, options).unwrap()
+    }
+
+    fn run(binary: Binary) -> u32 {
+        let device = UnitDevice::new(binary);
+
+        assert!(matches!(device.execute_until([]), Err(ProgramCompleted(..))));
+
+        device.get(T2)
+    }
+
+    fn words(data: &[u8]) -> Vec<u32> {
+        data.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+    }
+
+    fn size(binary: &Binary) -> usize {
+        binary.regions.iter().map(|region| region.data.len()).sum()
+    }
+
+    #[test]
+    fn optimizing_keeps_relaxed_skips() {
+        let plain = assemble(FAR_BRANCHES, false);
+        let optimized = assemble(FAR_BRANCHES, true);
+
+        // Both far branches lose their lui.
+        assert_eq!(size(&plain) - size(&optimized), 8);
+
+        let kernel = &optimized.regions[0].data;
+        let skip = u32::from_le_bytes(kernel[8..12].try_into().unwrap());
+
+        assert_eq!(skip, 0x15090002); // bne $t0, $t1, +2 over ori, jr
+
+        assert_eq!(run(plain), 101);
+        assert_eq!(run(optimized), 101);
+    }
+
+    #[test]
+    fn branches_past_128_kib_relax() {
+        let source = "
+            main:
+                li $t0, 1
+                beq $t0, $zero, far
+                addi $t2, $t2, 1
+                beq $t0, $t0, far
+                .space 0x20000
+            far:
+                addi $t2, $t2, 100
+        ";
+
+        let binary = assemble(source, false);
+        let code = words(&binary.regions[0].data[..28]);
+
+        // Each beq became the inverted branch over a j, far is at 0x400000 + 24 + 0x20000.
+        assert_eq!(code, vec![0x24080001, 0x15000001, 0x08108006, 0x214A0001, 0x15080001, 0x08108006, 0]);
+        assert_eq!(binary.labels["far"], 0x00420018);
+
+        assert_eq!(run(binary), 101);
+    }
+
+    #[test]
+    fn relaxed_regions_cannot_overlap() {
+        let source = ".text 0x400000\nbeq $t0, $t1, far\n.text 0x400004\nnop\n.text 0x500000\nfar: nop\n";
+        let options = AssembleOptions { relax_branches: true, ..AssembleOptions::default() };
+
+        let Err(SourceError::Assembler(error)) = assemble_from_with_options(source, options) else {
+            panic!("expected an assembler error")
+        };
+
+        assert!(matches!(error.reason, RelaxedOverlap(Text, 0x400000, 0x400004)));
+    }
+
+    const KERNEL_CALL: &str = "
+        main:
+            jal handler
+            addi $t2, $t2, 1
+            j done
+        .ktext
+        handler:
+            addi $t2, $t2, 100
+            jr $ra
+        .text 0x400100
+        done:
+            nop
+    ";
+
+    #[test]
+    fn jumps_across_segments_fail_or_relax() {
+        let Err(SourceError::Assembler(error)) = assemble_from_with_options(KERNEL_CALL, AssembleOptions::default()) else {
+            panic!("expected an assembler error")
+        };
+
+        assert!(matches!(error.reason, JumpOutOfRange(0x80000000, 0x400000)), "{}", error.reason);
+
+        let binary = assemble(KERNEL_CALL, false);
+
+        // lui $at, 0x8000, ori $at, $at, 0, jalr $at
+        assert_eq!(words(&binary.regions[0].data[..12]), vec![0x3c018000, 0x34210000, 0x0020f809]);
+        assert_eq!(run(binary), 101);
+
+        // Relaxing needs $at.
+        let options = AssembleOptions { relax_branches: true, ..AssembleOptions::default() };
+        let source = format!(".set noat\n{KERNEL_CALL}");
+
+        let Err(SourceError::Assembler(error)) = assemble_from_with_options(&source, options) else {
+            panic!("expected an assembler error")
+        };
+
+        assert!(matches!(error.reason, JumpOutOfRange(0x80000000, 0x400000)), "{}", error.reason);
+    }
+
+    // (address, length) of each region, and the labels by name.
+    type Layout = (Vec<(u32, usize)>, Vec<(String, u32)>);
+
+    fn layout(source: &str) -> Layout {
+        let binary = assemble_from_with_options(source, AssembleOptions::default()).unwrap();
+
+        let regions = binary.regions.iter().map(|region| (region.address, region.data.len())).collect();
+        let mut labels: Vec<_> = binary.labels.into_iter().collect();
+
+        labels.sort();
+
+        (regions, labels)
+    }
+
+    #[test]
+    fn labels_before_empty_regions_move_on() {
+        // start waits in a region nothing is emitted into, so it follows .text to 0x500000.
+        assert_eq!(
+            layout(".text\nstart:\n.text 0x500000\nnop\n"),
+            (vec![(0x500000, 4)], vec![("start".into(), 0x500000)])
+        );
+
+        assert_eq!(
+            layout(".text 0x500000\nhere:\n.text 0x600000\nthere:\n.text 0x700000\nnop\n"),
+            (vec![(0x700000, 4)], vec![("here".into(), 0x700000), ("there".into(), 0x700000)])
+        );
+
+        // Switching to another section and back keeps the region, the label stays at its start.
+        assert_eq!(
+            layout(".data\nfirst:\n.text\nnop\n.data\n.word 1\n"),
+            (vec![(0x400000, 4), (0x10010000, 4)], vec![("first".into(), 0x10010000)])
+        );
+
+        // Nothing after it in its section, it keeps its address without a region.
+        assert_eq!(
+            layout("nop\n.kdata\nlonely:\n"),
+            (vec![(0x400000, 4)], vec![("lonely".into(), 0x90000000)])
+        );
+    }
+
+    #[test]
+    fn section_switches_leave_no_empty_regions() {
+        assert_eq!(layout(".data\n.text\n.data\n.word 1\n.text\nnop\n").0, [(0x400000, 4), (0x10010000, 4)]);
+        assert_eq!(layout(".data\n.align 2\n.kdata\n.ktext\n.data\n.word 1\n").0, [(0x10010000, 4)]);
+        assert_eq!(layout("").0, []);
+    }
+
+    #[test]
+    fn skips_follow_inserted_words() {
+        let encode = |words: &[u32]| words.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+
+        // Same as insert_after and remove_after, without the labels and breakpoints.
+        let insert = |data: &mut Vec<u8>, skips: &mut Vec<usize>, offset: usize, count: usize| {
+            data.splice(offset..offset, vec![0; 4 * count]);
+            adjust_skips(data, skips, offset, count as i32);
+        };
+
+        let remove = |data: &mut Vec<u8>, skips: &mut Vec<usize>, offset: usize| {
+            data.drain(offset..offset + 4);
+            adjust_skips(data, skips, offset, -1);
+        };
+
+        // bne +2 at 0 over the words at 4 and 8, then bne +1 at 16 over the word at 20.
+        let mut data = encode(&[0x15090002, 1, 2, 3, 0x15090001, 4, 5]);
+        let mut skips = vec![0, 16];
+
+        // After the first skipped word.
+        insert(&mut data, &mut skips, 8, 2);
+
+        assert_eq!(words(&data), vec![0x15090004, 1, 0, 0, 2, 3, 0x15090001, 4, 5]);
+        assert_eq!(skips, vec![0, 24]);
+
+        // Right after the last skipped word still belongs to the skip, the target moves.
+        insert(&mut data, &mut skips, 20, 1);
+
+        assert_eq!(words(&data), vec![0x15090005, 1, 0, 0, 2, 0, 3, 0x15090001, 4, 5]);
+
+        // The second branch's only skipped word, then a word after it that nothing skips.
+        remove(&mut data, &mut skips, 32);
+        remove(&mut data, &mut skips, 32);
+
+        assert_eq!(words(&data), vec![0x15090005, 1, 0, 0, 2, 0, 3, 0x15090000]);
+        assert_eq!(skips, vec![0, 28]);
+    }
+
+    fn target_error(source: &str, options: AssembleOptions) -> AssemblerError {
+        let Err(SourceError::Assembler(error)) = assemble_from_with_options(source, options) else {
+            panic!("{source:?} assembled")
+        };
+
+        error
+    }
+
+    #[test]
+    fn branches_to_labels_after_odd_data_fail() {
+        for branch in ["beq $t0, $zero, odd", "j odd", "jal odd", "b odd"] {
+            let source = format!(".data\n.ascii \"abc\"\nodd: .byte 0\n.text\nmain: {branch}\n");
+            let error = target_error(&source, AssembleOptions::default());
+
+            assert!(
+                matches!(&error.reason, MisalignedTarget(name, 0x10010003, Some(_)) if name == "odd"),
+                "{branch}: {:?}", error.reason
+            );
+        }
+
+        // Named with where the label is, on line 3.
+        let source = ".data\n.ascii \"abc\"\nodd: .byte 0\n.text\nmain: beq $t0, $zero, odd\n";
+        let pool = FileProviderPool::in_memory(HashMap::from([("main.asm".to_string(), source.to_string())]));
+        let error = assemble_with_provider(&pool.in_memory_provider("main.asm").unwrap()).unwrap_err();
+        let error = file_error(&pool, Path::new("main.asm"), error);
+
+        assert_eq!(error.place.as_deref(), Some("main.asm:5"));
+        assert_eq!(error.definition.as_deref(), Some("main.asm:3"));
+    }
+
+    #[test]
+    fn branches_into_data_warn_or_fail() {
+        // Close enough to reach, otherwise the branch is just out of range.
+        let source = ".data 0x410000\narray: .word 1, 2\n.text\nmain: beq $t0, $zero, array\n";
+
+        let binary = assemble_from_with_options(source, AssembleOptions::default()).unwrap();
+
+        assert!(binary.warnings.iter().any(|warning| matches!(
+            &warning.reason, AssemblerWarningReason::TargetNotCode(name, 0x410000) if name == "array"
+        )));
+
+        let strict = AssembleOptions { reject_data_targets: true, ..AssembleOptions::default() };
+        let error = target_error(source, strict);
+
+        assert!(matches!(&error.reason, TargetNotCode(name, 0x410000) if name == "array"));
+
+        // Out of reach, the data is still what's reported.
+        let far = ".data\narray: .word 1\n.text\nbeq $t0, $zero, array\n";
+        let error = target_error(far, AssembleOptions::default());
+
+        assert!(matches!(&error.reason, TargetNotCode(name, 0x10010000) if name == "array"));
+
+        // Loading the address of data is fine either way.
+        let binary = assemble_from_with_options(".data\narray: .word 1\n.text\nla $t0, array\n", strict).unwrap();
+
+        assert!(binary.warnings.is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+use crate::assembler::binary::{Binary, BinaryBreakpoint, EntrySource, RawRegion, RegionFlags, Relocation, RelocationKind};
+use crate::assembler::binary_format::BinaryFormatError::{
+    InvalidEntrySource, InvalidFlags, InvalidMagic, InvalidRegion, InvalidRelocation, InvalidString, Truncated, TrailingBytes,
+    UnsupportedVersion,
+};
+use crate::assembler::lexer::Location;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{Cursor, Read};
+
+// Layout (all little endian):
+//   magic u32, version u32, entry u32
+//   regions:     count u32, then flags u32, address u32, length u32, data
+//   labels:      count u32, then name, address u32 (sorted by name, so output is stable)
+//   aliases:     count u32, then name, target name (sorted by name)
+//   breakpoints: count u32, then source u32, index u32, pc count u32, pcs u32...
+//   globals:     count u32, then name (sorted)
+//   relocations: count u32, then region u32, offset u32, kind u32, symbol name
+//   statements:  mnemonic, pseudo u8 for each breakpoint, in the same order
+//   entry source u8
+// Strings are a u32 length and UTF-8 bytes. Warnings are not stored.
+pub const BINARY_MAGIC: u32 = u32::from_le_bytes(*b"TBIN");
+pub const BINARY_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum BinaryFormatError {
+    InvalidMagic(u32),
+    UnsupportedVersion(u32),
+    Truncated,
+    TrailingBytes(usize),
+    InvalidFlags(u32),
+    InvalidRegion(u32), // address, region runs past the end of the address space
+    InvalidString,
+    InvalidRelocation(u32), // kind, a region index that doesn't exist, or an offset past the region
+    InvalidEntrySource(u8),
+}
+
+impl Display for BinaryFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidMagic(magic) => write!(f, "Not a compiled binary (magic is 0x{magic:08x})"),
+            UnsupportedVersion(version) => write!(
+                f, "Compiled binary has version {version}, but only version {BINARY_VERSION} is supported"),
+            Truncated => write!(f, "Compiled binary ends early, the data is truncated or corrupted"),
+            TrailingBytes(count) => write!(f, "Compiled binary has {count} unexpected bytes at the end"),
+            InvalidFlags(flags) => write!(f, "Compiled binary has unknown region flags 0x{flags:x}"),
+            InvalidRegion(address) => write!(
+                f, "Compiled binary has a region at 0x{address:08x} that does not fit in memory"),
+            InvalidString => write!(f, "Compiled binary has a name that is not valid UTF-8"),
+            InvalidRelocation(value) => write!(f, "Compiled binary has an invalid relocation ({value})"),
+            InvalidEntrySource(value) => write!(f, "Compiled binary has an unknown entry source ({value})"),
+        }
+    }
+}
+
+impl Error for BinaryFormatError {}
+
+fn write_string(output: &mut Vec<u8>, value: &str) {
+    output.write_u32::<LittleEndian>(value.len() as u32).unwrap();
+    output.extend_from_slice(value.as_bytes());
+}
+
+fn read_u32(input: &mut Cursor<&[u8]>) -> Result<u32, BinaryFormatError> {
+    input.read_u32::<LittleEndian>().map_err(|_| Truncated)
+}
+
+// Checks the length against what's left before allocating, so a corrupt length can't ask for gigabytes.
+fn read_bytes(input: &mut Cursor<&[u8]>, length: u32) -> Result<Vec<u8>, BinaryFormatError> {
+    let remaining = input.get_ref().len() as u64 - input.position();
+
+    if length as u64 > remaining {
+        return Err(Truncated)
+    }
+
+    let mut result = vec![0; length as usize];
+    input.read_exact(&mut result).map_err(|_| Truncated)?;
+
+    Ok(result)
+}
+
+fn read_string(input: &mut Cursor<&[u8]>) -> Result<String, BinaryFormatError> {
+    let length = read_u32(input)?;
+
+    String::from_utf8(read_bytes(input, length)?).map_err(|_| InvalidString)
+}
+
+// Every item takes at least one byte, so a count larger than what's left can't be right.
+fn read_u8(input: &mut Cursor<&[u8]>) -> Result<u8, BinaryFormatError> {
+    input.read_u8().map_err(|_| Truncated)
+}
+
+fn read_count(input: &mut Cursor<&[u8]>) -> Result<u32, BinaryFormatError> {
+    let count = read_u32(input)?;
+    let remaining = input.get_ref().len() as u64 - input.position();
+
+    if count as u64 > remaining {
+        return Err(Truncated)
+    }
+
+    Ok(count)
+}
+
+fn relocation_kind_id(kind: RelocationKind) -> u32 {
+    match kind {
+        RelocationKind::Upper => 0,
+        RelocationKind::Lower => 1,
+        RelocationKind::Jump => 2,
+        RelocationKind::Branch => 3,
+        RelocationKind::Full => 4,
+    }
+}
+
+fn relocation_kind(id: u32) -> Result<RelocationKind, BinaryFormatError> {
+    Ok(match id {
+        0 => RelocationKind::Upper,
+        1 => RelocationKind::Lower,
+        2 => RelocationKind::Jump,
+        3 => RelocationKind::Branch,
+        4 => RelocationKind::Full,
+        _ => return Err(InvalidRelocation(id)),
+    })
+}
+
+fn entry_source_id(source: EntrySource) -> u8 {
+    match source {
+        EntrySource::Directive => 0,
+        EntrySource::MainLabel => 1,
+        EntrySource::TextStart => 2,
+    }
+}
+
+fn entry_source(id: u8) -> Result<EntrySource, BinaryFormatError> {
+    Ok(match id {
+        0 => EntrySource::Directive,
+        1 => EntrySource::MainLabel,
+        2 => EntrySource::TextStart,
+        _ => return Err(InvalidEntrySource(id)),
+    })
+}
+
+impl Binary {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = vec![];
+
+        output.write_u32::<LittleEndian>(BINARY_MAGIC).unwrap();
+        output.write_u32::<LittleEndian>(BINARY_VERSION).unwrap();
+        output.write_u32::<LittleEndian>(self.entry).unwrap();
+
+        output.write_u32::<LittleEndian>(self.regions.len() as u32).unwrap();
+
+        for region in &self.regions {
+            output.write_u32::<LittleEndian>(region.flags.bits()).unwrap();
+            output.write_u32::<LittleEndian>(region.address).unwrap();
+            output.write_u32::<LittleEndian>(region.data.len() as u32).unwrap();
+            output.extend_from_slice(&region.data);
+        }
+
+        let mut labels: Vec<(&String, &u32)> = self.labels.iter().collect();
+        labels.sort();
+
+        output.write_u32::<LittleEndian>(labels.len() as u32).unwrap();
+
+        for (name, address) in labels {
+            write_string(&mut output, name);
+            output.write_u32::<LittleEndian>(*address).unwrap();
+        }
+
+        let mut aliases: Vec<(&String, &String)> = self.aliases.iter().collect();
+        aliases.sort();
+
+        output.write_u32::<LittleEndian>(aliases.len() as u32).unwrap();
+
+        for (name, target) in aliases {
+            write_string(&mut output, name);
+            write_string(&mut output, target);
+        }
+
+        output.write_u32::<LittleEndian>(self.breakpoints.len() as u32).unwrap();
+
+        for breakpoint in &self.breakpoints {
+            output.write_u32::<LittleEndian>(breakpoint.location.source as u32).unwrap();
+            output.write_u32::<LittleEndian>(breakpoint.location.index as u32).unwrap();
+            output.write_u32::<LittleEndian>(breakpoint.pcs.len() as u32).unwrap();
+
+            for pc in &breakpoint.pcs {
+                output.write_u32::<LittleEndian>(*pc).unwrap();
+            }
+        }
+
+        let mut globals: Vec<&String> = self.globals.iter().collect();
+        globals.sort();
+
+        output.write_u32::<LittleEndian>(globals.len() as u32).unwrap();
+
+        for name in globals {
+            write_string(&mut output, name);
+        }
+
+        output.write_u32::<LittleEndian>(self.relocations.len() as u32).unwrap();
+
+        for relocation in &self.relocations {
+            output.write_u32::<LittleEndian>(relocation.region as u32).unwrap();
+            output.write_u32::<LittleEndian>(relocation.offset as u32).unwrap();
+            output.write_u32::<LittleEndian>(relocation_kind_id(relocation.kind)).unwrap();
+            write_string(&mut output, &relocation.symbol);
+        }
+
+        for breakpoint in &self.breakpoints {
+            write_string(&mut output, &breakpoint.mnemonic);
+            output.write_u8(breakpoint.pseudo as u8).unwrap();
+        }
+
+        output.write_u8(entry_source_id(self.entry_source)).unwrap();
+
+        output
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Binary, BinaryFormatError> {
+        let mut input = Cursor::new(bytes);
+
+        let magic = read_u32(&mut input)?;
+
+        if magic != BINARY_MAGIC {
+            return Err(InvalidMagic(magic))
+        }
+
+        let version = read_u32(&mut input)?;
+
+        if version != BINARY_VERSION {
+            return Err(UnsupportedVersion(version))
+        }
+
+        let mut binary = Binary::new();
+
+        binary.entry = read_u32(&mut input)?;
+
+        for _ in 0..read_count(&mut input)? {
+            let flags = read_u32(&mut input)?;
+            let flags = RegionFlags::from_bits(flags).ok_or(InvalidFlags(flags))?;
+
+            let address = read_u32(&mut input)?;
+            let length = read_u32(&mut input)?;
+
+            if address.checked_add(length).is_none() {
+                return Err(InvalidRegion(address))
+            }
+
+            let data = read_bytes(&mut input, length)?;
+
+            binary.regions.push(RawRegion { flags, address, data })
+        }
+
+        let mut labels = HashMap::new();
+
+        for _ in 0..read_count(&mut input)? {
+            let name = read_string(&mut input)?;
+
+            labels.insert(name, read_u32(&mut input)?);
+        }
+
+        binary.labels = labels;
+
+        for _ in 0..read_count(&mut input)? {
+            let name = read_string(&mut input)?;
+
+            binary.aliases.insert(name, read_string(&mut input)?);
+        }
+
+        for _ in 0..read_count(&mut input)? {
+            let location = Location {
+                source: read_u32(&mut input)? as usize,
+                index: read_u32(&mut input)? as usize,
+            };
+
+            let mut pcs = vec![];
+
+            for _ in 0..read_count(&mut input)? {
+                pcs.push(read_u32(&mut input)?)
+            }
+
+            binary.breakpoints.push(BinaryBreakpoint { location, pcs, mnemonic: String::new(), pseudo: false })
+        }
+
+        for _ in 0..read_count(&mut input)? {
+            binary.globals.insert(read_string(&mut input)?);
+        }
+
+        for _ in 0..read_count(&mut input)? {
+            let region = read_u32(&mut input)?;
+
+            if region as usize >= binary.regions.len() {
+                return Err(InvalidRelocation(region))
+            }
+
+            let offset = read_u32(&mut input)?;
+
+            // Every relocation patches a full word, so it has to sit inside its region.
+            if offset as u64 + 4 > binary.regions[region as usize].data.len() as u64 {
+                return Err(InvalidRelocation(offset))
+            }
+
+            let kind = relocation_kind(read_u32(&mut input)?)?;
+            let symbol = read_string(&mut input)?;
+
+            binary.relocations.push(Relocation { region: region as usize, offset: offset as usize, kind, symbol })
+        }
+
+        for breakpoint in &mut binary.breakpoints {
+            breakpoint.mnemonic = read_string(&mut input)?;
+            breakpoint.pseudo = read_u8(&mut input)? != 0;
+        }
+
+        binary.entry_source = entry_source(read_u8(&mut input)?)?;
+
+        let remaining = bytes.len() - input.position() as usize;
+
+        if remaining != 0 {
+            return Err(TrailingBytes(remaining))
+        }
+
+        Ok(binary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::binary::{Binary, EntrySource, RawRegion, RegionFlags, Relocation, RelocationKind};
+    use crate::assembler::binary_format::{BinaryFormatError, BINARY_VERSION};
+    use crate::assembler::string::assemble_from;
+
+    fn relocated(offset: usize) -> Binary {
+        let mut binary = Binary::new();
+
+        binary.regions.push(RawRegion { flags: RegionFlags::all(), address: 0x400000, data: vec![0; 8] });
+        binary.relocations.push(Relocation {
+            region: 0, offset, kind: RelocationKind::Full, symbol: "target".into()
+        });
+
+        binary
+    }
+
+    #[test]
+    fn binaries_round_trip() {
+        let binary = assemble_from(".data\nvalue: .word 5\n.text\nmain: la $a0, value\nli $v0, 10\nsyscall\n").unwrap();
+        let bytes = binary.to_bytes();
+
+        assert_eq!(Binary::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+
+        let bytes = relocated(4).to_bytes();
+
+        assert_eq!(Binary::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = relocated(0).to_bytes();
+        bytes[4..8].copy_from_slice(&(BINARY_VERSION + 1).to_le_bytes());
+
+        assert!(matches!(Binary::from_bytes(&bytes), Err(BinaryFormatError::UnsupportedVersion(2))));
+    }
+
+    #[test]
+    fn relocations_past_the_region_are_rejected() {
+        for offset in [5, 8, 0xFFFFFFFF] {
+            let bytes = relocated(offset).to_bytes();
+
+            assert!(matches!(
+                Binary::from_bytes(&bytes),
+                Err(BinaryFormatError::InvalidRelocation(value)) if value as usize == offset
+            ));
+        }
+    }
+
+    #[test]
+    fn entry_sources_round_trip() {
+        for (source, expected) in [
+            ("main: nop\nstart: nop\n.entry start\n", EntrySource::Directive),
+            ("nop\nmain: nop\n", EntrySource::MainLabel),
+            ("nop\n", EntrySource::TextStart),
+        ] {
+            let binary = assemble_from(source).unwrap();
+            let loaded = Binary::from_bytes(&binary.to_bytes()).unwrap();
+
+            assert_eq!((loaded.entry, loaded.entry_source), (binary.entry, expected), "{source:?}");
+        }
+    }
+}
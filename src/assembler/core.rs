@@ -1,15 +1,17 @@
 use crate::assembler::assembler_util::AssemblerReason::{DuplicateLabel, MissingRegion, UnexpectedToken};
-use crate::assembler::assembler_util::{pc_for_region, AssemblerError};
+use crate::assembler::assembler_util::{pc_for_region, AssemblerError, AssemblerWarning, AssemblerWarningReason};
 use crate::assembler::binary::Binary;
 use crate::assembler::binary::BinarySection::Text;
-use crate::assembler::binary_builder::BinaryBuilder;
+use crate::assembler::binary_builder::{BinaryBuilder, DEFAULT_OUTPUT_LIMIT};
 use crate::assembler::cursor::{is_adjacent_kind, is_solid_kind, LexerCursor};
-use crate::assembler::directive::do_directive;
-use crate::assembler::emit::do_instruction;
+use crate::assembler::directive::{do_directive, is_data_directive};
+use crate::assembler::emit::{do_instruction, is_instruction_name};
 use crate::assembler::instructions::instructions_map;
 use crate::assembler::instructions::Instruction;
 use crate::assembler::lexer::TokenKind::{Directive, IntegerLiteral, Minus, Plus, Symbol};
 use crate::assembler::lexer::{Location, Token, TokenKind};
+use crate::assembler::registers::RegisterSlot;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
 enum SymbolType {
@@ -17,6 +19,22 @@ enum SymbolType {
     Instruction,
 }
 
+// `count .word 0`, with permit_colonless_labels. Only in data sections and only right before a data directive,
+// so a misspelled directive or a stray instruction still fails the way it would without the option.
+fn is_colonless_label(
+    name: &str,
+    next: Option<&Token>,
+    builder: &BinaryBuilder,
+    map: &HashMap<&str, &Instruction>,
+) -> bool {
+    let Some(Directive(directive)) = next.map(|token| &token.kind) else { return false };
+
+    builder.colonless_labels
+        && builder.state.mode.is_data()
+        && is_data_directive(directive)
+        && !is_instruction_name(name, map)
+}
+
 fn do_symbol(
     name: &str,
     location: Location,
@@ -24,6 +42,10 @@ fn do_symbol(
     builder: &mut BinaryBuilder,
     map: &HashMap<&str, &Instruction>,
 ) -> Result<SymbolType, AssemblerError> {
+    let after_name = iter.get_position();
+    let next = iter.seek_without(is_adjacent_kind);
+    let colonless = is_colonless_label(name, next, builder, map);
+
     // We need this region!
 
     let region = builder.region().ok_or(AssemblerError {
@@ -31,25 +53,48 @@ fn do_symbol(
         reason: MissingRegion,
     })?;
 
-    match iter.seek_without(is_adjacent_kind) {
-        Some(token) if token.kind == TokenKind::Colon => {
-            iter.next(); // consume
+    match next {
+        Some(token) if token.kind == TokenKind::Colon || colonless => {
+            if !colonless {
+                iter.next(); // consume
+            }
 
-            let pc = pc_for_region(&region.raw, Some(location))?;
-            
-            // If we already have this label, we want to panic!
-            if builder.labels.contains_key(name) {
-                return Err(AssemblerError {
-                    location: Some(location),
-                    reason: DuplicateLabel(name.to_string())
+            let pc = pc_for_region(region, Some(location))?;
+
+            if colonless {
+                builder.warnings.push(AssemblerWarning {
+                    location,
+                    reason: AssemblerWarningReason::ColonlessLabel(name.to_string()),
                 })
             }
-            
+
+            // One lookup both detects duplicates and inserts.
+            match builder.definitions.entry(name.to_string()) {
+                Entry::Occupied(first) => {
+                    return Err(AssemblerError {
+                        location: Some(location),
+                        reason: DuplicateLabel(name.to_string(), *first.get())
+                    })
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(location);
+                }
+            }
+
             builder.labels.insert(name.to_string(), pc);
 
+            if let Some(index) = builder.state.index() {
+                let offset = builder.regions[index].raw.data.len();
+
+                builder.offsets.insert(name.to_string(), (index, offset));
+            }
+
             Ok(SymbolType::Label)
         }
         _ => {
+            // Operands start right after the name, so a leading comma is still there to be checked.
+            iter.set_position(after_name);
+
             do_instruction(name, location, iter, builder, map)?;
 
             Ok(SymbolType::Instruction)
@@ -57,12 +102,69 @@ fn do_symbol(
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct AssembleOptions {
+    pub relax_branches: bool, // off keeps the output byte-for-byte what was written
+    pub optimize: bool, // shorter pseudo expansions where the resolved label allows (la of a low address)
+    pub permit_colonless_labels: bool, // `count .word 0` in data sections, which MARS rejects
+    pub warn_implicit_padding: bool, // .half and .word data that had to be padded to its alignment
+    pub output_limit: usize, // bytes of output across all sections, past this assembling fails
+    pub relocatable: bool, // every label reference becomes a relocation, undefined labels are left to a linker
+    pub reject_data_targets: bool, // a branch or jump into a data section is an error, not a warning
+    pub strict_commas: bool, // `add $t0 $t1,, $t2` is an error, off accepts any commas between operands (or none)
+    pub temporary_register: RegisterSlot, // used by pseudo instruction expansions in place of $at (ex. $k1)
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        AssembleOptions {
+            relax_branches: false,
+            optimize: false,
+            permit_colonless_labels: false,
+            warn_implicit_padding: true,
+            output_limit: DEFAULT_OUTPUT_LIMIT,
+            relocatable: false,
+            reject_data_targets: false,
+            strict_commas: false,
+            temporary_register: RegisterSlot::AssemblerTemporary,
+        }
+    }
+}
+
 pub fn assemble(items: &[Token], instructions: &[Instruction]) -> Result<Binary, AssemblerError> {
+    assemble_with_options(items, instructions, AssembleOptions::default())
+}
+
+pub fn assemble_with_options(
+    items: &[Token], instructions: &[Instruction], options: AssembleOptions
+) -> Result<Binary, AssemblerError> {
+    emit_with_options(items, instructions, options)?.build()
+}
+
+// Everything up to label resolution, which is left to BinaryBuilder::build.
+pub fn emit_with_options(
+    items: &[Token], instructions: &[Instruction], options: AssembleOptions
+) -> Result<BinaryBuilder, AssemblerError> {
+    emit_into(items, instructions, options, BinaryBuilder::new())
+}
+
+// Same, into a builder that was set up beforehand (ex. with origins, for a unit of a project).
+pub fn emit_into(
+    items: &[Token], instructions: &[Instruction], options: AssembleOptions, mut builder: BinaryBuilder
+) -> Result<BinaryBuilder, AssemblerError> {
     let mut cursor = LexerCursor::new(items);
 
     let map = instructions_map(instructions);
 
-    let mut builder = BinaryBuilder::new();
+    builder.relax_branches = options.relax_branches;
+    builder.optimize = options.optimize;
+    builder.colonless_labels = options.permit_colonless_labels;
+    builder.padding_warnings = options.warn_implicit_padding;
+    builder.output_limit = options.output_limit;
+    builder.relocatable = options.relocatable;
+    builder.reject_data_targets = options.reject_data_targets;
+    builder.strict_commas = options.strict_commas;
+    builder.temporary = options.temporary_register;
     builder.seek_mode(Text);
 
     let mut last_directive = Option::<(&str, Location)>::None;
@@ -90,6 +192,8 @@ pub fn assemble(items: &[Token], instructions: &[Instruction]) -> Result<Binary,
 
                 do_directive(directive, token.location, &mut cursor, &mut builder)?
             }
+            // Each label binds to the pc where it's written, so `end: done: jr $ra` gives both the same address
+            // and a label with nothing after it (even at the end of the file) names the end of its region.
             Symbol(name) => {
                 let result = do_symbol(name.get(), token.location, &mut cursor, &mut builder, &map)?;
 
@@ -106,5 +210,190 @@ pub fn assemble(items: &[Token], instructions: &[Instruction]) -> Result<Binary,
         }
     }
 
-    builder.build()
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_util::AssemblerReason;
+    use crate::assembler::assembler_util::AssemblerReason::{ConstantOutOfRange, TemporaryClobbered, TemporaryUnavailable};
+    use crate::assembler::assembler_util::AssemblerWarningReason::ColonlessLabel;
+    use crate::assembler::core::AssembleOptions;
+    use crate::assembler::registers::RegisterSlot::Kernel1;
+    use crate::assembler::string::{assemble_from, assemble_from_with_options, SourceError};
+
+    fn with_k1(source: &str) -> Result<crate::assembler::binary::Binary, SourceError> {
+        assemble_from_with_options(source, AssembleOptions { temporary_register: Kernel1, ..Default::default() })
+    }
+
+    #[test]
+    fn labels_in_a_row_share_an_address() {
+        let binary = assemble_from("main: nop\nend: done: jr $ra\n").unwrap();
+
+        assert_eq!(binary.labels["end"], 0x400004);
+        assert_eq!(binary.labels["done"], 0x400004);
+    }
+
+    #[test]
+    fn labels_at_the_end_name_the_end_of_their_region() {
+        // No newline after the last label either.
+        let binary = assemble_from("main: nop\nlast:").unwrap();
+
+        assert_eq!(binary.labels["last"], 0x400004);
+
+        let binary = assemble_from(".text\nonly:\n").unwrap();
+
+        assert_eq!(binary.labels["only"], 0x400000);
+        assert!(binary.regions.is_empty());
+    }
+
+    #[test]
+    fn labels_before_padding_keep_their_address() {
+        // end - buf is the length of buf, the padding for w comes after end.
+        let binary = assemble_from(".data\nbuf: .byte 1, 2, 3\nend:\nw: .word 1\n").unwrap();
+
+        assert_eq!(binary.labels["end"] - binary.labels["buf"], 3);
+        assert_eq!(binary.regions[0].data, vec![1, 2, 3, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn expanding_over_the_temporary_is_an_error() {
+        let Err(SourceError::Assembler(error)) = with_k1("l: blt $k1, $t0, l\n") else {
+            panic!("blt through $k1 assembled")
+        };
+
+        assert!(matches!(error.reason, TemporaryClobbered(ref name, Kernel1) if name == "blt"));
+
+        // $at is only a warning, like MARS.
+        assert!(assemble_from("l: blt $at, $t0, l\n").is_ok());
+        assert!(with_k1("l: blt $t1, $t0, l\n").is_ok());
+    }
+
+    #[test]
+    fn noat_only_reserves_at() {
+        let source = ".set noat\nl: blt $t1, $t0, l\n";
+
+        let Err(SourceError::Assembler(error)) = assemble_from(source) else {
+            panic!("blt expanded through $at under .set noat")
+        };
+
+        assert!(matches!(error.reason, TemporaryUnavailable(ref name) if name == "blt"));
+
+        // slt $k1, $t1, $t0 then bne $k1, $zero.
+        let binary = with_k1(source).unwrap();
+
+        assert_eq!(binary.regions[0].data[..4], 0x0128d82au32.to_le_bytes());
+    }
+
+    fn colonless(source: &str) -> Result<crate::assembler::binary::Binary, SourceError> {
+        assemble_from_with_options(source, AssembleOptions { permit_colonless_labels: true, ..Default::default() })
+    }
+
+    #[test]
+    fn colonless_data_labels() {
+        let source = ".data\ncount .word 7\nname .asciiz \"a\"\n.text\nlw $t0, count\n";
+
+        let binary = colonless(source).unwrap();
+
+        assert_eq!(binary.labels["count"], 0x10010000);
+        assert_eq!(binary.labels["name"], 0x10010004);
+        let data = binary.regions.iter().find(|region| region.address == 0x10010000).unwrap();
+
+        assert_eq!(data.data, [7, 0, 0, 0, b'a', 0]);
+
+        let names: Vec<_> = binary.warnings.iter()
+            .filter_map(|warning| match &warning.reason {
+                ColonlessLabel(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(names, ["count", "name"]);
+    }
+
+    #[test]
+    fn colonless_labels_are_off_by_default() {
+        let source = ".data\ncount .word 7\n";
+
+        let Err(SourceError::Assembler(default)) = assemble_from(source) else {
+            panic!("a colonless label assembled by default")
+        };
+
+        assert_eq!(default.to_string(), "Unknown instruction named \"count\", check for typos");
+        assert!(colonless(source).is_ok());
+
+        // Only in data sections, before a data directive, and never an instruction name.
+        for source in [".text\ncount .word 7\n", ".data\ncount .wrod 7\n", ".data\nlw .word 7\n"] {
+            let Err(SourceError::Assembler(with)) = colonless(source) else {
+                panic!("{source:?} assembled with colonless labels")
+            };
+
+            let Err(SourceError::Assembler(without)) = assemble_from(source) else {
+                panic!("{source:?} assembled")
+            };
+
+            assert_eq!(with.to_string(), without.to_string(), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn noat_allows_li_and_at_itself() {
+        // lui $t0, 0x1234 then ori $t0, $t0, 0x5678, no $at.
+        let binary = assemble_from(".set noat\nli $t0, 0x12345678\nli $t1, 5\naddu $at, $t0, $t1\n").unwrap();
+
+        assert_eq!(binary.regions[0].data[..8], [0x34, 0x12, 0x08, 0x3c, 0x78, 0x56, 0x08, 0x35]);
+        assert!(binary.warnings.is_empty(), "{:?}", binary.warnings);
+    }
+
+    #[test]
+    fn noat_rejects_expansions_through_at() {
+        let sources = [
+            ("addi $t0, $t0, 0x12345", "addi"),
+            ("lw $t0, value", "lw"),
+            ("sw $t0, 0x12345($t1)", "sw"),
+            ("l: bge $t0, $t1, l", "bge"),
+            ("sgt $t0, $t1, 5", "sgt"),
+        ];
+
+        for (line, instruction) in sources {
+            let source = format!(".data\nvalue: .word 0\n.text\n.set noat\n{line}\n");
+
+            let Err(SourceError::Assembler(error)) = assemble_from(&source) else {
+                panic!("{line:?} assembled under .set noat")
+            };
+
+            assert!(matches!(&error.reason, TemporaryUnavailable(name) if name == instruction), "{line:?}: {error}");
+
+            // .set at allows it again.
+            assert!(assemble_from(&source.replace(".set noat", ".set noat\n.set at")).is_ok(), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn shift_amounts_stop_at_31() {
+        assert!(assemble_from("sll $t0, $t0, 31\n").is_ok());
+
+        for source in ["sll $t0, $t0, 32\n", "srl $t0, $t0, 33\n", "sra $t0, $t0, -1\n"] {
+            let Err(SourceError::Assembler(error)) = assemble_from(source) else {
+                panic!("{source:?} assembled")
+            };
+
+            assert!(matches!(error.reason, ConstantOutOfRange(0, 31)), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn duplicate_labels_say_where_both_are() {
+        let Err(SourceError::Assembler(error)) = assemble_from("start: nop\nloop: nop\nstart: nop\n") else {
+            panic!("expected an assembler error")
+        };
+
+        let AssemblerReason::DuplicateLabel(name, first) = &error.reason else {
+            panic!("{}", error.reason)
+        };
+
+        assert_eq!(name, "start");
+        assert_eq!(first.index, 0);
+        assert_eq!(error.location.map(|location| location.index), Some(21));
+    }
 }
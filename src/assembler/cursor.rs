@@ -1,6 +1,9 @@
 use crate::assembler::lexer::TokenKind::{Comma, Comment, NewLine};
 use crate::assembler::lexer::{Token, TokenKind};
 
+// Comments are transparent to both: operand parsers should only step with these
+// (next_adjacent, seek_without, peek_adjacent) so a trailing comment never changes a parse.
+
 pub fn is_solid_kind(kind: &TokenKind) -> bool {
     match kind {
         Comment(_) => false,
@@ -36,6 +39,13 @@ impl<'a, 'b> LexerCursor<'a, 'b> {
         self.index = index
     }
 
+    // Tokens passed over since position start (clamped to the end of the stream).
+    pub fn tokens_since(&self, start: usize) -> &'b [Token<'a>] {
+        let end = self.index.min(self.tokens.len());
+
+        &self.tokens[start.min(end)..end]
+    }
+
     pub fn peek(&self) -> Option<&'b Token<'a>> {
         self.tokens.get(self.index)
     }
@@ -128,16 +138,4 @@ impl<'a, 'b> LexerCursor<'a, 'b> {
 
         (end, result)
     }
-
-    pub fn consume_until(&mut self, index: usize) -> Vec<&'b Token<'a>> {
-        if index < self.index {
-            vec![]
-        } else {
-            let result = self.tokens[self.index..index].iter().collect();
-
-            self.index = index;
-
-            result
-        }
-    }
 }
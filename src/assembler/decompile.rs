@@ -0,0 +1,444 @@
+use crate::assembler::binary::BinarySection::Text;
+use crate::assembler::binary::{Binary, RawRegion, RegionFlags};
+use crate::assembler::string::assemble_from;
+use crate::cpu::decoder::Decoder;
+use crate::cpu::disassemble::{Disassembler, LabelProvider};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+// Same limit as .space itself, larger counts start a new region.
+const MAX_SPACE: usize = 0x100000;
+
+// Guessing thresholds: shorter strings or zero runs are left as .byte/.word.
+const MIN_STRING: usize = 3;
+const MIN_SPACE: usize = 8;
+
+const INDENT: &str = "    ";
+
+struct DecompileLabels<'a> {
+    names: &'a HashMap<u32, String>,
+    hex: bool, // absolute addresses only, for checking instructions before labels exist
+}
+
+impl LabelProvider for DecompileLabels<'_> {
+    fn label_for(&mut self, address: u32) -> String {
+        match self.names.get(&address) {
+            Some(name) if !self.hex => name.clone(),
+            _ => format!("0x{address:08x}"),
+        }
+    }
+}
+
+fn disassemble(word: u32, pc: u32, names: &HashMap<u32, String>, hex: bool) -> Option<String> {
+    let mut disassembler = Disassembler { pc, labels: DecompileLabels { names, hex }, raw_nop: false };
+
+    disassembler.dispatch(word)
+}
+
+fn words(region: &RawRegion) -> impl Iterator<Item = (u32, u32)> + '_ {
+    region.data.chunks_exact(4).enumerate().map(|(i, bytes)| {
+        let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        (region.address.wrapping_add(4 * i as u32), word)
+    })
+}
+
+fn assembles_to(source: &str, word: u32) -> bool {
+    assemble_from(source).is_ok_and(|binary| {
+        binary.regions.last().is_some_and(|region| region.data == word.to_le_bytes())
+    })
+}
+
+// The disassembler isn't exact for every encoding (ignored fields, assembler quirks), so every
+// instruction is assembled again on its own, at its own address. Only exact matches are kept.
+fn checked_instructions(binary: &Binary, names: &HashMap<u32, String>) -> HashSet<u32> {
+    let mut candidates = vec![];
+
+    for region in binary.text_regions() {
+        for (pc, word) in words(region) {
+            if let Some(text) = disassemble(word, pc, names, true) {
+                candidates.push((pc, word, text))
+            }
+        }
+    }
+
+    // One region per instruction, so one assembler run covers all of them.
+    let mut source = String::new();
+
+    for (pc, _, text) in &candidates {
+        writeln!(source, ".text 0x{pc:08x}\n{text}").unwrap();
+    }
+
+    let batch = assemble_from(&source).ok().filter(|result| {
+        result.regions.len() == candidates.len()
+    });
+
+    let Some(batch) = batch else {
+        // Something in there doesn't assemble at all, fall back to checking one at a time.
+        return candidates.into_iter()
+            .filter(|(pc, word, text)| assembles_to(&format!(".text 0x{pc:08x}\n{text}"), *word))
+            .map(|(pc, _, _)| pc)
+            .collect()
+    };
+
+    candidates.iter().zip(&batch.regions)
+        .filter(|((pc, word, _), region)| region.address == *pc && region.data == word.to_le_bytes())
+        .map(|((pc, _, _), _)| *pc)
+        .collect()
+}
+
+fn is_string_byte(byte: u8) -> bool {
+    (0x20..0x7F).contains(&byte) || matches!(byte, b'\n' | b'\t' | b'\r')
+}
+
+fn escape_string(bytes: &[u8]) -> String {
+    let mut result = String::new();
+
+    for byte in bytes {
+        match byte {
+            b'\n' => result.push_str("\\n"),
+            b'\t' => result.push_str("\\t"),
+            b'\r' => result.push_str("\\r"),
+            b'"' => result.push_str("\\\""),
+            b'\\' => result.push_str("\\\\"),
+            _ => result.push(*byte as char),
+        }
+    }
+
+    result
+}
+
+// Length of a printable, null terminated string at the start of bytes (not counting the null).
+fn string_length(bytes: &[u8]) -> Option<usize> {
+    let length = bytes.iter().position(|byte| !is_string_byte(*byte))?;
+
+    (length >= MIN_STRING && bytes[length] == 0).then_some(length)
+}
+
+struct RegionWriter<'a> {
+    output: &'a mut String,
+    labels: &'a BTreeMap<u32, Vec<String>>,
+    names: &'a HashMap<u32, String>,
+    instructions: &'a HashSet<u32>,
+}
+
+impl RegionWriter<'_> {
+    fn write_labels(&mut self, address: u32) {
+        for name in self.labels.get(&address).into_iter().flatten() {
+            writeln!(self.output, "{name}:").unwrap();
+        }
+    }
+
+    fn write_statement(&mut self, text: &str) {
+        writeln!(self.output, "{INDENT}{text}").unwrap();
+    }
+
+    fn write_values(&mut self, directive: &str, values: &[String]) {
+        self.write_statement(&format!("{directive} {}", values.join(", ")))
+    }
+
+    // Writes the next statement for bytes (which sit at address and end before the next label).
+    // Returns how many bytes it covered.
+    fn write_next(&mut self, address: u32, bytes: &[u8], executable: bool) -> usize {
+        if executable && address % 4 == 0 && bytes.len() >= 4 {
+            let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+            if self.instructions.contains(&address) {
+                let text = disassemble(word, address, self.names, false).unwrap();
+                self.write_statement(&text);
+            } else {
+                self.write_statement(&format!(".word 0x{word:08x}"));
+            }
+
+            return 4
+        }
+
+        if !executable {
+            if let Some(length) = string_length(bytes) {
+                self.write_statement(&format!(".asciiz \"{}\"", escape_string(&bytes[..length])));
+
+                return length + 1
+            }
+
+            let zeros = bytes.iter().take(MAX_SPACE).take_while(|byte| **byte == 0).count();
+
+            if zeros >= MIN_SPACE {
+                self.write_statement(&format!(".space {zeros}"));
+
+                return zeros
+            }
+
+            if address % 4 == 0 && bytes.len() >= 4 {
+                let values: Vec<String> = bytes.chunks_exact(4).take(4)
+                    .map(|word| format!("0x{:08x}", u32::from_le_bytes([word[0], word[1], word[2], word[3]])))
+                    .collect();
+
+                self.write_values(".word", &values);
+
+                return 4 * values.len()
+            }
+        }
+
+        // Up to the next word boundary, so words can pick up from there.
+        let until_aligned = (4 - address % 4) as usize;
+        let count = until_aligned.min(bytes.len());
+
+        let values: Vec<String> = bytes[..count].iter().map(|byte| format!("0x{byte:02x}")).collect();
+        self.write_values(".byte", &values);
+
+        count
+    }
+
+    fn write_region(&mut self, region: &RawRegion) {
+        let executable = region.flags.contains(RegionFlags::EXECUTABLE);
+        let end = region.address as u64 + region.data.len() as u64;
+
+        let mut offset = 0;
+
+        while offset < region.data.len() {
+            let address = region.address + offset as u32;
+
+            self.write_labels(address);
+
+            // Statements stop at the next label, so it can go right where it belongs.
+            let stop = self.labels.range(address + 1..).next()
+                .map(|(next, _)| *next as u64)
+                .filter(|next| *next < end)
+                .map(|next| (next - region.address as u64) as usize)
+                .unwrap_or(region.data.len());
+
+            offset += self.write_next(address, &region.data[offset..stop], executable);
+        }
+
+        if end <= u32::MAX as u64 {
+            self.write_labels(end as u32);
+        }
+    }
+}
+
+impl Binary {
+    // Assembly that titan assembles back into the same regions, byte for byte.
+    // Data is guessed into .asciiz/.space/.word/.byte, instructions that don't survive a round trip become .word.
+    pub fn decompile(&self) -> String {
+        let aliases: HashSet<&String> = self.aliases.keys().collect();
+
+        // Every label is written once: in the first region holding it, otherwise after the last region ending there.
+        let owner = |address: u32| {
+            let inside = self.regions.iter().position(|region| {
+                region.address <= address && (address as u64) < region.address as u64 + region.data.len() as u64
+            });
+
+            inside.or_else(|| self.regions.iter().rposition(|region| {
+                region.address as u64 + region.data.len() as u64 == address as u64
+            }))
+        };
+
+        let mut sorted: Vec<(&String, &u32)> = self.labels.iter()
+            .filter(|(name, _)| !aliases.contains(name))
+            .collect();
+        sorted.sort();
+
+        let mut labels: Vec<BTreeMap<u32, Vec<String>>> = vec![BTreeMap::new(); self.regions.len()];
+        let mut names: HashMap<u32, String> = HashMap::new();
+        let mut missing = vec![];
+
+        for (name, address) in sorted {
+            let Some(index) = owner(*address) else {
+                missing.push((name, *address));
+
+                continue
+            };
+
+            labels[index].entry(*address).or_default().push(name.clone());
+            names.entry(*address).or_insert_with(|| name.clone());
+        }
+
+        let instructions = checked_instructions(self, &names);
+
+        let mut output = String::new();
+
+        for (name, address) in missing {
+            writeln!(output, "# {name} (0x{address:08x}) is outside every region").unwrap();
+        }
+
+        let mut globals: Vec<&String> = self.globals.iter().collect();
+        globals.sort();
+
+        for name in globals {
+            writeln!(output, ".globl {name}").unwrap();
+        }
+
+        if self.entry != Text.default_address() {
+            let entry = names.get(&self.entry).cloned().unwrap_or_else(|| format!("0x{:08x}", self.entry));
+
+            writeln!(output, ".entry {entry}").unwrap();
+        }
+
+        let mut seen = HashSet::new();
+
+        for (region, labels) in self.regions.iter().zip(&labels) {
+            let section = region.section();
+
+            if !output.is_empty() {
+                output.push('\n');
+            }
+
+            // The first region of a section at its default address is the one the assembler starts with.
+            if seen.insert(section) && region.address == section.default_address() {
+                writeln!(output, "{}", section.directive()).unwrap();
+            } else {
+                writeln!(output, "{} 0x{:08x}", section.directive(), region.address).unwrap();
+            }
+
+            RegionWriter {
+                output: &mut output,
+                labels,
+                names: &names,
+                instructions: &instructions,
+            }.write_region(region);
+        }
+
+        let mut aliases: Vec<(&String, &String)> = self.aliases.iter().collect();
+        aliases.sort();
+
+        if !aliases.is_empty() {
+            output.push('\n');
+        }
+
+        for (name, target) in aliases {
+            writeln!(output, ".alias {name}, {target}").unwrap();
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::binary::Binary;
+    use crate::assembler::string::assemble_from;
+
+    const CORPUS: &[&str] = &[
+        "
+        main: la $a0, hello
+        li $v0, 4
+        syscall
+        loop: beq $t0, $zero, loop
+        .word 0xFC000000
+        jr $ra
+        .data
+        hello: .asciiz \"hi there\"
+        .byte 1, 2
+        .align 2
+        table: .word main, 7
+        .space 16
+        .half 3
+        ",
+        "
+        .globl start
+        .entry start
+        .text 0x500000
+        start: jal routine
+        j start
+        routine: sll $t0, $t1, 3
+        mult $t0, $t1
+        mflo $v0
+        jr $ra
+        .text
+        nop
+        end:
+        .alias old_routine, routine
+        ",
+        "
+        .ktext
+        handler: addiu $k0, $k0, 4
+        resume: jr $k0
+        .kdata
+        saved: .word 0:4
+        message: .ascii \"tab\\there\\n\"
+        .data 0x10020000
+        .byte 0xFF
+        .text
+        bne $t0, $t1, handler_far
+        handler_far: nop
+        ",
+        "
+        .data
+        big: .space 0x100010
+        after: .word after
+        .text
+        lw $t0, big + 4
+        sw $t0, after
+        ",
+    ];
+
+    fn round_trip(binary: &Binary) -> Binary {
+        let source = binary.decompile();
+
+        assemble_from(&source).unwrap_or_else(|error| panic!("{error}\n{source}"))
+    }
+
+    fn assert_same(original: &Binary, decompiled: &Binary) {
+        let regions = |binary: &Binary| {
+            binary.regions.iter().map(|region| (region.address, region.data.clone())).collect::<Vec<_>>()
+        };
+
+        assert_eq!(regions(decompiled), regions(original));
+        assert_eq!(decompiled.labels, original.labels);
+        assert_eq!(decompiled.aliases, original.aliases);
+        assert_eq!(decompiled.globals, original.globals);
+        assert_eq!(decompiled.entry, original.entry);
+    }
+
+    #[test]
+    fn corpus_round_trips() {
+        for source in CORPUS {
+            let binary = assemble_from(source).unwrap();
+
+            assert_same(&binary, &round_trip(&binary));
+        }
+    }
+
+    #[test]
+    fn data_is_guessed_from_its_bytes() {
+        let binary = assemble_from(CORPUS[0]).unwrap();
+
+        assert_eq!(binary.decompile(), "\
+.text
+main:
+    lui $a0, 0x1001
+    ori $a0, $a0, 0x0
+    addiu $v0, $zero, 4
+    syscall
+loop:
+    beq $t0, $zero, loop
+    .word 0xfc000000
+    jr $ra
+
+.data
+hello:
+    .asciiz \"hi there\"
+    .byte 0x01, 0x02, 0x00
+table:
+    .word 0x00400000, 0x00000007, 0x00000000, 0x00000000
+    .space 8
+    .byte 0x03, 0x00
+");
+    }
+
+    #[test]
+    fn any_words_round_trip() {
+        let mut seed = 0x2545F491u32;
+
+        // Random words in text, some decode and some don't, every byte has to come back.
+        let words: Vec<String> = (0..2000).map(|_| {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+
+            format!("0x{seed:08x}")
+        }).collect();
+
+        let binary = assemble_from(&format!(".word {}\n", words.join(", "))).unwrap();
+
+        assert_same(&binary, &round_trip(&binary));
+    }
+}
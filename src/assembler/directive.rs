@@ -1,15 +1,14 @@
 use crate::assembler::assembler_util::AssemblerReason::{
-    ConstantOutOfRange, EndOfFile, ExpectedConstant, MissingRegion, OverwriteEdge, UnknownDirective,
+    ConstantOutOfRange, EndOfFile, ExpectedConstant, ExpectedLabel, MissingComma, MissingRegion, UnknownDirective,
 };
-use crate::assembler::assembler_util::{default_start, get_constant, get_integer, get_integer_adjacent, get_string, pc_for_region, AssemblerError, get_label, maybe_get_value};
-use crate::assembler::binary::AddressLabel::Label;
+use crate::assembler::assembler_util::{default_start, fits_width, AssemblerWarning, AssemblerWarningReason, get_constant, get_integer, get_integer_adjacent, get_string, pc_for_region, check_region_edge, overwrite_edge, AssemblerError, get_label, expression_sum};
+use crate::assembler::binary::AddressLabel::{Difference, Label};
 use crate::assembler::binary::BinarySection::{Data, KernelData, KernelText, Text};
-use crate::assembler::binary::{BinarySection, NamedLabel};
-use crate::assembler::binary_builder::{BinaryBuilder, BinaryBuilderLabel, BinaryBuilderRegion, InstructionLabel, InstructionLabelKind};
+use crate::assembler::binary::{AddressLabel, BinarySection, DifferenceLabel, NamedLabel};
+use crate::assembler::binary_builder::{BinaryBuilder, BinaryBuilderAlias, BinaryBuilderLabel, BinaryBuilderRegion, InstructionLabel, InstructionLabelKind};
 use crate::assembler::cursor::{is_adjacent_kind, is_solid_kind, LexerCursor};
-use crate::assembler::lexer::TokenKind::{Colon, NewLine};
+use crate::assembler::lexer::TokenKind::{Colon, Comma, Comment, Directive, Minus, NewLine, Plus, StringLiteral, Symbol};
 use crate::assembler::lexer::{Location, Token, TokenKind};
-use byteorder::{ByteOrder, LittleEndian};
 use TokenKind::LeftBrace;
 
 const MISSING_REGION: AssemblerError = AssemblerError {
@@ -32,35 +31,52 @@ fn do_seek_directive(
     Ok(())
 }
 
-fn do_globl_directive(iter: &mut LexerCursor, _: &mut BinaryBuilder) -> Result<(), AssemblerError> {
-    iter.collect_without(|kind| kind == &NewLine);
+fn do_globl_directive(iter: &mut LexerCursor, builder: &mut BinaryBuilder) -> Result<(), AssemblerError> {
+    let tokens = iter.collect_without(|kind| kind == &NewLine);
 
-    // Ignore, dummy directive since no multi-file support at the moment.
+    // No multi-file support at the moment, but globals are expected to go unreferenced.
+    for token in tokens {
+        if let Symbol(name) = &token.kind {
+            builder.globals.insert(name.get().to_string());
+        }
+    }
 
     Ok(())
 }
 
+// `.asciiz "a", "b"` writes both (like MARS), each with its own terminator.
 fn do_ascii_directive(
+    location: Location,
     iter: &mut LexerCursor,
     builder: &mut BinaryBuilder,
+    terminated: bool,
 ) -> Result<(), AssemblerError> {
-    let mut bytes = get_string(iter)?.into_bytes();
-    let region = builder.region().ok_or(MISSING_REGION)?;
+    let mut bytes = vec![];
 
-    region.raw.data.append(&mut bytes);
+    loop {
+        bytes.append(&mut get_string(iter)?);
 
-    Ok(())
-}
+        if terminated {
+            bytes.push(0);
+        }
 
-fn do_asciiz_directive(
-    iter: &mut LexerCursor,
-    builder: &mut BinaryBuilder,
-) -> Result<(), AssemblerError> {
-    let mut bytes = get_string(iter)?.into_bytes();
-    bytes.push(0);
+        // A comma before each string after the first, and none after the last.
+        match iter.seek_without(|kind| !matches!(kind, Comment(_))) {
+            Some(Token { kind: Comma, .. }) => { iter.next(); }
+            Some(Token { kind: StringLiteral(_), location }) => return Err(AssemblerError {
+                location: Some(*location),
+                reason: MissingComma,
+            }),
+            _ => break
+        }
+    }
+
+    builder.reserve_output(bytes.len())?;
 
     let region = builder.region().ok_or(MISSING_REGION)?;
 
+    check_region_edge(region, bytes.len() as u64, Some(location))?;
+
     region.raw.data.append(&mut bytes);
 
     Ok(())
@@ -68,16 +84,15 @@ fn do_asciiz_directive(
 
 const MAX_ZERO: usize = 0x100000;
 
-fn align_with_zeros(region: &mut BinaryBuilderRegion, align: u32) -> Result<(), AssemblerError> {
-    let pc = pc_for_region(&region.raw, None)?;
+// Bytes from pc up to the next multiple of align, counted without computing the target (which can be 1 << 32).
+fn align_count(pc: u32, align: u32) -> u32 {
+    (align - pc % align) % align
+}
 
-    let (select, remainder) = (pc / align, pc % align);
-    let correction = if remainder > 0 { 1 } else { 0 };
+fn align_with_zeros(region: &mut BinaryBuilderRegion, align: u32) -> Result<(), AssemblerError> {
+    let pc = pc_for_region(region, None)?;
 
-    let target = (select + correction) * align;
-    let align_count = target as usize - pc as usize;
-    
-    let mut align_bytes = vec![0; align_count];
+    let mut align_bytes = vec![0; align_count(pc, align) as usize];
 
     region.raw.data.append(&mut align_bytes);
     
@@ -85,6 +100,7 @@ fn align_with_zeros(region: &mut BinaryBuilderRegion, align: u32) -> Result<(),
 }
 
 fn do_align_directive(
+    location: Location,
     iter: &mut LexerCursor,
     builder: &mut BinaryBuilder,
 ) -> Result<(), AssemblerError> {
@@ -100,47 +116,61 @@ fn do_align_directive(
     let align = 1 << shift;
 
     let region = builder.region().ok_or(MISSING_REGION)?;
-    let pc = pc_for_region(&region.raw, None)?;
+    let pc = pc_for_region(region, Some(location))?;
+
+    let count = align_count(pc, align);
 
-    let (select, remainder) = (pc / align, pc % align);
-    let correction = if remainder > 0 { 1 } else { 0 };
+    check_region_edge(region, count as u64, Some(location))?;
 
-    let target = (select + correction) * align;
-    let align_count = target as usize - pc as usize;
+    if count as usize > MAX_ZERO {
+        // Fits, but seeking to exactly 1 << 32 would wrap around to 0.
+        let Some(target) = pc.checked_add(count) else {
+            return Err(overwrite_edge(region, region.raw.data.len() as u64 + count as u64, Some(location)))
+        };
 
-    if align_count > MAX_ZERO {
         builder.seek_mode_address(builder.state.mode, target)
     } else {
-        let mut align_bytes = vec![0; align_count];
+        builder.reserve_output(count as usize)?;
+
+        let mut align_bytes = vec![0; count as usize];
 
-        region.raw.data.append(&mut align_bytes);
+        builder.region().ok_or(MISSING_REGION)?.raw.data.append(&mut align_bytes);
     }
 
     Ok(())
 }
 
 fn do_space_directive(
+    location: Location,
     iter: &mut LexerCursor,
     builder: &mut BinaryBuilder,
 ) -> Result<(), AssemblerError> {
     let region = builder.region().ok_or(MISSING_REGION)?;
-    let pc = pc_for_region(&region.raw, None)?;
+    let pc = pc_for_region(region, Some(location))?;
 
-    let byte_count = get_constant(iter)? as usize;
+    let count = get_constant(iter)?;
 
-    if byte_count > MAX_ZERO {
-        let Some(target) = pc.checked_add(byte_count as u32) else {
-            return Err(AssemblerError {
-                location: None,
-                reason: OverwriteEdge(pc, Some(byte_count as u64))
-            })
+    let Ok(byte_count) = u32::try_from(count) else {
+        return Err(AssemblerError {
+            location: None,
+            reason: ConstantOutOfRange(0, u32::MAX as i64),
+        })
+    };
+
+    check_region_edge(region, byte_count as u64, Some(location))?;
+
+    if byte_count as usize > MAX_ZERO {
+        let Some(target) = pc.checked_add(byte_count) else {
+            return Err(overwrite_edge(region, region.raw.data.len() as u64 + byte_count as u64, Some(location)))
         };
 
         builder.seek_mode_address(builder.state.mode, target)
     } else {
-        let mut space_bytes = vec![0; byte_count];
+        builder.reserve_output(byte_count as usize)?;
+
+        let mut space_bytes = vec![0; byte_count as usize];
 
-        region.raw.data.append(&mut space_bytes);
+        builder.region().ok_or(MISSING_REGION)?.raw.data.append(&mut space_bytes);
     }
 
     Ok(())
@@ -149,20 +179,23 @@ fn do_space_directive(
 const REPEAT_LIMIT: u64 = 0x100000;
 
 struct ConstantInfo {
+    location: Location,
     value: u64,
     count: u64,
 }
 
-// Specifically for .word
+// For .byte, .half and .word
 enum ConstantOrLabel {
     Constant(ConstantInfo),
-    Label(NamedLabel),
+    Label(Location, AddressLabel), // where the first name was written
 }
 
 fn grab_value(
     value: &Token,
     iter: &mut LexerCursor,
 ) -> Result<Option<ConstantInfo>, AssemblerError> {
+    let location = value.location;
+
     let Some(value) = get_integer(value, iter, true) else {
         return Ok(None)
     };
@@ -195,10 +228,50 @@ fn grab_value(
         1u64
     };
 
-    Ok(Some(ConstantInfo { value, count }))
+    Ok(Some(ConstantInfo { location, value, count }))
 }
 
-fn get_constant_or_labels(iter: &mut LexerCursor) -> Result<Vec<ConstantOrLabel>, AssemblerError> {
+// What can follow a label in a data directive: `buffer+16`, `table + 4 * 2` or `end-start`.
+// The sign has to come right after the label, `.word a, -1` is still two values.
+fn grab_label_tail(mut label: NamedLabel, iter: &mut LexerCursor) -> Result<AddressLabel, AssemblerError> {
+    let position = iter.get_position();
+
+    let Some(sign) = iter.peek().filter(|token| matches!(token.kind, Plus | Minus)) else {
+        return Ok(Label(label))
+    };
+
+    iter.next(); // sign
+
+    if sign.kind == Minus {
+        if let Some(Token { kind: Symbol(name), location }) = iter.seek_without(is_adjacent_kind) {
+            iter.next();
+
+            let right = NamedLabel {
+                name: name.get().to_string(),
+                location: *location,
+                offset: 0,
+            };
+
+            return Ok(Difference(DifferenceLabel { left: label, right }))
+        }
+    }
+
+    iter.set_position(position);
+
+    label.offset = expression_sum(iter)?;
+
+    Ok(Label(label))
+}
+
+const DATA_DIRECTIVES: [&str; 9] = ["ascii", "asciiz", "align", "space", "byte", "half", "word", "float", "double"];
+
+pub fn is_data_directive(name: &str) -> bool {
+    DATA_DIRECTIVES.contains(&name.to_lowercase().as_str())
+}
+
+fn get_constant_or_labels(
+    iter: &mut LexerCursor, colonless_labels: bool
+) -> Result<Vec<ConstantOrLabel>, AssemblerError> {
     let mut result: Vec<ConstantOrLabel> = vec![];
 
     while let Some(value) = iter.seek_without(is_solid_kind) {
@@ -214,6 +287,7 @@ fn get_constant_or_labels(iter: &mut LexerCursor) -> Result<Vec<ConstantOrLabel>
             let do_skip = match token.map(|x| &x.kind) {
                 Some(Colon) => true,     // label
                 Some(LeftBrace) => true, // Macro
+                Some(Directive(directive)) => colonless_labels && is_data_directive(directive), // `count .word 0`
                 _ => false,
             };
 
@@ -230,7 +304,7 @@ fn get_constant_or_labels(iter: &mut LexerCursor) -> Result<Vec<ConstantOrLabel>
                 offset: 0,
             };
 
-            ConstantOrLabel::Label(address)
+            ConstantOrLabel::Label(value.location, grab_label_tail(address, iter)?)
         } else {
             let Some(constant) = grab_value(value, iter)? else { break };
 
@@ -255,68 +329,17 @@ fn get_constants(iter: &mut LexerCursor) -> Result<Vec<ConstantInfo>, AssemblerE
     Ok(result)
 }
 
-fn do_byte_directive(
-    iter: &mut LexerCursor,
-    builder: &mut BinaryBuilder,
-) -> Result<(), AssemblerError> {
-    let values = get_constants(iter)?;
-
-    let region = builder.region().ok_or(MISSING_REGION)?;
-
-    for value in values {
-        if value.count > REPEAT_LIMIT {
-            continue;
-        }
-
-        if value.count == 1 {
-            region.raw.data.push(value.value as u8)
-        } else {
-            region
-                .raw
-                .data
-                .append(&mut vec![value.value as u8; value.count as usize])
-        }
-    }
-
-    Ok(())
-}
-
-fn do_half_directive(
-    iter: &mut LexerCursor,
-    builder: &mut BinaryBuilder,
-) -> Result<(), AssemblerError> {
-    let values = get_constants(iter)?;
-
-    let region = builder.region().ok_or(MISSING_REGION)?;
-
-    align_with_zeros(region, 2)?;
-    
-    for value in values {
-        if value.count > REPEAT_LIMIT {
-            continue;
-        }
-
-        let mut array = [0u8; 2];
-        LittleEndian::write_u16(&mut array, value.value as u16);
-
-        region.raw.data.reserve(2 * value.count as usize);
-
-        for _ in 0..value.count {
-            region.raw.data.extend_from_slice(&array);
-        }
-    }
-
-    Ok(())
-}
-
-fn do_word_directive(
+// Shared by .byte, .half and .word: constants are written directly, labels are patched at build.
+fn do_data_directive(
+    location: Location,
     iter: &mut LexerCursor,
     builder: &mut BinaryBuilder,
+    kind: InstructionLabelKind,
 ) -> Result<(), AssemblerError> {
     // Being extra cautious for when these features are enabled.
     // Don't want it to consume "symbols" of instructions.
     let values = if builder.state.mode.is_data() {
-        get_constant_or_labels(iter)?
+        get_constant_or_labels(iter, builder.colonless_labels)?
     } else {
         get_constants(iter)?
             .into_iter()
@@ -324,25 +347,65 @@ fn do_word_directive(
             .collect()
     };
 
+    let size = kind.size();
+
+    // Anything that fits read either signed or unsigned: .byte takes -128 to 255.
+    for value in &values {
+        if let ConstantOrLabel::Constant(constant) = value {
+            if !fits_width(constant.value, size) {
+                let bits = 8 * size as u32;
+
+                return Err(AssemblerError {
+                    location: Some(constant.location),
+                    reason: ConstantOutOfRange(-(1i64 << (bits - 1)), (1i64 << bits) - 1),
+                })
+            }
+        }
+    }
+
     let region = builder.region().ok_or(MISSING_REGION)?;
+    let padding = align_count(pc_for_region(region, Some(location))?, size as u32) as usize;
+
+    // Offsets counted by hand from the previous data are off by this much.
+    if padding > 0 && builder.padding_warnings {
+        builder.warnings.push(AssemblerWarning {
+            location,
+            reason: AssemblerWarningReason::ImplicitPadding(padding, size),
+        })
+    }
+
+    // Repetitions are each under REPEAT_LIMIT, but a line can hold any number of them.
+    let total = values.iter()
+        .map(|value| match value {
+            ConstantOrLabel::Label(..) => size,
+            ConstantOrLabel::Constant(value) if value.count > REPEAT_LIMIT => 0,
+            ConstantOrLabel::Constant(value) => size * value.count as usize,
+        })
+        .fold(padding, usize::saturating_add);
+
+    check_region_edge(builder.region().ok_or(MISSING_REGION)?, total as u64, Some(location))?;
+
+    builder.reserve_output(total)?;
 
-    // First, align to 4 bytes
+    let region = builder.region().ok_or(MISSING_REGION)?;
 
-    align_with_zeros(region, 4)?;
+    // First, align to the data size.
+    align_with_zeros(region, size as u32)?;
 
     for value in values {
         match value {
-            ConstantOrLabel::Label(label) => {
+            ConstantOrLabel::Label(location, label) => {
                 let offset = region.raw.data.len();
 
-                region.raw.data.extend_from_slice(&[0u8; 4]);
+                region.raw.data.resize(offset + size, 0);
                 region.labels.push(BinaryBuilderLabel {
                     offset,
-                    location: label.location,
+                    location,
                     label: InstructionLabel {
-                        kind: InstructionLabelKind::Full,
-                        label: Label(label),
+                        kind,
+                        label,
                     },
+                    temporary: false,
                 })
             }
             ConstantOrLabel::Constant(value) => {
@@ -350,13 +413,12 @@ fn do_word_directive(
                     continue;
                 }
 
-                let mut array = [0u8; 4];
-                LittleEndian::write_u32(&mut array, value.value as u32);
+                let bytes = value.value.to_le_bytes();
 
-                region.raw.data.reserve(4 * value.count as usize);
+                region.raw.data.reserve(size * value.count as usize);
 
                 for _ in 0..value.count {
-                    region.raw.data.extend_from_slice(&array);
+                    region.raw.data.extend_from_slice(&bytes[..size]);
                 }
             }
         }
@@ -388,6 +450,59 @@ fn do_entry_directive(iter: &mut LexerCursor, builder: &mut BinaryBuilder) -> Re
     Ok(())
 }
 
+// Only the $at option means anything here, the rest (reorder, macro, ...) are accepted and ignored.
+fn do_set_directive(iter: &mut LexerCursor, builder: &mut BinaryBuilder) -> Result<(), AssemblerError> {
+    let (start, token) = iter.peek_adjacent();
+
+    if let Some(Token { kind: Symbol(option), .. }) = token {
+        iter.set_position(start + 1);
+
+        match &option.get().to_lowercase() as &str {
+            "at" => builder.state.at = true,
+            "noat" => builder.state.at = false,
+            _ => { }
+        }
+    }
+
+    iter.collect_without(|kind| kind == &NewLine);
+
+    Ok(())
+}
+
+// Like get_label, but integers aren't accepted in place of a name.
+fn get_named_label(iter: &mut LexerCursor) -> Result<NamedLabel, AssemblerError> {
+    if let (_, Some(token)) = iter.peek_adjacent() {
+        if !matches!(token.kind, Symbol(_)) {
+            return Err(AssemblerError {
+                location: Some(token.location),
+                reason: ExpectedLabel(token.kind.strip()),
+            })
+        }
+    }
+
+    match get_label(iter)? {
+        Label(label) => Ok(label),
+        _ => Err(AssemblerError {
+            location: None,
+            reason: EndOfFile,
+        }),
+    }
+}
+
+// .alias name, label defines name at the same address as label, resolved once everything is assembled.
+fn do_alias_directive(iter: &mut LexerCursor, builder: &mut BinaryBuilder) -> Result<(), AssemblerError> {
+    let name = get_named_label(iter)?;
+    let target = get_named_label(iter)?;
+
+    builder.aliases.push(BinaryBuilderAlias {
+        name: name.name,
+        location: name.location,
+        target,
+    });
+
+    Ok(())
+}
+
 fn do_extern_directive(
     iter: &mut LexerCursor,
     _: &mut BinaryBuilder,
@@ -415,13 +530,13 @@ pub fn do_directive(
     match &lowercase as &str {
         "globl" | "global" => do_globl_directive(iter, builder),
 
-        "ascii" => do_ascii_directive(iter, builder),
-        "asciiz" => do_asciiz_directive(iter, builder),
-        "align" => do_align_directive(iter, builder),
-        "space" => do_space_directive(iter, builder),
-        "byte" => do_byte_directive(iter, builder),
-        "half" => do_half_directive(iter, builder),
-        "word" => do_word_directive(iter, builder),
+        "ascii" => do_ascii_directive(location, iter, builder, false),
+        "asciiz" => do_ascii_directive(location, iter, builder, true),
+        "align" => do_align_directive(location, iter, builder),
+        "space" => do_space_directive(location, iter, builder),
+        "byte" => do_data_directive(location, iter, builder, InstructionLabelKind::Byte),
+        "half" => do_data_directive(location, iter, builder, InstructionLabelKind::Half),
+        "word" => do_data_directive(location, iter, builder, InstructionLabelKind::Full),
         "float" => do_float_directive(iter, builder),
         "double" => do_double_directive(iter, builder),
         "entry" => do_entry_directive(iter, builder),
@@ -432,6 +547,8 @@ pub fn do_directive(
         "kdata" => do_seek_directive(KernelData, iter, builder),
 
         "extern" => do_extern_directive(iter, builder),
+        "set" => do_set_directive(iter, builder),
+        "alias" => do_alias_directive(iter, builder),
         _ => Err(AssemblerError {
             location: Some(location),
             reason: UnknownDirective(directive.to_string()),
@@ -439,3 +556,420 @@ pub fn do_directive(
     }
     .map_err(default_start(location))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_util::AssemblerReason;
+    use crate::assembler::assembler_util::AssemblerReason::{
+        ConstantOutOfRange, DuplicateAlias, ExpectedLabel, ExpectedString, LabelOutOfRange, MissingComma,
+        OutputTooLarge, OverwriteEdge, UnknownLabel,
+    };
+    use crate::assembler::core::AssembleOptions;
+    use crate::assembler::assembler_util::AssemblerWarningReason::ImplicitPadding;
+    use crate::assembler::binary::BinarySection::Data;
+    use crate::assembler::binary::EntrySource;
+    use crate::assembler::lexer::LexerReason::{InvalidEscape, InvalidString};
+    use crate::assembler::lexer::StrippedKind;
+    use crate::assembler::project::file_error;
+    use crate::assembler::source::FileProviderPool;
+    use crate::assembler::string::assemble_with_provider;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use crate::assembler::string::{assemble_from, assemble_from_with_options, SourceError};
+
+    #[test]
+    fn space_stops_at_the_end_of_memory() {
+        let binary = assemble_from(".data 0xFFFFFFF0\n.space 16\n").unwrap();
+
+        assert_eq!(binary.regions[0].data, vec![0; 16]);
+
+        let Err(SourceError::Assembler(error)) = assemble_from(".data 0xFFFFFFF0\n.space 32\n") else {
+            panic!("expected an assembler error")
+        };
+
+        assert!(matches!(error.reason, OverwriteEdge(Data, 0xFFFFFFF0, 0x20)));
+        assert_eq!(error.location.map(|location| location.index), Some(17)); // the .space directive
+        assert_eq!(
+            error.reason.to_string(),
+            ".data region at 0xfffffff0 would hold 0x20 bytes, past the end of memory at 0xffffffff"
+        );
+    }
+
+    fn data(source: &str) -> Vec<u8> {
+        assemble_from(&format!(".data\n{source}\n")).unwrap().regions[0].data.clone()
+    }
+
+    #[test]
+    fn strings_on_one_line() {
+        assert_eq!(data(r#".asciiz "ab", "c""#), b"ab\0c\0");
+        assert_eq!(data(r#".ascii "ab", "c" # done"#), b"abc");
+
+        let Err(SourceError::Assembler(error)) = assemble_from(".data\n.asciiz \"a\" \"b\"\n") else {
+            panic!("strings without a comma assembled")
+        };
+
+        assert!(matches!(error.reason, MissingComma));
+        assert_eq!(error.location.map(|location| location.index), Some(17)); // "b", tokens start where the last one ended
+
+        let Err(SourceError::Assembler(error)) = assemble_from(".data\n.asciiz \"a\",\n") else {
+            panic!("a trailing comma assembled")
+        };
+
+        assert!(matches!(error.reason, ExpectedString(StrippedKind::NewLine)));
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(data(r#".ascii "\x41\x7a\\\"\t""#), b"Az\\\"\t");
+        assert_eq!(data(r#".ascii "\x80\xff\xC3\xa9""#), [0x80, 0xFF, 0xC3, 0xA9]);
+        assert_eq!(data(".ascii \"é\""), "é".as_bytes());
+        assert_eq!(data(r".byte '\xff'"), [0xFF]);
+
+        let Err(SourceError::Lexer(error)) = assemble_from(r#".ascii "\x4""#) else {
+            panic!("a short hex escape lexed")
+        };
+
+        assert!(matches!(error.reason, InvalidEscape('x')));
+
+        let Err(SourceError::Lexer(error)) = assemble_from(r#".ascii "\q""#) else {
+            panic!("an unknown escape lexed")
+        };
+
+        assert!(matches!(error.reason, InvalidEscape('q')));
+    }
+
+    #[test]
+    fn unterminated_strings_end_with_their_line() {
+        let Err(SourceError::Lexer(error)) = assemble_from(".data\n.asciiz \"abc\n.word 1\n") else {
+            panic!("an unterminated string lexed")
+        };
+
+        assert!(matches!(error.reason, InvalidString));
+        assert_eq!(error.location.index, 14); // the quote, the next line lexes on its own
+    }
+
+    #[test]
+    fn nul_in_ascii_and_asciiz() {
+        // .ascii adds nothing, .asciiz one terminator per string.
+        assert_eq!(data(r#".ascii "a\0b""#), b"a\0b");
+        assert_eq!(data(r#".asciiz "a\0b""#), b"a\0b\0");
+        assert_eq!(data(r#".asciiz "\0", "\0""#), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn label_data_that_does_not_fit_names_the_label() {
+        // Offsets between text labels fit in a half, the address itself doesn't.
+        let source = "start: nop\nnext: nop\n.data\n.half next - start, 0\n.byte start\n";
+
+        let pool = FileProviderPool::in_memory(HashMap::from([("main.asm".to_string(), source.to_string())]));
+        let error = assemble_with_provider(&pool.in_memory_provider("main.asm").unwrap()).unwrap_err();
+
+        let SourceError::Assembler(inner) = &error else { panic!("expected an assembler error") };
+        assert!(matches!(&inner.reason, LabelOutOfRange(name, 0x400000, 1, Some(_)) if name == "start"));
+
+        let error = file_error(&pool, Path::new("main.asm"), error);
+
+        assert_eq!(error.place.as_deref(), Some("main.asm:5"));
+        assert_eq!(error.definition.as_deref(), Some("main.asm:1"));
+
+        let binary = assemble_from("start: nop\nnext: nop\n.data\n.half next - start, 0\n").unwrap();
+
+        assert_eq!(binary.regions[1].data, [4, 0, 0, 0]);
+    }
+
+    fn limited(source: &str) -> Result<crate::assembler::binary::Binary, SourceError> {
+        assemble_from_with_options(source, AssembleOptions { output_limit: 64, ..Default::default() })
+    }
+
+    // The line of the directive that went over, with the limit at 64 bytes.
+    fn too_large(source: &str) -> usize {
+        let Err(SourceError::Assembler(error)) = limited(source) else { panic!("{source:?} fit") };
+
+        assert!(matches!(error.reason, OutputTooLarge(64)), "{source:?}");
+
+        let index = error.location.unwrap().index;
+
+        source[..index].matches('\n').count() + 1
+    }
+
+    #[test]
+    fn output_up_to_the_limit_assembles() {
+        let text = "a".repeat(63);
+
+        for source in [
+            ".data\n.space 32\n.space 32\n".to_string(),
+            ".data\n.word 0:8, 0:8\n".to_string(),
+            format!(".data\n.asciiz \"{text}\"\n"),
+            ".data\n.byte 1\n.align 5\n.space 32\n".to_string(),
+        ] {
+            let binary = limited(&source).unwrap();
+
+            assert_eq!(binary.regions.iter().map(|region| region.data.len()).sum::<usize>(), 64, "{source:?}");
+        }
+    }
+
+    #[test]
+    fn output_past_the_limit_fails_at_the_directive() {
+        let text = "a".repeat(64);
+
+        assert_eq!(too_large(".data\n.space 32\n.space 33\n"), 3);
+        assert_eq!(too_large(".data\n.word 0:8, 0:9\n"), 2);
+        assert_eq!(too_large(&format!(".data\n.space 1\n.asciiz \"{text}\"\n")), 3);
+        assert_eq!(too_large(".data\n.byte 1\n.align 7\n"), 3);
+
+        // The limit counts every section together.
+        assert_eq!(too_large(".text\n.space 40\n.data\n.space 40\n"), 4);
+    }
+
+    fn words(data: &[u8]) -> Vec<u32> {
+        data.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn jump_tables_take_label_offsets() {
+        let binary = assemble_from("
+            case0: nop
+            case1: nop
+                nop
+            .data
+            table: .word case0, case1+4, case1-4
+        ").unwrap();
+
+        assert_eq!(words(&binary.regions[1].data), [0x400000, 0x400008, 0x400000]);
+    }
+
+    #[test]
+    fn lengths_from_label_differences() {
+        let binary = assemble_from("
+            .data
+            str: .ascii \"hello\"
+            strend:
+            .align 2
+            len: .word strend-str
+            back: .half str-strend
+        ").unwrap();
+
+        let data = &binary.regions[0].data;
+
+        assert_eq!(data[8..12], 5u32.to_le_bytes());
+        assert_eq!(data[12..14], (-5i16).to_le_bytes());
+    }
+
+    #[test]
+    fn missing_names_in_offsets_and_differences() {
+        for (source, name) in [
+            (".data\n.word 0\n.word missing+4\n", "missing"),
+            (".data\nhere: .word 0\n.word here-missing\n", "missing"),
+            (".data\nhere: .word 0\n.word missing-here\n", "missing"),
+        ] {
+            let Err(SourceError::Assembler(error)) = assemble_from(source) else { panic!("{source:?} assembled") };
+
+            assert!(matches!(&error.reason, UnknownLabel(label) if label == name), "{source:?}");
+
+            let index = error.location.unwrap().index;
+
+            assert_eq!(source[..index].matches('\n').count() + 1, 3, "{source:?}");
+        }
+    }
+
+    fn padding_warnings(source: &str, warn: bool) -> Vec<String> {
+        let options = AssembleOptions { warn_implicit_padding: warn, ..Default::default() };
+        let binary = assemble_from_with_options(source, options).unwrap();
+
+        binary.warnings.iter()
+            .filter(|warning| matches!(warning.reason, ImplicitPadding(..)))
+            .map(|warning| warning.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn implicit_padding_is_a_warning() {
+        assert_eq!(padding_warnings(".data\n.ascii \"abc\"\n.half 5\n", true), [
+            "1 byte(s) of padding were inserted to align this data to 2 bytes, add .align 1 before it to make that explicit"
+        ]);
+        assert_eq!(padding_warnings(".data\n.byte 1\n.word 2\n", true), [
+            "3 byte(s) of padding were inserted to align this data to 4 bytes, add .align 2 before it to make that explicit"
+        ]);
+
+        // The option turns it off, the padding stays.
+        assert!(padding_warnings(".data\n.byte 1\n.word 2\n", false).is_empty());
+    }
+
+    #[test]
+    fn aligned_data_has_no_padding_warning() {
+        for source in [
+            ".data\n.word 1\n.half 2\n.half 3\n.word 4\n",
+            ".data\n.byte 1, 2\n.half 3\n",
+            ".data\n.ascii \"abc\"\n.align 2\n.word 4\n",
+            ".data\n.byte 1\n.byte 2\n.ascii \"x\"\n",
+        ] {
+            assert!(padding_warnings(source, true).is_empty(), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn label_offsets_show_the_real_layout() {
+        let binary = assemble_from(".data\ntext: .ascii \"abc\"\nnumber: .half 5\nlast: .word 6\n").unwrap();
+
+        // number is bound before its .half is padded to offset 4, last right after the .half at 6.
+        assert_eq!(binary.regions[0].data, [b'a', b'b', b'c', 0, 5, 0, 0, 0, 6, 0, 0, 0]);
+
+        let offsets: Vec<_> = binary.label_offsets().into_iter()
+            .map(|label| (label.name, label.address, label.region, label.offset))
+            .collect();
+
+        assert_eq!(offsets, [
+            ("text".to_string(), 0x10010000, 0, 0),
+            ("number".to_string(), 0x10010003, 0, 3),
+            ("last".to_string(), 0x10010006, 0, 6),
+        ]);
+    }
+
+    #[test]
+    fn data_constants_at_their_boundaries() {
+        let binary = assemble_from("
+            .data
+            .byte 'A', -128, -1, 255, '\\n'
+            .byte 0x7F : 2
+            .half -32768, 65535
+            .word -2147483648, 4294967295
+        ").unwrap();
+
+        assert_eq!(binary.regions[0].data, [
+            b'A', 0x80, 0xFF, 0xFF, b'\n', 0x7F, 0x7F, 0,
+            0x00, 0x80, 0xFF, 0xFF,
+            0x00, 0x00, 0x00, 0x80, 0xFF, 0xFF, 0xFF, 0xFF,
+        ]);
+    }
+
+    #[test]
+    fn data_constants_past_their_range() {
+        for (source, min, max) in [
+            (".byte 1, 256\n", -128, 255),
+            (".byte 1, -129\n", -128, 255),
+            (".byte 1, 300 : 2\n", -128, 255),
+            (".half 1, 65536\n", -32768, 65535),
+            (".half 1, -32769\n", -32768, 65535),
+            (".word 1, 4294967296\n", -2147483648, 4294967295),
+            (".word 1, -2147483649\n", -2147483648, 4294967295),
+        ] {
+            let source = format!(".data\n{source}");
+            let Err(SourceError::Assembler(error)) = assemble_from(&source) else { panic!("{source:?} assembled") };
+
+            assert!(matches!(error.reason, ConstantOutOfRange(a, b) if (a, b) == (min, max)), "{source:?}");
+
+            // At the value, not the directive.
+            let index = error.location.unwrap().index;
+
+            assert!(source[index..].trim_start().starts_with(['2', '3', '4', '6', '-']), "{source:?}");
+            assert!(index > source.find(',').unwrap(), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn entry_comes_from_the_directive_first() {
+        let binary = assemble_from("main: nop\nstart: nop\n.entry start\n").unwrap();
+
+        assert_eq!(binary.entry, 0x400004);
+        assert_eq!(binary.entry_source, EntrySource::Directive);
+        assert_eq!(binary.entry_label(), Some("start"));
+
+        // Offsets work too, without a label right there.
+        let binary = assemble_from(".entry main + 4\nmain: nop\nnop\n").unwrap();
+
+        assert_eq!(binary.entry, 0x400004);
+        assert_eq!(binary.entry_label(), None);
+    }
+
+    #[test]
+    fn entry_falls_back_to_main_then_the_text_start() {
+        let binary = assemble_from("helper: jr $ra\nmain: jal helper\n").unwrap();
+
+        assert_eq!((binary.entry, binary.entry_source), (0x400004, EntrySource::MainLabel));
+        assert_eq!(binary.entry_label(), Some("main"));
+
+        // The first text region with anything in it, wherever it is.
+        let binary = assemble_from(".data\n.word 1\n.text 0x500000\nfirst: nop\n").unwrap();
+
+        assert_eq!((binary.entry, binary.entry_source), (0x500000, EntrySource::TextStart));
+        assert_eq!(binary.entry_label(), Some("first"));
+
+        let binary = assemble_from("").unwrap();
+
+        assert_eq!((binary.entry, binary.entry_source), (0x400000, EntrySource::TextStart));
+        assert_eq!(binary.entry_label(), None);
+    }
+
+    #[test]
+    fn entry_must_name_a_label() {
+        let source = "main: nop\n.entry nowhere\nnop\n";
+        let Err(SourceError::Assembler(error)) = assemble_from(source) else { panic!() };
+
+        assert!(matches!(&error.reason, UnknownLabel(name) if name == "nowhere"));
+        assert_eq!(source[..error.location.unwrap().index].matches('\n').count() + 1, 2);
+
+        // Relocatable output leaves it to the linker.
+        let options = AssembleOptions { relocatable: true, ..AssembleOptions::default() };
+
+        assert!(assemble_from_with_options(source, options).is_ok());
+    }
+
+    #[test]
+    fn aliases_take_their_label_address() {
+        // Both forward, and an alias of an alias.
+        let binary = assemble_from("
+            .alias old_double, double
+            .alias older_double, old_double
+            jal old_double
+            jal double
+            double: add $v0, $a0, $a0
+            jr $ra
+        ").unwrap();
+
+        assert_eq!(binary.labels["double"], 0x400008);
+        assert_eq!(binary.labels["old_double"], 0x400008);
+        assert_eq!(binary.labels["older_double"], 0x400008);
+        assert_eq!(binary.aliases["old_double"], "double");
+        assert_eq!(binary.aliases["older_double"], "old_double");
+        assert!(!binary.aliases.contains_key("double"));
+
+        // Both jals encode the same target.
+        assert_eq!(binary.regions[0].data[0..4], binary.regions[0].data[4..8]);
+    }
+
+    fn alias_error(source: &str) -> (AssemblerReason, usize) {
+        match assemble_from(source) {
+            Err(SourceError::Assembler(error)) => (error.reason, error.location.unwrap().index),
+            Err(error) => panic!("{source:?}: {error}"),
+            Ok(_) => panic!("{source:?} assembled"),
+        }
+    }
+
+    #[test]
+    fn aliases_cannot_collide_or_dangle() {
+        // Points at the alias name, tokens start right after the one before.
+        let (reason, index) = alias_error("double: nop\nhalve: nop\n.alias halve, double\n");
+
+        assert!(matches!(&reason, DuplicateAlias(name) if name == "halve"), "{reason}");
+        assert_eq!(index, 29);
+        assert_eq!(reason.to_string(), "Alias \"halve\" has the same name as another label or alias");
+
+        let (reason, _) = alias_error("double: nop\n.alias old, double\n.alias old, double\n");
+
+        assert!(matches!(&reason, DuplicateAlias(name) if name == "old"), "{reason}");
+
+        // Points at the missing target.
+        let (reason, index) = alias_error("nop\n.alias old, missing\n");
+
+        assert!(matches!(&reason, UnknownLabel(name) if name == "missing"), "{reason}");
+        assert_eq!(index, 15);
+
+        let (reason, _) = alias_error("nop\n.alias first, second\n.alias second, first\n");
+
+        assert!(matches!(reason, UnknownLabel(_)), "{reason}");
+
+        let (reason, _) = alias_error("nop\n.alias old, 4\n");
+
+        assert!(matches!(reason, ExpectedLabel(StrippedKind::IntegerLiteral)), "{reason}");
+    }
+}
@@ -1,89 +1,41 @@
 use crate::assembler::assembler_util::AssemblerReason::{
-    ConstantOutOfRange, MissingRegion, UnknownInstruction,
+    ConstantOutOfRange, FloatingPointInstruction, MissingComma, MissingOperand, MissingRegion, TemporaryClobbered,
+    TemporaryUnavailable, UnexpectedComma, UnknownInstruction,
 };
 use crate::assembler::assembler_util::{
-    default_start, get_constant, get_label, get_offset_or_label, get_register, get_value,
-    maybe_get_value, pc_for_region, AssemblerError, InstructionValue, OffsetOrLabel,
+    check_region_edge, default_start, fits_width, get_constant, get_integer_adjacent, get_label, get_offset_or_label,
+    get_register, get_value, is_end_of_statement, maybe_get_value, pc_for_region, AssemblerError, AssemblerReason,
+    AssemblerWarning, AssemblerWarningReason, InstructionValue, OffsetOrLabel,
 };
 use crate::assembler::binary::{AddressLabel, BinaryBreakpoint};
 use crate::assembler::binary_builder::BinaryBuilder;
 use crate::assembler::binary_builder::InstructionLabelKind::{Branch, Jump, Lower, Upper};
 use crate::assembler::binary_builder::{BinaryBuilderLabel, InstructionLabel};
-use crate::assembler::cursor::LexerCursor;
-use crate::assembler::instructions::Opcode::{Func, Op, Special};
-use crate::assembler::instructions::{Encoding, Instruction, Opcode};
+use crate::assembler::cursor::{is_adjacent_kind, LexerCursor};
+use crate::assembler::encode::InstructionBuilder;
+use crate::assembler::instructions::Opcode::{Func, Op};
+use crate::assembler::instructions::{Encoding, Instruction, Opcode, OperandKind, PSEUDO_INSTRUCTIONS};
 use crate::assembler::registers::RegisterSlot;
 use crate::assembler::registers::RegisterSlot::{AssemblerTemporary, Zero};
 use byteorder::{LittleEndian, WriteBytesExt};
-use num_traits::ToPrimitive;
 use std::collections::HashMap;
-use Opcode::Algebra;
-use crate::assembler::lexer::Location;
-
-fn instruction_base(op: &Opcode) -> u32 {
-    match op {
-        Op(key) => (*key as u32 & 0b111111) << 26,
-        Func(key) => *key as u32 & 0b111111, // opcode: 0
-        Special(key) => (*key as u32 & 0b111111) << 16 | (1 << 26), // opcode: 1
-        Algebra(key) => *key as u32 & 0b111111 | (28 << 26),
-    }
-}
-
-fn register_source(slot: RegisterSlot) -> u32 {
-    slot.to_u32().unwrap()
-}
-
-struct InstructionBuilder(u32);
-
-impl InstructionBuilder {
-    fn from_op(op: &Opcode) -> InstructionBuilder {
-        InstructionBuilder(instruction_base(op))
-    }
-
-    fn with_slot_offset<const OFFSET: u32>(mut self, slot: RegisterSlot) -> InstructionBuilder {
-        self.0 &= !(0b11111 << OFFSET);
-        self.0 |= register_source(slot) << OFFSET;
-
-        self
-    }
-
-    fn with_dest(self, slot: RegisterSlot) -> InstructionBuilder {
-        self.with_slot_offset::<11>(slot)
-    }
-
-    fn with_temp(self, slot: RegisterSlot) -> InstructionBuilder {
-        self.with_slot_offset::<16>(slot)
-    }
-
-    fn with_source(self, slot: RegisterSlot) -> InstructionBuilder {
-        self.with_slot_offset::<21>(slot)
-    }
-
-    fn with_immediate(mut self, imm: u16) -> InstructionBuilder {
-        self.0 &= 0xFFFF0000;
-        self.0 |= imm as u32;
-
-        self
-    }
-
-    fn with_sham(mut self, sham: u8) -> InstructionBuilder {
-        self.0 &= !(0b11111 << 6);
-        self.0 |= (sham as u32) << 6;
-
-        self
-    }
-}
+use crate::assembler::lexer::{Location, Token};
+use crate::assembler::lexer::TokenKind::{Comma, Register};
 
 type InstructionPair = (u32, Option<InstructionLabel>);
 
 struct EmitInstruction {
     instructions: Vec<InstructionPair>,
+    temporary: bool, // expansion uses $at on its own (not because the user wrote it)
+    warnings: Vec<AssemblerWarningReason>,
 }
 
 impl EmitInstruction {
     fn with(instruction: u32) -> EmitInstruction {
         EmitInstruction {
             instructions: vec![(instruction, None)],
+            temporary: false,
+            warnings: vec![],
         }
     }
 }
@@ -137,6 +89,7 @@ fn make_label(label: AddressLabel, dest: RegisterSlot) -> Vec<InstructionPair> {
     // Load Address may not know the label location yet.
     // So we will never optimize away the size of this instruction,
     // as this might change the label location.
+    // BinaryBuilder's optimize pass (-O) can shrink it once every label is placed.
 
     match label {
         AddressLabel::Constant(constant) => {
@@ -145,7 +98,7 @@ fn make_label(label: AddressLabel, dest: RegisterSlot) -> Vec<InstructionPair> {
                 .map(|instruction| (instruction, None))
                 .collect()
         }
-        AddressLabel::Label(_) => {
+        AddressLabel::Label(_) | AddressLabel::Difference(_) => {
             let label_upper = label.clone();
             let label_lower = label;
 
@@ -176,7 +129,7 @@ fn make_label(label: AddressLabel, dest: RegisterSlot) -> Vec<InstructionPair> {
     }
 }
 
-fn make_offset_or_label(offset: OffsetOrLabel) -> (u16, RegisterSlot, Vec<InstructionPair>) {
+fn make_offset_or_label(offset: OffsetOrLabel, at: RegisterSlot) -> (u16, RegisterSlot, Vec<InstructionPair>) {
     match offset {
         OffsetOrLabel::Offset(label, register) => {
             match label {
@@ -186,42 +139,40 @@ fn make_offset_or_label(offset: OffsetOrLabel) -> (u16, RegisterSlot, Vec<Instru
                     (constant as u16, register, vec![])
                 }
                 _ => {
-                    let mut instructions = make_label(label, AssemblerTemporary);
+                    let mut instructions = make_label(label, at);
 
                     let add = InstructionBuilder::from_op(&Func(32))
-                        .with_dest(AssemblerTemporary)
-                        .with_source(AssemblerTemporary)
+                        .with_dest(at)
+                        .with_source(at)
                         .with_temp(register)
                         .0;
 
                     instructions.push((add, None));
 
-                    (0, AssemblerTemporary, instructions)
+                    (0, at, instructions)
                 }
             }
         }
         OffsetOrLabel::Label(label) => {
-            let instructions = make_label(label, AssemblerTemporary);
+            let instructions = make_label(label, at);
 
-            (0, AssemblerTemporary, instructions)
+            (0, at, instructions)
         }
     }
 }
 
-fn unpack_value(value: InstructionValue) -> (RegisterSlot, Vec<u32>) {
+fn unpack_value(value: InstructionValue, at: RegisterSlot) -> (RegisterSlot, Vec<u32>) {
     match value {
         InstructionValue::Slot(slot) => (slot, vec![]),
-        InstructionValue::Literal(constant) => (
-            AssemblerTemporary,
-            load_immediate(constant, AssemblerTemporary),
-        ),
+        InstructionValue::Literal(constant) => (at, load_immediate(constant, at)),
     }
 }
 
 fn emit_unpack_value(
     value: InstructionValue,
+    at: RegisterSlot,
 ) -> (RegisterSlot, Vec<(u32, Option<InstructionLabel>)>) {
-    let (slot, instructions) = unpack_value(value);
+    let (slot, instructions) = unpack_value(value, at);
 
     (
         slot,
@@ -235,12 +186,15 @@ fn emit_unpack_value(
 fn do_register_instruction(
     op: &Opcode,
     iter: &mut LexerCursor,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let dest = get_register(iter)?;
     let source = get_register(iter)?;
     let temp = get_value(iter)?;
 
-    let (slot, mut instructions) = emit_unpack_value(temp);
+    let (slot, mut instructions) = emit_unpack_value(temp, at);
+
+    let temporary = !instructions.is_empty();
 
     let inst = InstructionBuilder::from_op(op)
         .with_dest(dest)
@@ -250,7 +204,7 @@ fn do_register_instruction(
 
     instructions.push((inst, None));
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary, warnings: vec![] })
 }
 
 fn do_register_shift_instruction(
@@ -269,7 +223,7 @@ fn do_register_shift_instruction(
 
     let instructions = vec![(inst, None)];
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: false, warnings: vec![] })
 }
 
 fn do_source_instruction(
@@ -297,13 +251,15 @@ fn do_destination_instruction(
 fn do_inputs_instruction(
     op: &Opcode,
     iter: &mut LexerCursor,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let first = get_register(iter)?;
     let second = get_register(iter)?;
     let div = maybe_get_value(iter);
 
     if let Some(value) = div {
-        let (slot, mut instructions) = emit_unpack_value(value);
+        let (slot, mut instructions) = emit_unpack_value(value, at);
+        let temporary = !instructions.is_empty();
 
         let inst = InstructionBuilder::from_op(op)
             .with_source(second)
@@ -316,7 +272,7 @@ fn do_inputs_instruction(
 
         instructions.append(&mut vec![(inst, None), (mflo, None)]);
 
-        Ok(EmitInstruction { instructions })
+        Ok(EmitInstruction { instructions, temporary, warnings: vec![] })
     } else {
         let inst = InstructionBuilder::from_op(op)
             .with_source(first)
@@ -335,6 +291,10 @@ fn do_sham_instruction(
     let temp = get_register(iter)?;
     let sham = get_constant(iter)?;
 
+    if sham > 31 {
+        return Err(AssemblerError { location: None, reason: ConstantOutOfRange(0, 31) })
+    }
+
     let inst = InstructionBuilder::from_op(op)
         .with_dest(dest)
         .with_temp(temp)
@@ -361,13 +321,17 @@ fn do_special_branch_instruction(
         }),
     )];
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: false, warnings: vec![] })
 }
 
+// The alt opcode is the register form of the same operation (add for addi, and for andi, etc.).
+// It's only used when the constant can't be encoded directly, so $at is left alone otherwise.
 fn do_immediate_instruction(
     op: &Opcode,
     alt: Option<&Opcode>,
+    sign_extended: bool,
     iter: &mut LexerCursor,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let temp = get_register(iter)?;
     let source = get_register(iter)?;
@@ -375,9 +339,15 @@ fn do_immediate_instruction(
 
     let signed = constant as i64;
 
-    if !(-0x8000..0x8000).contains(&signed) {
+    let (min, max) = if sign_extended {
+        (-0x8000i64, 0x7FFFi64)
+    } else {
+        (0i64, 0xFFFFi64)
+    };
+
+    if !(min..=max).contains(&signed) {
         if let Some(alt) = alt {
-            let mut instructions = load_immediate(constant, AssemblerTemporary)
+            let mut instructions = load_immediate(constant, at)
                 .into_iter()
                 .map(|i| (i, None))
                 .collect::<Vec<InstructionPair>>();
@@ -385,16 +355,16 @@ fn do_immediate_instruction(
             let inst = InstructionBuilder::from_op(alt)
                 .with_source(source)
                 .with_dest(temp)
-                .with_temp(AssemblerTemporary)
+                .with_temp(at)
                 .0;
 
             instructions.push((inst, None));
 
-            Ok(EmitInstruction { instructions })
+            Ok(EmitInstruction { instructions, temporary: true, warnings: vec![] })
         } else {
             Err(AssemblerError {
                 location: None,
-                reason: ConstantOutOfRange(-8000, 0x7fff),
+                reason: ConstantOutOfRange(min, max),
             })
         }
     } else {
@@ -420,7 +390,13 @@ fn do_load_immediate_instruction(
         .with_immediate(constant as u16)
         .0;
 
-    Ok(EmitInstruction::with(inst))
+    let mut emit = EmitInstruction::with(inst);
+
+    if !fits_width(constant, 2) {
+        emit.warnings.push(AssemblerWarningReason::ConstantTruncated(constant, 2))
+    }
+
+    Ok(emit)
 }
 
 fn do_jump_instruction(
@@ -433,18 +409,23 @@ fn do_jump_instruction(
 
     Ok(EmitInstruction {
         instructions: vec![(inst, Some(InstructionLabel { label, kind: Jump }))],
+        temporary: false,
+        warnings: vec![],
     })
 }
 
 fn do_branch_instruction(
     op: &Opcode,
     iter: &mut LexerCursor,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let source = get_register(iter)?;
     let temp = get_value(iter)?;
     let label = get_label(iter)?;
 
-    let (slot, mut instructions) = emit_unpack_value(temp);
+    let (slot, mut instructions) = emit_unpack_value(temp, at);
+
+    let temporary = !instructions.is_empty();
 
     let inst = InstructionBuilder::from_op(op)
         .with_source(source)
@@ -459,7 +440,7 @@ fn do_branch_instruction(
         }),
     ));
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary, warnings: vec![] })
 }
 
 fn do_branch_zero_instruction(
@@ -479,7 +460,7 @@ fn do_branch_zero_instruction(
         }),
     )];
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: false, warnings: vec![] })
 }
 
 fn do_parameterless_instruction(
@@ -491,15 +472,36 @@ fn do_parameterless_instruction(
     Ok(EmitInstruction::with(inst))
 }
 
+fn do_code_instruction(
+    op: &Opcode,
+    iter: &mut LexerCursor,
+) -> Result<EmitInstruction, AssemblerError> {
+    let code = get_integer_adjacent(iter).unwrap_or(0);
+
+    if code >= 1 << 20 {
+        return Err(AssemblerError {
+            location: None,
+            reason: ConstantOutOfRange(0, (1 << 20) - 1),
+        })
+    }
+
+    let inst = InstructionBuilder::from_op(op).0 | (code as u32) << 6;
+
+    Ok(EmitInstruction::with(inst))
+}
+
 fn do_offset_instruction(
     op: &Opcode,
     iter: &mut LexerCursor,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let temp = get_register(iter)?;
 
     let offset = get_offset_or_label(iter)?;
 
-    let (immediate, register, mut instructions) = make_offset_or_label(offset);
+    let (immediate, register, mut instructions) = make_offset_or_label(offset, at);
+
+    let temporary = !instructions.is_empty();
 
     let inst = InstructionBuilder::from_op(op)
         .with_source(register)
@@ -509,7 +511,7 @@ fn do_offset_instruction(
 
     instructions.push((inst, None));
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary, warnings: vec![] })
 }
 
 fn do_nop_instruction(_: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
@@ -518,32 +520,32 @@ fn do_nop_instruction(_: &mut LexerCursor) -> Result<EmitInstruction, AssemblerE
     Ok(EmitInstruction::with(instruction))
 }
 
-fn do_abs_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
+fn do_abs_instruction(iter: &mut LexerCursor, at: RegisterSlot) -> Result<EmitInstruction, AssemblerError> {
     let dest = get_register(iter)?;
     let source = get_register(iter)?;
 
     // Instruction Pattern from MARS (e.g. branchless)
     let shift = InstructionBuilder::from_op(&Func(3)) // sra
-        .with_dest(AssemblerTemporary)
+        .with_dest(at)
         .with_temp(source)
         .with_immediate(31)
         .0;
 
     let xor = InstructionBuilder::from_op(&Func(38)) // xor
         .with_dest(dest)
-        .with_temp(AssemblerTemporary)
+        .with_temp(at)
         .with_source(source)
         .0;
 
     let sub = InstructionBuilder::from_op(&Func(35)) // subu
         .with_dest(dest)
-        .with_temp(AssemblerTemporary)
+        .with_temp(at)
         .with_source(dest)
         .0;
 
     let instructions = vec![(shift, None), (xor, None), (sub, None)];
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: true, warnings: vec![] })
 }
 
 fn do_branch_custom_instruction(
@@ -551,12 +553,13 @@ fn do_branch_custom_instruction(
     greater_than: bool,
     result_true: bool,
     unsigned: bool,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let source = get_register(iter)?;
     let temp = get_value(iter)?;
     let label = get_label(iter)?;
 
-    let (slot, mut instructions) = emit_unpack_value(temp);
+    let (slot, mut instructions) = emit_unpack_value(temp, at);
 
     let (first, second) = if greater_than {
         (slot, source)
@@ -569,11 +572,11 @@ fn do_branch_custom_instruction(
     let compare = InstructionBuilder::from_op(set_op) // slt
         .with_source(first)
         .with_temp(second)
-        .with_dest(AssemblerTemporary)
+        .with_dest(at)
         .0;
 
     let branch = InstructionBuilder::from_op(branch_op) // bne
-        .with_source(AssemblerTemporary)
+        .with_source(at)
         .with_temp(Zero)
         .0;
 
@@ -588,7 +591,7 @@ fn do_branch_custom_instruction(
         ),
     ]);
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: true, warnings: vec![] })
 }
 
 fn do_set_custom_instruction(
@@ -596,12 +599,15 @@ fn do_set_custom_instruction(
     greater_than: bool,
     result_true: bool,
     unsigned: bool,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let dest = get_register(iter)?;
     let source = get_register(iter)?;
     let temp = get_value(iter)?;
 
-    let (slot, mut instructions) = emit_unpack_value(temp);
+    let (slot, mut instructions) = emit_unpack_value(temp, at);
+
+    let temporary = !instructions.is_empty();
 
     let (first, second) = if greater_than {
         (slot, source)
@@ -629,15 +635,17 @@ fn do_set_custom_instruction(
         instructions.push((xori, None))
     }
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary, warnings: vec![] })
 }
 
-fn do_seq_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
+fn do_seq_instruction(iter: &mut LexerCursor, at: RegisterSlot) -> Result<EmitInstruction, AssemblerError> {
     let dest = get_register(iter)?;
     let source = get_register(iter)?;
     let temp = get_value(iter)?;
 
-    let (slot, mut instructions) = emit_unpack_value(temp);
+    let (slot, mut instructions) = emit_unpack_value(temp, at);
+
+    let temporary = !instructions.is_empty();
 
     let subu = InstructionBuilder::from_op(&Func(35))
         .with_dest(dest)
@@ -659,15 +667,17 @@ fn do_seq_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, Assembl
 
     instructions.extend([(subu, None), (sltu, None), (xori, None)]);
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary, warnings: vec![] })
 }
 
-fn do_sne_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
+fn do_sne_instruction(iter: &mut LexerCursor, at: RegisterSlot) -> Result<EmitInstruction, AssemblerError> {
     let dest = get_register(iter)?;
     let source = get_register(iter)?;
     let temp = get_value(iter)?;
 
-    let (slot, mut instructions) = emit_unpack_value(temp);
+    let (slot, mut instructions) = emit_unpack_value(temp, at);
+
+    let temporary = !instructions.is_empty();
 
     let subu = InstructionBuilder::from_op(&Func(35))
         .with_dest(dest)
@@ -683,7 +693,7 @@ fn do_sne_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, Assembl
 
     instructions.extend([(subu, None), (sltu, None)]);
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary, warnings: vec![] })
 }
 
 fn do_neg_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
@@ -725,6 +735,7 @@ fn do_not_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, Assembl
     Ok(EmitInstruction::with(nor))
 }
 
+// Any constant is built in dest itself (lui then ori for large ones), so li never needs $at, even under .set noat.
 fn do_li_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
     let dest = get_register(iter)?;
     let constant = get_constant(iter)?;
@@ -734,7 +745,7 @@ fn do_li_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, Assemble
         .map(|inst| (inst, None))
         .collect();
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: false, warnings: vec![] })
 }
 
 fn do_la_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
@@ -743,7 +754,7 @@ fn do_la_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, Assemble
 
     let instructions = make_label(label, dest);
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: false, warnings: vec![] })
 }
 
 fn do_move_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, AssemblerError> {
@@ -775,7 +786,7 @@ fn do_b_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, Assembler
         }),
     )];
 
-    Ok(EmitInstruction { instructions })
+    Ok(EmitInstruction { instructions, temporary: false, warnings: vec![] })
 }
 
 // MARS seems to load the instruction itself like `li`. I'm not sure about this! Do it yourself!
@@ -807,31 +818,33 @@ fn do_subiu_instruction(iter: &mut LexerCursor) -> Result<EmitInstruction, Assem
     Ok(EmitInstruction::with(addiu))
 }
 
+// Every name here is listed in PSEUDO_INSTRUCTIONS, with its operands.
 fn dispatch_pseudo(
     instruction: &str,
     iter: &mut LexerCursor,
+    at: RegisterSlot,
 ) -> Result<Option<EmitInstruction>, AssemblerError> {
     Ok(Some(match instruction {
         "nop" => do_nop_instruction(iter),
-        "abs" => do_abs_instruction(iter),
-        "blt" => do_branch_custom_instruction(iter, false, true, false),
-        "bgt" => do_branch_custom_instruction(iter, true, true, false),
-        "ble" => do_branch_custom_instruction(iter, true, false, false),
-        "bge" => do_branch_custom_instruction(iter, false, false, false),
-        "bltu" => do_branch_custom_instruction(iter, false, true, true),
-        "bgtu" => do_branch_custom_instruction(iter, true, true, true),
-        "bleu" => do_branch_custom_instruction(iter, true, false, true),
-        "bgeu" => do_branch_custom_instruction(iter, false, false, true),
-        "sge" => do_set_custom_instruction(iter, false, false, false),
-        "sgt" => do_set_custom_instruction(iter, true, true, false),
-        "sle" => do_set_custom_instruction(iter, true, false, false),
-        "sgeu" => do_set_custom_instruction(iter, false, false, true),
-        "sgtu" => do_set_custom_instruction(iter, true, true, true),
-        "sleu" => do_set_custom_instruction(iter, true, false, true),
+        "abs" => do_abs_instruction(iter, at),
+        "blt" => do_branch_custom_instruction(iter, false, true, false, at),
+        "bgt" => do_branch_custom_instruction(iter, true, true, false, at),
+        "ble" => do_branch_custom_instruction(iter, true, false, false, at),
+        "bge" => do_branch_custom_instruction(iter, false, false, false, at),
+        "bltu" => do_branch_custom_instruction(iter, false, true, true, at),
+        "bgtu" => do_branch_custom_instruction(iter, true, true, true, at),
+        "bleu" => do_branch_custom_instruction(iter, true, false, true, at),
+        "bgeu" => do_branch_custom_instruction(iter, false, false, true, at),
+        "sge" => do_set_custom_instruction(iter, false, false, false, at),
+        "sgt" => do_set_custom_instruction(iter, true, true, false, at),
+        "sle" => do_set_custom_instruction(iter, true, false, false, at),
+        "sgeu" => do_set_custom_instruction(iter, false, false, true, at),
+        "sgtu" => do_set_custom_instruction(iter, true, true, true, at),
+        "sleu" => do_set_custom_instruction(iter, true, false, true, at),
         "beqz" => do_branch_zero_instruction(&Op(4), iter),
         "bnez" => do_branch_zero_instruction(&Op(5), iter),
-        "seq" => do_seq_instruction(iter),
-        "sne" => do_sne_instruction(iter),
+        "seq" => do_seq_instruction(iter, at),
+        "sne" => do_sne_instruction(iter, at),
         "neg" => do_neg_instruction(iter),
         "negu" => do_negu_instruction(iter),
         "not" => do_not_instruction(iter),
@@ -845,41 +858,185 @@ fn dispatch_pseudo(
     }?))
 }
 
+// Whether do_instruction would take name as an instruction (real or pseudo).
+pub fn is_instruction_name(name: &str, map: &HashMap<&str, &Instruction>) -> bool {
+    let lowercase = name.to_lowercase();
+
+    map.contains_key(lowercase.as_str()) || PSEUDO_INSTRUCTIONS.iter().any(|(pseudo, _)| *pseudo == lowercase)
+}
+
+// Coprocessor 1 moves, loads and stores (MARS pseudos included), and anything with a format suffix (add.s, cvt.d.w).
+// That includes comparisons (c.eq.s 1, $f0, $f2), so a condition code operand is never parsed or encoded.
+fn is_floating_point(instruction: &str) -> bool {
+    const NAMES: [&str; 14] = [
+        "mtc1", "mfc1", "mtc1.d", "mfc1.d", "lwc1", "swc1", "ldc1", "sdc1", "bc1t", "bc1f", "l.s", "l.d", "s.s", "s.d"
+    ];
+
+    NAMES.contains(&instruction) || [".s", ".d", ".w"].iter().any(|suffix| instruction.ends_with(suffix))
+}
+
 fn dispatch_instruction(
     instruction: &str,
     iter: &mut LexerCursor,
     map: &HashMap<&str, &Instruction>,
+    at: RegisterSlot,
 ) -> Result<EmitInstruction, AssemblerError> {
     let Some(instruction) = map.get(&instruction) else {
-        return dispatch_pseudo(instruction, iter)?
+        return dispatch_pseudo(instruction, iter, at)?
             .ok_or_else(|| AssemblerError {
                 location: None,
-                reason: UnknownInstruction(instruction.to_string())
+                reason: if is_floating_point(instruction) {
+                    FloatingPointInstruction(instruction.to_string())
+                } else {
+                    UnknownInstruction(instruction.to_string())
+                }
             });
     };
 
     let op = &instruction.opcode;
 
     let emit = match &instruction.encoding {
-        Encoding::Register => do_register_instruction(op, iter),
+        Encoding::Register => do_register_instruction(op, iter, at),
         Encoding::RegisterShift => do_register_shift_instruction(op, iter),
         Encoding::Source => do_source_instruction(op, iter),
         Encoding::Destination => do_destination_instruction(op, iter),
-        Encoding::Inputs => do_inputs_instruction(op, iter),
+        Encoding::Inputs => do_inputs_instruction(op, iter, at),
         Encoding::Sham => do_sham_instruction(op, iter),
         Encoding::SpecialBranch => do_special_branch_instruction(op, iter),
-        Encoding::Immediate(alt) => do_immediate_instruction(op, alt.as_ref(), iter),
+        Encoding::Immediate(alt) => do_immediate_instruction(op, alt.as_ref(), true, iter, at),
+        Encoding::UnsignedImmediate(alt) => do_immediate_instruction(op, alt.as_ref(), false, iter, at),
         Encoding::LoadImmediate => do_load_immediate_instruction(op, iter),
         Encoding::Jump => do_jump_instruction(op, iter),
-        Encoding::Branch => do_branch_instruction(op, iter),
+        Encoding::Branch => do_branch_instruction(op, iter, at),
         Encoding::BranchZero => do_branch_zero_instruction(op, iter),
         Encoding::Parameterless => do_parameterless_instruction(op, iter),
-        Encoding::Offset => do_offset_instruction(op, iter),
+        Encoding::Offset => do_offset_instruction(op, iter, at),
+        Encoding::Code => do_code_instruction(op, iter),
     }?;
 
     Ok(emit)
 }
 
+fn expected_operands(instruction: &str, map: &HashMap<&str, &Instruction>) -> Option<&'static [OperandKind]> {
+    match map.get(instruction) {
+        Some(value) => Some(value.encoding.operands().0),
+        None => PSEUDO_INSTRUCTIONS.iter().find(|(name, _)| *name == instruction).map(|(_, operands)| *operands),
+    }
+}
+
+fn get_operand(kind: &OperandKind, iter: &mut LexerCursor) -> Result<(), AssemblerError> {
+    match kind {
+        OperandKind::Register => get_register(iter).map(|_| ()),
+        OperandKind::Value => get_value(iter).map(|_| ()),
+        OperandKind::Label => get_label(iter).map(|_| ()),
+        OperandKind::Offset => get_offset_or_label(iter).map(|_| ()),
+        OperandKind::Immediate | OperandKind::UnsignedImmediate | OperandKind::Shift
+        | OperandKind::Constant | OperandKind::Code => get_constant(iter).map(|_| ()),
+    }
+}
+
+// Walks the operands the instruction expects from the start of the statement to find the first one that isn't there.
+fn missing_operand(
+    instruction: &str,
+    start: usize,
+    iter: &mut LexerCursor,
+    map: &HashMap<&str, &Instruction>,
+) -> Option<AssemblerReason> {
+    let operands = expected_operands(instruction, map)?;
+
+    iter.set_position(start);
+
+    for (index, kind) in operands.iter().enumerate() {
+        match get_operand(kind, iter) {
+            Ok(()) => continue,
+            Err(error) if is_end_of_statement(&error) => return Some(MissingOperand {
+                instruction: instruction.to_string(),
+                expected: *kind,
+                position: index + 1,
+            }),
+            Err(_) => return None,
+        }
+    }
+
+    None
+}
+
+fn first_comma(tokens: &[Token]) -> Option<Location> {
+    tokens.iter().find(|token| token.kind == Comma).map(|token| token.location)
+}
+
+// With strict_commas, operands need exactly one comma between each, and none before the first or after the last.
+// The statement has already assembled (leniently), so this walks its operands again just to see where commas sit.
+fn check_commas(
+    instruction: &str,
+    start: usize,
+    iter: &mut LexerCursor,
+    map: &HashMap<&str, &Instruction>,
+) -> Result<(), AssemblerError> {
+    let end = iter.get_position();
+
+    let Some(operands) = expected_operands(instruction, map) else { return Ok(()) };
+
+    let comma_error = |location: Location, reason: AssemblerReason| AssemblerError {
+        location: Some(location),
+        reason,
+    };
+
+    iter.set_position(start);
+
+    let mut previous = start;
+
+    for (index, kind) in operands.iter().enumerate() {
+        let next = iter.seek_without(is_adjacent_kind);
+
+        let first = iter.get_position();
+
+        // Fewer operands than listed (ex. mult with two), the rest is checked as trailing.
+        let Some(next) = next.filter(|_| first < end) else { break };
+
+        let separators = iter.tokens_since(previous);
+        let commas: Vec<&Token> = separators.iter().filter(|token| token.kind == Comma).collect();
+
+        if index == 0 && !commas.is_empty() {
+            return Err(comma_error(commas[0].location, UnexpectedComma))
+        }
+
+        if index > 0 && commas.is_empty() {
+            return Err(comma_error(next.location, MissingComma))
+        }
+
+        if commas.len() > 1 {
+            return Err(comma_error(commas[1].location, UnexpectedComma))
+        }
+
+        // Written some way the operand list doesn't describe, nothing to check against.
+        if get_operand(kind, iter).is_err() {
+            iter.set_position(end);
+
+            return Ok(())
+        }
+
+        // ex. lw $t0, 4,($sp)
+        if let Some(location) = first_comma(iter.tokens_since(first)) {
+            return Err(comma_error(location, UnexpectedComma))
+        }
+
+        previous = iter.get_position();
+    }
+
+    iter.set_position(previous);
+    iter.seek_without(is_adjacent_kind);
+
+    let trailing = first_comma(iter.tokens_since(previous));
+
+    iter.set_position(end);
+
+    match trailing {
+        Some(location) => Err(comma_error(location, UnexpectedComma)),
+        None => Ok(()),
+    }
+}
+
 pub fn do_instruction(
     instruction: &str,
     location: Location,
@@ -889,18 +1046,74 @@ pub fn do_instruction(
 ) -> Result<(), AssemblerError> {
     let lowercase = instruction.to_lowercase();
 
-    let emit = dispatch_instruction(&lowercase, iter, map)
+    let start = iter.get_position();
+
+    let emit = dispatch_instruction(&lowercase, iter, map, builder.temporary)
+        .map_err(|error| {
+            if !is_end_of_statement(&error) {
+                return error
+            }
+
+            match missing_operand(&lowercase, start, iter, map) {
+                Some(reason) => AssemblerError { location: Some(location), reason },
+                None => error,
+            }
+        })
         .map_err(default_start(location))?;
 
+    if builder.strict_commas {
+        check_commas(&lowercase, start, iter, map)?
+    }
+
+    if emit.temporary && !builder.temporary_available() {
+        return Err(AssemblerError {
+            location: Some(location),
+            reason: TemporaryUnavailable(lowercase),
+        })
+    }
+
+    let explicit_at = iter.tokens_since(start)
+        .iter()
+        .any(|token| token.kind == Register(builder.temporary));
+
+    // A register picked in place of $at is reserved for the assembler, so expanding over an operand is an error.
+    // $at itself keeps the warning below, like MARS.
+    if explicit_at && emit.temporary && builder.temporary != AssemblerTemporary {
+        return Err(AssemblerError {
+            location: Some(location),
+            reason: TemporaryClobbered(lowercase, builder.temporary),
+        })
+    }
+
+    if explicit_at && builder.temporary_available() {
+        builder.warnings.push(AssemblerWarning {
+            location,
+            reason: AssemblerWarningReason::TemporaryUsed(builder.temporary),
+        })
+    }
+
+    for reason in emit.warnings {
+        builder.warnings.push(AssemblerWarning { location, reason })
+    }
+
+    let at = builder.temporary_available();
+
     let region = builder.region().ok_or(AssemblerError {
         location: Some(location),
         reason: MissingRegion,
     })?;
 
-    let mut breakpoint = BinaryBreakpoint { location, pcs: vec![] };
+    let mut breakpoint = BinaryBreakpoint {
+        location,
+        pcs: vec![],
+        pseudo: !map.contains_key(lowercase.as_str()),
+        mnemonic: lowercase,
+    };
 
     for (word, branch) in emit.instructions {
-        let pc = pc_for_region(&region.raw, Some(location))?;
+        check_region_edge(region, 4, Some(location))?;
+
+        let pc = pc_for_region(region, Some(location))?;
 
         breakpoint.pcs.push(pc);
 
@@ -911,6 +1124,7 @@ pub fn do_instruction(
                 offset,
                 location,
                 label,
+                temporary: at,
             });
         }
 
@@ -924,3 +1138,254 @@ pub fn do_instruction(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_util::AssemblerReason;
+    use crate::assembler::assembler_util::AssemblerReason::{
+        ExpectedRegister, MissingComma, MissingOperand, UnexpectedComma,
+    };
+    use crate::assembler::core::AssembleOptions;
+    use crate::assembler::cursor::LexerCursor;
+    use crate::assembler::emit::dispatch_pseudo;
+    use crate::assembler::instructions::{OperandKind, INSTRUCTIONS, PSEUDO_INSTRUCTIONS};
+    use crate::assembler::registers::RegisterSlot::AssemblerTemporary;
+    use crate::assembler::string::{assemble_from, assemble_from_with_options, SourceError};
+
+    fn dispatches(name: &str) -> bool {
+        !matches!(dispatch_pseudo(name, &mut LexerCursor::new(&[]), AssemblerTemporary), Ok(None))
+    }
+
+    // PSEUDO_INSTRUCTIONS is what the CLI and is_instruction_name know about, it has to match the arms exactly.
+    #[test]
+    fn pseudo_instructions_match_dispatch() {
+        let source = include_str!("emit.rs");
+        let start = source.find("fn dispatch_pseudo(").unwrap();
+        let end = start + source[start..].find("_ => return Ok(None)").unwrap();
+
+        let mut arms: Vec<&str> = source[start..end].lines()
+            .filter_map(|line| line.trim().strip_prefix('"'))
+            .filter_map(|line| line.split('"').next())
+            .collect();
+
+        let mut names: Vec<&str> = PSEUDO_INSTRUCTIONS.iter().map(|(name, _)| *name).collect();
+
+        arms.sort();
+        names.sort();
+
+        assert_eq!(arms, names);
+
+        for name in names {
+            assert!(dispatches(name), "{name} is listed but not dispatched")
+        }
+
+        for instruction in INSTRUCTIONS.iter() {
+            assert!(!dispatches(instruction.name), "{} is a real instruction", instruction.name)
+        }
+
+        for name in ["mov", "lii", "add.s", ""] {
+            assert!(!dispatches(name), "{name:?} dispatched")
+        }
+    }
+
+    fn strict(line: &str) -> Result<(), (bool, usize)> {
+        let options = AssembleOptions { strict_commas: true, ..Default::default() };
+
+        match assemble_from_with_options(&format!("label: {line}\n"), options) {
+            Ok(_) => Ok(()),
+            Err(SourceError::Assembler(error)) => {
+                let missing = match error.reason {
+                    MissingComma => true,
+                    UnexpectedComma => false,
+                    _ => panic!("{line:?}: {error}"),
+                };
+
+                // Without "label: ", where the error points in line.
+                Err((missing, error.location.unwrap().index - 7))
+            }
+            Err(error) => panic!("{line:?}: {error}"),
+        }
+    }
+
+    #[test]
+    fn strict_commas_accept_one_comma_between_operands() {
+        let lines = [
+            "add $t0, $t1, $t2",
+            "addi $t0, $t1, -4",
+            "lw $t0, 4($sp)",
+            "sw $t0, -8 ( $sp )",
+            "lw $t0, label",
+            "lw $t0, label + 4($t1)",
+            "mult $t0, $t1",
+            "jr $ra",
+            "syscall",
+            "li $t0, 5 # a comment, with a comma",
+            "blt $t0, $t1, label",
+        ];
+
+        for line in lines {
+            assert_eq!(strict(line), Ok(()), "{line:?}")
+        }
+    }
+
+    #[test]
+    fn strict_commas_reject_malformed_separators() {
+        // (a missing comma, where) with where the index in the line, tokens start right after the one before.
+        let lines = [
+            ("add $t0 $t1, $t2", (true, 7)),
+            ("add $t0, $t1 $t2", (true, 12)),
+            ("add $t0, $t1,, $t2", (false, 13)),
+            ("add $t0, , $t1, $t2", (false, 8)),
+            ("add , $t0, $t1, $t2", (false, 3)),
+            ("add $t0, $t1, $t2,", (false, 17)),
+            ("lw $t0 4($sp)", (true, 6)),
+            ("lw $t0, 4,($sp)", (false, 9)),
+            ("lw $t0, 4($sp),", (false, 14)),
+            ("mult $t0 $t1", (true, 8)),
+            ("jr, $ra", (false, 2)),
+        ];
+
+        for (line, expected) in lines {
+            assert_eq!(strict(line), Err(expected), "{line:?}");
+
+            // The default stays lenient.
+            assert!(assemble_from(&format!("label: {line}\n")).is_ok(), "{line:?}")
+        }
+    }
+
+    fn text_words(source: &str) -> Vec<u32> {
+        let binary = assemble_from(source).unwrap();
+
+        binary.regions[0].data.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn label_offsets_are_constant_expressions() {
+        let words = text_words("
+            .eqv INDEX 3
+            .text
+            la $t0, table + 4 * 5
+            la $t1, table + 4*INDEX
+            lw $t2, table + (INDEX - 1) * 4
+            .data
+            table: .word 0:8
+        ");
+
+        assert_eq!(words, [
+            0x3c081001, 0x35080014, // table + 20
+            0x3c091001, 0x3529000c, // table + 12
+            0x3c011001, 0x34210008, 0x8c2a0000, // table + 8, through $at
+        ]);
+    }
+
+    #[test]
+    fn negative_label_offsets() {
+        let words = text_words("
+            la $t0, table - 8
+            la $t1, table - 0x20000000
+            .data
+            table: .word 0
+        ");
+
+        // The second one wraps below address 0 instead of panicking.
+        assert_eq!(words, [0x3c081000, 0x3508fff8, 0x3c09f001, 0x35290000]);
+
+        let binary = assemble_from(".data\n.word 1\ntable: .word table - 4, table + 2 * 2\n").unwrap();
+
+        assert_eq!(binary.regions[0].data[4..], [0x00, 0x00, 0x01, 0x10, 0x08, 0x00, 0x01, 0x10]);
+    }
+
+    fn missing(source: &str) -> (AssemblerReason, usize) {
+        match assemble_from(source) {
+            Err(SourceError::Assembler(error)) => (error.reason, error.location.unwrap().index),
+            Err(error) => panic!("{source:?}: {error}"),
+            Ok(_) => panic!("{source:?} assembled"),
+        }
+    }
+
+    // (instruction, expected, position, index) for a missing operand error.
+    fn missing_operand(source: &str) -> (String, OperandKind, usize, usize) {
+        match missing(source) {
+            (MissingOperand { instruction, expected, position }, index) => (instruction, expected, position, index),
+            (reason, _) => panic!("{source:?}: {reason}"),
+        }
+    }
+
+    #[test]
+    fn missing_operands_are_reported_on_their_line() {
+        // The instruction on the next line is never read as an operand, the error points at "add" at index 4.
+        let (reason, index) = missing("nop\nadd $t0, $t1\nadd $t2, $t3, $t4\n");
+
+        assert_eq!(index, 4);
+        assert_eq!(
+            reason.to_string(),
+            "Instruction \"add\" is missing its 3rd operand, expected a register or a constant"
+        );
+
+        let cases = [
+            ("add $t0, $t1", "add", OperandKind::Value, 3),
+            ("add $t0,\n$t1, $t2\n", "add", OperandKind::Register, 2),
+            ("addi $t0, $t1\nnop\n", "addi", OperandKind::Immediate, 3),
+            ("lw $t0\nnop\n", "lw", OperandKind::Offset, 2),
+            ("li $t0\nli $t1, 2\n", "li", OperandKind::Constant, 2),
+            ("beqz $t0\nnop\n", "beqz", OperandKind::Label, 2),
+        ];
+
+        for (source, instruction, expected, position) in cases {
+            assert_eq!(missing_operand(source), (instruction.to_string(), expected, position, 0), "{source:?}")
+        }
+    }
+
+    #[test]
+    fn wrong_operands_are_not_missing() {
+        let (reason, index) = missing("add $t0, 5, $t1\n");
+
+        assert!(matches!(reason, ExpectedRegister(_)), "{reason}");
+        assert_eq!(index, 8);
+    }
+
+    #[test]
+    fn comments_never_change_operands() {
+        // Offset, label + offset, value and macro argument forms.
+        let statements = [
+            "lw $t0, 8($sp)",
+            "sw $t0, -4 ( $sp )",
+            "lw $t0, table",
+            "lw $t0, table + 4",
+            "lw $t0, table + 4($t1)",
+            "la $t0, table + 2 * 4",
+            "add $t0, $t1, 5",
+            "add $t0, $t1, $t2",
+            "li $t0, 0x12345",
+            "beq $t0, $t1, target + 4",
+            "add_to($t0, 4)",
+            "add_to($t0, -4)",
+        ];
+
+        // Each can look like the start of another operand.
+        let comments = ["#", "# load x", "#($sp)", "# , $t1", "#)", "# + 4", "# 8($sp)"];
+
+        let program = |statement: &str| format!("
+            .macro add_to(%register, %value)
+            addi %register, %register, %value
+            .end_macro
+            {statement}
+            target: nop
+            nop
+            .data
+            table: .word 1, 2, 3, 4
+        ");
+
+        for statement in statements {
+            let plain = text_words(&program(statement));
+
+            for comment in comments {
+                for space in ["", " ", "\t"] {
+                    let commented = format!("{statement}{space}{comment}");
+
+                    assert_eq!(text_words(&program(&commented)), plain, "{commented:?}")
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use crate::assembler::binary::{Binary, RegionFlags};
+use crate::assembler::encode::EncodeError::{
+    BranchOutOfRange, CodeOutOfRange, JumpOutOfRange, MisalignedTarget, ShiftOutOfRange, UnknownInstruction,
+};
+use crate::assembler::encode::PatchError::{Encode, Memory, Misaligned, NotExecutable, Relocated, Unmapped};
+use crate::assembler::instructions::Opcode::{Algebra, Func, Op, Special};
+use crate::assembler::instructions::{Opcode, INSTRUCTIONS};
+use crate::assembler::registers::RegisterSlot;
+use crate::unit::instruction::Instruction;
+use crate::unit::register::RegisterName;
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+fn instruction_base(op: &Opcode) -> u32 {
+    match op {
+        Op(key) => (*key as u32 & 0b111111) << 26,
+        Func(key) => *key as u32 & 0b111111, // opcode: 0
+        Special(key) => (*key as u32 & 0b111111) << 16 | (1 << 26), // opcode: 1
+        Algebra(key) => *key as u32 & 0b111111 | (28 << 26),
+    }
+}
+
+fn register_source(slot: RegisterSlot) -> u32 {
+    slot.to_u32().unwrap()
+}
+
+pub struct InstructionBuilder(pub u32);
+
+impl InstructionBuilder {
+    pub fn from_op(op: &Opcode) -> InstructionBuilder {
+        InstructionBuilder(instruction_base(op))
+    }
+
+    fn with_slot_offset<const OFFSET: u32>(mut self, slot: RegisterSlot) -> InstructionBuilder {
+        self.0 &= !(0b11111 << OFFSET);
+        self.0 |= register_source(slot) << OFFSET;
+
+        self
+    }
+
+    pub fn with_dest(self, slot: RegisterSlot) -> InstructionBuilder {
+        self.with_slot_offset::<11>(slot)
+    }
+
+    pub fn with_temp(self, slot: RegisterSlot) -> InstructionBuilder {
+        self.with_slot_offset::<16>(slot)
+    }
+
+    pub fn with_source(self, slot: RegisterSlot) -> InstructionBuilder {
+        self.with_slot_offset::<21>(slot)
+    }
+
+    pub fn with_immediate(mut self, imm: u16) -> InstructionBuilder {
+        self.0 &= 0xFFFF0000;
+        self.0 |= imm as u32;
+
+        self
+    }
+
+    pub fn with_sham(mut self, sham: u8) -> InstructionBuilder {
+        self.0 &= !(0b11111 << 6);
+        self.0 |= (sham as u32) << 6;
+
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    BranchOutOfRange(u32, u32), // target, pc
+    JumpOutOfRange(u32, u32), // target, pc
+    MisalignedTarget(u32),
+    ShiftOutOfRange(u8),
+    CodeOutOfRange(u32), // break code, 20 bits
+    UnknownInstruction(&'static str), // no INSTRUCTIONS entry has this name
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BranchOutOfRange(target, pc) => write!(f, "Branch at 0x{pc:08x} can't reach 0x{target:08x} (more than 32 KiB instructions away)"),
+            JumpOutOfRange(target, pc) => write!(f, "Jump at 0x{pc:08x} can't reach 0x{target:08x} (outside its 256 MiB segment)"),
+            MisalignedTarget(target) => write!(f, "Target 0x{target:08x} is not a multiple of 4"),
+            ShiftOutOfRange(sham) => write!(f, "Shift amount {sham} is past 31"),
+            CodeOutOfRange(code) => write!(f, "Break code 0x{code:x} doesn't fit in 20 bits"),
+            UnknownInstruction(name) => write!(f, "No opcode is known for instruction \"{name}\""),
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+fn slot(name: RegisterName) -> RegisterSlot {
+    RegisterSlot::from_u8(name.to_u8().unwrap()).unwrap()
+}
+
+fn branch_offset(pc: u32, target: u32) -> Result<u16, EncodeError> {
+    if target % 4 != 0 {
+        return Err(MisalignedTarget(target))
+    }
+
+    let offset = (target.wrapping_sub(pc.wrapping_add(4)) as i32) >> 2;
+
+    i16::try_from(offset)
+        .map(|offset| offset as u16)
+        .map_err(|_| BranchOutOfRange(target, pc))
+}
+
+fn jump_index(pc: u32, target: u32) -> Result<u32, EncodeError> {
+    if target % 4 != 0 {
+        return Err(MisalignedTarget(target))
+    }
+
+    // j/jal keep the top 4 bits of the delay slot's address.
+    if target & 0xF0000000 != pc.wrapping_add(4) & 0xF0000000 {
+        return Err(JumpOutOfRange(target, pc))
+    }
+
+    Ok((target >> 2) & 0x03FFFFFF)
+}
+
+// The inverse of InstructionDecoder::decode, pc is where the word will sit (branch and jump targets are absolute).
+// decode(pc, encode(pc, instruction)?) gives back the same instruction.
+pub fn encode(pc: u32, instruction: &Instruction) -> Result<u32, EncodeError> {
+    let name = instruction.name();
+    let op = &INSTRUCTIONS.iter()
+        .find(|entry| entry.name == name)
+        .ok_or(UnknownInstruction(name))?
+        .opcode;
+
+    let builder = InstructionBuilder::from_op(op);
+
+    let word = match instruction {
+        Instruction::Add { s, t, d } | Instruction::Addu { s, t, d } | Instruction::And { s, t, d }
+        | Instruction::Nor { s, t, d } | Instruction::Or { s, t, d } | Instruction::Sllv { s, t, d }
+        | Instruction::Srav { s, t, d } | Instruction::Srlv { s, t, d } | Instruction::Sub { s, t, d }
+        | Instruction::Subu { s, t, d } | Instruction::Xor { s, t, d } | Instruction::Slt { s, t, d }
+        | Instruction::Sltu { s, t, d } | Instruction::Mul { s, t, d } => builder
+            .with_source(slot(*s))
+            .with_temp(slot(*t))
+            .with_dest(slot(*d)).0,
+        Instruction::Div { s, t } | Instruction::Divu { s, t } | Instruction::Mult { s, t }
+        | Instruction::Multu { s, t } | Instruction::Madd { s, t } | Instruction::Maddu { s, t }
+        | Instruction::Msub { s, t } | Instruction::Msubu { s, t } => builder
+            .with_source(slot(*s))
+            .with_temp(slot(*t)).0,
+        Instruction::Sll { t, d, sham } | Instruction::Sra { t, d, sham } | Instruction::Srl { t, d, sham } => {
+            if *sham > 31 {
+                return Err(ShiftOutOfRange(*sham))
+            }
+
+            builder
+                .with_temp(slot(*t))
+                .with_dest(slot(*d))
+                .with_sham(*sham).0
+        }
+        Instruction::Jr { s } | Instruction::Jalr { s } | Instruction::Mthi { s } | Instruction::Mtlo { s } => builder
+            .with_source(slot(*s)).0,
+        Instruction::Mfhi { d } | Instruction::Mflo { d } => builder
+            .with_dest(slot(*d)).0,
+        Instruction::Addi { s, t, imm } | Instruction::Addiu { s, t, imm } | Instruction::Andi { s, t, imm }
+        | Instruction::Ori { s, t, imm } | Instruction::Xori { s, t, imm } | Instruction::Slti { s, t, imm }
+        | Instruction::Sltiu { s, t, imm } | Instruction::Lb { s, t, imm } | Instruction::Lbu { s, t, imm }
+        | Instruction::Lh { s, t, imm } | Instruction::Lhu { s, t, imm } | Instruction::Lw { s, t, imm }
+        | Instruction::Sb { s, t, imm } | Instruction::Sh { s, t, imm } | Instruction::Sw { s, t, imm }
+        | Instruction::Ll { s, t, imm } | Instruction::Sc { s, t, imm } => builder
+            .with_source(slot(*s))
+            .with_temp(slot(*t))
+            .with_immediate(*imm).0,
+        // The decoder names lui's register s, but it's in the rt field like lhi and llo.
+        Instruction::Lui { s: t, imm } | Instruction::Lhi { t, imm } | Instruction::Llo { t, imm } => builder
+            .with_temp(slot(*t))
+            .with_immediate(*imm).0,
+        Instruction::Beq { s, t, address } | Instruction::Bne { s, t, address } => builder
+            .with_source(slot(*s))
+            .with_temp(slot(*t))
+            .with_immediate(branch_offset(pc, *address)?).0,
+        Instruction::Bgtz { s, address } | Instruction::Blez { s, address } | Instruction::Bltz { s, address }
+        | Instruction::Bgez { s, address } | Instruction::Bltzal { s, address }
+        | Instruction::Bgezal { s, address } => builder
+            .with_source(slot(*s))
+            .with_immediate(branch_offset(pc, *address)?).0,
+        Instruction::J { address } | Instruction::Jal { address } => builder.0 | jump_index(pc, *address)?,
+        Instruction::Trap | Instruction::Syscall | Instruction::Sync => builder.0,
+        Instruction::Break { code } => {
+            if *code >= 1 << 20 {
+                return Err(CodeOutOfRange(*code))
+            }
+
+            builder.0 | code << 6
+        }
+    };
+
+    Ok(word)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PatchError {
+    Misaligned(u32),
+    Unmapped(u32), // no region holds the whole word
+    NotExecutable(u32), // the word is in a data region
+    Relocated(u32), // linking would overwrite the word
+    Encode(EncodeError),
+    Memory(crate::cpu::error::Error), // writing to a running device
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Misaligned(address) => write!(f, "Address 0x{address:08x} is not a multiple of 4"),
+            Unmapped(address) => write!(f, "No region holds an instruction at 0x{address:08x}"),
+            NotExecutable(address) => write!(f, "Address 0x{address:08x} is in a data region, not text"),
+            Relocated(address) => write!(f, "Instruction at 0x{address:08x} has a relocation, linking would overwrite it"),
+            Encode(error) => write!(f, "{error}"),
+            Memory(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+impl From<EncodeError> for PatchError {
+    fn from(value: EncodeError) -> Self {
+        Encode(value)
+    }
+}
+
+impl Binary {
+    // Replaces the word at address in place, ex. to flip a beq to a bne for mutation testing.
+    // Sizes don't change, so breakpoints and labels stay where they were.
+    pub fn patch_instruction(&mut self, address: u32, new: Instruction) -> Result<(), PatchError> {
+        if address % 4 != 0 {
+            return Err(Misaligned(address))
+        }
+
+        let word = encode(address, &new)?;
+
+        let (index, region) = self.regions.iter_mut()
+            .enumerate()
+            .find(|(_, region)| region.contains(address) && region.contains(address + 3))
+            .ok_or(Unmapped(address))?;
+
+        if !region.flags.contains(RegionFlags::EXECUTABLE) {
+            return Err(NotExecutable(address))
+        }
+
+        let offset = (address - region.address) as usize;
+
+        if self.relocations.iter().any(|relocation| relocation.region == index && relocation.offset == offset) {
+            return Err(Relocated(address))
+        }
+
+        region.data[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::encode::encode;
+    use crate::assembler::encode::EncodeError::{
+        BranchOutOfRange, CodeOutOfRange, JumpOutOfRange, MisalignedTarget, ShiftOutOfRange,
+    };
+    use crate::assembler::instructions::INSTRUCTIONS;
+    use crate::unit::instruction::{Instruction, InstructionDecoder};
+    use crate::unit::register::RegisterName;
+    use std::collections::HashSet;
+
+    // One of every variant, with fields that don't repeat so a swapped field shows up.
+    fn samples(pc: u32) -> Vec<Instruction> {
+        let (s, t, d) = (RegisterName::from(4), RegisterName::from(17), RegisterName::from(31));
+        let (imm, sham, code) = (0x8421, 31, 0xFFFFF);
+        let (branch, jump) = (pc.wrapping_add(0x104), (pc.wrapping_add(4) & 0xF0000000) | 0x0ABCDEF0);
+
+        vec![
+            Instruction::Add { s, t, d }, Instruction::Addu { s, t, d }, Instruction::And { s, t, d },
+            Instruction::Div { s, t }, Instruction::Divu { s, t }, Instruction::Mult { s, t },
+            Instruction::Multu { s, t }, Instruction::Nor { s, t, d }, Instruction::Or { s, t, d },
+            Instruction::Sll { t, d, sham }, Instruction::Sllv { s, t, d }, Instruction::Sra { t, d, sham },
+            Instruction::Srav { s, t, d }, Instruction::Srl { t, d, sham }, Instruction::Srlv { s, t, d },
+            Instruction::Sub { s, t, d }, Instruction::Subu { s, t, d }, Instruction::Xor { s, t, d },
+            Instruction::Slt { s, t, d }, Instruction::Sltu { s, t, d }, Instruction::Jr { s },
+            Instruction::Jalr { s }, Instruction::Madd { s, t }, Instruction::Maddu { s, t },
+            Instruction::Mul { s, t, d }, Instruction::Msub { s, t }, Instruction::Msubu { s, t },
+            Instruction::Addi { s, t, imm }, Instruction::Addiu { s, t, imm }, Instruction::Andi { s, t, imm },
+            Instruction::Ori { s, t, imm }, Instruction::Xori { s, t, imm }, Instruction::Lui { s, imm },
+            Instruction::Lhi { t, imm }, Instruction::Llo { t, imm }, Instruction::Slti { s, t, imm },
+            Instruction::Sltiu { s, t, imm }, Instruction::Beq { s, t, address: branch },
+            Instruction::Bne { s, t, address: branch }, Instruction::Bgtz { s, address: branch },
+            Instruction::Blez { s, address: branch }, Instruction::Bltz { s, address: branch },
+            Instruction::Bgez { s, address: branch }, Instruction::Bltzal { s, address: branch },
+            Instruction::Bgezal { s, address: branch }, Instruction::J { address: jump },
+            Instruction::Jal { address: jump }, Instruction::Lb { s, t, imm }, Instruction::Lbu { s, t, imm },
+            Instruction::Lh { s, t, imm }, Instruction::Lhu { s, t, imm }, Instruction::Lw { s, t, imm },
+            Instruction::Sb { s, t, imm }, Instruction::Sh { s, t, imm }, Instruction::Sw { s, t, imm },
+            Instruction::Ll { s, t, imm }, Instruction::Sc { s, t, imm }, Instruction::Mfhi { d },
+            Instruction::Mflo { d }, Instruction::Mthi { s }, Instruction::Mtlo { s }, Instruction::Trap,
+            Instruction::Syscall, Instruction::Break { code }, Instruction::Sync,
+        ]
+    }
+
+    fn round_trip(pc: u32, instruction: &Instruction) {
+        let word = encode(pc, instruction).unwrap_or_else(|error| panic!("{instruction:?} at 0x{pc:08x}: {error}"));
+
+        assert_eq!(InstructionDecoder::decode(pc, word).as_ref(), Some(instruction), "0x{word:08x} at 0x{pc:08x}");
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        let names: HashSet<&str> = samples(0).iter().map(|instruction| instruction.name()).collect();
+
+        for entry in INSTRUCTIONS.iter() {
+            assert!(names.contains(entry.name), "no sample for {}", entry.name);
+        }
+
+        // Both ends of memory, and pcs whose delay slot starts a new 256 MiB segment.
+        for pc in [0u32, 0x00400000, 0x0C000000, 0x0FFFFFF8, 0x0FFFFFFC, 0x80000180, 0xFFFFFFF8, 0xFFFFFFFC] {
+            for instruction in samples(pc) {
+                round_trip(pc, &instruction)
+            }
+        }
+    }
+
+    #[test]
+    fn targets_at_the_edges_round_trip() {
+        let s = RegisterName::from(8);
+
+        for pc in [0u32, 0x00400000, 0x7FFFFFFC, 0xFFFFFFFC] {
+            let slot = pc.wrapping_add(4);
+
+            // Farthest each way, wrapping around the ends of memory.
+            for address in [slot.wrapping_add(0x1FFFC), slot.wrapping_sub(0x20000), slot] {
+                round_trip(pc, &Instruction::Bgez { s, address });
+            }
+
+            for address in [slot.wrapping_add(0x20000), slot.wrapping_sub(0x20004)] {
+                assert_eq!(encode(pc, &Instruction::Bgez { s, address }), Err(BranchOutOfRange(address, pc)));
+            }
+
+            assert_eq!(encode(pc, &Instruction::Beq { s, t: s, address: slot + 2 }), Err(MisalignedTarget(slot + 2)));
+
+            let segment = slot & 0xF0000000;
+
+            for address in [segment, segment | 0x0FFFFFFC] {
+                round_trip(pc, &Instruction::J { address });
+            }
+
+            let outside = segment.wrapping_add(0x10000000);
+
+            assert_eq!(encode(pc, &Instruction::Jal { address: outside }), Err(JumpOutOfRange(outside, pc)));
+        }
+
+        // The delay slot of the last word in a segment is the first word of the next one.
+        round_trip(0x0FFFFFFC, &Instruction::J { address: 0x10000000 });
+        assert_eq!(encode(0x0FFFFFFC, &Instruction::J { address: 0x0FFFFFF8 }), Err(JumpOutOfRange(0x0FFFFFF8, 0x0FFFFFFC)));
+    }
+
+    #[test]
+    fn fields_out_of_range_are_rejected() {
+        let (t, d) = (RegisterName::from(1), RegisterName::from(2));
+
+        assert_eq!(encode(0, &Instruction::Sll { t, d, sham: 32 }), Err(ShiftOutOfRange(32)));
+        assert_eq!(encode(0, &Instruction::Break { code: 1 << 20 }), Err(CodeOutOfRange(1 << 20)));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::assembler::binary::{Binary, RawRegion, RegionFlags};
+use crate::assembler::flat::FlatError::{BelowBase, FillTooLarge, Io, UnalignedBase};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+// Flat images are for loading into simulated hardware (ex. a Verilog testbench), so anything past this is a mistake.
+pub const DEFAULT_FILL_LIMIT: u32 = 16 * 1024 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlatFormat {
+    Bin, // raw bytes from base, zero filled between regions
+    Hex, // the same image, one little endian word per line as 8 hex digits
+    Memh, // $readmemh: @index (in words from base) before each region, then its words
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FlatOptions {
+    pub base: Option<u32>, // address of the first byte, the lowest region's address if None
+    pub fill_limit: u32, // bytes, for the whole image
+    pub include_data: bool, // data regions too, not only text
+}
+
+impl Default for FlatOptions {
+    fn default() -> Self {
+        FlatOptions {
+            base: None,
+            fill_limit: DEFAULT_FILL_LIMIT,
+            include_data: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FlatError {
+    BelowBase(u32), // a region's address
+    FillTooLarge(u64), // bytes the image would need
+    UnalignedBase(u32), // memh indices count words, so the base has to be on a word boundary
+    Io(std::io::Error),
+}
+
+impl Display for FlatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BelowBase(address) => write!(f, "Region at 0x{address:08x} starts before the image base address"),
+            FillTooLarge(size) => write!(f, "Flat image would be {size} bytes, past the fill limit (regions too far apart?)"),
+            UnalignedBase(base) => write!(f, "Base address 0x{base:08x} is not word aligned, memh images count in words"),
+            Io(error) => write!(f, "Failed to write image: {error}"),
+        }
+    }
+}
+
+impl Error for FlatError {}
+
+impl From<std::io::Error> for FlatError {
+    fn from(value: std::io::Error) -> Self {
+        Io(value)
+    }
+}
+
+fn write_words<W: Write>(output: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    for chunk in bytes.chunks(4) {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+
+        writeln!(output, "{:08x}", u32::from_le_bytes(word))?;
+    }
+
+    Ok(())
+}
+
+impl Binary {
+    fn flat_regions(&self, options: &FlatOptions) -> Vec<&RawRegion> {
+        let mut regions: Vec<&RawRegion> = self.regions.iter()
+            .filter(|region| !region.data.is_empty())
+            .filter(|region| options.include_data || region.flags.contains(RegionFlags::EXECUTABLE))
+            .collect();
+
+        regions.sort_by_key(|region| region.address);
+
+        regions
+    }
+
+    // Every selected region, at its offset from base, later regions win where they overlap.
+    pub fn flat_image(&self, options: &FlatOptions) -> Result<Vec<u8>, FlatError> {
+        let regions = self.flat_regions(options);
+
+        let Some(lowest) = regions.first() else { return Ok(vec![]) };
+        let base = options.base.unwrap_or(lowest.address);
+
+        let mut size = 0u64;
+
+        for region in &regions {
+            if region.address < base {
+                return Err(BelowBase(region.address))
+            }
+
+            size = size.max((region.address - base) as u64 + region.data.len() as u64);
+        }
+
+        if size > options.fill_limit as u64 {
+            return Err(FillTooLarge(size))
+        }
+
+        let mut image = vec![0; size as usize];
+
+        for region in regions {
+            let start = (region.address - base) as usize;
+
+            image[start..start + region.data.len()].copy_from_slice(&region.data);
+        }
+
+        Ok(image)
+    }
+
+    pub fn write_flat<W: Write>(&self, output: &mut W, format: FlatFormat, options: &FlatOptions) -> Result<(), FlatError> {
+        match format {
+            FlatFormat::Bin => output.write_all(&self.flat_image(options)?)?,
+            FlatFormat::Hex => write_words(output, &self.flat_image(options)?)?,
+            FlatFormat::Memh => {
+                let regions = self.flat_regions(options);
+
+                if let Some(base) = options.base.filter(|base| base % 4 != 0) {
+                    return Err(UnalignedBase(base))
+                }
+
+                let Some(lowest) = regions.first() else { return Ok(()) };
+                let base = options.base.unwrap_or(lowest.address & !3);
+
+                for region in regions {
+                    if region.address < base {
+                        return Err(BelowBase(region.address))
+                    }
+
+                    // A region off a word boundary starts with zeros up to its first byte.
+                    let offset = region.address - base;
+                    let padding = (offset % 4) as usize;
+
+                    let mut bytes = vec![0; padding];
+                    bytes.extend_from_slice(&region.data);
+
+                    writeln!(output, "@{:x}", offset / 4)?;
+                    write_words(output, &bytes)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::binary::Binary;
+    use crate::assembler::flat::{FlatError, FlatFormat, FlatOptions};
+    use crate::assembler::string::assemble_from;
+
+    // Text at 0x400000, data at 0x400010 (so the image stays small), with a byte off a word boundary.
+    fn binary() -> Binary {
+        assemble_from(".text\nli $t0, 1\nli $t1, 2\n.data 0x400012\n.byte 0xAA, 0xBB\n").unwrap()
+    }
+
+    fn written(format: FlatFormat, options: &FlatOptions) -> Result<Vec<u8>, FlatError> {
+        let mut output = vec![];
+
+        binary().write_flat(&mut output, format, options).map(|_| output)
+    }
+
+    fn text(format: FlatFormat, options: &FlatOptions) -> String {
+        String::from_utf8(written(format, options).unwrap()).unwrap()
+    }
+
+    const ALL: FlatOptions = FlatOptions { base: None, fill_limit: 0x1000, include_data: true };
+
+    #[test]
+    fn bin_images() {
+        assert_eq!(written(FlatFormat::Bin, &FlatOptions::default()).unwrap(), [
+            0x01, 0x00, 0x08, 0x24, 0x02, 0x00, 0x09, 0x24,
+        ]);
+
+        assert_eq!(written(FlatFormat::Bin, &ALL).unwrap(), [
+            0x01, 0x00, 0x08, 0x24, 0x02, 0x00, 0x09, 0x24,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0xAA, 0xBB,
+        ]);
+    }
+
+    #[test]
+    fn hex_images() {
+        assert_eq!(text(FlatFormat::Hex, &ALL), "24080001\n24090002\n00000000\n00000000\nbbaa0000\n");
+
+        let options = FlatOptions { base: Some(0x3FFFFC), ..ALL };
+
+        assert_eq!(text(FlatFormat::Hex, &options), "00000000\n24080001\n24090002\n00000000\n00000000\nbbaa0000\n");
+    }
+
+    #[test]
+    fn memh_images() {
+        assert_eq!(text(FlatFormat::Memh, &ALL), "@0\n24080001\n24090002\n@4\nbbaa0000\n");
+
+        let options = FlatOptions { base: Some(0x3FFFF0), ..ALL };
+
+        assert_eq!(text(FlatFormat::Memh, &options), "@4\n24080001\n24090002\n@8\nbbaa0000\n");
+    }
+
+    #[test]
+    fn bad_bases_and_sizes() {
+        let unaligned = FlatOptions { base: Some(0x3FFFFE), ..ALL };
+
+        assert!(matches!(written(FlatFormat::Memh, &unaligned), Err(FlatError::UnalignedBase(0x3FFFFE))));
+        assert_eq!(written(FlatFormat::Bin, &unaligned).unwrap().len(), 22, "bin images can start anywhere");
+
+        let above = FlatOptions { base: Some(0x400004), ..ALL };
+
+        for format in [FlatFormat::Bin, FlatFormat::Hex, FlatFormat::Memh] {
+            assert!(matches!(written(format, &above), Err(FlatError::BelowBase(0x400000))), "{format:?}");
+        }
+
+        let small = FlatOptions { fill_limit: 16, ..ALL };
+
+        assert!(matches!(written(FlatFormat::Bin, &small), Err(FlatError::FillTooLarge(20))));
+    }
+}
@@ -1,9 +1,12 @@
 use crate::assembler::instructions::Encoding::{
-    Branch, BranchZero, Destination, Immediate, Inputs, Jump, LoadImmediate, Offset, Parameterless,
-    Register, RegisterShift, Sham, Source, SpecialBranch,
+    Branch, BranchZero, Code, Destination, Immediate, Inputs, Jump, LoadImmediate, Offset, Parameterless,
+    Register, RegisterShift, Sham, Source, SpecialBranch, UnsignedImmediate,
 };
 use crate::assembler::instructions::Opcode::{Algebra, Func, Op, Special};
+use crate::assembler::instructions::OperandKind as Kind;
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
 
 pub enum Encoding {
     Register,                  // $, $, $, opcode: 0
@@ -13,13 +16,74 @@ pub enum Encoding {
     Inputs,                    // $, $, opcode: 0
     Sham,                      // $, $, sham, opcode: 0
     SpecialBranch,             // opcode: 1
-    Immediate(Option<Opcode>), // $, $, I
+    Immediate(Option<Opcode>), // $, $, I (sign-extended, -0x8000 to 0x7FFF)
+    UnsignedImmediate(Option<Opcode>), // $, $, I (zero-extended, 0 to 0xFFFF)
     LoadImmediate,
     Jump,   // I or Label
     Branch, // I or Label
     BranchZero,
     Parameterless,
     Offset,
+    Code, // optional 20 bit code, opcode: 0
+}
+
+// What an operand may be written as, for editors (completion, signature help).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    Register,
+    Value, // a register or a constant
+    Immediate, // sign-extended, -0x8000 to 0x7FFF
+    UnsignedImmediate, // zero-extended, 0 to 0xFFFF
+    Shift, // 0 to 31
+    Constant, // any 32 bit value
+    Label,
+    Offset, // offset($register), or a label
+    Code,
+}
+
+impl Display for OperandKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Kind::Register => "a register",
+            Kind::Value => "a register or a constant",
+            Kind::Immediate => "a constant from -0x8000 to 0x7fff",
+            Kind::UnsignedImmediate => "a constant from 0 to 0xffff",
+            Kind::Shift => "a shift amount from 0 to 31",
+            Kind::Constant => "a constant",
+            Kind::Label => "a label",
+            Kind::Offset => "an offset($register) or a label",
+            Kind::Code => "a code",
+        };
+
+        write!(f, "{text}")
+    }
+}
+
+impl Encoding {
+    // Operands in order, and how many at the end may be left out.
+    pub fn operands(&self) -> (&'static [OperandKind], usize) {
+        match self {
+            Register => (&[Kind::Register, Kind::Register, Kind::Value], 0),
+            RegisterShift => (&[Kind::Register, Kind::Register, Kind::Register], 0),
+            Source | Destination => (&[Kind::Register], 0),
+            Inputs => (&[Kind::Register, Kind::Register, Kind::Value], 1),
+            Sham => (&[Kind::Register, Kind::Register, Kind::Shift], 0),
+            SpecialBranch | BranchZero => (&[Kind::Register, Kind::Label], 0),
+            Immediate(_) => (&[Kind::Register, Kind::Register, Kind::Immediate], 0),
+            UnsignedImmediate(_) => (&[Kind::Register, Kind::Register, Kind::UnsignedImmediate], 0),
+            LoadImmediate => (&[Kind::Register, Kind::UnsignedImmediate], 0),
+            Jump => (&[Kind::Label], 0),
+            Branch => (&[Kind::Register, Kind::Value, Kind::Label], 0),
+            Parameterless => (&[], 0),
+            Offset => (&[Kind::Register, Kind::Offset], 0),
+            Code => (&[Kind::Code], 1),
+        }
+    }
+
+    // Immediates out of range are loaded into $at and the register form is used instead.
+    pub fn has_alternate(&self) -> bool {
+        matches!(self, Immediate(Some(_)) | UnsignedImmediate(Some(_)))
+    }
 }
 
 pub enum Opcode {
@@ -35,7 +99,7 @@ pub struct Instruction<'a> {
     pub encoding: Encoding,
 }
 
-pub const INSTRUCTIONS: [Instruction; 61] = [
+pub const INSTRUCTIONS: [Instruction; 65] = [
     Instruction {
         name: "sll",
         opcode: Func(0),
@@ -178,12 +242,12 @@ pub const INSTRUCTIONS: [Instruction; 61] = [
     },
     Instruction {
         name: "bltzal",
-        opcode: Special(6),
+        opcode: Special(16),
         encoding: SpecialBranch,
     },
     Instruction {
         name: "bgezal",
-        opcode: Special(7),
+        opcode: Special(17),
         encoding: SpecialBranch,
     },
     Instruction {
@@ -239,17 +303,17 @@ pub const INSTRUCTIONS: [Instruction; 61] = [
     Instruction {
         name: "andi",
         opcode: Op(12),
-        encoding: Immediate(Some(Func(36))),
+        encoding: UnsignedImmediate(Some(Func(36))),
     },
     Instruction {
         name: "ori",
         opcode: Op(13),
-        encoding: Immediate(Some(Func(37))),
+        encoding: UnsignedImmediate(Some(Func(37))),
     },
     Instruction {
         name: "xori",
         opcode: Op(14),
-        encoding: Immediate(Some(Func(38))),
+        encoding: UnsignedImmediate(Some(Func(38))),
     },
     Instruction {
         name: "lui",
@@ -276,6 +340,16 @@ pub const INSTRUCTIONS: [Instruction; 61] = [
         opcode: Func(12),
         encoding: Parameterless,
     },
+    Instruction {
+        name: "break",
+        opcode: Func(13),
+        encoding: Code,
+    },
+    Instruction {
+        name: "sync",
+        opcode: Func(15),
+        encoding: Parameterless,
+    },
     Instruction {
         name: "lb",
         opcode: Op(32),
@@ -316,6 +390,16 @@ pub const INSTRUCTIONS: [Instruction; 61] = [
         opcode: Op(43),
         encoding: Offset,
     },
+    Instruction {
+        name: "ll",
+        opcode: Op(48),
+        encoding: Offset,
+    },
+    Instruction {
+        name: "sc",
+        opcode: Op(56),
+        encoding: Offset,
+    },
     Instruction {
         name: "madd",
         opcode: Algebra(0),
@@ -343,6 +427,77 @@ pub const INSTRUCTIONS: [Instruction; 61] = [
     },
 ];
 
+// Names emit's dispatch_pseudo handles and what they take, keep in sync with it (a test in emit checks).
+pub const PSEUDO_INSTRUCTIONS: [(&str, &[OperandKind]); 29] = [
+    ("nop", &[]),
+    ("abs", &[Kind::Register, Kind::Register]),
+    ("blt", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("bgt", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("ble", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("bge", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("bltu", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("bgtu", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("bleu", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("bgeu", &[Kind::Register, Kind::Value, Kind::Label]),
+    ("sge", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("sgt", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("sle", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("sgeu", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("sgtu", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("sleu", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("beqz", &[Kind::Register, Kind::Label]),
+    ("bnez", &[Kind::Register, Kind::Label]),
+    ("seq", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("sne", &[Kind::Register, Kind::Register, Kind::Value]),
+    ("neg", &[Kind::Register, Kind::Register]),
+    ("negu", &[Kind::Register, Kind::Register]),
+    ("not", &[Kind::Register, Kind::Register]),
+    ("li", &[Kind::Register, Kind::Constant]),
+    ("la", &[Kind::Register, Kind::Label]),
+    ("move", &[Kind::Register, Kind::Register]),
+    ("b", &[Kind::Label]),
+    ("subi", &[Kind::Register, Kind::Register, Kind::Immediate]),
+    ("subiu", &[Kind::Register, Kind::Register, Kind::Immediate]),
+];
+
+#[derive(Clone, Debug)]
+pub struct InstructionInfo {
+    pub name: &'static str,
+    pub operands: &'static [OperandKind],
+    pub optional: usize, // trailing operands that can be left out
+    pub pseudo: bool,
+    pub alternate: bool, // see Encoding::has_alternate
+}
+
+// Every mnemonic the assembler takes, real instructions first, then pseudo instructions.
+pub fn all_instructions() -> &'static [InstructionInfo] {
+    static ALL: OnceLock<Vec<InstructionInfo>> = OnceLock::new();
+
+    ALL.get_or_init(|| {
+        let real = INSTRUCTIONS.iter().map(|instruction| {
+            let (operands, optional) = instruction.encoding.operands();
+
+            InstructionInfo {
+                name: instruction.name,
+                operands,
+                optional,
+                pseudo: false,
+                alternate: instruction.encoding.has_alternate(),
+            }
+        });
+
+        let pseudo = PSEUDO_INSTRUCTIONS.iter().map(|(name, operands)| InstructionInfo {
+            name,
+            operands,
+            optional: 0,
+            pseudo: true,
+            alternate: false,
+        });
+
+        real.chain(pseudo).collect()
+    })
+}
+
 pub fn instructions_map<'a, 'b>(
     instructions: &'b [Instruction<'a>],
 ) -> HashMap<&'a str, &'b Instruction<'a>> {
@@ -351,3 +506,219 @@ pub fn instructions_map<'a, 'b>(
         .map(|instruction| (instruction.name, instruction))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::encode::InstructionBuilder;
+    use crate::assembler::instructions::{
+        all_instructions, Encoding, Opcode, OperandKind, INSTRUCTIONS, PSEUDO_INSTRUCTIONS,
+    };
+    use crate::assembler::registers::RegisterSlot;
+    use crate::assembler::string::assemble_from;
+    use crate::unit::device::UnitDevice;
+    use crate::unit::instruction::InstructionDecoder;
+    use crate::unit::register::RegisterName::T0;
+    use num_traits::FromPrimitive;
+
+    const PC: u32 = 0x00400000;
+
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+
+            self.0 as u32
+        }
+
+        fn slot(&mut self) -> RegisterSlot {
+            RegisterSlot::from_u32(self.next() % 32).unwrap()
+        }
+    }
+
+    // A word with every field the encoding uses filled in, and the rest left zero (like the assembler leaves them).
+    fn build(opcode: &Opcode, encoding: &Encoding, random: &mut Random) -> u32 {
+        let builder = InstructionBuilder::from_op(opcode);
+
+        match encoding {
+            Encoding::Register | Encoding::RegisterShift => builder
+                .with_source(random.slot())
+                .with_temp(random.slot())
+                .with_dest(random.slot()).0,
+            Encoding::Source => builder.with_source(random.slot()).0,
+            Encoding::Destination => builder.with_dest(random.slot()).0,
+            Encoding::Inputs => builder.with_source(random.slot()).with_temp(random.slot()).0,
+            Encoding::Sham => builder
+                .with_temp(random.slot())
+                .with_dest(random.slot())
+                .with_sham((random.next() % 32) as u8).0,
+            Encoding::SpecialBranch | Encoding::BranchZero => builder
+                .with_source(random.slot())
+                .with_immediate(random.next() as u16).0,
+            Encoding::Immediate(_) | Encoding::UnsignedImmediate(_) | Encoding::Branch | Encoding::Offset => builder
+                .with_source(random.slot())
+                .with_temp(random.slot())
+                .with_immediate(random.next() as u16).0,
+            Encoding::LoadImmediate => builder
+                .with_temp(random.slot())
+                .with_immediate(random.next() as u16).0,
+            Encoding::Jump => builder.0 | random.next() & 0x03FFFFFF,
+            Encoding::Parameterless => builder.0,
+            Encoding::Code => builder.0 | (random.next() & 0xFFFFF) << 6,
+        }
+    }
+
+    // INSTRUCTIONS entry -> InstructionBuilder -> InstructionDecoder -> Display -> assembler gives back the same word.
+    #[test]
+    fn decoded_instructions_reassemble() {
+        let mut random = Random(0x9E3779B97F4A7C15);
+
+        for entry in &INSTRUCTIONS {
+            for _ in 0..64 {
+                let word = build(&entry.opcode, &entry.encoding, &mut random);
+
+                let instruction = InstructionDecoder::decode(PC, word)
+                    .unwrap_or_else(|| panic!("{} 0x{word:08x} doesn't decode", entry.name));
+
+                assert_eq!(instruction.name(), entry.name);
+
+                let text = instruction.to_string();
+                let binary = assemble_from(&format!(".text 0x{PC:x}\n{text}\n"))
+                    .unwrap_or_else(|error| panic!("{text} (0x{word:08x}) doesn't assemble: {error}"));
+
+                let data = &binary.regions.iter()
+                    .find(|region| region.address == PC)
+                    .unwrap_or_else(|| panic!("{text} emitted nothing"))
+                    .data;
+
+                assert_eq!(data.len(), 4, "{text} (0x{word:08x}) expanded to {} bytes", data.len());
+
+                let back = u32::from_le_bytes(data[..4].try_into().unwrap());
+
+                assert_eq!(back, word, "{text} assembled to 0x{back:08x}, decoded from 0x{word:08x}");
+            }
+        }
+    }
+
+    const BOUNDARIES: [i64; 5] = [0x7FFF, 0x8000, 0xFFFF, 0x10000, -1];
+
+    // op $t0, $t1, constant for each of BOUNDARIES. Sign extended ops take -0x8000..=0x7FFF as is, zero extended
+    // ones 0..=0xFFFF, anything else is built in $at (ori, lui or addiu) and goes through the register form.
+    const IMMEDIATES: [(&str, [&[u32]; 5]); 7] = [
+        ("addi", [&[0x21287FFF], &[0x34018000, 0x01214020], &[0x3401FFFF, 0x01214020], &[0x3C010001, 0x01214020], &[0x2128FFFF]]),
+        ("addiu", [&[0x25287FFF], &[0x34018000, 0x01214021], &[0x3401FFFF, 0x01214021], &[0x3C010001, 0x01214021], &[0x2528FFFF]]),
+        ("slti", [&[0x29287FFF], &[0x34018000, 0x0121402A], &[0x3401FFFF, 0x0121402A], &[0x3C010001, 0x0121402A], &[0x2928FFFF]]),
+        ("sltiu", [&[0x2D287FFF], &[0x34018000, 0x01214029], &[0x3401FFFF, 0x01214029], &[0x3C010001, 0x01214029], &[0x2D28FFFF]]),
+        ("andi", [&[0x31287FFF], &[0x31288000], &[0x3128FFFF], &[0x3C010001, 0x01214024], &[0x2401FFFF, 0x01214024]]),
+        ("ori", [&[0x35287FFF], &[0x35288000], &[0x3528FFFF], &[0x3C010001, 0x01214025], &[0x2401FFFF, 0x01214025]]),
+        ("xori", [&[0x39287FFF], &[0x39288000], &[0x3928FFFF], &[0x3C010001, 0x01214026], &[0x2401FFFF, 0x01214026]]),
+    ];
+
+    // What MARS leaves in $t0, with $t1 = 0x12345678 and the constant taken as a 32 bit value.
+    fn expected(op: &str, t1: u32, constant: u32) -> u32 {
+        match op {
+            "addi" | "addiu" => t1.wrapping_add(constant),
+            "slti" => ((t1 as i32) < constant as i32) as u32,
+            "sltiu" => (t1 < constant) as u32,
+            "andi" => t1 & constant,
+            "ori" => t1 | constant,
+            "xori" => t1 ^ constant,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn immediate_boundaries() {
+        const T1: u32 = 0x12345678;
+
+        for (op, expansions) in IMMEDIATES {
+            for (constant, words) in BOUNDARIES.into_iter().zip(expansions) {
+                let binary = assemble_from(&format!("{op} $t0, $t1, {constant}\n")).unwrap();
+                let data: Vec<u32> = binary.regions[0].data.chunks(4)
+                    .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                    .collect();
+
+                assert_eq!(data, words, "{op} $t0, $t1, {constant}");
+
+                let device = UnitDevice::new(assemble_from(
+                    &format!("li $t1, {T1}\n{op} $t0, $t1, {constant}\n")
+                ).unwrap());
+
+                for _ in 0..2 + words.len() {
+                    device.step().unwrap()
+                }
+
+                assert_eq!(device.get(T0), expected(op, T1, constant as u32), "{op} $t0, $t1, {constant}");
+            }
+        }
+    }
+
+    // The names dispatch_pseudo matches on, read out of emit.rs so a new pseudo can't be left out of the table.
+    fn dispatched_pseudos() -> Vec<String> {
+        let source = include_str!("emit.rs");
+        let start = source.find("fn dispatch_pseudo(").unwrap();
+        let end = start + source[start..].find("_ => return Ok(None)").unwrap();
+
+        source[start..end].lines()
+            .filter_map(|line| line.trim().strip_prefix('"')?.split_once("\" =>").map(|(name, _)| name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn every_mnemonic_appears_once() {
+        let all = all_instructions();
+        let count = |name: &str| all.iter().filter(|info| info.name == name).count();
+
+        for instruction in &INSTRUCTIONS {
+            assert_eq!(count(instruction.name), 1, "{}", instruction.name);
+        }
+
+        let pseudos = dispatched_pseudos();
+
+        assert_eq!(pseudos.len(), PSEUDO_INSTRUCTIONS.len());
+
+        for name in &pseudos {
+            assert_eq!(count(name), 1, "{name}");
+            assert!(all.iter().any(|info| info.name == name && info.pseudo), "{name}");
+        }
+
+        assert_eq!(all.len(), INSTRUCTIONS.len() + pseudos.len());
+    }
+
+    fn sample(kind: &OperandKind) -> &'static str {
+        match kind {
+            OperandKind::Register => "$t0",
+            OperandKind::Value => "$t1",
+            OperandKind::Immediate => "-5",
+            OperandKind::UnsignedImmediate => "5",
+            OperandKind::Shift => "3",
+            OperandKind::Constant => "0x12345678",
+            OperandKind::Label => "target",
+            OperandKind::Offset => "4($sp)",
+            OperandKind::Code => "7",
+        }
+    }
+
+    // Each shape, with and without its optional operands, is something the assembler takes.
+    #[test]
+    fn operand_shapes_assemble() {
+        for info in all_instructions() {
+            for given in info.operands.len() - info.optional ..= info.operands.len() {
+                let operands: Vec<_> = info.operands[..given].iter().map(sample).collect();
+                let source = format!("target:\n{} {}\n", info.name, operands.join(", "));
+
+                assert!(assemble_from(&source).is_ok(), "{source}");
+            }
+        }
+
+        // Values can be constants too.
+        assert!(assemble_from("target: blt $t0, 5, target\nsgt $t0, $t1, 5\n").is_ok());
+
+        let alternates: Vec<_> = all_instructions().iter().filter(|info| info.alternate).map(|info| info.name).collect();
+
+        assert!(alternates.contains(&"addi") && alternates.contains(&"ori"), "{alternates:?}");
+        assert!(!alternates.contains(&"lui"));
+    }
+}
@@ -1,13 +1,12 @@
-use num::FromPrimitive;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::ptr;
 use std::str::FromStr;
 use SymbolName::Owned;
-use TokenKind::{Minus, Plus};
+use TokenKind::{Minus, NumericReference, Plus, Slash, Star};
 
 use crate::assembler::lexer::LexerReason::{
-    ImproperLiteral, InvalidString, Stuck, UnexpectedCharacter, UnknownRegister,
+    FloatingPointRegister, ImproperLiteral, InvalidEscape, InvalidString, Stuck, UnexpectedCharacter, UnknownRegister,
 };
 use crate::assembler::lexer::SymbolName::Slice;
 use crate::assembler::lexer::TokenKind::{
@@ -15,11 +14,12 @@ use crate::assembler::lexer::TokenKind::{
     RightBrace, StringLiteral, Symbol,
 };
 use crate::assembler::registers::RegisterSlot;
+use std::rc::Rc;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum SymbolName<'a> {
     Slice(&'a str),
-    Owned(String),
+    Owned(Rc<str>), // Shared, so cloning expanded tokens does not copy the name.
 }
 
 impl<'a> SymbolName<'a> {
@@ -44,8 +44,11 @@ pub enum StrippedKind {
     IntegerLiteral,
     StringLiteral,
     Symbol,
+    NumericReference,
     Plus,
     Minus,
+    Star,
+    Slash,
     Comma,
     Colon,
     NewLine,
@@ -60,10 +63,13 @@ pub enum TokenKind<'a> {
     Parameter(&'a str),     // %*
     Register(RegisterSlot), // $*
     IntegerLiteral(u64),    // 123 -> also characters
-    StringLiteral(String),
+    StringLiteral(Vec<u8>),
     Symbol(SymbolName<'a>),
+    NumericReference(u64, bool), // 1b -> (1, false), 1f -> (1, true), see resolve_numeric_labels
     Plus,
     Minus,
+    Star,  // only in label offsets, ex. table + 4 * 2
+    Slash,
     Comma,
     Colon,
     NewLine,
@@ -84,8 +90,11 @@ impl Display for StrippedKind {
                 StrippedKind::IntegerLiteral => "Integer Literal",
                 StrippedKind::StringLiteral => "String Literal",
                 StrippedKind::Symbol => "Symbol",
+                StrippedKind::NumericReference => "Numeric Label Reference",
                 StrippedKind::Plus => "Plus",
                 StrippedKind::Minus => "Minus",
+                StrippedKind::Star => "Star",
+                StrippedKind::Slash => "Slash",
                 StrippedKind::Comma => "Comma",
                 StrippedKind::Colon => "Colon",
                 StrippedKind::NewLine => "NewLine",
@@ -106,8 +115,11 @@ impl<'a> TokenKind<'a> {
             IntegerLiteral(_) => StrippedKind::IntegerLiteral,
             StringLiteral(_) => StrippedKind::StringLiteral,
             Symbol(_) => StrippedKind::Symbol,
+            NumericReference(_, _) => StrippedKind::NumericReference,
             Plus => StrippedKind::Plus,
             Minus => StrippedKind::Minus,
+            Star => StrippedKind::Star,
+            Slash => StrippedKind::Slash,
             Comma => StrippedKind::Comma,
             Colon => StrippedKind::Colon,
             NewLine => StrippedKind::NewLine,
@@ -133,8 +145,10 @@ pub struct Token<'a> {
 pub enum LexerReason {
     Stuck,
     UnknownRegister(String),
+    FloatingPointRegister(String), // $f0 to $f31
     UnexpectedCharacter(char),
     InvalidString,
+    InvalidEscape(char), // the character after the backslash
     ImproperLiteral,
 }
 
@@ -143,8 +157,12 @@ impl Display for LexerReason {
         match self {
             Stuck => write!(f, "Lexer got stuck on this token. Please file an issue at https://github.com/1whatleytay/titan/issues"),
             UnknownRegister(register) => write!(f, "Unknown register \"{register}\""),
+            FloatingPointRegister(register) => write!(
+                f, "Register \"${register}\" belongs to the floating point unit, which titan does not support, expected an integer register"),
             UnexpectedCharacter(c) => write!(f, "Unexpected character \"{c}\""),
-            InvalidString => write!(f, "String literal is incorrectly formatted. Check that you have closing quotes"),
+            InvalidString => write!(f, "String literal is missing its closing quote before the end of the line"),
+            InvalidEscape(c) => write!(
+                f, "String literal has an unknown escape \"\\{c}\", expected \\n, \\r, \\t, \\0, \\\\, \\\", \\' or \\x00 to \\x7f"),
             ImproperLiteral => write!(f, "Integer literal is incorrectly formatted or too big"),
         }
     }
@@ -154,6 +172,7 @@ impl Display for LexerReason {
 pub struct LexerError {
     pub location: Location,
     pub reason: LexerReason,
+    pub length: usize, // bytes from location the error covers, 0 if only the point is known
 }
 
 impl Display for LexerError {
@@ -244,45 +263,63 @@ fn take_name(input: &str) -> (&str, &str) {
     take_split(input, |c| !is_hard(c))
 }
 
-// MARS does not seem to support \x, \u or \U escapes (which require variable consumption).
-// We will not support it either then.
-fn escape(c: char) -> char {
-    // backslash and quotes are handled under the regular case
-    match c {
-        'n' => '\n',
-        'r' => '\r',
-        't' => '\t',
-        '0' => '\0',
-        _ => c,
-    }
+// Anything else after a backslash is a typo, not the character itself.
+// \x takes exactly two hex digits and is that byte, even 0x80 and up (which isn't a character on its own in UTF-8).
+fn escape(input: &str) -> Result<(u8, usize), LexerReason> {
+    let c = input.chars().next().ok_or(InvalidString)?;
+
+    let value = match c {
+        'n' => b'\n',
+        'r' => b'\r',
+        't' => b'\t',
+        '0' => 0,
+        '\\' | '\"' | '\'' => c as u8,
+        'x' => {
+            let value = input.get(1..3)
+                .filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()))
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or(InvalidEscape(c))?;
+
+            return Ok((value, 3))
+        }
+        '\n' => return Err(InvalidString),
+        _ => return Err(InvalidEscape(c)),
+    };
+
+    Ok((value, c.len_utf8()))
 }
 
-// If Some is returned, then the first char of .0 should be quote.
-fn string_body(mut input: &str, quote: char) -> Option<(&str, String)> {
-    let mut result = "".to_string();
+// If Ok is returned, then the first char of .0 should be quote.
+// Strings end with their line (like MARS), so a missing quote doesn't swallow the rest of the file.
+// Bytes, not a String: the text is UTF-8, but \x escapes can be any byte.
+fn string_body(mut input: &str, quote: char) -> Result<(&str, Vec<u8>), LexerReason> {
+    let mut result = vec![];
 
     loop {
-        let start = input.chars().next()?;
+        let start = input.chars().next().ok_or(InvalidString)?;
 
         match start {
+            '\n' => return Err(InvalidString),
             '\\' => {
-                result += &escape(input.chars().nth(1)?).to_string();
+                let (value, size) = escape(&input[1..])?;
+
+                result.push(value);
 
-                input = &input[2..];
+                input = &input[1 + size..];
             }
             _ if start == quote => {
                 break; // don't consume
             }
             _ => {
-                let (rest, body) = take_split(input, |c| c != quote && c != '\\');
+                let (rest, body) = take_split(input, |c| c != quote && c != '\\' && c != '\n');
 
                 input = rest;
-                result += body;
+                result.extend_from_slice(body.as_bytes());
             }
         }
     }
 
-    Some((input, result))
+    Ok((input, result))
 }
 
 fn integer_decimal(input: &str) -> Option<(&str, u64)> {
@@ -313,14 +350,29 @@ fn integer_character(input: &str) -> Option<(&str, u64)> {
     // assert(input.starts_with("\'")
     let input = &input[1..];
 
-    let (input, body) = string_body(input, '\'')?;
+    let (input, body) = string_body(input, '\'').ok()?;
 
     if body.len() != 1 {
         return None;
     }
 
     // Should be over a quote...
-    Some((&input[1..], body.chars().next()? as u64))
+    Some((&input[1..], body[0] as u64))
+}
+
+// 1b or 1f, the closest `1:` label before or after. Only tried once input isn't a literal, so 0b1 is still binary.
+fn numeric_reference(input: &str) -> Option<(&str, TokenKind<'_>)> {
+    let (rest, value) = take_name(input);
+
+    let forward = match value.chars().last()? {
+        'b' => false,
+        'f' => true,
+        _ => return None,
+    };
+
+    let number = u64::from_str(&value[..value.len() - 1]).ok()?;
+
+    Some((rest, NumericReference(number, forward)))
 }
 
 fn integer_literal(input: &str) -> Option<(&str, u64)> {
@@ -332,7 +384,7 @@ fn integer_literal(input: &str) -> Option<(&str, u64)> {
     }
 }
 
-fn lex_item(input: &str) -> Result<Option<(&str, TokenKind)>, LexerReason> {
+fn lex_item(input: &str) -> Result<Option<(&str, TokenKind<'_>)>, LexerReason> {
     let input = take_space(input);
 
     let Some(leading) = input.chars().next() else { return Ok(None) };
@@ -357,13 +409,26 @@ fn lex_item(input: &str) -> Result<Option<(&str, TokenKind)>, LexerReason> {
         '$' => {
             let (rest, value) = take_name(after_leading);
 
-            RegisterSlot::from_string(value)
-                .or_else(|| RegisterSlot::from_u64(u64::from_str(value).ok()?))
+            // Register names are case insensitive, $T0 is $t0.
+            RegisterSlot::from_str(value)
                 .map(|slot| Some((rest, Register(slot))))
-                .ok_or_else(|| UnknownRegister(value.to_string()))
+                .map_err(|_| {
+                    // Every register operand is an integer one, so a coprocessor 1 name is never right.
+                    let floating = value.strip_prefix('f')
+                        .and_then(|index| u8::from_str(index).ok())
+                        .is_some_and(|index| index < 32);
+
+                    if floating {
+                        FloatingPointRegister(value.to_string())
+                    } else {
+                        UnknownRegister(value.to_string())
+                    }
+                })
         }
         '+' => Ok(Some((&input[1..], Plus))),
         '-' => Ok(Some((&input[1..], Minus))),
+        '*' => Ok(Some((&input[1..], Star))),
+        '/' => Ok(Some((&input[1..], Slash))),
         ',' => Ok(Some((&input[1..], Comma))),
         '(' => Ok(Some((&input[1..], LeftBrace))),
         ')' => Ok(Some((&input[1..], RightBrace))),
@@ -371,10 +436,10 @@ fn lex_item(input: &str) -> Result<Option<(&str, TokenKind)>, LexerReason> {
         '\n' => Ok(Some((&input[1..], NewLine))),
         '0'..='9' | '\'' => integer_literal(input)
             .map(|(out, value)| Some((out, IntegerLiteral(value))))
+            .or_else(|| numeric_reference(input).map(Some))
             .ok_or(ImproperLiteral),
         '\"' => string_body(after_leading, '\"')
-            .map(|(out, body)| Some((&out[1..], StringLiteral(body))))
-            .ok_or(InvalidString),
+            .map(|(out, body)| Some((&out[1..], StringLiteral(body)))),
         _ if is_hard(leading) => Err(UnexpectedCharacter(leading)),
         _ => Ok({
             let (rest, value) = take_name(input);
@@ -384,34 +449,129 @@ fn lex_item(input: &str) -> Result<Option<(&str, TokenKind)>, LexerReason> {
     }
 }
 
-pub fn lex_with_source(mut input: &str, source: usize) -> Result<Vec<Token>, LexerError> {
+// Every error in the input, lexing resumes at the next line after each one.
+pub fn lex_recovering(mut input: &str, source: usize) -> (Vec<Token<'_>>, Vec<LexerError>) {
     let begin = input;
     let mut result = vec![];
+    let mut errors = vec![];
 
     while !input.is_empty() {
         let trail = input;
         let start = offset_from_start(begin, trail);
         let location = Location { source, index: start };
 
-        let Some((next, kind)) = lex_item(input)
-            .map_err(|reason| LexerError { location, reason })? else {
-            break
-        };
+        let (next, kind) = match lex_item(input) {
+            Ok(Some(item)) if !ptr::eq(trail.as_ptr(), item.0.as_ptr()) => item,
+            Ok(Some(_)) => {
+                errors.push(LexerError { location, reason: Stuck, length: 0 });
 
-        if ptr::eq(trail.as_ptr(), next.as_ptr()) {
-            return Err(LexerError {
-                location,
-                reason: Stuck,
-            });
-        }
+                break
+            }
+            Ok(None) => break,
+            Err(reason) => {
+                let line = take_count(input, |c| c != '\n');
+
+                // Points at what failed (ex. the opening quote), spaces before it are skipped by lex_item.
+                let skipped = input.len() - take_space(input).len();
+                let location = Location { source, index: start + skipped };
+
+                let length = match reason {
+                    InvalidString | InvalidEscape(_) => line - skipped,
+                    _ => 0,
+                };
+
+                errors.push(LexerError { location, reason, length });
+
+                input = &input[line..]; // keeps the newline
+
+                continue
+            }
+        };
 
         result.push(Token { location, kind });
         input = next;
     }
 
-    Ok(result)
+    (result, errors)
+}
+
+pub fn lex_with_source(input: &str, source: usize) -> Result<Vec<Token<'_>>, LexerError> {
+    let (result, errors) = lex_recovering(input, source);
+
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(result),
+    }
 }
 
-pub fn lex(input: &str) -> Result<Vec<Token>, LexerError> {
+pub fn lex(input: &str) -> Result<Vec<Token<'_>>, LexerError> {
     lex_with_source(input, 0)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::lexer::LexerReason::{InvalidString, UnexpectedCharacter};
+    use crate::assembler::lexer::TokenKind::{Register, StringLiteral, Symbol};
+    use crate::assembler::lexer::{lex, lex_recovering};
+    use crate::assembler::registers::RegisterSlot;
+    use num::FromPrimitive;
+
+    const UNTERMINATED: &str = ".data\nfirst: .asciiz \"no end\nsecond: .asciiz \"fine\"\nthird: .byte @\n";
+
+    #[test]
+    fn strings_stop_at_the_end_of_their_line() {
+        let error = lex(UNTERMINATED).unwrap_err();
+        let quote = UNTERMINATED.find('"').unwrap();
+
+        assert!(matches!(error.reason, InvalidString));
+        assert_eq!(error.location.index, quote);
+        assert_eq!(error.length, "\"no end".len());
+    }
+
+    #[test]
+    fn later_lines_still_report() {
+        let (tokens, errors) = lex_recovering(UNTERMINATED, 0);
+
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0].reason, InvalidString));
+        assert!(matches!(errors[1].reason, UnexpectedCharacter('@')));
+        assert_eq!(errors[1].location.index, UNTERMINATED.find('@').unwrap());
+
+        // The string on the next line is still its own literal.
+        assert!(tokens.iter().any(|token| matches!(&token.kind, StringLiteral(body) if body == b"fine")));
+        assert!(tokens.iter().any(|token| matches!(&token.kind, Symbol(name) if name.get() == "third")));
+    }
+
+    #[test]
+    fn escapes_cannot_continue_a_string() {
+        let error = lex(".asciiz \"line \\\nnext\"\n").unwrap_err();
+
+        assert!(matches!(error.reason, InvalidString));
+        assert_eq!(error.location.index, 8);
+
+        let tokens = lex(".asciiz \"tab\\there\"\n").unwrap();
+
+        assert!(matches!(&tokens[1].kind, StringLiteral(body) if body == b"tab\there"));
+    }
+
+    #[test]
+    fn registers_lex_like_they_parse() {
+        let spellings = (0..32).flat_map(|index: u8| {
+            let slot = RegisterSlot::from_u8(index).unwrap();
+
+            [slot.as_string().to_string(), slot.as_string().to_ascii_uppercase(), index.to_string()]
+        });
+
+        for spelling in spellings {
+            let source = format!("${spelling}");
+            let tokens = lex(&source).unwrap();
+
+            assert!(matches!(tokens[0].kind, Register(slot) if Ok(slot) == spelling.parse()), "{spelling}");
+        }
+
+        for spelling in ["32", "f0", "s8", "zer0"] {
+            assert!(lex(&format!("${spelling}")).is_err(), "{spelling}");
+            assert!(spelling.parse::<RegisterSlot>().is_err(), "{spelling}");
+        }
+    }
+}
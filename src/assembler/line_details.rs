@@ -1,4 +1,7 @@
 use std::cmp::min;
+use std::collections::HashMap;
+use crate::assembler::lexer::Location;
+use crate::assembler::source::FileProviderPool;
 
 pub struct LineDetails<'a> {
     pub line_number: usize,
@@ -87,3 +90,66 @@ impl<'a> LineDetails<'a> {
         }
     }
 }
+
+// Line and column for a Location, both from 0. The column counts bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub source: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+// Where every line of each source starts, so looking up many locations doesn't rescan the text.
+#[derive(Clone, Debug, Default)]
+pub struct LineMap {
+    starts: HashMap<usize, Vec<usize>>, // source id -> byte offset of each line
+    sources: HashMap<usize, String>, // for skipping the spaces a location starts with
+}
+
+impl LineMap {
+    pub fn new() -> LineMap {
+        LineMap { starts: HashMap::new(), sources: HashMap::new() }
+    }
+
+    // A single source, with the id assemble_from gives it.
+    pub fn from_source(source: &str) -> LineMap {
+        let mut map = LineMap::new();
+        map.add(0, source);
+
+        map
+    }
+
+    // Every source the pool has read, included files too.
+    pub fn from_pool(pool: &FileProviderPool) -> LineMap {
+        let mut map = LineMap::new();
+
+        for id in 0.. {
+            let Some(source) = pool.source(id) else { break };
+
+            map.add(id, &source);
+        }
+
+        map
+    }
+
+    pub fn add(&mut self, id: usize, source: &str) {
+        let starts: Vec<usize> = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+
+        self.starts.insert(id, starts);
+        self.sources.insert(id, source.to_string());
+    }
+
+    pub fn position(&self, location: Location) -> Option<SourcePosition> {
+        // A token's location includes the spaces before it (ex. after a label), skip to the token itself.
+        let source = self.sources.get(&location.source)?;
+        let rest = source.get(location.index..).unwrap_or("");
+        let index = location.index + rest.len() - rest.trim_start_matches([' ', '\t']).len();
+
+        let starts = self.starts.get(&location.source)?;
+        let line = starts.partition_point(|start| *start <= index).saturating_sub(1);
+
+        Some(SourcePosition { source: location.source, line, column: index - starts[line] })
+    }
+}
@@ -0,0 +1,205 @@
+use crate::assembler::binary::{Binary, RawRegion, RegionFlags};
+use crate::assembler::line_details::{LineMap, SourcePosition};
+use crate::unit::instruction::InstructionDecoder;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ListingRowKind {
+    Label, // text is "name:", no bytes
+    Instruction, // a word that doesn't decode is written as .word
+    Data,
+}
+
+// One row of an assembly listing (like the MARS text segment window).
+#[derive(Clone, Debug)]
+pub struct ListingRow {
+    pub kind: ListingRowKind,
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub source: Option<SourcePosition>, // the statement that emitted it, if the binary knows
+}
+
+struct ListingWriter<'a> {
+    labels: BTreeMap<u32, Vec<&'a str>>,
+    statements: HashMap<u32, SourcePosition>, // pc -> source, from the breakpoints
+    rows: Vec<ListingRow>,
+}
+
+impl ListingWriter<'_> {
+    fn write_labels(&mut self, start: u32, end: u64) {
+        for (address, names) in self.labels.range(start..) {
+            if *address as u64 >= end {
+                break
+            }
+
+            for name in names {
+                self.rows.push(ListingRow {
+                    kind: ListingRowKind::Label,
+                    address: *address,
+                    bytes: vec![],
+                    text: format!("{name}:"),
+                    source: None,
+                })
+            }
+        }
+    }
+
+    fn write_region(&mut self, region: &RawRegion) {
+        let executable = region.flags.contains(RegionFlags::EXECUTABLE);
+
+        for (index, bytes) in region.data.chunks(4).enumerate() {
+            let address = region.address.wrapping_add(4 * index as u32);
+
+            self.write_labels(address, address as u64 + bytes.len() as u64);
+
+            let text = match bytes.try_into().map(u32::from_le_bytes) {
+                Ok(word) if executable => InstructionDecoder::decode(address, word)
+                    .map_or_else(|| format!(".word 0x{word:08x}"), |instruction| instruction.to_string()),
+                Ok(word) => format!(".word 0x{word:08x}"),
+                Err(_) => {
+                    let values: Vec<String> = bytes.iter().map(|byte| format!("0x{byte:02x}")).collect();
+
+                    format!(".byte {}", values.join(", "))
+                }
+            };
+
+            self.rows.push(ListingRow {
+                kind: if executable { ListingRowKind::Instruction } else { ListingRowKind::Data },
+                address,
+                bytes: bytes.to_vec(),
+                text,
+                source: self.statements.get(&address).copied(),
+            })
+        }
+    }
+}
+
+impl Binary {
+    // Every text word in address order, decoded once, with labels as their own rows.
+    pub fn listing(&self, line_map: &LineMap) -> Vec<ListingRow> {
+        self.listing_regions(line_map, false)
+    }
+
+    // Same, with data regions too, a .word row for each word.
+    pub fn listing_with_data(&self, line_map: &LineMap) -> Vec<ListingRow> {
+        self.listing_regions(line_map, true)
+    }
+
+    fn listing_regions(&self, line_map: &LineMap, data: bool) -> Vec<ListingRow> {
+        let aliases: HashSet<&String> = self.aliases.keys().collect();
+
+        let mut labels: BTreeMap<u32, Vec<&str>> = BTreeMap::new();
+
+        for (name, address) in &self.labels {
+            if !aliases.contains(name) {
+                labels.entry(*address).or_default().push(name);
+            }
+        }
+
+        for names in labels.values_mut() {
+            names.sort();
+        }
+
+        let statements = self.breakpoints.iter()
+            .filter_map(|breakpoint| Some((breakpoint, line_map.position(breakpoint.location)?)))
+            .flat_map(|(breakpoint, position)| breakpoint.pcs.iter().map(move |pc| (*pc, position)))
+            .collect();
+
+        let mut writer = ListingWriter { labels, statements, rows: vec![] };
+
+        let mut regions: Vec<&RawRegion> = self.regions.iter()
+            .filter(|region| data || region.flags.contains(RegionFlags::EXECUTABLE))
+            .collect();
+
+        regions.sort_by_key(|region| region.address);
+
+        for region in regions {
+            writer.write_region(region);
+        }
+
+        writer.rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::line_details::{LineMap, SourcePosition};
+    use crate::assembler::listing::ListingRowKind::{Data, Instruction, Label};
+    use crate::assembler::listing::{ListingRow, ListingRowKind};
+    use crate::assembler::source::FileProviderPool;
+    use crate::assembler::string::{assemble_from, assemble_with_provider};
+    use std::collections::HashMap;
+
+    const SOURCE: &str = "main:
+  li $t0, 0x12345
+.alias start, main
+loop: addi $t0, $t0, -1
+  bnez $t0, loop
+.word 0xFC000000
+.data
+value: .word 7
+.byte 1, 2
+";
+
+    fn rows(rows: &[ListingRow]) -> Vec<(ListingRowKind, u32, &str)> {
+        rows.iter().map(|row| (row.kind, row.address, row.text.as_str())).collect()
+    }
+
+    fn at(line: usize, column: usize) -> Option<SourcePosition> {
+        Some(SourcePosition { source: 0, line, column })
+    }
+
+    #[test]
+    fn text_words_list_with_their_labels_and_lines() {
+        let binary = assemble_from(SOURCE).unwrap();
+        let listing = binary.listing(&LineMap::from_source(SOURCE));
+
+        // Aliases aren't rows of their own.
+        assert_eq!(rows(&listing), [
+            (Label, 0x400000, "main:"),
+            (Instruction, 0x400000, "lui $t0, 1"),
+            (Instruction, 0x400004, "ori $t0, $t0, 0x2345"),
+            (Label, 0x400008, "loop:"),
+            (Instruction, 0x400008, "addi $t0, $t0, -1"),
+            (Instruction, 0x40000c, "bne $t0, $zero, 0x400008"),
+            (Instruction, 0x400010, ".word 0xfc000000"),
+        ]);
+
+        // Both words of li come from its line, columns land on the instruction rather than the spaces before it.
+        let sources: Vec<_> = listing.iter().map(|row| row.source).collect();
+
+        assert_eq!(sources, [None, at(1, 2), at(1, 2), None, at(3, 6), at(4, 2), None]);
+        assert_eq!(listing[1].bytes, 0x3C080001u32.to_le_bytes());
+    }
+
+    #[test]
+    fn data_is_listed_on_request() {
+        let binary = assemble_from(SOURCE).unwrap();
+        let listing = binary.listing_with_data(&LineMap::from_source(SOURCE));
+
+        // A short last word is written as bytes.
+        assert_eq!(rows(&listing[7..]), [
+            (Label, 0x10010000, "value:"),
+            (Data, 0x10010000, ".word 0x00000007"),
+            (Data, 0x10010004, ".byte 0x01, 0x02"),
+        ]);
+
+        assert_eq!(listing[9].bytes, [1, 2]);
+    }
+
+    #[test]
+    fn included_lines_point_into_their_file() {
+        let pool = FileProviderPool::in_memory(HashMap::from([
+            ("main.asm".to_string(), "nop\n.include \"lib.asm\"\n".to_string()),
+            ("lib.asm".to_string(), "\n\tsyscall\n".to_string()),
+        ]));
+
+        let binary = assemble_with_provider(&pool.in_memory_provider("main.asm").unwrap()).unwrap();
+        let listing = binary.listing(&LineMap::from_pool(&pool));
+
+        assert_eq!(listing[0].source, at(0, 0));
+        assert_eq!(listing[1].text, "syscall");
+        assert_eq!(listing[1].source, Some(SourcePosition { source: 1, line: 1, column: 1 }));
+    }
+}
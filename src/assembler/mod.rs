@@ -4,12 +4,18 @@ pub mod preprocessor;
 
 mod assembler_util;
 pub mod binary;
+pub mod binary_format;
+pub mod flat;
+pub mod decompile;
 mod binary_builder;
 pub mod core;
 mod directive;
 mod emit;
+pub mod encode;
 pub mod instructions;
 pub mod line_details;
-mod registers;
+pub mod listing;
+pub mod registers;
 pub mod string;
+pub mod project;
 pub mod source;
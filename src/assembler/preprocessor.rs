@@ -1,10 +1,10 @@
 use crate::assembler::cursor::{is_adjacent_kind, LexerCursor};
 use crate::assembler::lexer::SymbolName::Owned;
 use crate::assembler::lexer::TokenKind::{
-    Colon, Directive, LeftBrace, NewLine, Parameter, RightBrace, Symbol,
+    Colon, Comment, Directive, IntegerLiteral, LeftBrace, NewLine, NumericReference, Parameter, RightBrace, Symbol,
 };
 use crate::assembler::lexer::{LexerError, Location, StrippedKind, SymbolName, Token, TokenKind};
-use crate::assembler::preprocessor::PreprocessorReason::{EndOfFile, ExpectedLeftBrace, ExpectedParameter, ExpectedRightBrace, ExpectedSymbol, MacroParameterCount, MacroUnknownParameter, RecursiveExpansion, IncludeUnsupported, ExpectedString, FailedToFindFile, FailedToLexFile, RecursiveInclude};
+use crate::assembler::preprocessor::PreprocessorReason::{EndOfFile, ExpectedLeftBrace, ExpectedParameter, ExpectedRightBrace, ExpectedSymbol, MacroParameterCount, MacroUnknownParameter, RecursiveExpansion, IncludeUnsupported, ExpectedString, FailedToFindFile, FailedToLexFile, RecursiveInclude, RecursiveEqv, EqvTooDeep, EqvRedefined, MissingNumericLabel};
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
@@ -27,7 +27,11 @@ pub enum PreprocessorReason {
     NoFilePathAssociated,
     FailedToFindFile(String),
     FailedToLexFile(LexerError),
-    RecursiveInclude
+    RecursiveInclude(Vec<String>), // include chain, ending with the repeated file
+    RecursiveEqv(Vec<String>), // eqv chain, ending with the repeated name
+    EqvTooDeep(String),
+    EqvRedefined(String, Location), // name, location of the first definition
+    MissingNumericLabel(u64, bool), // number, forward
 }
 
 impl Display for PreprocessorReason {
@@ -43,7 +47,7 @@ impl Display for PreprocessorReason {
             }
             ExpectedLeftBrace(kind) => write!(f, "Expected a left brace, but found {kind}"),
             ExpectedRightBrace(kind) => write!(f, "Expected a right brace, but found {kind}"),
-            ExpectedString(kind) => write!(f, "Expected a string brace, but found {kind}"),
+            ExpectedString(kind) => write!(f, "Expected a string, but found {kind}"),
             RecursiveExpansion => write!(
                 f,
                 "Macro recursively calls itself, so preprocessor has stopped expanding"
@@ -57,7 +61,14 @@ impl Display for PreprocessorReason {
             NoFilePathAssociated => write!(f, "This file is not saved to disk, so there is no path for this file."),
             FailedToFindFile(name) => write!(f, "Failed to find file \"{name}\""),
             FailedToLexFile(error) => write!(f, "File has invalid format, {error}"),
-            RecursiveInclude => write!(f, "Include is recursive (includes itself), this is not allowed")
+            RecursiveInclude(chain) => write!(f, "Include is recursive (includes itself), this is not allowed: {}", chain.join(" -> ")),
+            RecursiveEqv(chain) => write!(f, "Eqv refers to itself, so it cannot be expanded: {}", chain.join(" -> ")),
+            EqvTooDeep(name) => write!(f, "Eqv \"{name}\" refers to more than {MAX_EQV_DEPTH} other eqvs, so preprocessor has stopped expanding"),
+            EqvRedefined(name, _) => write!(f, "Eqv \"{name}\" is already defined, it cannot be defined again"),
+            MissingNumericLabel(number, true) => write!(
+                f, "No label \"{number}:\" follows this {number}f before the next named label"),
+            MissingNumericLabel(number, false) => write!(
+                f, "No label \"{number}:\" comes before this {number}b since the last named label")
         }
     }
 }
@@ -78,14 +89,14 @@ impl Error for PreprocessorError {}
 
 #[derive(Clone)]
 struct Macro<'a> {
-    name: String,
+    name: Rc<str>,
     parameters: Vec<&'a str>,
     labels: HashSet<String>,
     items: Vec<Token<'a>>,
 }
 
 impl<'a> Macro<'a> {
-    fn new(name: String) -> Macro<'a> {
+    fn new(name: Rc<str>) -> Macro<'a> {
         Macro {
             name,
             parameters: vec![],
@@ -95,11 +106,19 @@ impl<'a> Macro<'a> {
     }
 }
 
+const MAX_EQV_DEPTH: usize = 64;
+
+struct Eqv<'a> {
+    location: Location,
+    value: Vec<TokenKind<'a>>,
+}
+
 struct Cache<'a> {
     seed: usize,
-    tokens: HashMap<String, Vec<TokenKind<'a>>>,
+    tokens: HashMap<String, Eqv<'a>>,
     macros: HashMap<String, Rc<Macro<'a>>>,
-    expanding: HashSet<String>,
+    expanding: HashSet<Rc<str>>,
+    included: HashSet<String>, // paths of every included file, for include_once
 }
 
 impl<'a> Cache<'a> {
@@ -109,6 +128,7 @@ impl<'a> Cache<'a> {
             tokens: HashMap::new(),
             macros: HashMap::new(),
             expanding: HashSet::new(),
+            included: HashSet::new(),
         }
     }
 }
@@ -119,9 +139,11 @@ fn consume_eqv<'a>(
     let Some(symbol) = iter.next_adjacent() else { return Err(EndOfFile) };
 
     let Symbol(key) = &symbol.kind else { return Err(ExpectedSymbol(symbol.kind.strip())) };
+    // A trailing comment is not part of the value (it would otherwise land mid-line at every use).
     let value = iter
         .collect_without(|kind| kind == &NewLine)
         .into_iter()
+        .filter(|token| !matches!(token.kind, TokenKind::Comment(_)))
         .map(|token| token.kind.clone())
         .collect();
 
@@ -132,27 +154,35 @@ fn consume_macro<'a>(iter: &mut LexerCursor<'a, '_>) -> Result<Macro<'a>, Prepro
     let Some(symbol) = iter.next_adjacent() else { return Err(EndOfFile) };
     let Symbol(name) = &symbol.kind else { return Err(ExpectedSymbol(symbol.kind.strip())) };
 
-    let mut result = Macro::new(name.get().to_string());
+    let mut result = Macro::new(Rc::from(name.get()));
 
     let Some(left_brace) = iter.next_adjacent() else { return Err(EndOfFile) };
-    if left_brace.kind != LeftBrace {
-        return Err(ExpectedLeftBrace(left_brace.kind.strip()));
+
+    // MARS allows leaving out the parameter list entirely.
+    let has_parameters = match left_brace.kind {
+        LeftBrace => true,
+        NewLine => false,
+        _ => return Err(ExpectedLeftBrace(left_brace.kind.strip())),
     };
 
-    loop {
-        let Some(next) = iter.next_adjacent() else { return Err(EndOfFile) };
-
-        match next.kind {
-            LeftBrace => continue,
-            RightBrace => continue,
-            Parameter(name) => result.parameters.push(name),
-            NewLine => break,
-            _ => return Err(ExpectedParameter(next.kind.strip())),
+    if has_parameters {
+        loop {
+            let Some(next) = iter.next_adjacent() else { return Err(EndOfFile) };
+
+            match next.kind {
+                LeftBrace => continue,
+                RightBrace => continue,
+                Parameter(name) => result.parameters.push(name),
+                NewLine => break,
+                _ => return Err(ExpectedParameter(next.kind.strip())),
+            }
         }
     }
 
     let mut body: Vec<Token> = vec![];
 
+    // Comments are kept in the body, but never count when pairing a symbol with its colon
+    // (is_adjacent_kind skips them), so `label: # note` is still renamed per expansion.
     let mut stop = false;
     while !stop {
         let mut items = iter.collect_until(is_adjacent_kind);
@@ -190,29 +220,35 @@ fn consume_macro<'a>(iter: &mut LexerCursor<'a, '_>) -> Result<Macro<'a>, Prepro
 }
 
 fn consume_include<'a, P: TokenProvider<'a>>(
-    iter: &mut LexerCursor<'a, '_>, provider: &P, cache: &mut Cache<'a>
+    iter: &mut LexerCursor<'a, '_>, provider: &P, cache: &mut Cache<'a>, once: bool
 ) -> Result<Vec<Token<'a>>, PreprocessorReason> {
-    let next = iter.next().ok_or(EndOfFile)?;
+    let next = iter.next_adjacent().ok_or(EndOfFile)?;
 
     let TokenKind::StringLiteral(path) = &next.kind else {
         return Err(ExpectedString(next.kind.strip()))
     };
 
-    let new_provider = provider.extend(path)
+    let new_provider = provider.extend(&String::from_utf8_lossy(path))
         .map_err(|e| match e {
             ExtendError::NotSupported => IncludeUnsupported,
             ExtendError::FailedToRead(f) => FailedToFindFile(f),
             ExtendError::LexerFailed(e) => FailedToLexFile(e),
-            ExtendError::RecursiveInclude => RecursiveInclude
+            ExtendError::RecursiveInclude(chain) => RecursiveInclude(chain)
         })?;
 
+    if let Some(path) = new_provider.get_path() {
+        if !cache.included.insert(path) && once {
+            return Ok(vec![])
+        }
+    }
+
     preprocess_cached(&new_provider, new_provider.get(), cache)
         .map_err(|e| e.reason) // strip any location info ATM
 }
 
 fn expand_macro<'a, P: TokenProvider<'a>>(
     macro_info: Rc<Macro<'a>>,
-    parameters: Vec<Vec<Token<'a>>>,
+    parameters: Vec<Vec<&Token<'a>>>,
     provider: &P,
     cache: &mut Cache<'a>,
 ) -> Result<Vec<Token<'a>>, PreprocessorReason> {
@@ -229,41 +265,40 @@ fn expand_macro<'a, P: TokenProvider<'a>>(
         ));
     }
 
-    let label_names: HashMap<&str, String> = macro_info
+    let label_names: HashMap<&str, Rc<str>> = macro_info
         .labels
         .iter()
         .map(|name| {
             (
                 &name[..],
-                format!("_M{}_{}", name, {
+                Rc::from(format!("_M{}_{}", name, {
                     cache.seed += 1;
                     cache.seed
-                }),
+                })),
             )
         })
         .collect();
 
-    let mut parameter_map: HashMap<&'a str, Vec<TokenKind>> = HashMap::new();
-
-    for (index, value) in parameters.into_iter().enumerate() {
-        let name = macro_info.parameters[index];
-
-        parameter_map.insert(name, value.into_iter().map(|token| token.kind).collect());
-    }
+    let parameter_map: HashMap<&'a str, Vec<&Token<'a>>> = macro_info
+        .parameters
+        .iter()
+        .copied()
+        .zip(parameters)
+        .collect();
 
-    let mut result = vec![];
+    let mut result = Vec::with_capacity(macro_info.items.len());
 
     for token in &macro_info.items {
         let mapped_kind = match &token.kind {
             Parameter(name) => {
-                let kinds = parameter_map
+                let tokens = parameter_map
                     .get(name)
                     .ok_or_else(|| MacroUnknownParameter(name.to_string()))?;
                 
-                for kind in kinds {
+                for parameter in tokens {
                     result.push(Token {
                         location: token.location,
-                        kind: kind.clone(),
+                        kind: parameter.kind.clone(),
                     });
                 }
                 
@@ -294,82 +329,158 @@ fn expand_macro<'a, P: TokenProvider<'a>>(
     Ok(result)
 }
 
+// Eqv values may name other eqvs, which are expanded when used (so definition order does not matter).
+fn expand_eqv<'a>(
+    name: &SymbolName<'a>,
+    location: Location,
+    tokens: &HashMap<String, Eqv<'a>>,
+    chain: &mut Vec<String>,
+    result: &mut Vec<Token<'a>>,
+) -> Result<(), PreprocessorReason> {
+    let Some(eqv) = tokens.get(name.get()) else {
+        result.push(Token { location, kind: Symbol(name.clone()) });
+
+        return Ok(())
+    };
+
+    let recursive = chain.iter().any(|item| item == name.get());
+
+    chain.push(name.get().to_string());
+
+    if recursive {
+        return Err(RecursiveEqv(std::mem::take(chain)))
+    }
+
+    if chain.len() > MAX_EQV_DEPTH {
+        return Err(EqvTooDeep(chain[0].clone()))
+    }
+
+    for kind in &eqv.value {
+        match kind {
+            Symbol(inner) => expand_eqv(inner, location, tokens, chain, result)?,
+            _ => result.push(Token { location, kind: kind.clone() }),
+        }
+    }
+
+    chain.pop();
+
+    Ok(())
+}
+
 fn handle_symbol<'a, P: TokenProvider<'a>>(
     name: &SymbolName<'a>,
     location: Location,
+    instruction: bool, // symbol is where an instruction would start (MARS-style calls allowed)
     iter: &mut LexerCursor<'a, '_>,
     provider: &P,
     cache: &mut Cache<'a>,
-) -> Result<Vec<Token<'a>>, PreprocessorReason> {
-    if let Some(tokens) = cache.tokens.get(name.get()) {
-        return Ok(tokens
-            .iter()
-            .map(|kind| Token {
-                location,
-                kind: kind.clone(),
-            })
-            .collect());
+    result: &mut Vec<Token<'a>>,
+) -> Result<(), PreprocessorReason> {
+    if cache.tokens.contains_key(name.get()) {
+        return expand_eqv(name, location, &cache.tokens, &mut vec![], result)
     }
 
-    // Consumes nothing until we call iter.consume_until(position)
-    let (position, token) = iter.peek_adjacent();
+    let mut symbol = || {
+        result.push(Token { location, kind: Symbol(name.clone()) });
 
-    let Some(last) = token else {
-        return Ok(vec![Token { location, kind: Symbol(name.clone()) }])
+        Ok(())
     };
 
-    let start = iter.get_position();
+    let Some(macro_info) = cache.macros.get(name.get()).cloned() else {
+        return symbol()
+    };
 
-    match last.kind {
-        LeftBrace => {
-            iter.next(); /* pop */
-        }
-        _ => {
-            return Ok(vec![Token {
-                location,
-                kind: Symbol(name.clone()),
-            }])
-        }
-    }
+    // Consumes nothing until we call iter.set_position(position)
+    let (position, token) = iter.peek_adjacent();
 
-    // Treat as a macro!
-    iter.consume_until(position); // includes the item
-
-    let Some(macro_info) = cache.macros.get(name.get()) else {
-        iter.set_position(start);
-        return Ok(vec![Token {
-            location,
-            kind: Symbol(name.clone()),
-        }]);
+    let enclosed = match token.map(|token| &token.kind) {
+        Some(LeftBrace) => true,
+        Some(Colon) => return symbol(), // Label with the same name as the macro.
+        _ if instruction => false,
+        _ => return symbol(),
     };
 
+    // Treat as a macro!
+    if enclosed {
+        iter.set_position(position);
+        iter.next(); /* pop */
+    }
+
+    let parameters = consume_macro_arguments(iter, enclosed)?;
+
+    result.append(&mut expand_macro(macro_info, parameters, provider, cache)?);
+
+    Ok(())
+}
+
+// Whether a token belongs to the argument built so far (as opposed to starting a new one).
+// Keeps things like 8($sp), label+4 and -4 together even when commas are left out.
+fn continues_argument(current: &[&Token], kind: &TokenKind) -> bool {
+    let Some(last) = current.last() else { return false };
+
+    match kind {
+        LeftBrace => true,
+        TokenKind::Plus | TokenKind::Minus => matches!(last.kind, Symbol(_)),
+        TokenKind::Star | TokenKind::Slash => matches!(last.kind, Symbol(_) | IntegerLiteral(_) | RightBrace),
+        _ => matches!(last.kind, TokenKind::Plus | TokenKind::Minus | TokenKind::Star | TokenKind::Slash | LeftBrace),
+    }
+}
+
+// Call arguments, either after the opening brace up to and including the matching right brace
+// (enclosed), or up to (not including) the end of the line for `name arg1, arg2` calls.
+// Top level commas always split arguments, braces nest.
+fn consume_macro_arguments<'a, 'b>(
+    iter: &mut LexerCursor<'a, 'b>,
+    enclosed: bool,
+) -> Result<Vec<Vec<&'b Token<'a>>>, PreprocessorReason> {
     let mut parameters = vec![];
+    let mut current: Vec<&'b Token<'a>> = vec![];
+    let mut depth = 0usize;
 
     loop {
-        let Some(next) = iter.next_adjacent() else { return Err(EndOfFile) };
+        let next = match iter.peek() {
+            Some(next) => next,
+            None if enclosed => return Err(EndOfFile),
+            None => break,
+        };
+
+        match &next.kind {
+            NewLine if !enclosed => break,
+            _ => iter.next(),
+        };
 
-        match next.kind {
-            RightBrace => break,
+        match &next.kind {
+            TokenKind::Comment(_) => continue,
             NewLine => return Err(ExpectedRightBrace(next.kind.strip())),
-            TokenKind::Plus | TokenKind::Minus => {
-                let mut result = vec![next.clone()];
-                
-                if let (position, Some(number)) = iter.peek_adjacent() {
-                    if let TokenKind::IntegerLiteral(_) = number.kind {
-                        iter.set_position(position);
-                        iter.next();
-                        
-                        result.push(number.clone());
-                    }
+            TokenKind::Comma if depth == 0 => {
+                if !current.is_empty() {
+                    parameters.push(std::mem::take(&mut current));
                 }
-                
-                parameters.push(result);
             }
-            _ => parameters.push(vec![next.clone()]),
+            RightBrace if depth == 0 && enclosed => break,
+            kind => {
+                match kind {
+                    LeftBrace => depth += 1,
+                    RightBrace => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+
+                let joined = depth > 0 || *kind == RightBrace || continues_argument(&current, kind);
+
+                if !joined && !current.is_empty() {
+                    parameters.push(std::mem::take(&mut current));
+                }
+
+                current.push(next);
+            }
         }
     }
 
-    expand_macro(macro_info.clone(), parameters, provider, cache)
+    if !current.is_empty() {
+        parameters.push(current);
+    }
+
+    Ok(parameters)
 }
 
 fn preprocess_cached<'a, P: TokenProvider<'a>>(
@@ -380,8 +491,6 @@ fn preprocess_cached<'a, P: TokenProvider<'a>>(
     let mut iter = LexerCursor::new(items);
     let mut result: Vec<Token> = Vec::with_capacity(items.len());
 
-    let watched_directives = HashSet::from(["eqv", "macro", "include", "file_path"]);
-
     while let Some(element) = iter.next() {
         let fail = |reason: PreprocessorReason| PreprocessorError {
             location: element.location,
@@ -389,19 +498,25 @@ fn preprocess_cached<'a, P: TokenProvider<'a>>(
         };
 
         match &element.kind {
-            Directive(directive) if watched_directives.contains(directive) => match *directive {
+            Directive(directive) if matches!(*directive, "eqv" | "macro" | "include" | "include_once" | "file_path") => match *directive {
                 "eqv" => {
                     let (key, value) = consume_eqv(&mut iter).map_err(fail)?;
 
-                    cache.tokens.insert(key, value);
+                    if let Some(previous) = cache.tokens.get(&key) {
+                        return Err(fail(EqvRedefined(key, previous.location)))
+                    }
+
+                    cache.tokens.insert(key, Eqv { location: element.location, value });
                 }
                 "macro" => {
                     let value = consume_macro(&mut iter).map_err(fail)?;
 
-                    cache.macros.insert(value.name.clone(), Rc::new(value));
+                    cache.macros.insert(value.name.to_string(), Rc::new(value));
                 }
-                "include" => {
-                    let tokens = consume_include(&mut iter, provider, cache)
+                "include" | "include_once" => {
+                    let once = *directive == "include_once";
+
+                    let tokens = consume_include(&mut iter, provider, cache, once)
                         .map_err(fail)?;
 
                     result.extend(tokens);
@@ -412,7 +527,7 @@ fn preprocess_cached<'a, P: TokenProvider<'a>>(
                     if let Some(path) = path {
                         result.push(Token {
                             location: element.location,
-                            kind: TokenKind::StringLiteral(path)
+                            kind: TokenKind::StringLiteral(path.into_bytes())
                         })
                     } else {
                         return Err(fail(NoFilePathAssociated))
@@ -421,10 +536,14 @@ fn preprocess_cached<'a, P: TokenProvider<'a>>(
                 _ => panic!(), // ??
             },
             Symbol(name) => {
-                let mut elements = handle_symbol(name, element.location, &mut iter, provider, cache)
-                    .map_err(fail)?;
+                let instruction = result
+                    .iter()
+                    .rev()
+                    .find(|token| !matches!(token.kind, TokenKind::Comment(_)))
+                    .map_or(true, |token| matches!(token.kind, NewLine | Colon));
 
-                result.append(&mut elements)
+                handle_symbol(name, element.location, instruction, &mut iter, provider, cache, &mut result)
+                    .map_err(fail)?;
             }
 
             _ => result.push(element.clone()),
@@ -447,6 +566,82 @@ pub fn mark_parameters_as_error(result: Vec<Token>) -> Result<Vec<Token>, Prepro
     Ok(result)
 }
 
+// At the start of a statement and followed by a colon, comments aside.
+fn defines_label(tokens: &[Token], index: usize) -> bool {
+    let starts = tokens[..index].iter().rev()
+        .find(|token| !matches!(token.kind, Comment(_)))
+        .map_or(true, |token| matches!(token.kind, NewLine | Colon));
+
+    let colon = tokens[index + 1..].iter()
+        .find(|token| !matches!(token.kind, Comment(_)))
+        .is_some_and(|token| token.kind == Colon);
+
+    starts && colon
+}
+
+// Names given to numeric labels by resolve_numeric_labels, the only labels with a colon in them.
+pub fn is_numeric_label(name: &str) -> bool {
+    name.contains(':')
+}
+
+struct NumericLabel {
+    index: usize, // of the token
+    number: u64,
+    scope: usize, // named labels before it
+}
+
+// Numeric labels (`1:`) can be defined any number of times, 1b and 1f are the closest `1:` before or after.
+// Neither looks past a named label, so every function can have its own `1:` loop.
+// Each definition becomes a label named `1:0`, `1:1`, ... (number, then its place among all numeric labels),
+// which no symbol can be. The builder keeps these out of Binary::labels, see is_numeric_label.
+fn resolve_numeric_labels(mut tokens: Vec<Token>) -> Result<Vec<Token>, PreprocessorError> {
+    let mut scope = 0;
+    let mut definitions = vec![];
+    let mut references = vec![];
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            Symbol(_) if defines_label(&tokens, index) => scope += 1,
+            IntegerLiteral(number) if defines_label(&tokens, index) => {
+                definitions.push(NumericLabel { index, number, scope })
+            }
+            NumericReference(number, forward) => references.push((index, number, forward, scope)),
+            _ => {}
+        }
+    }
+
+    let name = |definition: usize| {
+        Symbol(Owned(Rc::from(format!("{}:{definition}", definitions[definition].number))))
+    };
+
+    for (index, number, forward, scope) in references {
+        let split = definitions.partition_point(|definition| definition.index < index);
+        let in_scope = |definition: &usize| definitions[*definition].scope == scope;
+        let matches = |definition: &usize| definitions[*definition].number == number;
+
+        let found = if forward {
+            (split..definitions.len()).take_while(in_scope).find(matches)
+        } else {
+            (0..split).rev().take_while(in_scope).find(matches)
+        };
+
+        let Some(definition) = found else {
+            return Err(PreprocessorError {
+                location: tokens[index].location,
+                reason: MissingNumericLabel(number, forward),
+            })
+        };
+
+        tokens[index].kind = name(definition);
+    }
+
+    for (definition, label) in definitions.iter().enumerate() {
+        tokens[label.index].kind = name(definition);
+    }
+
+    Ok(tokens)
+}
+
 pub fn preprocess<'a, P: TokenProvider<'a>>(
     provider: &P
 ) -> Result<Vec<Token<'a>>, PreprocessorError> {
@@ -454,4 +649,92 @@ pub fn preprocess<'a, P: TokenProvider<'a>>(
 
     preprocess_cached(provider, provider.get(), &mut cache)
         .and_then(mark_parameters_as_error)
+        .and_then(resolve_numeric_labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::preprocessor::PreprocessorReason::{EqvRedefined, MissingNumericLabel};
+    use crate::assembler::binary::Binary;
+    use crate::assembler::project::file_error;
+    use crate::assembler::source::FileProviderPool;
+    use crate::assembler::string::{assemble_from, assemble_with_provider, SourceError};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn words(binary: &Binary) -> Vec<u32> {
+        binary.regions[0].data.chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn names(binary: &Binary) -> Vec<&str> {
+        let mut result: Vec<&str> = binary.labels.keys().map(|name| name.as_str()).collect();
+        result.sort();
+
+        result
+    }
+
+    fn missing(result: Result<Binary, SourceError>) -> Option<(u64, bool)> {
+        match result {
+            Err(SourceError::Preprocessor(error)) => match error.reason {
+                MissingNumericLabel(number, forward) => Some((number, forward)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn numeric_labels_stay_in_scope() {
+        let binary = assemble_from(
+            "first:\n1: addi $t0, $t0, 1\nbne $t0, $zero, 1b\nj 1f\n1: nop\nsecond:\n1: b 1b\n"
+        ).unwrap();
+
+        // bne back to the first 1:, j to the second, b to the one under second.
+        assert_eq!(words(&binary), vec![0x21080001, 0x1500FFFE, 0x08100003, 0, 0x1000FFFF]);
+        assert_eq!(names(&binary), vec!["first", "second"]);
+
+        assert_eq!(missing(assemble_from("first:\n1: nop\nsecond:\nb 1b\n")), Some((1, false)));
+        assert_eq!(missing(assemble_from("first:\nb 1f\nsecond:\n1: nop\n")), Some((1, true)));
+    }
+
+    #[test]
+    fn numeric_labels_repeat_across_included_files() {
+        let assemble = |main: &str| {
+            let pool = FileProviderPool::in_memory(HashMap::from([
+                ("main.asm".to_string(), format!(".include \"other.asm\"\n{main}")),
+                ("other.asm".to_string(), "helper:\n1: b 1f\n1: b 1b\n".to_string()),
+            ]));
+
+            assemble_with_provider(&pool.in_memory_provider("main.asm").unwrap())
+        };
+
+        let binary = assemble("main:\n1: b 1f\n1: b 1b\n").unwrap();
+
+        assert_eq!(words(&binary), vec![0x10000000, 0x1000FFFF, 0x10000000, 0x1000FFFF]);
+        assert_eq!(names(&binary), vec!["helper", "main"]);
+
+        // The 1: labels in other.asm belong to helper, main can't reach them.
+        assert_eq!(missing(assemble("main:\nb 1b\n")), Some((1, false)));
+    }
+
+    #[test]
+    fn eqv_redefinitions_name_the_first_one() {
+        let pool = FileProviderPool::in_memory(HashMap::from([
+            ("main.asm".to_string(), ".include \"other.asm\"\nnop\n.eqv SIZE 8\n".to_string()),
+            ("other.asm".to_string(), "\n.eqv SIZE 4\n".to_string()),
+        ]));
+
+        let error = assemble_with_provider(&pool.in_memory_provider("main.asm").unwrap()).unwrap_err();
+
+        let SourceError::Preprocessor(inner) = &error else { panic!("expected a preprocessor error") };
+        assert!(matches!(&inner.reason, EqvRedefined(name, _) if name == "SIZE"));
+
+        let error = file_error(&pool, Path::new("main.asm"), error);
+
+        assert_eq!(error.place.as_deref(), Some("main.asm:3"));
+        assert_eq!(error.first_definition.as_deref(), Some("other.asm:2"));
+        assert!(error.to_string().ends_with("(first defined at other.asm:2)"), "{error}");
+    }
 }
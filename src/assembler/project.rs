@@ -0,0 +1,373 @@
+use crate::assembler::assembler_util::AssemblerError;
+use crate::assembler::assembler_util::AssemblerReason::{DuplicateLabel, LabelOutOfRange, MisalignedTarget};
+use crate::assembler::binary::{Binary, BinarySection, EntrySource, Relocation};
+use crate::assembler::binary_builder::BinaryBuilder;
+use crate::assembler::core::{emit_into, AssembleOptions};
+use crate::assembler::instructions::INSTRUCTIONS;
+use crate::assembler::lexer::{lex_recovering, Location};
+use crate::assembler::preprocessor::PreprocessorReason::EqvRedefined;
+use crate::assembler::preprocessor::{preprocess, PreprocessorError};
+use crate::assembler::source::FileProviderPool;
+use crate::assembler::string::SourceError;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub struct FileError {
+    pub path: PathBuf, // the unit being assembled
+    pub place: Option<String>, // "path:line", the path can be a file the unit includes
+    pub first_definition: Option<String>, // "path:line" of the other definition, for duplicate labels and eqvs
+    pub definition: Option<String>, // "path:line" of the label a misaligned branch or jump targets, or data that doesn't fit
+    pub error: SourceError,
+}
+
+impl Error for FileError {}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let place = self.place.clone().unwrap_or_else(|| self.path.to_string_lossy().to_string());
+
+        write!(f, "{place}: {}", self.error)?;
+
+        if let Some(first) = &self.first_definition {
+            write!(f, " (first defined at {first})")?;
+        }
+
+        if let Some(definition) = &self.definition {
+            write!(f, " (defined at {definition})")?;
+        }
+
+        Ok(())
+    }
+}
+
+// Every problem found, across all files. Files are still checked after one of them fails.
+#[derive(Debug)]
+pub struct AssemblerErrors {
+    pub errors: Vec<FileError>,
+}
+
+impl Display for AssemblerErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, error) in self.errors.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+
+            write!(f, "{error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for AssemblerErrors {}
+
+struct Unit {
+    path: PathBuf,
+    builder: BinaryBuilder,
+}
+
+struct Global {
+    address: u32,
+    location: Location,
+}
+
+// Places the error (and the first definition, for duplicate labels and eqvs) as "path:line", through included files too.
+pub fn file_error(pool: &FileProviderPool, path: &Path, error: SourceError) -> FileError {
+    let first_definition = match &error {
+        SourceError::Assembler(AssemblerError { reason: DuplicateLabel(_, first), .. }) => pool.describe(*first),
+        SourceError::Preprocessor(PreprocessorError { reason: EqvRedefined(_, first), .. }) => pool.describe(*first),
+        _ => None,
+    };
+
+    let definition = match &error {
+        SourceError::Assembler(AssemblerError { reason: MisalignedTarget(_, _, Some(at)), .. }) => pool.describe(*at),
+        SourceError::Assembler(AssemblerError { reason: LabelOutOfRange(_, _, _, Some(at)), .. }) => pool.describe(*at),
+        _ => None,
+    };
+
+    FileError {
+        path: path.to_path_buf(),
+        place: error.start().and_then(|location| pool.describe(location)),
+        first_definition,
+        definition,
+        error,
+    }
+}
+
+// Lexing stops a file at its first error, the rest of that source is lexed again so later lines still report.
+pub fn file_errors(pool: &FileProviderPool, path: &Path, error: SourceError) -> Vec<FileError> {
+    let SourceError::Lexer(first) = &error else {
+        return vec![file_error(pool, path, error)]
+    };
+
+    let Some(source) = pool.source(first.location.source) else {
+        return vec![file_error(pool, path, error)]
+    };
+
+    let (_, errors) = lex_recovering(&source, first.location.source);
+
+    errors.into_iter()
+        .map(|error| file_error(pool, path, error.into()))
+        .collect()
+}
+
+// Emits one file, placing each of its sections right after where the previous file left off.
+fn emit_unit(
+    pool: &FileProviderPool, path: &Path, source: String, origins: HashMap<BinarySection, u32>
+) -> Result<BinaryBuilder, SourceError> {
+    let provider = pool.provider_sourced(source, Rc::new(path.to_path_buf()))?.to_provider();
+    let items = preprocess(&provider)?;
+
+    let mut builder = BinaryBuilder::new();
+    builder.origins = origins;
+
+    // Relaxing branches resizes a unit after the next one has been placed behind it, so it stays off here.
+    Ok(emit_into(&items, &INSTRUCTIONS, AssembleOptions::default(), builder)?)
+}
+
+// Labels named by .globl are shared between files, every other label stays in the file that defines it.
+// Each file keeps its own .include chain. Sections follow one another in file order, like MARS.
+pub fn assemble_project(files: Vec<(PathBuf, String)>) -> Result<Binary, AssemblerErrors> {
+    let pool = FileProviderPool::new();
+
+    let mut errors = vec![];
+    let mut units = vec![];
+    let mut origins = HashMap::new();
+
+    for (path, source) in files {
+        match emit_unit(&pool, &path, source, origins.clone()) {
+            Ok(builder) => {
+                origins.extend(builder.ends());
+
+                units.push(Unit { path, builder })
+            }
+            Err(error) => errors.extend(file_errors(&pool, &path, error)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(AssemblerErrors { errors })
+    }
+
+    let mut globals: HashMap<String, Global> = HashMap::new();
+
+    for unit in &units {
+        for name in &unit.builder.globals {
+            let (Some(address), Some(location)) = (unit.builder.labels.get(name), unit.builder.definitions.get(name)) else {
+                continue // used here, defined in another file
+            };
+
+            match globals.entry(name.clone()) {
+                Entry::Occupied(first) => {
+                    let error = AssemblerError {
+                        location: Some(*location),
+                        reason: DuplicateLabel(name.clone(), first.get().location),
+                    };
+
+                    errors.push(file_error(&pool, &unit.path, error.into()))
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(Global { address: *address, location: *location });
+                }
+            }
+        }
+    }
+
+    let mut binary = Binary::new();
+    let mut entry = None;
+
+    for (name, global) in &globals {
+        binary.labels.insert(name.clone(), global.address);
+    }
+
+    for mut unit in units {
+        for (name, global) in &globals {
+            unit.builder.labels.entry(name.clone()).or_insert(global.address);
+        }
+
+        let has_entry = unit.builder.entry.is_some();
+
+        let part = match unit.builder.build() {
+            Ok(part) => part,
+            Err(error) => {
+                errors.push(file_error(&pool, &unit.path, error.into()));
+
+                continue
+            }
+        };
+
+        if has_entry && entry.is_none() {
+            entry = Some(part.entry)
+        }
+
+        let first = binary.regions.len();
+
+        binary.relocations.extend(part.relocations.into_iter()
+            .map(|relocation| Relocation { region: relocation.region + first, ..relocation }));
+
+        binary.regions.extend(part.regions);
+        binary.breakpoints.extend(part.breakpoints);
+        binary.warnings.extend(part.warnings);
+        binary.globals.extend(part.globals);
+
+        // Locals can repeat between files, the first file to define a name keeps it.
+        for (name, address) in part.labels {
+            binary.labels.entry(name).or_insert(address);
+        }
+
+        for (name, target) in part.aliases {
+            binary.aliases.entry(name).or_insert(target);
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(AssemblerErrors { errors })
+    }
+
+    if let Some(entry) = entry {
+        binary.entry = entry;
+        binary.entry_source = EntrySource::Directive;
+    } else {
+        binary.default_entry();
+    }
+
+    Ok(binary)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_util::AssemblerReason::{DuplicateLabel, UnknownLabel};
+    use crate::assembler::project::assemble_project;
+    use crate::assembler::string::SourceError;
+    use crate::unit::device::UnitDevice;
+    use crate::unit::register::RegisterName::{T0, T1};
+    use crate::unit::terminal::OutputWait;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn files(sources: &[(&str, &str)]) -> Vec<(PathBuf, String)> {
+        sources.iter().map(|(path, source)| (PathBuf::from(path), source.to_string())).collect()
+    }
+
+    #[test]
+    fn globals_link_across_files() {
+        let binary = assemble_project(files(&[
+            ("main.asm", "
+                .globl main
+                main:
+                    li $t0, 4
+                    jal add_three
+                    li $v0, 10
+                    syscall
+                done:
+            "),
+            ("list.asm", "
+                .globl add_three
+                add_three:
+                    addi $t0, $t0, 3
+                    j done
+                done:
+                    move $t1, $ra
+                    jr $ra
+            "),
+        ])).unwrap();
+
+        // The second file's text follows the first's. Both define done, the binary keeps the first file's.
+        assert_eq!(binary.labels["main"], 0x400000);
+        assert_eq!(binary.labels["add_three"], 0x400010);
+        assert_eq!(binary.labels["done"], 0x400010);
+
+        let device = UnitDevice::new(binary);
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert_eq!(device.get(T0), 7);
+        // j done went to list.asm's own done, which saw jal's return address.
+        assert_eq!(device.get(T1), 0x400008);
+    }
+
+    #[test]
+    fn locals_stay_in_their_file() {
+        let error = assemble_project(files(&[
+            ("main.asm", "nop\njal helper\n"),
+            ("other.asm", "helper: jr $ra\n"),
+        ])).unwrap_err();
+
+        assert_eq!(error.errors.len(), 1);
+
+        let error = &error.errors[0];
+
+        assert_eq!(error.path, PathBuf::from("main.asm"));
+        assert_eq!(error.place.as_deref(), Some("main.asm:2"));
+        assert!(matches!(&error.error, SourceError::Assembler(inner) if matches!(&inner.reason, UnknownLabel(name) if name == "helper")));
+    }
+
+    #[test]
+    fn duplicate_globals_name_both_files() {
+        let error = assemble_project(files(&[
+            ("a.asm", ".globl shared\nshared: nop\n"),
+            ("b.asm", "nop\n.globl shared\nshared: nop\n"),
+        ])).unwrap_err();
+
+        assert_eq!(error.errors.len(), 1);
+
+        let error = &error.errors[0];
+
+        assert!(matches!(&error.error, SourceError::Assembler(inner) if matches!(&inner.reason, DuplicateLabel(name, _) if name == "shared")));
+        assert_eq!(error.place.as_deref(), Some("b.asm:3"));
+        assert_eq!(error.first_definition.as_deref(), Some("a.asm:2"));
+        assert_eq!(error.to_string().lines().count(), 1);
+        assert!(error.to_string().ends_with("(first defined at a.asm:2)"), "{error}");
+    }
+
+    #[test]
+    fn every_file_reports_its_errors() {
+        let error = assemble_project(files(&[
+            ("a.asm", "bad $t0\n"),
+            ("b.asm", "nop\nnop\nworse $t0\n"),
+        ])).unwrap_err();
+
+        let places: Vec<_> = error.errors.iter().map(|error| error.place.as_deref()).collect();
+
+        assert_eq!(places, [Some("a.asm:1"), Some("b.asm:3")]);
+    }
+
+    #[test]
+    fn unterminated_strings_keep_later_errors() {
+        let error = assemble_project(files(&[
+            ("a.asm", ".data\n.asciiz \"open\n.byte 1\n.byte @\n"),
+        ])).unwrap_err();
+
+        let places: Vec<_> = error.errors.iter().map(|error| error.place.as_deref()).collect();
+
+        assert_eq!(places, [Some("a.asm:2"), Some("a.asm:4")]);
+    }
+
+    #[test]
+    fn includes_resolve_within_each_unit() {
+        let directory = std::env::temp_dir().join(format!("titan-project-{}", std::process::id()));
+
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(directory.join("lib/value.asm"), "li $t1, 5\n").unwrap();
+
+        let binary = assemble_project(vec![
+            (directory.join("main.asm"), "li $t0, 1\n".into()),
+            (directory.join("lib/helper.asm"), ".include \"value.asm\"\n".into()),
+        ]);
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        let device = UnitDevice::new(binary.unwrap());
+
+        device.step().unwrap();
+        device.step().unwrap();
+
+        assert_eq!(device.get(T0), 1);
+        assert_eq!(device.get(T1), 5);
+    }
+}
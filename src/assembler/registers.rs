@@ -1,5 +1,8 @@
 use num_derive::{FromPrimitive, ToPrimitive};
+use num::FromPrimitive;
+use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, ToPrimitive, FromPrimitive)]
 pub enum RegisterSlot {
@@ -120,3 +123,81 @@ impl Display for RegisterSlot {
         write!(f, "${}", self.as_string())
     }
 }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownRegisterError(pub String);
+
+impl Display for UnknownRegisterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown register \"{}\"", self.0)
+    }
+}
+
+impl Error for UnknownRegisterError {}
+
+// Every spelling the lexer accepts after a $: names in any case (v0, V0) and numbers (3). The $ itself is optional.
+impl FromStr for RegisterSlot {
+    type Err = UnknownRegisterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.strip_prefix('$').unwrap_or(s);
+
+        let slot = if name.bytes().any(|c| c.is_ascii_uppercase()) {
+            RegisterSlot::from_string(&name.to_ascii_lowercase())
+        } else {
+            RegisterSlot::from_string(name)
+        };
+
+        // Digits only, u64::from_str would take +3 too.
+        let number = || {
+            let digits = !name.is_empty() && name.bytes().all(|c| c.is_ascii_digit());
+
+            RegisterSlot::from_u64(u64::from_str(name).ok().filter(|_| digits)?)
+        };
+
+        slot.or_else(number).ok_or_else(|| UnknownRegisterError(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::registers::{RegisterSlot, UnknownRegisterError};
+
+    #[test]
+    fn names_and_numbers_parse() {
+        let cases = [
+            ("$v0", RegisterSlot::Value0),
+            ("v0", RegisterSlot::Value0),
+            ("$V0", RegisterSlot::Value0),
+            ("$3", RegisterSlot::Value1),
+            ("3", RegisterSlot::Value1),
+            ("03", RegisterSlot::Value1),
+            ("$zero", RegisterSlot::Zero),
+            ("0", RegisterSlot::Zero),
+            ("$31", RegisterSlot::ReturnAddress),
+            ("Sp", RegisterSlot::StackPointer),
+        ];
+
+        for (name, slot) in cases {
+            assert_eq!(name.parse(), Ok(slot), "{name}")
+        }
+
+        // Every name round trips through Display.
+        for index in 0..32 {
+            let slot: RegisterSlot = index.to_string().parse().unwrap();
+
+            assert_eq!(slot.to_string().parse(), Ok(slot));
+        }
+    }
+
+    #[test]
+    fn unknown_names_fail() {
+        for name in ["", "$", "32", "+3", "-1", "$$v0", "f0", "$f12", "v2", " v0"] {
+            let name_without = name.strip_prefix('$').unwrap_or(name);
+
+            assert_eq!(name.parse::<RegisterSlot>(), Err(UnknownRegisterError(name_without.to_string())), "{name}");
+        }
+
+        assert_eq!(UnknownRegisterError("v2".into()).to_string(), "Unknown register \"v2\"");
+    }
+}
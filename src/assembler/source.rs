@@ -1,17 +1,18 @@
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::fs;
 use typed_arena::Arena;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
-use crate::assembler::lexer::{lex, lex_with_source, LexerError, Token};
+use crate::assembler::lexer::{lex, lex_with_source, LexerError, Location, Token};
 use crate::assembler::source::ExtendError::{FailedToRead, LexerFailed, NotSupported, RecursiveInclude};
 
+#[derive(Debug)]
 pub enum ExtendError {
     NotSupported,
     FailedToRead(String),
     LexerFailed(LexerError),
-    RecursiveInclude
+    RecursiveInclude(Vec<String>) // include chain, ending with the repeated file
 }
 
 pub trait TokenProvider<'a>: Sized {
@@ -27,11 +28,11 @@ pub struct HoldingProvider<'a> {
 }
 
 impl<'a> HoldingProvider<'a> {
-    pub fn new(tokens: Vec<Token<'a>>) -> HoldingProvider {
+    pub fn new(tokens: Vec<Token<'a>>) -> HoldingProvider<'a> {
         HoldingProvider { tokens }
     }
 
-    pub fn from_source(source: &str) -> Result<HoldingProvider, LexerError> {
+    pub fn from_source(source: &str) -> Result<HoldingProvider<'_>, LexerError> {
         Ok(HoldingProvider { tokens: lex(source)? })
     }
 }
@@ -57,20 +58,83 @@ pub struct FileProviderSource {
     pub source: Rc<String>
 }
 
+// Drops . and .. without touching the disk, ".." past the start is kept.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => { }
+            Component::ParentDir => {
+                if !result.pop() {
+                    result.push(component)
+                }
+            }
+            component => result.push(component)
+        }
+    }
+
+    result
+}
+
 pub struct FileProviderPool {
     arena: Arena<Rc<String>>,
-    sources: RefCell<Vec<FileProviderSource>>
+    sources: RefCell<Vec<FileProviderSource>>,
+    files: Option<HashMap<PathBuf, String>>, // read instead of the disk when set (keys normalized)
+    pub search_paths: Vec<PathBuf> // tried in order after the including file's directory and the working directory
 }
 
+// Includes resolve the same way as on disk, just against the pool's files.
+pub type InMemoryTokenProvider<'a> = FileProvider<'a>;
+
 impl FileProviderPool {
     pub fn new() -> FileProviderPool {
         FileProviderPool {
             arena: Arena::new(),
-            sources: RefCell::new(Vec::new())
+            sources: RefCell::new(Vec::new()),
+            files: None,
+            search_paths: vec![]
+        }
+    }
+
+    pub fn with_search_paths(search_paths: Vec<PathBuf>) -> FileProviderPool {
+        FileProviderPool { search_paths, ..FileProviderPool::new() }
+    }
+
+    // Virtual paths to sources, nothing is read from disk (ex. for wasm).
+    pub fn in_memory(files: HashMap<String, String>) -> FileProviderPool {
+        let files = files.into_iter()
+            .map(|(path, source)| (normalize(Path::new(&path)), source))
+            .collect();
+
+        FileProviderPool { files: Some(files), ..FileProviderPool::new() }
+    }
+
+    // The in-memory file at path, as the root of an assembly.
+    pub fn in_memory_provider(&self, path: &str) -> Result<InMemoryTokenProvider<'_>, ExtendError> {
+        Ok(self.provider(Rc::new(normalize(Path::new(path))))?.to_provider())
+    }
+
+    // Canonical form of path if it exists, used for lookups and to detect include cycles.
+    fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        match &self.files {
+            Some(files) => {
+                let path = normalize(path);
+
+                files.contains_key(&path).then_some(path)
+            }
+            None => fs::canonicalize(path).ok()
+        }
+    }
+
+    fn read(&self, path: &Path) -> Option<String> {
+        match &self.files {
+            Some(files) => files.get(&normalize(path)).cloned(),
+            None => fs::read_to_string(path).ok()
         }
     }
 
-    pub fn provider_sourced(&self, source: String, path: Rc<PathBuf>) -> Result<FileInfo, LexerError> {
+    pub fn provider_sourced(&self, source: String, path: Rc<PathBuf>) -> Result<FileInfo<'_>, LexerError> {
         let (id, tokens) = {
             let source = Rc::new(source);
 
@@ -83,7 +147,7 @@ impl FileProviderPool {
 
             let item = self.arena.alloc(source);
 
-            (id, lex_with_source(&**item, id)?)
+            (id, lex_with_source(item, id)?)
         };
 
         Ok(FileInfo {
@@ -94,11 +158,32 @@ impl FileProviderPool {
         })
     }
 
-    pub fn provider(&self, path: Rc<PathBuf>) -> Result<FileInfo, ExtendError> {
-        let source = fs::read_to_string(&*path)
-            .map_err(|_| FailedToRead(path.to_string_lossy().to_string()))?;
+    pub fn source(&self, id: usize) -> Option<Rc<String>> {
+        self.sources.borrow().get(id).map(|item| item.source.clone())
+    }
+
+    // "path:line" for messages, line numbers start at 1.
+    pub fn describe(&self, location: Location) -> Option<String> {
+        let sources = self.sources.borrow();
+        let item = sources.get(location.source)?;
+
+        let before = item.source.get(..location.index).unwrap_or(&item.source);
+        let line = before.matches('\n').count() + 1;
+
+        Some(format!("{}:{}", item.path.to_string_lossy(), line))
+    }
+
+    pub fn provider(&self, path: Rc<PathBuf>) -> Result<FileInfo<'_>, ExtendError> {
+        let source = self.read(&path)
+            .ok_or_else(|| FailedToRead(path.to_string_lossy().to_string()))?;
+
+        self.provider_sourced(source, path).map_err(LexerFailed)
+    }
+}
 
-        self.provider_sourced(source, path).map_err(|e| LexerFailed(e))
+impl Default for FileProviderPool {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -111,19 +196,21 @@ pub struct FileInfo<'a> {
 
 impl<'a> FileInfo<'a> {
     pub fn to_provider(self) -> FileProvider<'a> {
-        // Don't canonicalize.
-        let path = self.path.clone();
+        // Don't canonicalize the path we report, only the one used to detect cycles.
+        let path = self.pool.resolve(&self.path)
+            .map(Rc::new)
+            .unwrap_or_else(|| self.path.clone());
 
         FileProvider {
             info: self,
-            history: HashSet::from([path]),
+            history: vec![path],
         }
     }
 }
 
 pub struct FileProvider<'a> {
     info: FileInfo<'a>,
-    history: HashSet<Rc<PathBuf>>
+    history: Vec<Rc<PathBuf>> // canonical paths, from the root file down to this one
 }
 
 impl<'a> TokenProvider<'a> for FileProvider<'a> {
@@ -137,24 +224,154 @@ impl<'a> TokenProvider<'a> for FileProvider<'a> {
     }
 
     fn extend(&self, path: &str) -> Result<Self, ExtendError> {
+        // Relative to the including file first, then to the working directory, then the search paths.
+        let pool = self.info.pool;
+
         let file = self.info.path.parent()
             .unwrap_or(&self.info.path)
             .join(path);
 
-        let file = fs::canonicalize(&file)
-            .map_err(|_| FailedToRead(file.to_string_lossy().to_string()))?;
+        let file = pool.resolve(&file)
+            .or_else(|| pool.resolve(Path::new(path)))
+            .or_else(|| pool.search_paths.iter().find_map(|directory| pool.resolve(&directory.join(path))))
+            .ok_or_else(|| FailedToRead(file.to_string_lossy().to_string()))?;
 
         let file = Rc::new(file);
 
         let mut history = self.history.clone();
+        let recursive = history.contains(&file);
 
-        if !history.insert(file.clone()) {
-            return Err(RecursiveInclude)
+        history.push(file.clone());
+
+        if recursive {
+            return Err(RecursiveInclude(history
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect()))
         }
 
         Ok(FileProvider {
-            info: self.info.pool.provider(file)?,
+            info: pool.provider(file)?,
             history
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::preprocessor::PreprocessorReason::{FailedToFindFile, RecursiveInclude};
+    use crate::assembler::source::FileProviderPool;
+    use crate::assembler::string::{assemble_with_provider, SourceError};
+    use crate::unit::device::UnitDevice;
+    use crate::unit::register::RegisterName::{T0, T1};
+    use std::fs;
+
+    fn pool(files: &[(&str, &str)]) -> FileProviderPool {
+        FileProviderPool::in_memory(files.iter().map(|(path, source)| (path.to_string(), source.to_string())).collect())
+    }
+
+    #[test]
+    fn programs_assemble_from_strings() {
+        let pool = pool(&[
+            ("src/main.asm", ".include \"lib/add.asm\"\nli $t0, 4\njal add_three\n"),
+            ("src/lib/add.asm", ".include \"../value.asm\"\nj skip\nadd_three: addi $t0, $t0, 3\njr $ra\nskip:\n"),
+            ("src/value.asm", "li $t1, 5\n"),
+        ]);
+
+        let binary = assemble_with_provider(&pool.in_memory_provider("src/main.asm").unwrap()).unwrap();
+        let device = UnitDevice::new(binary);
+
+        for _ in 0..6 {
+            device.step().unwrap();
+        }
+
+        assert_eq!(device.get(T0), 7);
+        assert_eq!(device.get(T1), 5);
+    }
+
+    #[test]
+    fn includes_fall_back_to_the_root() {
+        // Not next to the including file, but at the top of the virtual tree (the working directory on disk).
+        let pool = pool(&[
+            ("src/main.asm", ".include \"shared.asm\"\n"),
+            ("shared.asm", "li $t0, 1\n"),
+        ]);
+
+        assert!(assemble_with_provider(&pool.in_memory_provider("src/main.asm").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn missing_and_recursive_includes_fail() {
+        let pool = pool(&[
+            ("main.asm", ".include \"gone.asm\"\n"),
+            ("a.asm", ".include \"b.asm\"\n"),
+            ("b.asm", ".include \"./a.asm\"\n"),
+        ]);
+
+        let error = assemble_with_provider(&pool.in_memory_provider("main.asm").unwrap()).unwrap_err();
+
+        assert!(matches!(error, SourceError::Preprocessor(error) if matches!(&error.reason, FailedToFindFile(path) if path == "gone.asm")));
+
+        let error = assemble_with_provider(&pool.in_memory_provider("a.asm").unwrap()).unwrap_err();
+
+        assert!(matches!(error, SourceError::Preprocessor(error) if matches!(
+            &error.reason, RecursiveInclude(chain) if chain == &["a.asm", "b.asm", "a.asm"]
+        )));
+
+        assert!(pool.in_memory_provider("nowhere.asm").is_err());
+    }
+
+    #[test]
+    fn search_paths_are_tried_last() {
+        let directory = std::env::temp_dir().join(format!("titan-search-{}", std::process::id()));
+
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(directory.join("lib/value.asm"), "li $t0, 9\n").unwrap();
+
+        let source = ".include \"value.asm\"\n".to_string();
+        let path = directory.join("main.asm").into();
+
+        let without = FileProviderPool::new();
+        let missing = without.provider_sourced(source.clone(), path).unwrap().to_provider();
+
+        assert!(assemble_with_provider(&missing).is_err());
+
+        let with = FileProviderPool::with_search_paths(vec![directory.join("lib")]);
+        let found = with.provider_sourced(source, directory.join("main.asm").into()).unwrap().to_provider();
+        let binary = assemble_with_provider(&found);
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        let device = UnitDevice::new(binary.unwrap());
+
+        device.step().unwrap();
+
+        assert_eq!(device.get(T0), 9);
+    }
+
+    #[test]
+    fn include_paths_can_be_commented() {
+        let commented = pool(&[
+            ("main.asm", ".include \"value.asm\" # for $t1\n.include \"value.asm\"#again\n"),
+            ("value.asm", "addi $t1, $t1, 5\n"),
+        ]);
+
+        let binary = assemble_with_provider(&commented.in_memory_provider("main.asm").unwrap()).unwrap();
+        let device = UnitDevice::new(binary);
+
+        device.step().unwrap();
+        device.step().unwrap();
+
+        assert_eq!(device.get(T1), 10);
+
+        let numbered = pool(&[("main.asm", ".include 5\n")]);
+
+        let provider = numbered.in_memory_provider("main.asm").unwrap();
+
+        let Err(SourceError::Preprocessor(error)) = assemble_with_provider(&provider) else {
+            panic!("expected a preprocessor error")
+        };
+
+        assert_eq!(error.reason.to_string(), "Expected a string, but found Integer Literal");
+    }
+}
@@ -1,14 +1,15 @@
 use crate::assembler::assembler_util::AssemblerError;
-use crate::assembler::binary::Binary;
-use crate::assembler::core::assemble;
+use crate::assembler::binary::{Binary, SourceBreakpoint};
+use crate::assembler::core::{assemble, assemble_with_options, emit_with_options, AssembleOptions};
 use crate::assembler::instructions::INSTRUCTIONS;
-use crate::assembler::lexer::{lex, LexerError, Location};
+use crate::assembler::lexer::{lex, LexerError, Location, Token};
 use crate::assembler::preprocessor::{preprocess, PreprocessorError};
 use crate::assembler::string::SourceError::{Assembler, Lexer, Preprocessor};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::path::PathBuf;
-use crate::assembler::source::{FileProviderPool, HoldingProvider};
+use std::time::{Duration, Instant};
+use crate::assembler::source::{FileProviderPool, HoldingProvider, TokenProvider};
 
 #[derive(Debug)]
 pub enum SourceError {
@@ -67,13 +68,97 @@ pub fn assemble_from(source: &str) -> Result<Binary, SourceError> {
     Ok(binary)
 }
 
+pub fn assemble_from_with_options(source: &str, options: AssembleOptions) -> Result<Binary, SourceError> {
+    let items = lex(source)?;
+    let provider = HoldingProvider::new(items);
+
+    let items = preprocess(&provider)?;
+    let binary = assemble_with_options(&items, &INSTRUCTIONS, options)?;
+
+    Ok(binary)
+}
+
 pub fn assemble_from_path(source: String, path: PathBuf) -> Result<Binary, SourceError> {
     let pool = FileProviderPool::new();
 
     let provider = pool.provider_sourced(source, path.into())?.to_provider();
 
-    let items = preprocess(&provider)?;
+    assemble_with_provider(&provider)
+}
+
+// Any provider, ex. one from FileProviderPool::in_memory_provider so includes never touch the disk.
+pub fn assemble_with_provider<'a, P: TokenProvider<'a>>(provider: &P) -> Result<Binary, SourceError> {
+    let items = preprocess(provider)?;
     let binary = assemble(&items, &INSTRUCTIONS)?;
 
     Ok(binary)
 }
+
+// Wall time per phase, included files are lexed during preprocess.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AssembleTimings {
+    pub lex: Duration,
+    pub preprocess: Duration,
+    pub emit: Duration,
+    pub resolve: Duration, // labels, aliases and relaxation (BinaryBuilder::build)
+}
+
+pub struct AssembleOutput<'a> {
+    pub tokens: Vec<Token<'a>>, // as lexed, before any macros or includes
+    pub preprocessed: Vec<Token<'a>>,
+    pub binary: Binary,
+    pub breakpoints: Vec<SourceBreakpoint>, // lines of the root source only
+    pub timings: AssembleTimings,
+}
+
+fn assemble_provider<'a, P: TokenProvider<'a>>(
+    provider: &P, source: &str, lex: Duration, options: AssembleOptions
+) -> Result<AssembleOutput<'a>, SourceError> {
+    let start = Instant::now();
+    let preprocessed = preprocess(provider)?;
+    let preprocess = start.elapsed();
+
+    let start = Instant::now();
+    let builder = emit_with_options(&preprocessed, &INSTRUCTIONS, options)?;
+    let emit = start.elapsed();
+
+    let start = Instant::now();
+    let binary = builder.build()?;
+    let resolve = start.elapsed();
+
+    let breakpoints = binary.source_breakpoints(source, provider.id());
+
+    Ok(AssembleOutput {
+        tokens: provider.get().to_vec(),
+        preprocessed,
+        binary,
+        breakpoints,
+        timings: AssembleTimings { lex, preprocess, emit, resolve },
+    })
+}
+
+pub fn assemble_debug_from(source: &str) -> Result<AssembleOutput<'_>, SourceError> {
+    let start = Instant::now();
+    let provider = HoldingProvider::new(lex(source)?);
+
+    assemble_provider(&provider, source, start.elapsed(), AssembleOptions::default())
+}
+
+// Tokens borrow from the pool, so it has to outlive the output.
+pub fn assemble_debug(
+    pool: &FileProviderPool, source: String, path: PathBuf
+) -> Result<AssembleOutput<'_>, SourceError> {
+    assemble_debug_with_options(pool, source, path, AssembleOptions::default())
+}
+
+pub fn assemble_debug_with_options(
+    pool: &FileProviderPool, source: String, path: PathBuf, options: AssembleOptions
+) -> Result<AssembleOutput<'_>, SourceError> {
+    let start = Instant::now();
+    let provider = pool.provider_sourced(source, path.into())?.to_provider();
+    let lex = start.elapsed();
+
+    let source = pool.source(provider.id()).unwrap_or_default();
+
+    assemble_provider(&provider, &source, lex, options)
+}
@@ -1,5 +1,5 @@
 use crate::cpu::decoder::Decoder;
-use crate::cpu::error::Error::{CpuInvalid, CpuSyscall, CpuTrap};
+use crate::cpu::error::Error::{CpuBreak, CpuInvalid, CpuSyscall, CpuTrap};
 use crate::cpu::error::Result;
 use crate::cpu::{Memory, State};
 
@@ -8,6 +8,27 @@ impl<T: Memory> State<T> {
         (self.registers.hi as u64).wrapping_shl(32) | (self.registers.lo as u64)
     }
 
+    // Called before an instruction reads hi/lo (write_hilo before one writes them), Err if strict timing says it traps.
+    fn read_hilo(&mut self) -> Result<()> {
+        let pc = self.registers.pc.wrapping_sub(4);
+
+        if self.hazards.as_mut().map_or(true, |hazards| hazards.read(pc)) {
+            Ok(())
+        } else {
+            Err(CpuTrap)
+        }
+    }
+
+    fn write_hilo(&mut self) -> Result<()> {
+        let pc = self.registers.pc.wrapping_sub(4);
+
+        if self.hazards.as_mut().map_or(true, |hazards| hazards.write(pc)) {
+            Ok(())
+        } else {
+            Err(CpuTrap)
+        }
+    }
+
     fn load_hilo_or_trap(&mut self, result: Option<u64>) -> Result<()> {
         if let Some(result) = result {
             self.registers.hi = result.wrapping_shr(32) as u32;
@@ -39,8 +60,9 @@ impl<Mem: Memory> State<Mem> {
         self.registers.pc = destination as u32
     }
 
+    // j and jal keep the top 4 bits of pc, which is already the delay slot's address here.
     fn jump(&mut self, bits: u32) {
-        self.registers.pc = (self.registers.pc & 0xFC000000) | bits.wrapping_shl(2);
+        self.registers.pc = (self.registers.pc & 0xF0000000) | bits.wrapping_shl(2);
     }
 
     pub fn step(&mut self) -> Result<()> {
@@ -49,9 +71,44 @@ impl<Mem: Memory> State<Mem> {
 
         self.registers.pc = start.wrapping_add(4);
 
-        self.dispatch(instruction)
-            .unwrap_or(Err(CpuInvalid(instruction)))
-            .inspect_err(|_| self.registers.pc = start) // if error, keep pc here
+        let result = self.dispatch(instruction)
+            .unwrap_or(Err(CpuInvalid(start, instruction)));
+
+        if result.is_err() {
+            self.registers.pc = start // if error, keep pc here
+        } else if let Some(hazards) = &mut self.hazards {
+            hazards.retire()
+        }
+
+        result
+    }
+
+    // Moves past the conditional branch at pc as if it was (or was not) taken.
+    // Nothing else is executed, so the link variants do not write $ra.
+    // Returns false (pc untouched) if the instruction at pc is not a conditional branch.
+    pub fn force_branch(&mut self, taken: bool) -> Result<bool> {
+        let instruction = self.memory.get_u32(self.registers.pc)?;
+
+        let opcode = instruction >> 26;
+        let rt = (instruction >> 16) & 0x1F;
+
+        let branch = match opcode {
+            4..=7 => true, // beq, bne, blez, bgtz
+            1 => matches!(rt, 0 | 1 | 16 | 17), // bltz, bgez, bltzal, bgezal
+            _ => false,
+        };
+
+        if !branch {
+            return Ok(false)
+        }
+
+        self.registers.pc = self.registers.pc.wrapping_add(4);
+
+        if taken {
+            self.skip(instruction as u16);
+        }
+
+        Ok(true)
     }
 }
 
@@ -81,9 +138,11 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn div(&mut self, s: u8, t: u8) -> Result<()> {
+        self.write_hilo()?;
+
         let (a, b) = (*self.register(s) as i32, *self.register(t) as i32);
         let (lo, hi) = if b != 0 {
-            (a.wrapping_div(b), a % b)
+            (a.wrapping_div(b), a.wrapping_rem(b))
         } else {
             return self.trap();
         };
@@ -94,6 +153,8 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn divu(&mut self, s: u8, t: u8) -> Result<()> {
+        self.write_hilo()?;
+
         let (a, b) = (*self.register(s), *self.register(t));
 
         if b != 0 {
@@ -106,8 +167,10 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn mult(&mut self, s: u8, t: u8) -> Result<()> {
-        let (a, b) = (*self.register(s) as i64, *self.register(t) as i64);
-        let value = (a * b) as u64;
+        self.write_hilo()?;
+
+        let (a, b) = (*self.register(s) as i32 as i64, *self.register(t) as i32 as i64);
+        let value = a.wrapping_mul(b) as u64;
 
         (self.registers.lo, self.registers.hi) = (value as u32, value.wrapping_shr(32) as u32);
 
@@ -115,8 +178,10 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn multu(&mut self, s: u8, t: u8) -> Result<()> {
+        self.write_hilo()?;
+
         let (a, b) = (*self.register(s) as u64, *self.register(t) as u64);
-        let value = a * b;
+        let value = a.wrapping_mul(b);
 
         (self.registers.lo, self.registers.hi) = (value as u32, value.wrapping_shr(32) as u32);
 
@@ -141,8 +206,9 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
         Ok(())
     }
 
+    // Variable shifts only use the low 5 bits of $s.
     fn sllv(&mut self, s: u8, t: u8, d: u8) -> Result<()> {
-        *self.register(d) = self.register(t).wrapping_shl(*self.register(s));
+        *self.register(d) = *self.register(t) << (*self.register(s) & 0x1F);
 
         Ok(())
     }
@@ -158,7 +224,7 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     fn srav(&mut self, s: u8, t: u8, d: u8) -> Result<()> {
         let source = *self.register(t) as i32;
 
-        *self.register(d) = source.wrapping_shr(*self.register(s)) as u32;
+        *self.register(d) = (source >> (*self.register(s) & 0x1F)) as u32;
 
         Ok(())
     }
@@ -170,7 +236,7 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn srlv(&mut self, s: u8, t: u8, d: u8) -> Result<()> {
-        *self.register(d) = self.register(t).wrapping_shr(*self.register(s));
+        *self.register(d) = *self.register(t) >> (*self.register(s) & 0x1F);
 
         Ok(())
     }
@@ -230,6 +296,8 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn madd(&mut self, s: u8, t: u8) -> Result<()> {
+        self.write_hilo()?;
+
         let a = *self.register(s) as i32 as i64;
         let b = *self.register(t) as i32 as i64;
 
@@ -242,6 +310,8 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn maddu(&mut self, s: u8, t: u8) -> Result<()> {
+        self.write_hilo()?;
+
         let a = *self.register(s) as u64;
         let b = *self.register(t) as u64;
         let result = a.wrapping_mul(b).wrapping_add(self.hilo());
@@ -263,6 +333,8 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn msub(&mut self, s: u8, t: u8) -> Result<()> {
+        self.write_hilo()?;
+
         let a = *self.register(s) as i32 as i64;
         let b = *self.register(t) as i32 as i64;
 
@@ -275,6 +347,8 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn msubu(&mut self, s: u8, t: u8) -> Result<()> {
+        self.write_hilo()?;
+
         let a = *self.register(s) as u64;
         let b = *self.register(t) as u64;
         let result = self.hilo().wrapping_sub(a.wrapping_mul(b));
@@ -356,7 +430,8 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     }
 
     fn sltiu(&mut self, s: u8, t: u8, imm: u16) -> Result<()> {
-        let value = *self.register(s) < (imm as u32);
+        // The immediate is sign-extended, only the comparison is unsigned.
+        let value = *self.register(s) < (imm as i16 as i32 as u32);
 
         *self.register(t) = value as u32;
 
@@ -490,6 +565,7 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
         let value = *self.register(t) as u8;
 
         self.memory.set(address as u32, value)?;
+        self.link = false;
 
         Ok(())
     }
@@ -499,6 +575,7 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
         let value = *self.register(t) as u16;
 
         self.memory.set_u16(address as u32, value)?;
+        self.link = false;
 
         Ok(())
     }
@@ -508,29 +585,62 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
         let value = *self.register(t);
 
         self.memory.set_u32(address as u32, value)?;
+        self.link = false;
+
+        Ok(())
+    }
+
+    fn ll(&mut self, s: u8, t: u8, imm: u16) -> Result<()> {
+        let address = (*self.register(s) as i32).wrapping_add(imm as i16 as i32);
+
+        *self.register(t) = self.memory.get_u32(address as u32)?;
+        self.link = true;
+
+        Ok(())
+    }
+
+    fn sc(&mut self, s: u8, t: u8, imm: u16) -> Result<()> {
+        let address = (*self.register(s) as i32).wrapping_add(imm as i16 as i32);
+
+        if self.link {
+            let value = *self.register(t);
+
+            self.memory.set_u32(address as u32, value)?;
+        }
+
+        *self.register(t) = self.link as u32;
+        self.link = false;
 
         Ok(())
     }
 
     fn mfhi(&mut self, d: u8) -> Result<()> {
+        self.read_hilo()?;
+
         *self.register(d) = self.registers.hi;
 
         Ok(())
     }
 
     fn mflo(&mut self, d: u8) -> Result<()> {
+        self.read_hilo()?;
+
         *self.register(d) = self.registers.lo;
 
         Ok(())
     }
 
     fn mthi(&mut self, s: u8) -> Result<()> {
+        self.write_hilo()?;
+
         self.registers.hi = *self.register(s);
 
         Ok(())
     }
 
     fn mtlo(&mut self, s: u8) -> Result<()> {
+        self.write_hilo()?;
+
         self.registers.lo = *self.register(s);
 
         Ok(())
@@ -543,4 +653,264 @@ impl<Mem: Memory> Decoder<Result<()>> for State<Mem> {
     fn syscall(&mut self) -> Result<()> {
         Err(CpuSyscall)
     }
+
+    fn break_(&mut self, code: u32) -> Result<()> {
+        Err(CpuBreak(code))
+    }
+
+    // No caches or reordering to wait on.
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::error::Error::{CpuBreak, CpuTrap};
+    use crate::cpu::state::{HazardMode, HiLoHazard, HiLoHazardKind, HI_LO_HAZARD_DISTANCE};
+    use crate::unit::device::{StopCondition, UnitDevice, UnitDeviceError};
+    use crate::unit::register::RegisterName::{T0, T2};
+
+    // la is two instructions, so ll runs on the third step and sc on the fourth.
+    fn assembled(store: &str) -> UnitDevice {
+        let source = format!("
+            .data
+            value: .word 5
+            other: .word 0
+            .text
+            main:
+                la $s0, value
+                ll $t0, 0($s0)
+                addi $t0, $t0, 1
+                {store}
+                sc $t0, 0($s0)
+        ");
+
+        UnitDevice::new(assemble_from(&source).unwrap())
+    }
+
+    fn steps(device: &UnitDevice, count: usize) {
+        for _ in 0..count {
+            device.step().unwrap()
+        }
+    }
+
+    fn value(device: &UnitDevice) -> u32 {
+        let address = device.binary.labels["value"];
+
+        u32::from_le_bytes(device.get_data(address, 4).unwrap().try_into().unwrap())
+    }
+
+    #[test]
+    fn sc_stores_after_ll() {
+        let device = assembled("nop");
+
+        steps(&device, 6);
+
+        assert_eq!(device.get(T0), 1);
+        assert_eq!(value(&device), 6);
+    }
+
+    #[test]
+    fn any_store_breaks_the_reservation() {
+        for store in ["sw $zero, 4($s0)", "sh $zero, 4($s0)", "sb $zero, 4($s0)"] {
+            let device = assembled(store);
+
+            steps(&device, 6);
+
+            assert_eq!(device.get(T0), 0, "{store}");
+            assert_eq!(value(&device), 5, "{store}");
+        }
+    }
+
+    #[test]
+    fn backstep_restores_the_reservation() {
+        let device = assembled("nop");
+
+        steps(&device, 6);
+        assert!(device.backstep());
+
+        // Undoing sc gives back the reservation it used, so running it again stores again.
+        steps(&device, 1);
+
+        assert_eq!(device.get(T0), 1);
+
+        // Undoing ll takes the reservation away, so sc fails after running the rest without it.
+        let device = assembled("nop");
+
+        steps(&device, 3);
+        assert!(device.backstep());
+
+        device.jump_to(device.registers().pc + 12); // to sc, past ll, addi and nop
+
+        steps(&device, 1);
+
+        assert_eq!(device.get(T0), 0);
+        assert_eq!(value(&device), 5);
+    }
+
+    #[test]
+    fn snapshots_keep_the_reservation() {
+        let device = assembled("nop");
+
+        steps(&device, 5);
+
+        let snapshot = device.snapshot_bytes();
+
+        steps(&device, 1);
+        assert_eq!(device.get(T0), 1);
+
+        device.restore_from_bytes(&snapshot).unwrap();
+        steps(&device, 1);
+
+        assert_eq!(device.get(T0), 1);
+        assert_eq!(value(&device), 6);
+    }
+
+    #[test]
+    fn variable_shifts_use_the_low_five_bits() {
+        let cases = [
+            (32u32, 0x80000001u32, 0x80000001u32, 0x80000001u32),
+            (33, 0x00000002, 0x40000000, 0xC0000000),
+            (0xFFFFFFFF, 0x80000000, 0x00000001, 0xFFFFFFFF),
+        ];
+
+        for (amount, sllv, srlv, srav) in cases {
+            for (op, expected) in [("sllv", sllv), ("srlv", srlv), ("srav", srav)] {
+                let source = format!("
+                    lui $t0, 0x8000
+                    ori $t0, $t0, 1
+                    lui $t1, {}
+                    ori $t1, $t1, {}
+                    {op} $t2, $t0, $t1
+                ", amount >> 16, amount & 0xFFFF);
+
+                let device = UnitDevice::new(assemble_from(&source).unwrap());
+
+                steps(&device, 5);
+
+                assert_eq!(device.get(T2), expected, "{op} by {amount:#x}");
+            }
+        }
+    }
+
+    // first, then gap nops, then second, with hazards checked in mode.
+    fn hazards(first: &str, gap: usize, second: &str, mode: HazardMode) -> (UnitDevice, Result<(), UnitDeviceError>) {
+        let source = format!("
+            li $t0, 6
+            li $t1, 7
+            {first}
+            {}
+            {second}
+        ", "nop\n".repeat(gap));
+
+        let device = UnitDevice::new(assemble_from(&source).unwrap());
+        device.check_hi_lo_hazards(mode);
+
+        let result = device.execute_until([StopCondition::Complete]);
+
+        (device, result)
+    }
+
+    #[test]
+    fn reads_within_two_instructions_of_a_write_are_hazards() {
+        for (gap, distance) in [(0, 1), (1, 2)] {
+            let (device, result) = hazards("mult $t0, $t1", gap, "mflo $t2", HazardMode::Warn);
+
+            // Warned about, but still run.
+            assert!(result.is_ok());
+            assert_eq!(device.get(T2), 42);
+            assert_eq!(device.hi_lo_hazards(), [HiLoHazard {
+                pc: 0x400008 + 4 * (gap as u32 + 1), kind: HiLoHazardKind::ReadAfterWrite, distance
+            }]);
+        }
+
+        let (device, _) = hazards("mult $t0, $t1", HI_LO_HAZARD_DISTANCE as usize, "mflo $t2", HazardMode::Warn);
+
+        assert!(device.hi_lo_hazards().is_empty());
+
+        // Writing with mthi counts too.
+        let (device, _) = hazards("mthi $t0", 0, "mfhi $t2", HazardMode::Warn);
+
+        assert_eq!(device.hi_lo_hazards().len(), 1);
+    }
+
+    #[test]
+    fn writes_within_two_instructions_of_a_read_are_hazards() {
+        for (gap, distance) in [(0, 1), (1, 2)] {
+            let (device, _) = hazards("mfhi $t2", gap, "div $t0, $t1", HazardMode::Warn);
+
+            assert_eq!(device.hi_lo_hazards(), [HiLoHazard {
+                pc: 0x400008 + 4 * (gap as u32 + 1), kind: HiLoHazardKind::WriteAfterRead, distance
+            }]);
+        }
+
+        let (device, _) = hazards("mfhi $t2", 2, "multu $t0, $t1", HazardMode::Warn);
+
+        assert!(device.hi_lo_hazards().is_empty());
+    }
+
+    #[test]
+    fn strict_hazards_trap_before_running() {
+        let (device, result) = hazards("mult $t0, $t1", 1, "mflo $t2", HazardMode::Trap);
+
+        assert!(matches!(result, Err(UnitDeviceError::InvalidInstruction(CpuTrap, _))));
+        assert_eq!(device.registers().pc, 0x400010);
+        assert_eq!(device.get(T2), 0);
+
+        let (_, result) = hazards("mult $t0, $t1", 2, "mflo $t2", HazardMode::Trap);
+
+        assert!(result.is_ok());
+
+        // Off by default.
+        let device = UnitDevice::new(assemble_from("mult $t0, $t1\nmflo $t2\n").unwrap());
+
+        device.execute_until([StopCondition::Complete]).unwrap();
+
+        assert!(device.hi_lo_hazards().is_empty());
+    }
+
+    #[test]
+    fn hazards_describe_themselves() {
+        let read = HiLoHazard { pc: 0x40000c, kind: HiLoHazardKind::ReadAfterWrite, distance: 1 };
+        let write = HiLoHazard { pc: 0x400010, kind: HiLoHazardKind::WriteAfterRead, distance: 2 };
+
+        assert_eq!(read.to_string(), "hi/lo read at 0x0040000c only 1 instruction after it was written (undefined on MIPS I)");
+        assert_eq!(write.to_string(), "hi/lo written at 0x00400010 only 2 instructions after mfhi/mflo (undefined on MIPS I)");
+    }
+
+    #[test]
+    fn break_stops_with_its_code_and_sync_does_nothing() {
+        let device = UnitDevice::new(assemble_from("sync\nli $t0, 1\nbreak 7\nli $t0, 2\n").unwrap());
+
+        device.step().unwrap();
+
+        assert_eq!(device.registers().pc, 0x400004);
+        assert_eq!(device.get(T0), 0);
+
+        let error = device.execute_until([StopCondition::Complete]).unwrap_err();
+
+        assert!(matches!(error, UnitDeviceError::InvalidInstruction(CpuBreak(7), _)));
+        assert_eq!(device.registers().pc, 0x400008);
+        assert_eq!(device.get(T0), 1);
+        assert_eq!(device.stop_reason().to_string(), "Break (code 7) at pc 0x00400008 (break 7)");
+    }
+
+    #[test]
+    fn break_codes_fit_in_20_bits() {
+        let word = |source: &str| {
+            let binary = assemble_from(source).unwrap();
+
+            u32::from_le_bytes(binary.regions[0].data[..4].try_into().unwrap())
+        };
+
+        assert_eq!(word("break"), 0x0000000d);
+        assert_eq!(word("break 5"), 0x0000014d);
+        assert_eq!(word("break 0xFFFFF"), 0x03ffffcd);
+        assert_eq!(word("sync"), 0x0000000f);
+
+        assert!(assemble_from("break 0x100000").is_err());
+        assert!(assemble_from("break -1").is_err());
+    }
 }
@@ -1,4 +1,5 @@
 use crate::cpu::decoder::Decoder;
+use std::fmt::{Display, Formatter};
 use num_traits::abs;
 
 pub trait LabelProvider {
@@ -17,17 +18,18 @@ impl LabelProvider for HexLabelProvider {
 pub struct Disassembler<Provider: LabelProvider> {
     pub pc: u32,
     pub labels: Provider,
+    pub raw_nop: bool, // show the zero word as sll $zero, $zero, 0 instead of nop
 }
 
 fn jump_dest(pc: u32, imm: u32) -> u32 {
-    ((pc + 4) & 0xFC000000) | (imm << 2)
+    (pc.wrapping_add(4) & 0xF0000000) | (imm << 2)
 }
 
 fn rel_dest(pc: u32, imm: u16) -> u32 {
-    ((pc + 4) as i32 + ((imm as i16 as i32) << 2)) as u32
+    pc.wrapping_add(4).wrapping_add(((imm as i16 as i32) << 2) as u32)
 }
 
-fn reg(value: u8) -> &'static str {
+pub fn reg(value: u8) -> &'static str {
     match value {
         0 => "$zero",
         1 => "$at",
@@ -90,264 +92,484 @@ fn hex(imm: u16) -> String {
     format!("0x{imm:x}")
 }
 
-impl<Provider: LabelProvider> Decoder<String> for Disassembler<Provider> {
-    fn add(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("add {}, {}, {}", reg(d), reg(s), reg(t))
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DisasmTokenKind {
+    Mnemonic,
+    Register,
+    FpRegister, // never produced yet, titan has no FPU
+    Immediate,
+    Address, // branch and jump targets, as given by the LabelProvider
+    Punctuation, // separators and the parentheses around a base register, spaces included
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisasmToken {
+    pub text: String,
+    pub kind: DisasmTokenKind,
+}
+
+// One instruction, for coloring. The tokens concatenate to the plain disassembly.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisassembledLine {
+    pub tokens: Vec<DisasmToken>,
+}
+
+impl DisassembledLine {
+    pub fn text(&self) -> String {
+        self.tokens.iter().map(|token| token.text.as_str()).collect()
+    }
+
+    fn push(&mut self, text: impl Into<String>, kind: DisasmTokenKind) {
+        self.tokens.push(DisasmToken { text: text.into(), kind })
+    }
+
+    // Separates operands: a space after the mnemonic, a comma before the rest.
+    fn operand(mut self) -> Self {
+        let separator = if self.tokens.len() == 1 { " " } else { ", " };
+        self.push(separator, DisasmTokenKind::Punctuation);
+
+        self
+    }
+
+    fn reg(self, value: u8) -> Self {
+        let mut line = self.operand();
+        line.push(reg(value), DisasmTokenKind::Register);
+
+        line
+    }
+
+    fn immediate(self, text: String) -> Self {
+        let mut line = self.operand();
+        line.push(text, DisasmTokenKind::Immediate);
+
+        line
+    }
+
+    fn address(self, label: String) -> Self {
+        let mut line = self.operand();
+        line.push(label, DisasmTokenKind::Address);
+
+        line
+    }
+
+    // imm($base), for loads and stores.
+    fn offset(self, imm: String, base: u8) -> Self {
+        let mut line = self.immediate(imm);
+        line.push("(", DisasmTokenKind::Punctuation);
+        line.push(reg(base), DisasmTokenKind::Register);
+        line.push(")", DisasmTokenKind::Punctuation);
+
+        line
+    }
+}
+
+impl Display for DisassembledLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for token in &self.tokens {
+            f.write_str(&token.text)?
+        }
+
+        Ok(())
+    }
+}
+
+impl From<DisassembledLine> for String {
+    fn from(value: DisassembledLine) -> Self {
+        value.text()
+    }
+}
+
+fn line(mnemonic: &str) -> DisassembledLine {
+    let mut line = DisassembledLine::default();
+    line.push(mnemonic, DisasmTokenKind::Mnemonic);
+
+    line
+}
+
+impl<Provider: LabelProvider, T: From<DisassembledLine>> Decoder<T> for Disassembler<Provider> {
+    fn add(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("add").reg(d).reg(s).reg(t).into()
     }
 
-    fn addu(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("addu {}, {}, {}", reg(d), reg(s), reg(t))
+    fn addu(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("addu").reg(d).reg(s).reg(t).into()
     }
 
-    fn and(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("and {}, {}, {}", reg(d), reg(s), reg(t))
+    fn and(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("and").reg(d).reg(s).reg(t).into()
     }
 
-    fn div(&mut self, s: u8, t: u8) -> String {
-        format!("div {}, {}", reg(s), reg(t))
+    fn div(&mut self, s: u8, t: u8) -> T {
+        line("div").reg(s).reg(t).into()
     }
 
-    fn divu(&mut self, s: u8, t: u8) -> String {
-        format!("divu {}, {}", reg(s), reg(t))
+    fn divu(&mut self, s: u8, t: u8) -> T {
+        line("divu").reg(s).reg(t).into()
     }
 
-    fn mult(&mut self, s: u8, t: u8) -> String {
-        format!("mult {}, {}", reg(s), reg(t))
+    fn mult(&mut self, s: u8, t: u8) -> T {
+        line("mult").reg(s).reg(t).into()
     }
 
-    fn multu(&mut self, s: u8, t: u8) -> String {
-        format!("multu {}, {}", reg(s), reg(t))
+    fn multu(&mut self, s: u8, t: u8) -> T {
+        line("multu").reg(s).reg(t).into()
     }
 
-    fn nor(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("nor {}, {}, {}", reg(d), reg(s), reg(t))
+    fn nor(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("nor").reg(d).reg(s).reg(t).into()
     }
 
-    fn or(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("or {}, {}, {}", reg(d), reg(s), reg(t))
+    fn or(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("or").reg(d).reg(s).reg(t).into()
     }
 
-    fn sll(&mut self, t: u8, d: u8, sham: u8) -> String {
-        format!("sll {}, {}, {}", reg(d), reg(t), uns(sham as u16))
+    fn sll(&mut self, t: u8, d: u8, sham: u8) -> T {
+        if !self.raw_nop && (t, d, sham) == (0, 0, 0) {
+            return line("nop").into()
+        }
+
+        line("sll").reg(d).reg(t).immediate(uns(sham as u16)).into()
     }
 
-    fn sllv(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("sllv {}, {}, {}", reg(d), reg(t), reg(s))
+    fn sllv(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("sllv").reg(d).reg(t).reg(s).into()
     }
 
-    fn sra(&mut self, t: u8, d: u8, sham: u8) -> String {
-        format!("sra {}, {}, {}", reg(d), reg(t), uns(sham as u16))
+    fn sra(&mut self, t: u8, d: u8, sham: u8) -> T {
+        line("sra").reg(d).reg(t).immediate(uns(sham as u16)).into()
     }
 
-    fn srav(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("srav {}, {}, {}", reg(d), reg(t), reg(s))
+    fn srav(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("srav").reg(d).reg(t).reg(s).into()
     }
 
-    fn srl(&mut self, t: u8, d: u8, sham: u8) -> String {
-        format!("srl {}, {}, {}", reg(d), reg(t), uns(sham as u16))
+    fn srl(&mut self, t: u8, d: u8, sham: u8) -> T {
+        line("srl").reg(d).reg(t).immediate(uns(sham as u16)).into()
     }
 
-    fn srlv(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("srlv {}, {}, {}", reg(d), reg(t), reg(s))
+    fn srlv(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("srlv").reg(d).reg(t).reg(s).into()
     }
 
-    fn sub(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("sub {}, {}, {}", reg(d), reg(s), reg(t))
+    fn sub(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("sub").reg(d).reg(s).reg(t).into()
     }
 
-    fn subu(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("subu {}, {}, {}", reg(d), reg(s), reg(t))
+    fn subu(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("subu").reg(d).reg(s).reg(t).into()
     }
 
-    fn xor(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("xor {}, {}, {}", reg(d), reg(s), reg(t))
+    fn xor(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("xor").reg(d).reg(s).reg(t).into()
     }
 
-    fn slt(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("slt {}, {}, {}", reg(d), reg(s), reg(t))
+    fn slt(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("slt").reg(d).reg(s).reg(t).into()
     }
 
-    fn sltu(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("sltu {}, {}, {}", reg(d), reg(s), reg(t))
+    fn sltu(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("sltu").reg(d).reg(s).reg(t).into()
     }
 
-    fn jr(&mut self, s: u8) -> String {
-        format!("jr {}", reg(s))
+    fn jr(&mut self, s: u8) -> T {
+        line("jr").reg(s).into()
     }
 
-    fn jalr(&mut self, s: u8) -> String {
-        format!("jalr {}", reg(s))
+    fn jalr(&mut self, s: u8) -> T {
+        line("jalr").reg(s).into()
     }
 
-    fn madd(&mut self, s: u8, t: u8) -> String {
-        format!("madd {}, {}", reg(s), reg(t))
+    fn madd(&mut self, s: u8, t: u8) -> T {
+        line("madd").reg(s).reg(t).into()
     }
 
-    fn maddu(&mut self, s: u8, t: u8) -> String {
-        format!("maddu {}, {}", reg(s), reg(t))
+    fn maddu(&mut self, s: u8, t: u8) -> T {
+        line("maddu").reg(s).reg(t).into()
     }
 
-    fn mul(&mut self, s: u8, t: u8, d: u8) -> String {
-        format!("mul {}, {}, {}", reg(d), reg(s), reg(t))
+    fn mul(&mut self, s: u8, t: u8, d: u8) -> T {
+        line("mul").reg(d).reg(s).reg(t).into()
     }
 
-    fn msub(&mut self, s: u8, t: u8) -> String {
-        format!("msub {}, {}", reg(s), reg(t))
+    fn msub(&mut self, s: u8, t: u8) -> T {
+        line("msub").reg(s).reg(t).into()
     }
 
-    fn msubu(&mut self, s: u8, t: u8) -> String {
-        format!("msubu {}, {}", reg(s), reg(t))
+    fn msubu(&mut self, s: u8, t: u8) -> T {
+        line("msubu").reg(s).reg(t).into()
     }
 
-    fn addi(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("addi {}, {}, {}", reg(t), reg(s), sig(imm))
+    fn addi(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("addi").reg(t).reg(s).immediate(sig(imm)).into()
     }
 
-    fn addiu(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("addiu {}, {}, {}", reg(t), reg(s), sig(imm))
+    fn addiu(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("addiu").reg(t).reg(s).immediate(sig(imm)).into()
     }
 
-    fn andi(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("andi {}, {}, {}", reg(t), reg(s), hex(imm))
+    fn andi(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("andi").reg(t).reg(s).immediate(hex(imm)).into()
     }
 
-    fn ori(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("ori {}, {}, {}", reg(t), reg(s), hex(imm))
+    fn ori(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("ori").reg(t).reg(s).immediate(hex(imm)).into()
     }
 
-    fn xori(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("xori {}, {}, {}", reg(t), reg(s), hex(imm))
+    fn xori(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("xori").reg(t).reg(s).immediate(hex(imm)).into()
     }
 
-    fn lui(&mut self, t: u8, imm: u16) -> String {
-        format!("lui {}, {}", reg(t), hex(imm))
+    fn lui(&mut self, t: u8, imm: u16) -> T {
+        line("lui").reg(t).immediate(hex(imm)).into()
     }
 
-    fn lhi(&mut self, t: u8, imm: u16) -> String {
-        format!("lhi {}, {}", reg(t), hex(imm))
+    fn lhi(&mut self, t: u8, imm: u16) -> T {
+        line("lhi").reg(t).immediate(hex(imm)).into()
     }
 
-    fn llo(&mut self, t: u8, imm: u16) -> String {
-        format!("llo {}, {}", reg(t), hex(imm))
+    fn llo(&mut self, t: u8, imm: u16) -> T {
+        line("llo").reg(t).immediate(hex(imm)).into()
     }
 
-    fn slti(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("slti {}, {}, {}", reg(t), reg(s), sig(imm))
+    fn slti(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("slti").reg(t).reg(s).immediate(sig(imm)).into()
     }
 
-    fn sltiu(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("sltiu {}, {}, {}", reg(t), reg(s), uns(imm))
+    fn sltiu(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("sltiu").reg(t).reg(s).immediate(sig(imm)).into()
     }
 
-    fn beq(&mut self, s: u8, t: u8, imm: u16) -> String {
+    fn beq(&mut self, s: u8, t: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("beq {}, {}, {}", reg(s), reg(t), label)
+        line("beq").reg(s).reg(t).address(label).into()
     }
 
-    fn bne(&mut self, s: u8, t: u8, imm: u16) -> String {
+    fn bne(&mut self, s: u8, t: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("bne {}, {}, {}", reg(s), reg(t), label)
+        line("bne").reg(s).reg(t).address(label).into()
     }
 
-    fn bgtz(&mut self, s: u8, imm: u16) -> String {
+    fn bgtz(&mut self, s: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("bgtz {}, {}", reg(s), label)
+        line("bgtz").reg(s).address(label).into()
     }
 
-    fn blez(&mut self, s: u8, imm: u16) -> String {
+    fn blez(&mut self, s: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("blez {}, {}", reg(s), label)
+        line("blez").reg(s).address(label).into()
     }
 
-    fn bltz(&mut self, s: u8, imm: u16) -> String {
+    fn bltz(&mut self, s: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("bltz {}, {}", reg(s), label)
+        line("bltz").reg(s).address(label).into()
     }
 
-    fn bgez(&mut self, s: u8, imm: u16) -> String {
+    fn bgez(&mut self, s: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("bgez {}, {}", reg(s), label)
+        line("bgez").reg(s).address(label).into()
     }
 
-    fn bltzal(&mut self, s: u8, imm: u16) -> String {
+    fn bltzal(&mut self, s: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("bltzal {}, {}", reg(s), label)
+        line("bltzal").reg(s).address(label).into()
     }
 
-    fn bgezal(&mut self, s: u8, imm: u16) -> String {
+    fn bgezal(&mut self, s: u8, imm: u16) -> T {
         let label = self.labels.label_for(rel_dest(self.pc, imm));
 
-        format!("bgezal {}, {}", reg(s), label)
+        line("bgezal").reg(s).address(label).into()
+    }
+
+    fn j(&mut self, imm: u32) -> T {
+        line("j").address(self.labels.label_for(jump_dest(self.pc, imm))).into()
+    }
+
+    fn jal(&mut self, imm: u32) -> T {
+        line("jal").address(self.labels.label_for(jump_dest(self.pc, imm))).into()
+    }
+
+    fn lb(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("lb").reg(t).offset(sig(imm), s).into()
+    }
+
+    fn lbu(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("lbu").reg(t).offset(sig(imm), s).into()
     }
 
-    fn j(&mut self, imm: u32) -> String {
-        format!("j {}", self.labels.label_for(jump_dest(self.pc, imm)))
+    fn lh(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("lh").reg(t).offset(sig(imm), s).into()
     }
 
-    fn jal(&mut self, imm: u32) -> String {
-        format!("jal {}", self.labels.label_for(jump_dest(self.pc, imm)))
+    fn lhu(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("lhu").reg(t).offset(sig(imm), s).into()
     }
 
-    fn lb(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("lb {}, {}({})", reg(t), sig(imm), reg(s))
+    fn lw(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("lw").reg(t).offset(sig(imm), s).into()
     }
 
-    fn lbu(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("lbu {}, {}({})", reg(t), sig(imm), reg(s))
+    fn sb(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("sb").reg(t).offset(sig(imm), s).into()
     }
 
-    fn lh(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("lh {}, {}({})", reg(t), sig(imm), reg(s))
+    fn sh(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("sh").reg(t).offset(sig(imm), s).into()
     }
 
-    fn lhu(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("lhu {}, {}({})", reg(t), sig(imm), reg(s))
+    fn sw(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("sw").reg(t).offset(sig(imm), s).into()
     }
 
-    fn lw(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("lw {}, {}({})", reg(t), sig(imm), reg(s))
+    fn ll(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("ll").reg(t).offset(sig(imm), s).into()
     }
 
-    fn sb(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("sb {}, {}({})", reg(t), sig(imm), reg(s))
+    fn sc(&mut self, s: u8, t: u8, imm: u16) -> T {
+        line("sc").reg(t).offset(sig(imm), s).into()
     }
 
-    fn sh(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("sh {}, {}({})", reg(t), sig(imm), reg(s))
+    fn mfhi(&mut self, d: u8) -> T {
+        line("mfhi").reg(d).into()
     }
 
-    fn sw(&mut self, s: u8, t: u8, imm: u16) -> String {
-        format!("sw {}, {}({})", reg(t), sig(imm), reg(s))
+    fn mflo(&mut self, d: u8) -> T {
+        line("mflo").reg(d).into()
     }
 
-    fn mfhi(&mut self, d: u8) -> String {
-        format!("mfhi {}", reg(d))
+    fn mthi(&mut self, s: u8) -> T {
+        line("mthi").reg(s).into()
     }
 
-    fn mflo(&mut self, d: u8) -> String {
-        format!("mflo {}", reg(d))
+    fn mtlo(&mut self, s: u8) -> T {
+        line("mtlo").reg(s).into()
     }
 
-    fn mthi(&mut self, s: u8) -> String {
-        format!("mthi {}", reg(s))
+    fn trap(&mut self) -> T {
+        line("trap").into()
     }
 
-    fn mtlo(&mut self, s: u8) -> String {
-        format!("mtlo {}", reg(s))
+    fn syscall(&mut self) -> T {
+        line("syscall").into()
     }
 
-    fn trap(&mut self) -> String {
-        "trap".to_string()
+    fn break_(&mut self, code: u32) -> T {
+        if code == 0 {
+            line("break").into()
+        } else {
+            line("break").immediate(code.to_string()).into()
+        }
     }
 
-    fn syscall(&mut self) -> String {
-        "syscall".to_string()
+    fn sync(&mut self) -> T {
+        line("sync").into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::decoder::Decoder;
+    use crate::cpu::disassemble::{DisassembledLine, Disassembler, DisasmTokenKind, HexLabelProvider};
+
+    fn disassembler(pc: u32) -> Disassembler<HexLabelProvider> {
+        Disassembler { pc, labels: HexLabelProvider::default(), raw_nop: false }
+    }
+
+    // Each kind as a letter, so a line's shape fits on one line: M mnemonic, R register, I immediate,
+    // A address and P punctuation.
+    fn shape(line: &DisassembledLine) -> String {
+        line.tokens.iter()
+            .map(|token| match token.kind {
+                DisasmTokenKind::Mnemonic => 'M',
+                DisasmTokenKind::Register => 'R',
+                DisasmTokenKind::FpRegister => 'F',
+                DisasmTokenKind::Immediate => 'I',
+                DisasmTokenKind::Address => 'A',
+                DisasmTokenKind::Punctuation => 'P',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_format_is_typed() {
+        let cases = [
+            ("add $t0, $t1, $t2", "MPRPRPR"), // R
+            ("mult $t0, $t1", "MPRPR"),
+            ("jr $ra", "MPR"),
+            ("mfhi $v0", "MPR"),
+            ("sll $t0, $t1, 4", "MPRPRPI"), // shift
+            ("addi $t0, $t1, -0x14", "MPRPRPI"), // I, signed
+            ("ori $t0, $t1, 0xff", "MPRPRPI"), // I, unsigned
+            ("lui $t0, 0x1234", "MPRPI"),
+            ("lw $t0, 8($sp)", "MPRPIPRP"), // memory
+            ("sb $a0, -1($t1)", "MPRPIPRP"),
+            ("beq $t0, $t1, 0x00400000", "MPRPRPA"), // branch
+            ("bgez $t0, 0x00400000", "MPRPA"), // regimm
+            ("j 0x00400000", "MPA"), // J
+            ("jal 0x00400000", "MPA"),
+            ("syscall", "M"),
+            ("break 5", "MPI"),
+            ("nop", "M"),
+        ];
+
+        for (source, expected) in cases {
+            let binary = assemble_from(source).unwrap();
+            let word = u32::from_le_bytes(binary.regions[0].data[..4].try_into().unwrap());
+
+            let line: DisassembledLine = disassembler(0x400000).dispatch(word).unwrap();
+            let text: String = disassembler(0x400000).dispatch(word).unwrap();
+
+            // The plain text is made from the tokens, they can't disagree.
+            assert_eq!(line.text(), text);
+            assert_eq!(line.to_string(), text);
+            assert_eq!(text, source);
+
+            assert_eq!(shape(&line), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn tokens_split_where_they_should() {
+        let line: DisassembledLine = disassembler(0x400000).dispatch(0x8fa80008).unwrap(); // lw $t0, 8($sp)
+        let texts: Vec<&str> = line.tokens.iter().map(|token| token.text.as_str()).collect();
+
+        assert_eq!(texts, ["lw", " ", "$t0", ", ", "8", "(", "$sp", ")"]);
+
+        // Nothing uses the floating point kind.
+        assert!(!line.tokens.iter().any(|token| token.kind == DisasmTokenKind::FpRegister));
+    }
+
+    #[test]
+    fn break_and_sync_list_with_everything_else() {
+        let words = [
+            0x3c081001, // lui $t0, 0x1001
+            0x0000000d, // break
+            0x0000000f, // sync
+            0x21080004, // addi $t0, $t0, 4
+            0x03ffffcd, // break 0xfffff
+            0x03e00008, // jr $ra
+        ];
+
+        let listing: Option<Vec<String>> = words.iter().enumerate()
+            .map(|(index, word)| disassembler(0x400000 + 4 * index as u32).dispatch(*word))
+            .collect();
+
+        assert_eq!(listing.unwrap(), [
+            "lui $t0, 0x1001", "break", "sync", "addi $t0, $t0, 4", "break 1048575", "jr $ra",
+        ]);
+
+        // Functions that aren't instructions still aren't.
+        for word in [0x00000001u32, 0x00000005, 0x0000003f] {
+            let line: Option<String> = disassembler(0x400000).dispatch(word);
+
+            assert_eq!(line, None, "0x{word:08x}");
+        }
     }
 }
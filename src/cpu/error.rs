@@ -10,9 +10,10 @@ pub enum MemoryAlignment {
 pub enum Error {
     MemoryAlign(MemoryAlignment, u32),
     MemoryUnmapped(u32),
-    CpuInvalid(u32),
+    CpuInvalid(u32, u32), // pc, word
     CpuTrap,
     CpuSyscall, // Intended to be caught by higher level.
+    CpuBreak(u32), // code
 }
 
 impl Display for Error {
@@ -29,15 +30,63 @@ impl Display for Error {
             Error::MemoryUnmapped(address) => {
                 write!(f, "Memory access for address 0x{address:08x} is prohibited (unmapped memory).")
             }
-            Error::CpuInvalid(instruction) => {
-                write!(f, "Invalid CPU instruction 0x{instruction:08x}")
+            Error::CpuInvalid(pc, word) => {
+                write!(f, "Invalid CPU instruction 0x{word:08x} at 0x{pc:08x} ({})", describe_invalid(*word))
             }
             Error::CpuTrap => write!(f, "The instruction was given invalid parameters (CPU Trap was thrown)."),
             Error::CpuSyscall => write!(f, "CPU Syscall was not handled"),
+            Error::CpuBreak(code) => write!(f, "Break instruction was executed (code {code})"),
         }
     }
 }
 
+// A guess at what a word that doesn't decode is, for messages.
+pub fn describe_invalid(word: u32) -> String {
+    let bytes = word.to_le_bytes();
+    let printable = bytes.iter().filter(|byte| byte.is_ascii_graphic() || **byte == b' ').count();
+    let text = bytes.iter().all(|byte| byte.is_ascii_graphic() || matches!(byte, b' ' | b'\n' | b'\t' | 0));
+
+    if text && printable >= 2 {
+        let shown: String = bytes.iter().map(|byte| byte.escape_ascii().to_string()).collect();
+
+        return format!("looks like text: \"{shown}\"")
+    }
+
+    // Code is mostly upper bits (opcode and registers), small numbers are usually data that ran as code.
+    if word <= 0xFFFF {
+        return format!("looks like data: 0x{word:08x}")
+    }
+
+    match word >> 26 {
+        opcode @ (0 | 28) => format!("unknown function 0x{:02x} for opcode 0x{opcode:02x}", word & 0x3F),
+        1 => format!("unknown branch condition 0x{:02x}", (word >> 16) & 0x1F),
+        opcode => format!("unknown opcode 0x{opcode:02x}"),
+    }
+}
+
 impl std::error::Error for Error {}
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::error::{describe_invalid, Error};
+
+    #[test]
+    fn invalid_words_are_described() {
+        assert_eq!(describe_invalid(u32::from_le_bytes(*b"hi!\n")), "looks like text: \"hi!\\n\"");
+        assert_eq!(describe_invalid(0x0000000a), "looks like data: 0x0000000a");
+        assert_eq!(describe_invalid(0x0001003f), "unknown function 0x3f for opcode 0x00");
+        assert_eq!(describe_invalid(0x7001003f), "unknown function 0x3f for opcode 0x1c");
+        assert_eq!(describe_invalid(0x041f0000), "unknown branch condition 0x1f");
+        assert_eq!(describe_invalid(0xfc000000), "unknown opcode 0x3f");
+    }
+
+    #[test]
+    fn invalid_instructions_name_the_pc() {
+        assert_eq!(
+            Error::CpuInvalid(0x400008, 0x0000000a).to_string(),
+            "Invalid CPU instruction 0x0000000a at 0x00400008 (looks like data: 0x0000000a)"
+        );
+    }
+}
@@ -0,0 +1,598 @@
+use crate::cpu::decoder::{Decoder, WordField, CODE, FUNCT, IMMEDIATE, OPCODE, RD, RS, RT, SHAMT, TARGET};
+use crate::cpu::disassemble::reg;
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+use std::fmt::{Display, Formatter};
+
+// Coprocessor 1 words. Titan has no FPU so these never decode, they are only named.
+pub const FMT: WordField = WordField { name: "fmt", ..RS };
+pub const FT: WordField = WordField { name: "ft", ..RT };
+pub const FS: WordField = WordField { name: "fs", ..RD };
+pub const FD: WordField = WordField { name: "fd", ..SHAMT };
+
+const COP1: u32 = 17;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WordFormat {
+    Register,
+    Immediate,
+    Jump,
+    FloatRegister, // cop1 arithmetic, shown but never decoded
+}
+
+impl WordFormat {
+    pub fn layout(&self) -> &'static [WordField] {
+        match self {
+            WordFormat::Register => &[OPCODE, RS, RT, RD, SHAMT, FUNCT],
+            WordFormat::Immediate => &[OPCODE, RS, RT, IMMEDIATE],
+            WordFormat::Jump => &[OPCODE, TARGET],
+            WordFormat::FloatRegister => &[OPCODE, FMT, FT, FS, FD, FUNCT],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WordFormat::Register => "R",
+            WordFormat::Immediate => "I",
+            WordFormat::Jump => "J",
+            WordFormat::FloatRegister => "FR",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FieldValue {
+    pub field: WordField,
+    pub value: u32,
+    pub operand: Option<usize>, // position in the disassembly (0 is the first operand) this field is written to
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WordBreakdown {
+    pub word: u32,
+    pub format: WordFormat,
+    pub fields: Vec<FieldValue>, // from the highest bits down
+    pub instruction: Option<Instruction>, // None if the word doesn't decode, fields are then raw
+}
+
+// Field -> operand position, in the order the operands are written.
+type Operands = &'static [(WordField, usize)];
+
+const DST: Operands = &[(RD, 0), (RS, 1), (RT, 2)];
+const DTS: Operands = &[(RD, 0), (RT, 1), (RS, 2)];
+const SHIFT: Operands = &[(RD, 0), (RT, 1), (SHAMT, 2)];
+const ST: Operands = &[(RS, 0), (RT, 1)];
+const S: Operands = &[(RS, 0)];
+const D: Operands = &[(RD, 0)];
+const TSI: Operands = &[(RT, 0), (RS, 1), (IMMEDIATE, 2)];
+const TI: Operands = &[(RT, 0), (IMMEDIATE, 1)];
+const STI: Operands = &[(RS, 0), (RT, 1), (IMMEDIATE, 2)];
+const SI: Operands = &[(RS, 0), (IMMEDIATE, 1)];
+const MEMORY: Operands = &[(RT, 0), (IMMEDIATE, 1), (RS, 1)]; // lw $t0, imm($s)
+const JUMP: Operands = &[(TARGET, 0)];
+const NONE: Operands = &[];
+
+// The operands, and the layout if it isn't the format's usual one.
+type Explained = (Operands, Option<&'static [WordField]>);
+
+// Which fields feed which operands, through the same dispatch that decodes the word.
+struct OperandDecoder;
+
+impl Decoder<Explained> for OperandDecoder {
+    fn add(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn addu(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn and(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn div(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn divu(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn mult(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn multu(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn nor(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn or(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn sll(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (SHIFT, None)
+    }
+
+    fn sllv(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DTS, None)
+    }
+
+    fn sra(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (SHIFT, None)
+    }
+
+    fn srav(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DTS, None)
+    }
+
+    fn srl(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (SHIFT, None)
+    }
+
+    fn srlv(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DTS, None)
+    }
+
+    fn sub(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn subu(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn xor(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn slt(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn sltu(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn jr(&mut self, _: u8) -> Explained {
+        (S, None)
+    }
+
+    fn jalr(&mut self, _: u8) -> Explained {
+        (S, None)
+    }
+
+    fn madd(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn maddu(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn mul(&mut self, _: u8, _: u8, _: u8) -> Explained {
+        (DST, None)
+    }
+
+    fn msub(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn msubu(&mut self, _: u8, _: u8) -> Explained {
+        (ST, None)
+    }
+
+    fn addi(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (TSI, None)
+    }
+
+    fn addiu(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (TSI, None)
+    }
+
+    fn andi(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (TSI, None)
+    }
+
+    fn ori(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (TSI, None)
+    }
+
+    fn xori(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (TSI, None)
+    }
+
+    fn lui(&mut self, _: u8, _: u16) -> Explained {
+        (TI, None)
+    }
+
+    fn lhi(&mut self, _: u8, _: u16) -> Explained {
+        (TI, None)
+    }
+
+    fn llo(&mut self, _: u8, _: u16) -> Explained {
+        (TI, None)
+    }
+
+    fn slti(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (TSI, None)
+    }
+
+    fn sltiu(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (TSI, None)
+    }
+
+    fn beq(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (STI, None)
+    }
+
+    fn bne(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (STI, None)
+    }
+
+    fn bgtz(&mut self, _: u8, _: u16) -> Explained {
+        (SI, None)
+    }
+
+    fn blez(&mut self, _: u8, _: u16) -> Explained {
+        (SI, None)
+    }
+
+    fn bltz(&mut self, _: u8, _: u16) -> Explained {
+        (SI, None)
+    }
+
+    fn bgez(&mut self, _: u8, _: u16) -> Explained {
+        (SI, None)
+    }
+
+    fn bltzal(&mut self, _: u8, _: u16) -> Explained {
+        (SI, None)
+    }
+
+    fn bgezal(&mut self, _: u8, _: u16) -> Explained {
+        (SI, None)
+    }
+
+    fn j(&mut self, _: u32) -> Explained {
+        (JUMP, None)
+    }
+
+    fn jal(&mut self, _: u32) -> Explained {
+        (JUMP, None)
+    }
+
+    fn lb(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn lbu(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn lh(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn lhu(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn lw(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn sb(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn sh(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn sw(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn ll(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn sc(&mut self, _: u8, _: u8, _: u16) -> Explained {
+        (MEMORY, None)
+    }
+
+    fn mfhi(&mut self, _: u8) -> Explained {
+        (D, None)
+    }
+
+    fn mflo(&mut self, _: u8) -> Explained {
+        (D, None)
+    }
+
+    fn mthi(&mut self, _: u8) -> Explained {
+        (S, None)
+    }
+
+    fn mtlo(&mut self, _: u8) -> Explained {
+        (S, None)
+    }
+
+    fn trap(&mut self) -> Explained {
+        (NONE, None)
+    }
+
+    fn syscall(&mut self) -> Explained {
+        (NONE, None)
+    }
+
+    fn break_(&mut self, _: u32) -> Explained {
+        (&[(CODE, 0)], Some(&[OPCODE, CODE, FUNCT]))
+    }
+
+    fn sync(&mut self) -> Explained {
+        (NONE, None)
+    }
+}
+
+// The opcodes with a second level of dispatch, where a word can fail to decode but still has a known format.
+fn raw_format(word: u32) -> Option<WordFormat> {
+    match OPCODE.get(word) {
+        0 | 28 => Some(WordFormat::Register),
+        1 => Some(WordFormat::Immediate),
+        COP1 => Some(WordFormat::FloatRegister),
+        _ => None,
+    }
+}
+
+fn decoded_format(word: u32) -> WordFormat {
+    match OPCODE.get(word) {
+        0 | 28 => WordFormat::Register,
+        2 | 3 => WordFormat::Jump,
+        _ => WordFormat::Immediate,
+    }
+}
+
+// None for an opcode nothing decodes (outside of cop1).
+// Branch and jump targets in the instruction are worked out as if the word were at address 0, see explain_word_at.
+pub fn explain_word(word: u32) -> Option<WordBreakdown> {
+    explain_word_at(0, word)
+}
+
+pub fn explain_word_at(pc: u32, word: u32) -> Option<WordBreakdown> {
+    let decoded = OperandDecoder.dispatch(word);
+
+    let (format, operands, layout) = match decoded {
+        Some((operands, layout)) => {
+            let format = decoded_format(word);
+
+            (format, operands, layout.unwrap_or(format.layout()))
+        }
+        None => {
+            let format = raw_format(word)?;
+
+            (format, NONE, format.layout())
+        }
+    };
+
+    let fields = layout.iter()
+        .map(|field| FieldValue {
+            field: *field,
+            value: field.get(word),
+            operand: operands.iter()
+                .find(|(operand, _)| operand == field)
+                .map(|(_, index)| *index),
+        })
+        .collect();
+
+    Some(WordBreakdown {
+        word,
+        format,
+        fields,
+        instruction: decoded.and_then(|_| InstructionDecoder::decode(pc, word)),
+    })
+}
+
+impl FieldValue {
+    fn bits(&self) -> String {
+        format!("{:0width$b}", self.value, width = self.field.width as usize)
+    }
+
+    fn range(&self) -> String {
+        if self.field.width == 1 {
+            format!("{}", self.field.low)
+        } else {
+            format!("{}-{}", self.field.high(), self.field.low)
+        }
+    }
+
+    // What the operand row shows: the register for register fields, the operand position otherwise.
+    fn operand_text(&self) -> String {
+        let Some(index) = self.operand else { return String::new() };
+
+        match self.field {
+            RS | RT | RD => format!("#{} {}", index + 1, reg(self.value as u8)),
+            _ => format!("#{}", index + 1),
+        }
+    }
+}
+
+// One column per field:
+//
+//   add $t2, $t0, $t1 (R format, 0x01095020)
+//   | 31-26  | 25-21  | 20-16  | 15-11  | 10-6  | 5-0    |
+//   | opcode | rs     | rt     | rd     | shamt | funct  |
+//   | 000000 | 01000  | 01001  | 01010  | 00000 | 100000 |
+//   | 0      | 8      | 9      | 10     | 0     | 32     |
+//   |        | #2 $t0 | #3 $t1 | #1 $t2 |       |        |
+impl Display for WordBreakdown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.instruction {
+            Some(instruction) => write!(f, "{instruction}")?,
+            None => write!(f, "(does not decode)")?,
+        }
+
+        writeln!(f, " ({} format, 0x{:08x})", self.format.name(), self.word)?;
+
+        let rows: Vec<Vec<String>> = self.fields.iter()
+            .map(|field| vec![
+                field.range(),
+                field.field.name.to_string(),
+                field.bits(),
+                field.value.to_string(),
+                field.operand_text(),
+            ])
+            .collect();
+
+        let widths: Vec<usize> = rows.iter()
+            .map(|column| column.iter().map(|cell| cell.len()).max().unwrap_or(0))
+            .collect();
+
+        for row in 0..5 {
+            // Nothing feeds an operand (ex. syscall, or a word that doesn't decode).
+            if row == 4 && self.fields.iter().all(|field| field.operand.is_none()) {
+                break
+            }
+
+            write!(f, "|")?;
+
+            for (column, width) in rows.iter().zip(&widths) {
+                write!(f, " {:width$} |", column[row], width = *width)?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::decoder::{CODE, FUNCT, IMMEDIATE, OPCODE, RD, RS, RT, SHAMT, TARGET};
+    use crate::cpu::explain::{explain_word, explain_word_at, WordBreakdown, WordFormat, FD, FMT, FS, FT};
+    use crate::unit::instruction::Instruction;
+
+    // (field name, value, operand) for each field.
+    fn fields(breakdown: &WordBreakdown) -> Vec<(&'static str, u32, Option<usize>)> {
+        breakdown.fields.iter()
+            .map(|field| (field.field.name, field.value, field.operand))
+            .collect()
+    }
+
+    #[test]
+    fn register_words_map_fields_to_operands() {
+        let breakdown = explain_word(0x01095020).unwrap(); // add $t2, $t0, $t1
+
+        assert_eq!(breakdown.format, WordFormat::Register);
+        assert!(matches!(breakdown.instruction, Some(Instruction::Add { .. })));
+        assert_eq!(fields(&breakdown), [
+            ("opcode", 0, None), ("rs", 8, Some(1)), ("rt", 9, Some(2)),
+            ("rd", 10, Some(0)), ("shamt", 0, None), ("funct", 32, None),
+        ]);
+    }
+
+    #[test]
+    fn immediate_words_map_fields_to_operands() {
+        let breakdown = explain_word(0x8FA80008).unwrap(); // lw $t0, 8($sp)
+
+        assert_eq!(breakdown.format, WordFormat::Immediate);
+        assert!(matches!(breakdown.instruction, Some(Instruction::Lw { .. })));
+
+        // The offset and base are both the second operand.
+        assert_eq!(fields(&breakdown), [("opcode", 35, None), ("rs", 29, Some(1)), ("rt", 8, Some(0)), ("immediate", 8, Some(1))]);
+    }
+
+    #[test]
+    fn jump_words_have_a_target() {
+        let breakdown = explain_word_at(0x400000, 0x08100004).unwrap(); // j 0x400010
+
+        assert_eq!(breakdown.format, WordFormat::Jump);
+        assert_eq!(fields(&breakdown), [("opcode", 2, None), ("target", 0x100004, Some(0))]);
+        assert_eq!(breakdown.instruction.unwrap().to_string(), "j 0x400010");
+    }
+
+    #[test]
+    fn break_codes_have_their_own_layout() {
+        let breakdown = explain_word(0x0000014D).unwrap(); // break 5
+
+        assert_eq!(fields(&breakdown), [("opcode", 0, None), ("code", 5, Some(0)), ("funct", 13, None)]);
+    }
+
+    #[test]
+    fn words_that_dont_decode_keep_their_raw_fields() {
+        // add.s $f0, $f1, $f2, titan has no FPU.
+        let breakdown = explain_word(0x46020800).unwrap();
+
+        assert_eq!(breakdown.format, WordFormat::FloatRegister);
+        assert_eq!(breakdown.instruction, None);
+        assert_eq!(fields(&breakdown), [
+            ("opcode", 17, None), ("fmt", 16, None), ("ft", 2, None), ("fs", 1, None), ("fd", 0, None), ("funct", 0, None),
+        ]);
+
+        // A funct nothing decodes still splits as an R word.
+        let breakdown = explain_word(0x00000001).unwrap();
+
+        assert_eq!(breakdown.format, WordFormat::Register);
+        assert_eq!(breakdown.instruction, None);
+        assert!(breakdown.fields.iter().all(|field| field.operand.is_none()));
+
+        // An unknown opcode has no format to show.
+        assert_eq!(explain_word(0xFC000000), None);
+    }
+
+    #[test]
+    fn layouts_cover_every_bit_once() {
+        let layouts: [&[_]; 5] = [
+            WordFormat::Register.layout(),
+            WordFormat::Immediate.layout(),
+            WordFormat::Jump.layout(),
+            WordFormat::FloatRegister.layout(),
+            &[OPCODE, CODE, FUNCT],
+        ];
+
+        for layout in layouts {
+            let mut next = 31;
+
+            for field in layout {
+                assert_eq!(field.high(), next, "{}", field.name);
+
+                next = field.low.wrapping_sub(1);
+            }
+
+            assert_eq!(next, u32::MAX);
+        }
+
+        // The cop1 names sit on the same bits as the fields they rename.
+        assert_eq!([FMT.low, FT.low, FS.low, FD.low], [RS.low, RT.low, RD.low, SHAMT.low]);
+        assert_eq!((IMMEDIATE.high(), TARGET.high()), (15, 25));
+    }
+
+    #[test]
+    fn diagrams_line_up() {
+        let text = explain_word(0x01095020).unwrap().to_string();
+
+        assert_eq!(text, "\
+add $t2, $t0, $t1 (R format, 0x01095020)
+| 31-26  | 25-21  | 20-16  | 15-11  | 10-6  | 5-0    |
+| opcode | rs     | rt     | rd     | shamt | funct  |
+| 000000 | 01000  | 01001  | 01010  | 00000 | 100000 |
+| 0      | 8      | 9      | 10     | 0     | 32     |
+|        | #2 $t0 | #3 $t1 | #1 $t2 |       |        |
+");
+
+        // No operand row when nothing feeds one.
+        let text = explain_word(0x0000000C).unwrap().to_string(); // syscall
+
+        assert_eq!(text.lines().count(), 5);
+        assert!(text.starts_with("syscall (R format, 0x0000000c)"), "{text}");
+
+        let text = explain_word(0x46020800).unwrap().to_string();
+
+        assert!(text.starts_with("(does not decode) (FR format, 0x46020800)"), "{text}");
+    }
+}
@@ -1,18 +1,33 @@
-use crate::cpu::error::Result;
+use crate::cpu::error::Error::MemoryAlign;
+use crate::cpu::error::{MemoryAlignment, Result};
 use byteorder;
 use byteorder::{ByteOrder, LittleEndian};
 
+// Semantics every implementation follows (SectionMemory is the reference):
+//  - Values are little endian.
+//  - u16/u32 accesses need an address aligned to 2/4, otherwise MemoryAlign(_, address).
+//  - Touching an unmapped byte fails with MemoryUnmapped(byte address).
+//  - An aligned access may span two mounted regions if they are adjacent.
+//  - Mounting a region with no data maps nothing.
 pub trait Memory {
     fn get(&self, address: u32) -> Result<u8>;
     fn set(&mut self, address: u32, value: u8) -> Result<()>;
 
     fn get_u16(&self, address: u32) -> Result<u16> {
+        if address % 2 != 0 {
+            return Err(MemoryAlign(MemoryAlignment::Half, address));
+        }
+
         Ok(LittleEndian::read_u16(
             [self.get(address)?, self.get(address + 1)?].as_slice(),
         ))
     }
 
     fn get_u32(&self, address: u32) -> Result<u32> {
+        if address % 4 != 0 {
+            return Err(MemoryAlign(MemoryAlignment::Word, address));
+        }
+
         Ok(LittleEndian::read_u32(
             [
                 self.get(address)?,
@@ -25,6 +40,10 @@ pub trait Memory {
     }
 
     fn set_u16(&mut self, address: u32, value: u16) -> Result<()> {
+        if address % 2 != 0 {
+            return Err(MemoryAlign(MemoryAlignment::Half, address));
+        }
+
         let bytes = value.to_le_bytes();
 
         self.set(address, bytes[0])?;
@@ -32,6 +51,10 @@ pub trait Memory {
     }
 
     fn set_u32(&mut self, address: u32, value: u32) -> Result<()> {
+        if address % 4 != 0 {
+            return Err(MemoryAlign(MemoryAlignment::Word, address));
+        }
+
         let bytes = value.to_le_bytes();
 
         self.set(address, bytes[0])?;
@@ -39,6 +62,38 @@ pub trait Memory {
         self.set(address + 2, bytes[2])?;
         self.set(address + 3, bytes[3])
     }
+
+    // Reads that aren't the program's (ex. the value a write replaces, debugger views), so memory
+    // watching what the program reads can leave them out. Otherwise the same as get.
+    fn peek(&self, address: u32) -> Result<u8> {
+        self.get(address)
+    }
+
+    fn peek_u16(&self, address: u32) -> Result<u16> {
+        self.get_u16(address)
+    }
+
+    fn peek_u32(&self, address: u32) -> Result<u32> {
+        self.get_u32(address)
+    }
+
+    // For viewers, not the CPU: any address, one byte at a time, so a value may cross section
+    // boundaries. Addresses wrap past 0xFFFFFFFF. Peeked, so still fails if a byte is unmapped.
+    fn read_u16_unaligned(&self, address: u32) -> Result<u16> {
+        Ok(u16::from_le_bytes([self.peek(address)?, self.peek(address.wrapping_add(1))?]))
+    }
+
+    fn read_u32_unaligned(&self, address: u32) -> Result<u32> {
+        Ok(u32::from_le_bytes([
+            self.peek(address)?,
+            self.peek(address.wrapping_add(1))?,
+            self.peek(address.wrapping_add(2))?,
+            self.peek(address.wrapping_add(3))?,
+        ]))
+    }
+
+    // Called once after every executed instruction, so mounted devices can keep time.
+    fn tick(&mut self) {}
 }
 
 pub struct Region {
@@ -49,3 +104,134 @@ pub struct Region {
 pub trait Mountable {
     fn mount(&mut self, region: Region);
 }
+
+// A section's worth of memory (section::SECTION_SIZE bytes), as saved in a state snapshot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectionContents<'a> {
+    Uniform(u8), // every byte has this value
+    Data(&'a [u8]),
+}
+
+// Memory that can be saved and restored a section at a time. Device sections are never included,
+// they belong to whatever device is mounted when the state is restored.
+pub trait SavableMemory: Memory {
+    // Mapped sections by selector (the top 16 bits of their addresses), in order.
+    fn contents(&self) -> Vec<(usize, SectionContents<'_>)>;
+
+    // Replaces every section that isn't a device, anything not in contents ends up unmapped.
+    fn restore_contents(&mut self, contents: &[(usize, SectionContents)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::error::Error::{MemoryAlign, MemoryUnmapped};
+    use crate::cpu::error::MemoryAlignment::{Half, Word};
+    use crate::cpu::memory::region::RegionMemory;
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::cpu::memory::watched::WatchedMemory;
+    use crate::cpu::memory::{Memory, Mountable, Region};
+    use crate::execution::elf::setup::STACK_TOP;
+    use crate::execution::stack::StackGrowth;
+
+    const TEXT: u32 = 0x00400000;
+    const EDGE: u32 = 0x0001FFF8; // the last 8 bytes before a 64 KiB boundary, nothing mounted past it
+    const SPLIT: u32 = 0x0003FFFC; // a word whose halves are in two regions, on either side of a boundary
+    const EMPTY: u32 = 0x30000000;
+    const UNMAPPED: u32 = 0x70000000;
+
+    fn mounted<M: Memory + Mountable>(mut memory: M) -> M {
+        memory.mount(Region { start: TEXT, data: (0..16).collect() });
+        memory.mount(Region { start: EDGE, data: vec![0xAA; 8] });
+        memory.mount(Region { start: SPLIT, data: vec![0x11, 0x22] });
+        memory.mount(Region { start: SPLIT + 2, data: vec![0x33, 0x44] });
+        memory.mount(Region { start: EMPTY, data: vec![] });
+
+        memory
+    }
+
+    // The semantics listed above Memory, for any implementation.
+    fn conformance<M: Memory + Mountable>(memory: M) {
+        let mut memory = mounted(memory);
+
+        // Aligned, little endian.
+        assert_eq!(memory.get(TEXT + 1), Ok(1));
+        assert_eq!(memory.get_u16(TEXT + 2), Ok(0x0302));
+        assert_eq!(memory.get_u32(TEXT + 4), Ok(0x07060504));
+
+        memory.set_u32(TEXT + 8, 0xDEADBEEF).unwrap();
+        memory.set_u16(TEXT + 12, 0x1234).unwrap();
+
+        assert_eq!(memory.get(TEXT + 8), Ok(0xEF));
+        assert_eq!(memory.get_u32(TEXT + 8), Ok(0xDEADBEEF));
+        assert_eq!(memory.get_u32(TEXT + 12), Ok(0x0F0E1234));
+        assert_eq!(memory.peek_u32(TEXT + 8), memory.get_u32(TEXT + 8));
+
+        // Unaligned.
+        assert_eq!(memory.get_u16(TEXT + 1), Err(MemoryAlign(Half, TEXT + 1)));
+        assert_eq!(memory.get_u32(TEXT + 2), Err(MemoryAlign(Word, TEXT + 2)));
+        assert_eq!(memory.set_u16(TEXT + 3, 0), Err(MemoryAlign(Half, TEXT + 3)));
+        assert_eq!(memory.set_u32(TEXT + 1, 0), Err(MemoryAlign(Word, TEXT + 1)));
+        assert_eq!(memory.get_u32(TEXT), Ok(0x03020100), "a misaligned store wrote something");
+
+        assert_eq!(memory.read_u16_unaligned(TEXT + 1), Ok(0x0201));
+        assert_eq!(memory.read_u32_unaligned(TEXT + 1), Ok(0x04030201));
+
+        // Boundary, the last word before unmapped memory.
+        assert_eq!(memory.get_u32(EDGE + 4), Ok(0xAAAAAAAA));
+
+        memory.set_u32(EDGE + 4, 0x01020304).unwrap();
+
+        assert_eq!(memory.get_u32(EDGE + 4), Ok(0x01020304));
+        assert_eq!(memory.read_u32_unaligned(EDGE + 6), Err(MemoryUnmapped(EDGE + 8)));
+        assert_eq!(memory.get(EDGE + 8), Err(MemoryUnmapped(EDGE + 8)));
+
+        // Unmapped.
+        assert_eq!(memory.get(UNMAPPED), Err(MemoryUnmapped(UNMAPPED)));
+        assert_eq!(memory.get_u16(UNMAPPED), Err(MemoryUnmapped(UNMAPPED)));
+        assert_eq!(memory.get_u32(UNMAPPED), Err(MemoryUnmapped(UNMAPPED)));
+        assert_eq!(memory.set(UNMAPPED, 1), Err(MemoryUnmapped(UNMAPPED)));
+        assert_eq!(memory.set_u32(UNMAPPED, 1), Err(MemoryUnmapped(UNMAPPED)));
+        assert_eq!(memory.get(EMPTY), Err(MemoryUnmapped(EMPTY)), "an empty region mapped memory");
+
+        // Multi-region, an aligned access over two adjacent regions.
+        assert_eq!(memory.get_u32(SPLIT), Ok(0x44332211));
+
+        memory.set_u32(SPLIT, 0x88776655).unwrap();
+
+        assert_eq!(memory.get_u16(SPLIT), Ok(0x6655));
+        assert_eq!(memory.get_u16(SPLIT + 2), Ok(0x8877));
+    }
+
+    #[test]
+    fn region_memory() {
+        conformance(RegionMemory::new())
+    }
+
+    #[test]
+    fn section_memory() {
+        conformance(SectionMemory::<DefaultResponder>::new())
+    }
+
+    #[test]
+    fn watched_memory() {
+        conformance(WatchedMemory::new(SectionMemory::<DefaultResponder>::new()));
+        conformance(WatchedMemory::new(RegionMemory::new()))
+    }
+
+    // Viewers and stack growth read memory the program hasn't written, none of it is the program's read.
+    #[test]
+    fn viewer_reads_are_not_uninitialized_reads() {
+        let mut memory = mounted(SectionMemory::<DefaultResponder>::new());
+        memory.mount(Region { start: STACK_TOP - 0x1000, data: vec![0; 0x1000] });
+        memory.track_uninitialized_reads();
+
+        assert_eq!(memory.read_u16_unaligned(TEXT + 1), Ok(0x0201));
+        assert_eq!(memory.read_u32_unaligned(TEXT + 1), Ok(0x04030201));
+        assert_eq!(memory.peek_u32(TEXT), Ok(0x03020100));
+        assert!(StackGrowth::new(0x100000).page_for(&memory, STACK_TOP - 0x2000).is_some());
+        assert_eq!(memory.first_uninitialized_read(), None);
+
+        assert_eq!(memory.get_u32(TEXT + 4), Ok(0x07060504));
+        assert_eq!(memory.first_uninitialized_read(), Some(TEXT + 4));
+    }
+}
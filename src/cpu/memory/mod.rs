@@ -1,6 +1,7 @@
 pub mod region;
 pub mod section;
 pub mod watched;
+#[allow(clippy::module_inception)]
 pub mod memory;
 
-pub use memory::{Memory, Mountable, Region};
+pub use memory::{Memory, Mountable, Region, SavableMemory, SectionContents};
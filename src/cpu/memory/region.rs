@@ -2,11 +2,19 @@ use crate::cpu::error::Error::{MemoryAlign, MemoryUnmapped};
 use crate::cpu::error::{MemoryAlignment, Result};
 use crate::cpu::memory::{Mountable, Region};
 use crate::cpu::Memory;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 
 impl Region {
     pub fn contains(&self, address: u32) -> bool {
-        self.start <= address && address < self.start + self.data.len() as u32
+        let address = address as u64;
+        let start = self.start as u64;
+
+        start <= address && address < start + self.data.len() as u64
+    }
+
+    // True if all `size` bytes at address sit inside this region.
+    fn contains_all(&self, address: u32, size: usize) -> bool {
+        self.contains(address) && (address - self.start) as usize + size <= self.data.len()
     }
 }
 
@@ -18,7 +26,9 @@ type Endian = LittleEndian;
 
 impl Mountable for RegionMemory {
     fn mount(&mut self, region: Region) {
-        self.regions.push(region)
+        if !region.data.is_empty() {
+            self.regions.push(region)
+        }
     }
 }
 
@@ -26,6 +36,29 @@ impl RegionMemory {
     pub fn new() -> RegionMemory {
         RegionMemory { regions: vec![] }
     }
+
+    fn get_bytes<const N: usize>(&self, address: u32) -> Result<[u8; N]> {
+        let mut bytes = [0u8; N];
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.get(address.wrapping_add(i as u32))?;
+        }
+
+        Ok(bytes)
+    }
+
+    fn set_bytes(&mut self, address: u32, bytes: &[u8]) -> Result<()> {
+        // Check every byte first so a failed store leaves memory untouched.
+        for i in 0..bytes.len() {
+            self.get(address.wrapping_add(i as u32))?;
+        }
+
+        for (i, byte) in bytes.iter().enumerate() {
+            self.set(address.wrapping_add(i as u32), *byte)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for RegionMemory {
@@ -63,7 +96,7 @@ impl Memory for RegionMemory {
         }
 
         for region in &self.regions {
-            if region.contains(address) {
+            if region.contains_all(address, 2) {
                 let start = (address - region.start) as usize;
                 let data = (&region.data[start..start + 2]).read_u16::<Endian>();
 
@@ -71,7 +104,10 @@ impl Memory for RegionMemory {
             }
         }
 
-        Err(MemoryUnmapped(address))
+        // Value spans adjacent regions (or part of it is unmapped).
+        let bytes = self.get_bytes::<2>(address)?;
+
+        Ok(Endian::read_u16(&bytes))
     }
 
     fn get_u32(&self, address: u32) -> Result<u32> {
@@ -80,7 +116,7 @@ impl Memory for RegionMemory {
         }
 
         for region in &self.regions {
-            if region.contains(address) {
+            if region.contains_all(address, 4) {
                 let start = (address - region.start) as usize;
                 let data = (&region.data[start..start + 4]).read_u32::<Endian>();
 
@@ -88,16 +124,19 @@ impl Memory for RegionMemory {
             }
         }
 
-        Err(MemoryUnmapped(address))
+        // Value spans adjacent regions (or part of it is unmapped).
+        let bytes = self.get_bytes::<4>(address)?;
+
+        Ok(Endian::read_u32(&bytes))
     }
 
     fn set_u16(&mut self, address: u32, value: u16) -> Result<()> {
         if address % 2 != 0 {
-            panic!("Address 0x{address:08x} is not aligned for u16 read.");
+            return Err(MemoryAlign(MemoryAlignment::Half, address));
         }
 
         for region in &mut self.regions {
-            if region.contains(address) {
+            if region.contains_all(address, 2) {
                 let start = (address - region.start) as usize;
 
                 (&mut region.data[start..start + 2])
@@ -108,16 +147,19 @@ impl Memory for RegionMemory {
             }
         }
 
-        Err(MemoryUnmapped(address))
+        let mut bytes = [0u8; 2];
+        Endian::write_u16(&mut bytes, value);
+
+        self.set_bytes(address, &bytes)
     }
 
     fn set_u32(&mut self, address: u32, value: u32) -> Result<()> {
         if address % 4 != 0 {
-            panic!("Address 0x{address:08x} is not aligned for u32 read.");
+            return Err(MemoryAlign(MemoryAlignment::Word, address));
         }
 
         for region in &mut self.regions {
-            if region.contains(address) {
+            if region.contains_all(address, 4) {
                 let start = (address - region.start) as usize;
 
                 (&mut region.data[start..start + 4])
@@ -128,6 +170,9 @@ impl Memory for RegionMemory {
             }
         }
 
-        Err(MemoryUnmapped(address))
+        let mut bytes = [0u8; 4];
+        Endian::write_u32(&mut bytes, value);
+
+        self.set_bytes(address, &bytes)
     }
 }
@@ -1,8 +1,9 @@
 use crate::cpu::error::Error::{MemoryAlign, MemoryUnmapped};
 use crate::cpu::error::{MemoryAlignment, Result};
 use crate::cpu::memory::section::Section::{Data, Empty, Writable};
-use crate::cpu::memory::{Mountable, Region};
+use crate::cpu::memory::{Mountable, Region, SavableMemory, SectionContents};
 use crate::cpu::Memory;
+use std::cell::Cell;
 use std::fmt::{Debug, Formatter};
 use Section::Listen;
 
@@ -11,13 +12,95 @@ const SECTION_SELECTOR_START: u32 = 16;
 const SECTION_SELECTOR_MASK: u32 = !0u32 << SECTION_SELECTOR_START;
 const SECTION_INDEX_MASK: u32 = !0u32 >> (32 - SECTION_SELECTOR_START);
 const SECTION_COUNT: usize = 1 << (32 - SECTION_SELECTOR_START);
-const SECTION_SIZE: usize = 1 << SECTION_SELECTOR_START;
+pub const SECTION_SIZE: usize = 1 << SECTION_SELECTOR_START;
 
 const INITIAL_BYTE: u8 = 0xCC;
 
+// What memory holds before the program writes to it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InitPolicy {
+    Zero,
+    Fixed(u8),
+    RandomSeeded(u64), // the same seed gives the same bytes at the same addresses, in any order
+}
+
+impl Default for InitPolicy {
+    fn default() -> Self {
+        InitPolicy::Fixed(INITIAL_BYTE)
+    }
+}
+
+// splitmix64, one output per 8 byte block.
+fn random_block(seed: u64, block: u32) -> u64 {
+    let mut z = seed.wrapping_add((block as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+
+    z ^ (z >> 31)
+}
+
+impl InitPolicy {
+    // data is the memory starting at address.
+    pub fn fill(&self, address: u32, data: &mut [u8]) {
+        match self {
+            InitPolicy::Zero => data.fill(0),
+            InitPolicy::Fixed(value) => data.fill(*value),
+            InitPolicy::RandomSeeded(seed) => {
+                for (offset, value) in data.iter_mut().enumerate() {
+                    let address = address.wrapping_add(offset as u32);
+
+                    *value = (random_block(*seed, address >> 3) >> ((address & 7) * 8)) as u8
+                }
+            }
+        }
+    }
+}
+
+const POISON_WORDS: usize = SECTION_SIZE / 64;
+
+// One bit per byte, set once the byte is written. Sections without one haven't been written at all.
+#[derive(Clone)]
+struct Poison {
+    written: Vec<Option<Box<[u64; POISON_WORDS]>>>,
+    first_read: Cell<Option<u32>>,
+}
+
+impl Poison {
+    fn mark(&mut self, address: u32, count: u32) {
+        for address in (0 .. count).map(|offset| address.wrapping_add(offset)) {
+            let (selector, index) = split(address);
+
+            let bits = self.written[selector].get_or_insert_with(|| Box::new([0; POISON_WORDS]));
+
+            bits[index / 64] |= 1 << (index % 64)
+        }
+    }
+
+    fn check(&self, address: u32, count: u32) {
+        if self.first_read.get().is_some() {
+            return
+        }
+
+        for address in (0 .. count).map(|offset| address.wrapping_add(offset)) {
+            let (selector, index) = split(address);
+
+            let written = self.written[selector].as_ref()
+                .is_some_and(|bits| bits[index / 64] & (1 << (index % 64)) != 0);
+
+            if !written {
+                return self.first_read.set(Some(address))
+            }
+        }
+    }
+}
+
 pub trait ListenResponder {
     fn read(&self, address: u32) -> Result<u8>;
     fn write(&mut self, address: u32, value: u8) -> Result<()>;
+
+    // Called once after every executed instruction, for devices that keep time in instructions
+    // (ex. a transmitter that stays busy for a while after a write).
+    fn tick(&mut self) {}
 }
 
 #[derive(Clone)]
@@ -56,8 +139,32 @@ impl<T: ListenResponder> Debug for Section<T> {
     }
 }
 
+// How a mounted range of memory behaves, see SectionMemory::mounted_sections.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    Data,
+    Listen, // a device, reads and writes go to its responder
+    Writable, // reads as one value until it's first written
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SectionInfo {
+    pub selector: usize, // of the first section
+    pub kind: SectionKind,
+    pub len: u64, // bytes, a run of consecutive sections of the same kind
+}
+
+impl SectionInfo {
+    pub fn start(&self) -> u32 {
+        (self.selector << SECTION_SELECTOR_START) as u32
+    }
+}
+
 pub struct SectionMemory<T: ListenResponder> {
     sections: Box<[Section<T>; SECTION_COUNT]>,
+    listeners: Vec<usize>, // selectors of Listen sections, ticked after every instruction
+    pub init: InitPolicy, // for sections created from now on
+    poison: Option<Poison>,
 }
 
 impl<T: ListenResponder + Clone> Clone for SectionMemory<T> {
@@ -68,7 +175,12 @@ impl<T: ListenResponder + Clone> Clone for SectionMemory<T> {
             .try_into()
             .unwrap();
 
-        SectionMemory { sections }
+        SectionMemory {
+            sections,
+            listeners: self.listeners.clone(),
+            init: self.init,
+            poison: self.poison.clone()
+        }
     }
 }
 
@@ -81,7 +193,63 @@ impl<T: ListenResponder> SectionMemory<T> {
             .try_into()
             .unwrap();
 
-        SectionMemory { sections }
+        SectionMemory { sections, listeners: vec![], init: InitPolicy::default(), poison: None }
+    }
+
+    pub fn with_init(init: InitPolicy) -> SectionMemory<T> {
+        SectionMemory { init, ..Self::new() }
+    }
+
+    // From now on, remembers which bytes were written (mounting doesn't count, see mark_written)
+    // and the first data byte read before it was. Listen sections aren't tracked.
+    pub fn track_uninitialized_reads(&mut self) {
+        self.poison = Some(Poison {
+            written: vec![None; SECTION_COUNT],
+            first_read: Cell::new(None),
+        })
+    }
+
+    // ex. the binary's own regions, which start out initialized.
+    pub fn mark_written(&mut self, address: u32, count: u32) {
+        if let Some(poison) = &mut self.poison {
+            poison.mark(address, count)
+        }
+    }
+
+    // Peeks don't count.
+    pub fn first_uninitialized_read(&self) -> Option<u32> {
+        self.poison.as_ref()?.first_read.get()
+    }
+
+    pub fn clear_uninitialized_read(&self) {
+        if let Some(poison) = &self.poison {
+            poison.first_read.set(None)
+        }
+    }
+
+    fn written(&mut self, address: u32, count: u32) {
+        if let Some(poison) = &mut self.poison {
+            poison.mark(address, count)
+        }
+    }
+
+    fn read(&self, address: u32, count: u32) {
+        if let Some(poison) = &self.poison {
+            poison.check(address, count)
+        }
+    }
+
+    // Runs f without noting any uninitialized reads.
+    fn quietly<R>(&self, f: impl FnOnce(&Self) -> R) -> R {
+        let first_read = self.poison.as_ref().map(|poison| poison.first_read.get());
+
+        let result = f(self);
+
+        if let (Some(poison), Some(first_read)) = (&self.poison, first_read) {
+            poison.first_read.set(first_read)
+        }
+
+        result
     }
 
     fn allocate_data(value: u8) -> Box<[u8; SECTION_SIZE]> {
@@ -89,7 +257,10 @@ impl<T: ListenResponder> SectionMemory<T> {
     }
 
     fn create_section(&mut self, selector: usize) -> &mut [u8; SECTION_SIZE] {
-        self.sections[selector] = Data(Self::allocate_data(INITIAL_BYTE));
+        let mut data = Self::allocate_data(0);
+        self.init.fill((selector << SECTION_SELECTOR_START) as u32, data.as_mut_slice());
+
+        self.sections[selector] = Data(data);
 
         match &mut self.sections[selector] {
             Data(data) => data.as_mut(),
@@ -111,6 +282,24 @@ impl<T: ListenResponder> SectionMemory<T> {
     // selector is NOT an address! Leading 16-bits.
     pub fn mount_listen(&mut self, selector: usize, listener: T) {
         self.sections[selector] = Listen(listener);
+
+        if !self.listeners.contains(&selector) {
+            self.listeners.push(selector)
+        }
+    }
+
+    pub fn listener(&self, selector: usize) -> Option<&T> {
+        match &self.sections[selector] {
+            Listen(listener) => Some(listener),
+            _ => None,
+        }
+    }
+
+    pub fn listener_mut(&mut self, selector: usize) -> Option<&mut T> {
+        match &mut self.sections[selector] {
+            Listen(listener) => Some(listener),
+            _ => None,
+        }
     }
 
     pub fn mount_writable(&mut self, selector: usize, value: u8) {
@@ -119,6 +308,29 @@ impl<T: ListenResponder> SectionMemory<T> {
             self.sections[selector] = Writable(value)
         }
     }
+
+    // Everything mounted, in address order, without reading any of it.
+    pub fn mounted_sections(&self) -> Vec<SectionInfo> {
+        let mut result: Vec<SectionInfo> = vec![];
+
+        for (selector, section) in self.sections.iter().enumerate() {
+            let kind = match section {
+                Empty => continue,
+                Data(_) => SectionKind::Data,
+                Listen(_) => SectionKind::Listen,
+                Writable(_) => SectionKind::Writable,
+            };
+
+            match result.last_mut() {
+                Some(last) if last.kind == kind && last.selector + (last.len as usize / SECTION_SIZE) == selector => {
+                    last.len += SECTION_SIZE as u64
+                }
+                _ => result.push(SectionInfo { selector, kind, len: SECTION_SIZE as u64 }),
+            }
+        }
+
+        result
+    }
 }
 
 impl<T: ListenResponder> Default for SectionMemory<T> {
@@ -139,10 +351,18 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
         let (section, index) = split(address);
 
         match &self.sections[section] {
-            Data(section) => Ok(section[index]),
+            Data(section) => {
+                self.read(address, 1);
+
+                Ok(section[index])
+            }
             Listen(responder) => responder.read(address),
             Empty => Err(MemoryUnmapped(address)),
-            Writable(value) => Ok(*value),
+            Writable(value) => {
+                self.read(address, 1);
+
+                Ok(*value)
+            }
         }
     }
 
@@ -153,6 +373,8 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
             Data(section) => {
                 section[index] = value;
 
+                self.written(address, 1);
+
                 Ok(())
             }
             Listen(responder) => responder.write(address, value),
@@ -162,6 +384,7 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 data[index] = value;
 
                 self.sections[section] = Data(data);
+                self.written(address, 1);
 
                 Ok(())
             }
@@ -180,12 +403,19 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
         }
 
         match &self.sections[section] {
-            Data(section) =>
-                Ok(glue(section[index], section[index + 1])),
+            Data(section) => {
+                self.read(address, 2);
+
+                Ok(glue(section[index], section[index + 1]))
+            }
             Listen(responder) =>
                 Ok(glue(responder.read(address)?, responder.read(address + 1)?)),
             Empty => Err(MemoryUnmapped(address)),
-            Writable(value) => Ok(glue(*value, *value)),
+            Writable(value) => {
+                self.read(address, 2);
+
+                Ok(glue(*value, *value))
+            }
         }
     }
 
@@ -201,12 +431,11 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
         }
 
         match &self.sections[section] {
-            Data(section) => Ok(glue(
-                section[index],
-                section[index + 1],
-                section[index + 2],
-                section[index + 3]
-            )),
+            Data(section) => {
+                self.read(address, 4);
+
+                Ok(glue(section[index], section[index + 1], section[index + 2], section[index + 3]))
+            }
             Listen(responder) => Ok(glue(
                 responder.read(address)?,
                 responder.read(address + 1)?,
@@ -214,7 +443,11 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 responder.read(address + 3)?
             )),
             Empty => Err(MemoryUnmapped(address)),
-            Writable(value) => Ok(glue(*value, *value, *value, *value)),
+            Writable(value) => {
+                self.read(address, 4);
+
+                Ok(glue(*value, *value, *value, *value))
+            }
         }
     }
 
@@ -232,6 +465,8 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 section[index] = a;
                 section[index + 1] = b;
 
+                self.written(address, 2);
+
                 Ok(())
             }
             Listen(responder) => {
@@ -245,6 +480,7 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 data[index + 1] = b;
 
                 self.sections[section] = Data(data);
+                self.written(address, 2);
 
                 Ok(())
             }
@@ -272,6 +508,8 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 section[index + 2] = c;
                 section[index + 3] = d;
 
+                self.written(address, 4);
+
                 Ok(())
             }
             Listen(responder) => {
@@ -289,41 +527,193 @@ impl<T: ListenResponder> Memory for SectionMemory<T> {
                 data[index + 3] = d;
 
                 self.sections[section] = Data(data);
+                self.written(address, 4);
 
                 Ok(())
             }
         }
     }
+
+    fn peek(&self, address: u32) -> Result<u8> {
+        self.quietly(|memory| memory.get(address))
+    }
+
+    fn peek_u16(&self, address: u32) -> Result<u16> {
+        self.quietly(|memory| memory.get_u16(address))
+    }
+
+    fn peek_u32(&self, address: u32) -> Result<u32> {
+        self.quietly(|memory| memory.get_u32(address))
+    }
+
+    fn tick(&mut self) {
+        for selector in &self.listeners {
+            if let Listen(responder) = &mut self.sections[*selector] {
+                responder.tick()
+            }
+        }
+    }
 }
 
 impl<T: ListenResponder> Mountable for SectionMemory<T> {
     fn mount(&mut self, region: Region) {
-        let (start_selector, start_index) = split(region.start);
-        let (end_selector, end_index) = split(region.start + region.data.len() as u32);
-
-        let mut selector = start_selector;
         let mut data_index = 0;
 
-        while selector <= end_selector {
+        // Copy section by section. Stops early if the region runs past the end of memory.
+        while data_index < region.data.len() {
+            let Some(address) = region.start.checked_add(data_index as u32) else {
+                break
+            };
+
+            let (selector, index) = split(address);
+            let count = (SECTION_SIZE - index).min(region.data.len() - data_index);
+
             let section = self.pick_section(selector);
 
-            let begin = if selector == start_selector {
-                start_index
-            } else {
-                0
-            };
-            let end = if selector == end_selector {
-                end_index
-            } else {
-                SECTION_SIZE
-            };
+            section[index..index + count]
+                .copy_from_slice(&region.data[data_index..data_index + count]);
+
+            data_index += count;
+        }
+    }
+}
+
+impl<T: ListenResponder> SavableMemory for SectionMemory<T> {
+    fn contents(&self) -> Vec<(usize, SectionContents<'_>)> {
+        self.sections.iter().enumerate()
+            .filter_map(|(selector, section)| {
+                let contents = match section {
+                    Data(data) if data.iter().all(|value| *value == data[0]) => SectionContents::Uniform(data[0]),
+                    Data(data) => SectionContents::Data(data.as_slice()),
+                    Writable(value) => SectionContents::Uniform(*value),
+                    Empty | Listen(_) => return None,
+                };
+
+                Some((selector, contents))
+            })
+            .collect()
+    }
+
+    fn restore_contents(&mut self, contents: &[(usize, SectionContents)]) {
+        for section in self.sections.iter_mut() {
+            if !matches!(section, Listen(_)) {
+                *section = Empty
+            }
+        }
 
-            for i in section.iter_mut().take(end).skip(begin) {
-                *i = region.data[data_index];
-                data_index += 1;
+        for (selector, contents) in contents {
+            if matches!(self.sections[*selector], Listen(_)) {
+                continue
             }
 
-            selector += 1
+            self.sections[*selector] = match contents {
+                SectionContents::Uniform(value) => Writable(*value),
+                SectionContents::Data(data) => {
+                    let mut section = Self::allocate_data(0);
+                    section.copy_from_slice(data);
+
+                    Data(section)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::error::Result;
+    use crate::cpu::memory::section::{ListenResponder, SectionKind, SectionMemory};
+    use crate::cpu::memory::{Mountable, Region};
+    use crate::cpu::Memory;
+
+    // Only read and write, like a responder written before devices could tick.
+    #[derive(Clone)]
+    struct Latch(u8);
+
+    impl ListenResponder for Latch {
+        fn read(&self, _: u32) -> Result<u8> {
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _: u32, value: u8) -> Result<()> {
+            self.0 = value;
+
+            Ok(())
+        }
+    }
+
+    #[derive(Clone)]
+    struct Clock(u8);
+
+    impl ListenResponder for Clock {
+        fn read(&self, _: u32) -> Result<u8> {
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _: u32, _: u8) -> Result<()> {
+            Ok(())
         }
+
+        fn tick(&mut self) {
+            self.0 += 1
+        }
+    }
+
+    #[test]
+    fn responders_without_tick_keep_working() {
+        let mut memory = SectionMemory::new();
+        memory.mount_listen(0xFFFF, Latch(0));
+
+        memory.tick();
+        memory.set(0xFFFF0000, 7).unwrap();
+        memory.tick();
+
+        assert_eq!(memory.get(0xFFFF0000), Ok(7));
+    }
+
+    #[test]
+    fn responders_tick_once_an_instruction() {
+        let mut memory = SectionMemory::new();
+        memory.mount_listen(0xFFFF, Clock(0));
+
+        for _ in 0..3 {
+            memory.tick()
+        }
+
+        assert_eq!(memory.get(0xFFFF0000), Ok(3));
+    }
+
+    #[test]
+    fn mounted_sections_merge_runs_of_one_kind() {
+        let mut memory = SectionMemory::new();
+
+        assert_eq!(memory.mounted_sections(), vec![]);
+
+        memory.mount(Region { start: 0x40fff0, data: vec![0; 0x20] });
+        memory.mount_writable(0x1001, 0);
+        memory.mount_writable(0x1002, 0);
+        memory.mount_writable(0x1004, 0);
+        memory.mount_listen(0xFFFE, Latch(0));
+        memory.mount_listen(0xFFFF, Latch(0));
+
+        let sections: Vec<_> = memory.mounted_sections().iter()
+            .map(|section| (section.start(), section.kind, section.len))
+            .collect();
+
+        // The region straddles two sections, the gap at 0x1003 splits the writable run.
+        assert_eq!(sections, [
+            (0x00400000, SectionKind::Data, 0x20000),
+            (0x10010000, SectionKind::Writable, 0x20000),
+            (0x10040000, SectionKind::Writable, 0x10000),
+            (0xFFFE0000, SectionKind::Listen, 0x20000),
+        ]);
+
+        // Reading a writable section doesn't change how it's listed, writing makes it data.
+        memory.get(0x10010000).unwrap();
+        memory.set(0x10040000, 1).unwrap();
+
+        let kinds: Vec<_> = memory.mounted_sections().iter().map(|section| section.kind).collect();
+
+        assert_eq!(kinds, [SectionKind::Data, SectionKind::Writable, SectionKind::Data, SectionKind::Listen]);
     }
 }
@@ -1,7 +1,9 @@
 use smallvec::SmallVec;
+use std::collections::HashMap;
 use crate::cpu::Memory;
 use crate::cpu::error::Result;
-use crate::cpu::memory::{Mountable, Region};
+use crate::cpu::memory::{Mountable, Region, SavableMemory, SectionContents};
+use crate::cpu::memory::section::{ListenResponder, SectionInfo, SectionMemory};
 use crate::cpu::memory::watched::BackupValue::{Byte, Short, Word, Null};
 
 #[derive(Clone)]
@@ -20,13 +22,94 @@ pub struct WatchEntry {
 
 pub const LOG_SIZE: usize = 1;
 
+// In pixels, a pixel is one word.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// A bitmap display in memory, and the pixels written to it since the last take_dirty.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub address: u32,
+    pub line_byte_length: u32,
+    pub height: u32, // rows
+    dirty: Option<(u32, u32, u32, u32)>, // lowest x and y, highest x and y (inclusive)
+}
+
+impl Framebuffer {
+    pub fn new(address: u32, line_byte_length: u32, height: u32) -> Framebuffer {
+        Framebuffer { address, line_byte_length, height, dirty: None }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.line_byte_length / 4
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect { x: 0, y: 0, width: self.width(), height: self.height }
+    }
+
+    fn mark(&mut self, x: u32, y: u32) {
+        self.dirty = Some(match self.dirty {
+            Some((low_x, low_y, high_x, high_y)) => (low_x.min(x), low_y.min(y), high_x.max(x), high_y.max(y)),
+            None => (x, y, x, y),
+        })
+    }
+
+    // Any bytes from address on, only what lands inside the display counts.
+    pub fn touch(&mut self, address: u32, size: u32) {
+        let end = self.line_byte_length as u64 * self.height as u64;
+
+        for byte in [address, address.wrapping_add(size.max(1) - 1)] {
+            let offset = byte.wrapping_sub(self.address) as u64;
+
+            if offset >= end || self.line_byte_length == 0 {
+                continue
+            }
+
+            let offset = offset as u32;
+
+            self.mark((offset % self.line_byte_length) / 4, offset / self.line_byte_length)
+        }
+    }
+
+    pub fn touch_all(&mut self) {
+        if self.width() > 0 && self.height > 0 {
+            self.mark(0, 0);
+            self.mark(self.width() - 1, self.height - 1);
+        }
+    }
+
+    // The smallest rectangle covering every pixel written since the last call, None if nothing was.
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        let (low_x, low_y, high_x, high_y) = self.dirty.take()?;
+
+        Some(Rect { x: low_x, y: low_y, width: high_x - low_x + 1, height: high_y - low_y + 1 })
+    }
+}
+
 #[derive(Clone)]
 pub struct WatchedMemory<T: Memory> {
     pub backing: T,
-    log: SmallVec<[WatchEntry; LOG_SIZE]>
+    log: SmallVec<[WatchEntry; LOG_SIZE]>,
+    pub framebuffer: Option<Framebuffer>, // writes to it are tracked when set
 }
 
 impl WatchEntry {
+    // Bytes the entry restores.
+    pub fn size(&self) -> u32 {
+        match self.previous {
+            Byte(_) => 1,
+            Short(_) => 2,
+            Word(_) => 4,
+            Null => 0,
+        }
+    }
+
     pub fn apply<Mem: Memory>(self, memory: &mut Mem) -> Result<()> {
         match self.previous {
             Byte(value) => memory.set(self.address, value),
@@ -39,12 +122,80 @@ impl WatchEntry {
 
 impl<T: Memory> WatchedMemory<T> {
     pub fn new(backing: T) -> WatchedMemory<T> {
-        WatchedMemory { backing, log: SmallVec::new() }
+        WatchedMemory { backing, log: SmallVec::new(), framebuffer: None }
+    }
+
+    // Writes that skip this layer (ex. back-stepping through backing) still have to reach the framebuffer.
+    pub fn touch(&mut self, address: u32, size: u32) {
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.touch(address, size)
+        }
     }
 
     pub fn take(&mut self) -> SmallVec<[WatchEntry; LOG_SIZE]> {
         std::mem::take(&mut self.log)
     }
+
+    // Like take, but one entry per word written, holding the word from before the first write to it.
+    // An instruction stores once, so this only merges anything for writes outside of one (ex. a syscall
+    // filling a buffer a byte at a time). Words that aren't fully mapped keep their byte entries.
+    pub fn take_step_delta(&mut self) -> SmallVec<[WatchEntry; LOG_SIZE]> {
+        if self.log.len() <= 1 {
+            return self.take()
+        }
+
+        let mut words: Vec<(u32, [Option<u8>; 4])> = vec![]; // word address, each byte from before any write
+        let mut indices: HashMap<u32, usize> = HashMap::new(); // word address -> index in words
+
+        for entry in self.take() {
+            let bytes = match entry.previous {
+                Byte(value) => vec![value],
+                Short(value) => value.to_le_bytes().to_vec(),
+                Word(value) => value.to_le_bytes().to_vec(),
+                Null => continue, // the write failed, nothing changed
+            };
+
+            for (offset, value) in bytes.into_iter().enumerate() {
+                let address = entry.address.wrapping_add(offset as u32);
+                let word = address & !3;
+
+                let index = *indices.entry(word).or_insert_with(|| {
+                    words.push((word, [None; 4]));
+
+                    words.len() - 1
+                });
+
+                words[index].1[(address & 3) as usize].get_or_insert(value);
+            }
+        }
+
+        let mut result = SmallVec::new();
+
+        for (word, bytes) in words {
+            // Bytes that weren't written still hold their old value.
+            if let Ok(current) = self.backing.peek_u32(word) {
+                let mut previous = current.to_le_bytes();
+
+                for (byte, value) in previous.iter_mut().zip(bytes) {
+                    if let Some(value) = value {
+                        *byte = value
+                    }
+                }
+
+                result.push(WatchEntry { address: word, previous: Word(u32::from_le_bytes(previous)) });
+
+                continue
+            }
+
+            for (offset, value) in bytes.into_iter().enumerate() {
+                if let Some(value) = value {
+                    result.push(WatchEntry { address: word + offset as u32, previous: Byte(value) })
+                }
+            }
+        }
+
+        result
+    }
 }
 
 impl<T: Memory> Memory for WatchedMemory<T> {
@@ -54,9 +205,11 @@ impl<T: Memory> Memory for WatchedMemory<T> {
 
     fn set(&mut self, address: u32, value: u8) -> Result<()> {
         self.log.push(WatchEntry {
-            address, previous: self.backing.get(address).map_or(Null, Byte)
+            address, previous: self.backing.peek(address).map_or(Null, Byte)
         });
 
+        self.touch(address, 1);
+
         self.backing.set(address, value)
     }
 
@@ -64,25 +217,51 @@ impl<T: Memory> Memory for WatchedMemory<T> {
         self.backing.get_u16(address)
     }
 
+    fn peek(&self, address: u32) -> Result<u8> {
+        self.backing.peek(address)
+    }
+
+    fn peek_u16(&self, address: u32) -> Result<u16> {
+        self.backing.peek_u16(address)
+    }
+
+    fn peek_u32(&self, address: u32) -> Result<u32> {
+        self.backing.peek_u32(address)
+    }
+
     fn get_u32(&self, address: u32) -> Result<u32> {
         self.backing.get_u32(address)
     }
 
     fn set_u16(&mut self, address: u32, value: u16) -> Result<()> {
         self.log.push(WatchEntry {
-            address, previous: self.backing.get_u16(address).map_or(Null, Short)
+            address, previous: self.backing.peek_u16(address).map_or(Null, Short)
         });
 
+        self.touch(address, 2);
+
         self.backing.set_u16(address, value)
     }
 
     fn set_u32(&mut self, address: u32, value: u32) -> Result<()> {
         self.log.push(WatchEntry {
-            address, previous: self.backing.get_u32(address).map_or(Null, Word)
+            address, previous: self.backing.peek_u32(address).map_or(Null, Word)
         });
 
+        self.touch(address, 4);
+
         self.backing.set_u32(address, value)
     }
+
+    fn tick(&mut self) {
+        self.backing.tick()
+    }
+}
+
+impl<T: ListenResponder> WatchedMemory<SectionMemory<T>> {
+    pub fn mounted_sections(&self) -> Vec<SectionInfo> {
+        self.backing.mounted_sections()
+    }
 }
 
 impl<T: Memory + Mountable> Mountable for WatchedMemory<T> {
@@ -90,3 +269,186 @@ impl<T: Memory + Mountable> Mountable for WatchedMemory<T> {
         self.backing.mount(region)
     }
 }
+
+impl<T: SavableMemory> SavableMemory for WatchedMemory<T> {
+    fn contents(&self) -> Vec<(usize, SectionContents<'_>)> {
+        self.backing.contents()
+    }
+
+    // Entries logged before the restore would undo writes into memory that is no longer there.
+    fn restore_contents(&mut self, contents: &[(usize, SectionContents)]) {
+        self.log.clear();
+
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.touch_all()
+        }
+
+        self.backing.restore_contents(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::cpu::memory::watched::BackupValue::Word;
+    use crate::cpu::memory::watched::{Framebuffer, Rect, WatchedMemory};
+    use crate::cpu::memory::{Mountable, Region};
+    use crate::cpu::Memory;
+    use crate::unit::device::UnitDevice;
+
+    const BUFFER: u32 = 0x10010000;
+    const WORDS: u32 = 1000;
+
+    fn memory() -> WatchedMemory<SectionMemory<DefaultResponder>> {
+        let mut memory = WatchedMemory::new(SectionMemory::new());
+        memory.mount(Region { start: BUFFER, data: (0..4 * WORDS).map(|index| index as u8).collect() });
+
+        memory
+    }
+
+    // A memset a byte at a time (ex. a read syscall filling a buffer), all in one step.
+    #[test]
+    fn byte_writes_merge_into_words() {
+        let mut memory = memory();
+
+        for pass in 0..2 {
+            for offset in 0..4 * WORDS {
+                memory.set(BUFFER + offset, 0xA0 + pass).unwrap()
+            }
+        }
+
+        let delta = memory.take_step_delta();
+
+        assert_eq!(delta.len(), WORDS as usize);
+
+        // Each entry has the word from before the first pass.
+        for entry in delta.into_iter().rev() {
+            let offset = entry.address - BUFFER;
+            let bytes = [offset as u8, (offset + 1) as u8, (offset + 2) as u8, (offset + 3) as u8];
+
+            assert!(matches!(entry.previous, Word(value) if value == u32::from_le_bytes(bytes)));
+
+            entry.apply(&mut memory.backing).unwrap()
+        }
+
+        assert_eq!(memory.backing.get_u32(BUFFER + 4), Ok(0x07060504));
+    }
+
+    // sw already logs one entry, so a memset loop keeps one history entry a store either way.
+    #[test]
+    fn stores_log_one_entry_a_step() {
+        let device = UnitDevice::new(assemble_from(&format!("
+            .data
+            buffer: .space {}
+            .text
+            la $t0, buffer
+            li $t1, {WORDS}
+            loop:
+                sw $zero, 0($t0)
+                addi $t0, $t0, 4
+                addi $t1, $t1, -1
+                bnez $t1, loop
+        ", 4 * WORDS)).unwrap());
+
+        let mut entries = 0;
+
+        for _ in 0..2 + 4 * WORDS {
+            device.step().unwrap();
+
+            entries += device.executor.with_tracker(|history| history.last().map_or(0, |entry| entry.edits.len()));
+        }
+
+        assert_eq!(entries, WORDS as usize);
+    }
+
+    // 16 pixels a row, 8 rows, at the start of the buffer.
+    fn display() -> WatchedMemory<SectionMemory<DefaultResponder>> {
+        let mut memory = memory();
+        memory.framebuffer = Some(Framebuffer::new(BUFFER, 64, 8));
+
+        memory
+    }
+
+    fn pixel(x: u32, y: u32) -> u32 {
+        BUFFER + y * 64 + x * 4
+    }
+
+    fn take_dirty(memory: &mut WatchedMemory<SectionMemory<DefaultResponder>>) -> Option<Rect> {
+        memory.framebuffer.as_mut().unwrap().take_dirty()
+    }
+
+    #[test]
+    fn scattered_pixels_bound_the_dirty_rect() {
+        let mut memory = display();
+
+        assert_eq!(take_dirty(&mut memory), None);
+
+        memory.set_u32(pixel(3, 2), 1).unwrap();
+        memory.set_u32(pixel(10, 5), 1).unwrap();
+        memory.set(pixel(1, 6) + 3, 1).unwrap(); // the last byte of a pixel is still that pixel
+
+        assert_eq!(take_dirty(&mut memory), Some(Rect { x: 1, y: 2, width: 10, height: 5 }));
+        assert_eq!(take_dirty(&mut memory), None);
+
+        // One pixel is a 1 by 1 rect.
+        memory.set_u16(pixel(15, 7), 1).unwrap();
+
+        assert_eq!(take_dirty(&mut memory), Some(Rect { x: 15, y: 7, width: 1, height: 1 }));
+    }
+
+    #[test]
+    fn writes_outside_the_display_are_not_dirty() {
+        let mut memory = display();
+
+        memory.set_u32(BUFFER + 64 * 8, 1).unwrap();
+        memory.set_u32(BUFFER + 64 * 8 + 400, 1).unwrap();
+
+        assert_eq!(take_dirty(&mut memory), None);
+
+        memory.framebuffer.as_mut().unwrap().touch_all();
+
+        assert_eq!(take_dirty(&mut memory), Some(Rect { x: 0, y: 0, width: 16, height: 8 }));
+    }
+
+    #[test]
+    fn devices_report_and_read_dirty_pixels() {
+        let device = UnitDevice::new(assemble_from("
+            .data
+            display: .space 512
+            .text
+            la $t0, display
+            li $t1, 0xFF0000
+            sw $t1, 72($t0)
+            sw $t1, 300($t0)
+        ").unwrap());
+
+        let display = device.binary.labels["display"];
+
+        device.watch_display(display, 64, 8);
+
+        // The whole display, to draw the first frame.
+        assert_eq!(device.take_display_dirty(), Some(Rect { x: 0, y: 0, width: 16, height: 8 }));
+        assert_eq!(device.take_display_dirty(), None);
+
+        for _ in 0..5 {
+            device.step().unwrap();
+        }
+
+        // 72 is (2, 1) and 300 is (11, 4).
+        let dirty = device.take_display_dirty().unwrap();
+
+        assert_eq!(dirty, Rect { x: 2, y: 1, width: 10, height: 4 });
+
+        let pixels = device.read_rect(dirty).unwrap();
+
+        assert_eq!(pixels.len(), 40);
+        assert_eq!((pixels[0], pixels[39]), (0xFF0000, 0xFF0000));
+        assert_eq!(pixels.iter().filter(|pixel| **pixel != 0).count(), 2);
+
+        // Stepping back undoes a pixel, which has to be drawn again.
+        assert!(device.backstep());
+        assert_eq!(device.take_display_dirty(), Some(Rect { x: 11, y: 4, width: 1, height: 1 }));
+        assert_eq!(device.read_rect(Rect { x: 11, y: 4, width: 1, height: 1 }).unwrap(), [0]);
+    }
+}
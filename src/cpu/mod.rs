@@ -1,9 +1,12 @@
+// core.rs is the only CPU implementation (State's Decoder impl), there is no second core to keep in step.
 pub mod core;
 pub mod decoder;
 pub mod disassemble;
+pub mod explain;
 pub mod error;
 pub mod memory;
 pub mod state;
+pub mod snapshot;
 
 pub use memory::Memory;
 pub use state::State;
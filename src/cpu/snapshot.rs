@@ -0,0 +1,446 @@
+use crate::cpu::disassemble::reg;
+use crate::cpu::memory::section::SECTION_SIZE;
+use crate::cpu::memory::{SavableMemory, SectionContents};
+use crate::cpu::snapshot::StateFormatError::{
+    InvalidMagic, InvalidSection, TrailingBytes, Truncated, UnsupportedVersion,
+};
+use crate::cpu::state::Registers;
+use crate::cpu::State;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+
+// Layout (all little endian):
+//   magic u32, version u32
+//   registers: pc u32, 32 general purpose u32s, lo u32, hi u32, link u8 (the ll reservation)
+//   sections:  count u32, then selector u32, kind u8 and
+//              kind 0: one byte every byte of the section has
+//              kind 1: SECTION_SIZE bytes of data
+// Device sections (ex. the console) are not stored, restoring leaves them as they are.
+pub const STATE_MAGIC: u32 = u32::from_le_bytes(*b"TSTA");
+pub const STATE_VERSION: u32 = 2;
+
+const UNIFORM_SECTION: u8 = 0;
+const DATA_SECTION: u8 = 1;
+
+const SELECTOR_COUNT: u32 = 1 << 16;
+
+#[derive(Debug)]
+pub enum StateFormatError {
+    InvalidMagic(u32),
+    UnsupportedVersion(u32),
+    Truncated,
+    TrailingBytes(usize),
+    InvalidSection(u32), // selector
+}
+
+impl Display for StateFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidMagic(magic) => write!(f, "Not a saved state (magic is 0x{magic:08x})"),
+            UnsupportedVersion(version) => write!(
+                f, "Saved state has version {version}, but only version {STATE_VERSION} is supported"),
+            Truncated => write!(f, "Saved state ends early, the data is truncated or corrupted"),
+            TrailingBytes(count) => write!(f, "Saved state has {count} unexpected bytes at the end"),
+            InvalidSection(selector) => write!(f, "Saved state has an invalid memory section (selector 0x{selector:x})"),
+        }
+    }
+}
+
+impl Error for StateFormatError {}
+
+fn read_u32(input: &mut Cursor<&[u8]>) -> Result<u32, StateFormatError> {
+    input.read_u32::<LittleEndian>().map_err(|_| Truncated)
+}
+
+// Borrows from input, so nothing is copied until the state is restored.
+fn read_data<'a>(input: &mut Cursor<&'a [u8]>, length: usize) -> Result<&'a [u8], StateFormatError> {
+    let start = input.position() as usize;
+    let data = input.get_ref().get(start..start + length).ok_or(Truncated)?;
+
+    input.set_position((start + length) as u64);
+
+    Ok(data)
+}
+
+fn read_registers(input: &mut Cursor<&[u8]>) -> Result<Registers, StateFormatError> {
+    let mut registers = Registers::new(read_u32(input)?);
+
+    for value in &mut registers.line {
+        *value = read_u32(input)?
+    }
+
+    registers.lo = read_u32(input)?;
+    registers.hi = read_u32(input)?;
+
+    Ok(registers)
+}
+
+fn read_sections<'a>(input: &mut Cursor<&'a [u8]>) -> Result<Vec<(usize, SectionContents<'a>)>, StateFormatError> {
+    let count = read_u32(input)?;
+
+    if count > SELECTOR_COUNT {
+        return Err(Truncated)
+    }
+
+    let mut sections = Vec::with_capacity(count as usize);
+
+    for _ in 0..count {
+        let selector = read_u32(input)?;
+
+        if selector >= SELECTOR_COUNT {
+            return Err(InvalidSection(selector))
+        }
+
+        let contents = match input.read_u8().map_err(|_| Truncated)? {
+            UNIFORM_SECTION => SectionContents::Uniform(input.read_u8().map_err(|_| Truncated)?),
+            DATA_SECTION => SectionContents::Data(read_data(input, SECTION_SIZE)?),
+            _ => return Err(InvalidSection(selector)),
+        };
+
+        sections.push((selector as usize, contents))
+    }
+
+    Ok(sections)
+}
+
+impl<Mem: SavableMemory> State<Mem> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = vec![];
+
+        output.write_u32::<LittleEndian>(STATE_MAGIC).unwrap();
+        output.write_u32::<LittleEndian>(STATE_VERSION).unwrap();
+
+        let registers = &self.registers;
+
+        output.write_u32::<LittleEndian>(registers.pc).unwrap();
+
+        for value in registers.line {
+            output.write_u32::<LittleEndian>(value).unwrap();
+        }
+
+        output.write_u32::<LittleEndian>(registers.lo).unwrap();
+        output.write_u32::<LittleEndian>(registers.hi).unwrap();
+        output.write_u8(self.link as u8).unwrap();
+
+        let sections = self.memory.contents();
+
+        output.write_u32::<LittleEndian>(sections.len() as u32).unwrap();
+
+        for (selector, contents) in sections {
+            output.write_u32::<LittleEndian>(selector as u32).unwrap();
+
+            match contents {
+                SectionContents::Uniform(value) => {
+                    output.write_u8(UNIFORM_SECTION).unwrap();
+                    output.write_u8(value).unwrap();
+                }
+                SectionContents::Data(data) => {
+                    output.write_u8(DATA_SECTION).unwrap();
+                    output.extend_from_slice(data);
+                }
+            }
+        }
+
+        output
+    }
+
+    // Everything is checked before anything changes, so a bad snapshot leaves the state as it was.
+    pub fn restore_bytes(&mut self, bytes: &[u8]) -> Result<(), StateFormatError> {
+        let mut input = Cursor::new(bytes);
+
+        let magic = read_u32(&mut input)?;
+
+        if magic != STATE_MAGIC {
+            return Err(InvalidMagic(magic))
+        }
+
+        let version = read_u32(&mut input)?;
+
+        if version != STATE_VERSION {
+            return Err(UnsupportedVersion(version))
+        }
+
+        let registers = read_registers(&mut input)?;
+        let link = input.read_u8().map_err(|_| Truncated)? != 0;
+        let sections = read_sections(&mut input)?;
+
+        let remaining = bytes.len() - input.position() as usize;
+
+        if remaining > 0 {
+            return Err(TrailingBytes(remaining))
+        }
+
+        self.registers = registers;
+        self.link = link;
+        self.memory.restore_contents(&sections);
+
+        Ok(())
+    }
+}
+
+// Every register titan has. There is no FPU, so no $f registers or condition flags to report.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterId {
+    Pc,
+    Line(u8), // 0 to 31
+    Lo,
+    Hi,
+}
+
+impl Display for RegisterId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterId::Pc => write!(f, "pc"),
+            RegisterId::Line(index) => write!(f, "{}", reg(*index)),
+            RegisterId::Lo => write!(f, "lo"),
+            RegisterId::Hi => write!(f, "hi"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: RegisterId,
+    pub before: u32,
+    pub after: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryChange {
+    pub address: u32, // word aligned
+    pub before: Option<u32>, // None if unmapped
+    pub after: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub registers: Vec<RegisterChange>,
+    pub memory: Vec<MemoryChange>, // by address
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.memory.is_empty()
+    }
+}
+
+fn describe_word(value: Option<u32>) -> String {
+    value.map(|value| format!("0x{value:08x}")).unwrap_or_else(|| "unmapped".into())
+}
+
+impl Display for StateDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for change in &self.registers {
+            writeln!(f, "{}: 0x{:08x} -> 0x{:08x}", change.register, change.before, change.after)?
+        }
+
+        for change in &self.memory {
+            writeln!(
+                f, "0x{:08x}: {} -> {}",
+                change.address, describe_word(change.before), describe_word(change.after)
+            )?
+        }
+
+        Ok(())
+    }
+}
+
+fn register_changes(before: &Registers, after: &Registers) -> Vec<RegisterChange> {
+    let line = (0..32u8).map(|index| (RegisterId::Line(index), before.line[index as usize], after.line[index as usize]));
+
+    [(RegisterId::Pc, before.pc, after.pc)].into_iter()
+        .chain(line)
+        .chain([(RegisterId::Lo, before.lo, after.lo), (RegisterId::Hi, before.hi, after.hi)])
+        .filter(|(_, before, after)| before != after)
+        .map(|(register, before, after)| RegisterChange { register, before, after })
+        .collect()
+}
+
+fn section_word(contents: Option<&SectionContents>, offset: usize) -> Option<u32> {
+    Some(match contents? {
+        SectionContents::Uniform(value) => u32::from_le_bytes([*value; 4]),
+        SectionContents::Data(data) => u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()),
+    })
+}
+
+fn find_section<'a>(sections: &[(usize, SectionContents<'a>)], selector: usize) -> Option<SectionContents<'a>> {
+    sections.binary_search_by_key(&selector, |(selector, _)| *selector)
+        .ok()
+        .map(|index| sections[index].1)
+}
+
+impl<Mem: SavableMemory> State<Mem> {
+    // What changed going from self to other, memory compared a word at a time.
+    pub fn diff(&self, other: &State<Mem>) -> StateDiff {
+        let before = self.memory.contents();
+        let after = other.memory.contents();
+
+        let mut selectors: Vec<usize> = before.iter().chain(after.iter())
+            .map(|(selector, _)| *selector)
+            .collect();
+
+        selectors.sort();
+        selectors.dedup();
+
+        let mut memory = vec![];
+
+        for selector in selectors {
+            let (old, new) = (find_section(&before, selector), find_section(&after, selector));
+
+            if old == new {
+                continue
+            }
+
+            for offset in (0..SECTION_SIZE).step_by(4) {
+                let (before, after) = (section_word(old.as_ref(), offset), section_word(new.as_ref(), offset));
+
+                if before != after {
+                    let address = ((selector as u32) << 16) | offset as u32;
+
+                    memory.push(MemoryChange { address, before, after })
+                }
+            }
+        }
+
+        StateDiff {
+            registers: register_changes(&self.registers, &other.registers),
+            memory,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::snapshot::{MemoryChange, RegisterChange, RegisterId, StateFormatError, STATE_VERSION};
+    use crate::unit::device::UnitDevice;
+    use crate::unit::register::RegisterName::{S0, T0, T1};
+
+    // la is two instructions, so the store lands on the fourth step.
+    fn device() -> UnitDevice {
+        UnitDevice::new(assemble_from("
+            .data
+            value: .word 5
+            .text
+                la $s0, value
+                li $t0, 9
+                sw $t0, 0($s0)
+                addi $t1, $t0, 1
+                sw $t1, 0($s0)
+        ").unwrap())
+    }
+
+    fn steps(device: &UnitDevice, count: usize) {
+        for _ in 0..count {
+            device.step().unwrap()
+        }
+    }
+
+    #[test]
+    fn saved_states_load_into_another_device() {
+        let first = device();
+
+        steps(&first, 4);
+
+        let bytes = first.snapshot_bytes();
+
+        let second = device();
+        second.restore_from_bytes(&bytes).unwrap();
+
+        assert!(first.snapshot().diff(&second.snapshot()).is_empty());
+
+        // Both carry on the same way.
+        steps(&first, 2);
+        steps(&second, 2);
+
+        assert_eq!(second.get(T1), 10);
+        assert!(first.snapshot().diff(&second.snapshot()).is_empty());
+    }
+
+    #[test]
+    fn diffs_list_registers_and_words() {
+        let device = device();
+
+        steps(&device, 2);
+        let before = device.snapshot();
+
+        steps(&device, 2);
+        let after = device.snapshot();
+
+        let value = device.binary.labels["value"];
+        let diff = before.diff(&after);
+
+        assert_eq!(diff.registers, [
+            RegisterChange { register: RegisterId::Pc, before: 0x400008, after: 0x400010 },
+            RegisterChange { register: RegisterId::Line(T0 as u8), before: 0, after: 9 },
+        ]);
+        assert_eq!(diff.memory, [MemoryChange { address: value, before: Some(5), after: Some(9) }]);
+
+        assert_eq!(
+            diff.to_string(),
+            format!("pc: 0x00400008 -> 0x00400010\n$t0: 0x00000000 -> 0x00000009\n0x{value:08x}: 0x00000005 -> 0x00000009\n")
+        );
+
+        assert!(after.diff(&after).is_empty());
+        assert_eq!(after.diff(&before).registers[1].after, 0);
+    }
+
+    #[test]
+    fn bad_states_change_nothing() {
+        let device = device();
+
+        steps(&device, 4);
+
+        let bytes = device.snapshot_bytes();
+        let saved = device.snapshot();
+
+        let mut magic = bytes.clone();
+        magic[0] ^= 0xFF;
+
+        let mut version = bytes.clone();
+        version[4..8].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+
+        let mut trailing = bytes.clone();
+        trailing.extend([0, 0]);
+
+        // After the magic, version, pc, 32 registers, lo, hi and link comes the section count and the first selector.
+        let first_section = 4 * 37 + 1 + 4;
+
+        let mut selector = bytes.clone();
+        selector[first_section..first_section + 4].copy_from_slice(&(1u32 << 16).to_le_bytes());
+
+        let mut kind = bytes.clone();
+        kind[first_section + 4] = 7;
+
+        let fresh = || {
+            let other = UnitDevice::new(device.binary.clone());
+            other.set(S0, 1234);
+
+            other
+        };
+
+        for (bytes, expected) in [
+            (magic, "InvalidMagic"),
+            (version, "UnsupportedVersion"),
+            (bytes[..bytes.len() - 1].to_vec(), "Truncated"),
+            (trailing, "TrailingBytes(2)"),
+            (selector, "InvalidSection(65536)"),
+            (kind, "InvalidSection"),
+        ] {
+            let other = fresh();
+            let before = other.snapshot();
+
+            let error: StateFormatError = other.restore_from_bytes(&bytes).unwrap_err();
+
+            assert!(format!("{error:?}").starts_with(expected), "{error:?}");
+            assert!(before.diff(&other.snapshot()).is_empty(), "{expected}");
+        }
+
+        let other = fresh();
+        other.restore_from_bytes(&bytes).unwrap();
+
+        assert!(saved.diff(&other.snapshot()).is_empty());
+    }
+}
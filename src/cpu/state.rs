@@ -1,5 +1,8 @@
 use crate::cpu::Memory;
+use std::fmt::{Display, Formatter};
 
+// Plain values with no watch log (history lives in HistoryTracker), so DebugFrame and
+// UnitDevice::registers copy about 140 bytes. It is already the cheap snapshot, there's no FPU to add.
 #[derive(Copy, Clone, Debug)]
 pub struct Registers {
     pub pc: u32,
@@ -8,12 +11,105 @@ pub struct Registers {
     pub hi: u32,
 }
 
+// On MIPS I, hi and lo can't be read within two instructions of mult/div (or the like) writing them,
+// and a write within two instructions of mfhi/mflo can clobber what it reads.
+pub const HI_LO_HAZARD_DISTANCE: u32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HazardMode {
+    Warn, // note it in HiLoHazards::found and carry on
+    Trap, // stop with CpuTrap before the instruction runs
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HiLoHazardKind {
+    ReadAfterWrite, // mfhi/mflo too soon after hi or lo was written
+    WriteAfterRead, // hi or lo written too soon after mfhi/mflo
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HiLoHazard {
+    pub pc: u32, // of the second instruction
+    pub kind: HiLoHazardKind,
+    pub distance: u32, // 1 is the very next instruction
+}
+
+impl Display for HiLoHazard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.distance == 1 { "" } else { "s" };
+
+        match self.kind {
+            HiLoHazardKind::ReadAfterWrite => write!(
+                f, "hi/lo read at 0x{:08x} only {} instruction{plural} after it was written (undefined on MIPS I)",
+                self.pc, self.distance
+            ),
+            HiLoHazardKind::WriteAfterRead => write!(
+                f, "hi/lo written at 0x{:08x} only {} instruction{plural} after mfhi/mflo (undefined on MIPS I)",
+                self.pc, self.distance
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HiLoHazards {
+    pub mode: HazardMode,
+    pub found: Vec<HiLoHazard>,
+    since_write: u32, // instructions retired since hi or lo was written
+    since_read: u32,
+}
+
+impl HiLoHazards {
+    pub fn new(mode: HazardMode) -> HiLoHazards {
+        HiLoHazards { mode, found: vec![], since_write: u32::MAX, since_read: u32::MAX }
+    }
+
+    pub fn retire(&mut self) {
+        self.since_write = self.since_write.saturating_add(1);
+        self.since_read = self.since_read.saturating_add(1);
+    }
+
+    fn check(&mut self, pc: u32, kind: HiLoHazardKind, distance: u32) -> bool {
+        if distance > HI_LO_HAZARD_DISTANCE {
+            return true
+        }
+
+        match self.mode {
+            HazardMode::Warn => {
+                self.found.push(HiLoHazard { pc, kind, distance });
+
+                true
+            }
+            HazardMode::Trap => false,
+        }
+    }
+
+    // false if the instruction at pc should trap instead.
+    pub fn read(&mut self, pc: u32) -> bool {
+        let allowed = self.check(pc, HiLoHazardKind::ReadAfterWrite, self.since_write);
+        self.since_read = 0;
+
+        allowed
+    }
+
+    pub fn write(&mut self, pc: u32) -> bool {
+        let allowed = self.check(pc, HiLoHazardKind::WriteAfterRead, self.since_read);
+        self.since_write = 0;
+
+        allowed
+    }
+}
+
 #[derive(Clone)]
 pub struct State<Mem: Memory> {
     pub registers: Registers,
     pub memory: Mem,
     
     pub zero: u32, // temporary value to overwrite zero, always zero
+
+    pub hazards: Option<HiLoHazards>, // strict MIPS I hi/lo timing, off unless set
+
+    pub link: bool, // set by ll, cleared by any store, sc only stores while it's set (there's one core)
 }
 
 impl Registers {
@@ -33,6 +129,8 @@ impl<Mem: Memory> State<Mem> {
             registers: Registers::new(entry),
             memory,
             zero: 0,
+            hazards: None,
+            link: false,
         }
     }
 }
@@ -1,8 +1,11 @@
 use crate::elf::error::Result;
-use crate::elf::header::HeaderDetails;
-use crate::elf::landmark::Landmark::{Count, Data, Start};
+use crate::elf::header::{HeaderDetails, SECTION_HEADER_SIZE};
+use crate::elf::landmark::Landmark::{
+    Count, Data, SectionCount, SectionData, SectionEntrySize, SectionNames, SectionStart, Start,
+};
 use crate::elf::landmark::Landmarks;
 use crate::elf::program::ProgramHeader;
+use crate::elf::section::{SectionHeader, SectionHeaderFlags, SectionHeaderType, StringTable};
 use crate::elf::Header;
 use std::io::SeekFrom;
 use std::io::{Read, Seek, Write};
@@ -11,6 +14,7 @@ use std::io::{Read, Seek, Write};
 pub struct Elf {
     pub header: Header,
     pub program_headers: Vec<ProgramHeader>,
+    pub sections: Vec<SectionHeader>, // the null section and section names are added when written
 }
 
 impl Elf {
@@ -30,9 +34,11 @@ impl Elf {
             start_index += details.program_entry_size as u64;
         }
 
+        // Loading only needs the program headers, sections are not read back.
         Ok(Elf {
             header,
             program_headers,
+            sections: vec![],
         })
     }
 
@@ -42,7 +48,7 @@ impl Elf {
         landmarks.set(Count, self.program_headers.len() as u64);
 
         self.header.write(stream)?;
-        landmarks.merge(HeaderDetails::write_landmarks(stream)?);
+        landmarks.merge(HeaderDetails::write_landmarks(stream, self.header.flags)?);
 
         landmarks.mark(Start, stream)?;
         for (index, header) in self.program_headers.iter().enumerate() {
@@ -55,8 +61,69 @@ impl Elf {
             stream.write_all(&header.data[..])?;
         }
 
+        if !self.sections.is_empty() {
+            landmarks.merge(self.write_sections(stream)?);
+        }
+
         landmarks.fill_requests(stream)?;
 
         Ok(())
     }
+
+    fn pad_to<T: Write + Seek>(stream: &mut T, alignment: u32) -> Result<()> {
+        let position = stream.stream_position()?;
+        let alignment = alignment.max(1) as u64;
+
+        let padding = (alignment - position % alignment) % alignment;
+        stream.write_all(&vec![0; padding as usize])?;
+
+        Ok(())
+    }
+
+    fn write_sections<T: Write + Seek>(&self, stream: &mut T) -> Result<Landmarks> {
+        let mut landmarks = Landmarks::new();
+
+        let mut names = StringTable::new();
+        let offsets: Vec<u32> = self.sections.iter().map(|section| names.add(&section.name)).collect();
+        let names_offset = names.add(".shstrtab");
+
+        let names_section = SectionHeader {
+            name: ".shstrtab".into(),
+            header_type: SectionHeaderType::StringTable,
+            flags: SectionHeaderFlags::empty(),
+            address: 0,
+            link: 0,
+            info: 0,
+            alignment: 1,
+            entry_size: 0,
+            data: names.data,
+        };
+
+        let sections: Vec<(&SectionHeader, u32)> = self.sections.iter().zip(offsets)
+            .chain([(&names_section, names_offset)])
+            .collect();
+
+        for (index, (section, _)) in sections.iter().enumerate() {
+            Self::pad_to(stream, section.alignment)?;
+            landmarks.mark(SectionData(index), stream)?;
+
+            stream.write_all(&section.data)?;
+        }
+
+        Self::pad_to(stream, 4)?;
+        landmarks.mark(SectionStart, stream)?;
+
+        SectionHeader::write_null(stream)?;
+
+        for (index, (section, name)) in sections.iter().enumerate() {
+            landmarks.merge(section.write(stream, *name, index)?);
+        }
+
+        // Counting the null section, so section names are the last index.
+        landmarks.set(SectionCount, sections.len() as u64 + 1);
+        landmarks.set(SectionNames, sections.len() as u64);
+        landmarks.set(SectionEntrySize, SECTION_HEADER_SIZE as u64);
+
+        Ok(landmarks)
+    }
 }
@@ -9,6 +9,7 @@ pub enum Error {
     InvalidCPU,
     InvalidHeaderType,
     Requires32Bit,
+    UnalignedEntry(u32),
     IoError(std::io::Error),
 }
 
@@ -30,6 +31,7 @@ impl Display for Error {
                 Error::InvalidCPU => "Invalid CPU type found".into(),
                 Error::Requires32Bit => "32-bit elf expected, but found other (64-bit ELF?)".into(),
                 Error::InvalidHeaderType => "Invaid program header type found".into(),
+                Error::UnalignedEntry(entry) => format!("Entry point 0x{entry:08x} is not word aligned"),
                 IoError(error) => format!("{error}"),
             }
         )
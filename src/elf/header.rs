@@ -2,7 +2,7 @@ use crate::elf::error::Error::{
     InvalidBinaryType, InvalidCPU, InvalidEndian, InvalidMagic, Requires32Bit,
 };
 use crate::elf::error::Result;
-use crate::elf::landmark::Landmark::{Count, Start};
+use crate::elf::landmark::Landmark::{Count, SectionCount, SectionEntrySize, SectionNames, SectionStart, Start};
 use crate::elf::landmark::Landmarks;
 use crate::elf::landmark::PointerSize::{Bit16, Bit32};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
@@ -37,6 +37,46 @@ pub enum InstructionSet {
     RiscV = 0xF3,
 }
 
+// EF_MIPS_ARCH, the ISA level in the top 4 bits of e_flags.
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum MipsArch {
+    #[default]
+    Mips1 = 0,
+    Mips2 = 1,
+    Mips3 = 2,
+    Mips4 = 3,
+    Mips5 = 4,
+    Mips32 = 5,
+    Mips64 = 6,
+    Mips32R2 = 7,
+    Mips64R2 = 8,
+    Mips32R6 = 9,
+    Mips64R6 = 10,
+}
+
+pub const ARCH_SHIFT: u32 = 28;
+
+// The rest of e_flags.
+pub const FLAG_NOREORDER: u32 = 1 << 0;
+pub const FLAG_PIC: u32 = 1 << 1;
+pub const FLAG_CPIC: u32 = 1 << 2;
+
+// EI_OSABI.
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum OsAbi {
+    #[default]
+    SystemV = 0,
+    HpUx = 1,
+    NetBsd = 2,
+    Linux = 3,
+    Solaris = 6,
+    Aix = 7,
+    Irix = 8,
+    FreeBsd = 9,
+    OpenBsd = 12,
+    Standalone = 255,
+}
+
 #[derive(Debug)]
 pub struct Header {
     pub magic: u32,
@@ -49,6 +89,7 @@ pub struct Header {
     pub cpu: InstructionSet,
     pub elf_version: u32,
     pub program_entry: u32,
+    pub flags: u32, // e_flags, see MipsArch and FLAG_PIC
 }
 
 #[derive(Debug)]
@@ -86,6 +127,7 @@ impl Header {
             cpu: FromPrimitive::from_u16(stream.read_u16::<Endian>()?).ok_or(InvalidCPU)?,
             elf_version: stream.read_u32::<Endian>()?,
             program_entry: stream.read_u32::<Endian>()?,
+            flags: 0, // comes after the table positions, see below
         };
 
         if header.magic != MAGIC {
@@ -93,7 +135,9 @@ impl Header {
         } else if header.binary_type != BinaryType::Binary32 {
             Err(Requires32Bit)
         } else {
-            Ok((header, HeaderDetails::read(stream)?))
+            let details = HeaderDetails::read(stream)?;
+
+            Ok((Header { flags: details.flags, ..header }, details))
         }
     }
 
@@ -117,6 +161,7 @@ impl Header {
 
 const HEADER_SIZE: u16 = 52;
 const PROGRAM_HEADER_SIZE: u16 = 32;
+pub const SECTION_HEADER_SIZE: u16 = 40;
 
 impl HeaderDetails {
     pub fn read<T: Read>(stream: &mut T) -> Result<HeaderDetails> {
@@ -137,21 +182,25 @@ impl HeaderDetails {
         Ok(details)
     }
 
-    pub fn write_landmarks<T: Write + Seek>(stream: &mut T) -> Result<Landmarks> {
+    pub fn write_landmarks<T: Write + Seek>(stream: &mut T, flags: u32) -> Result<Landmarks> {
         type Endian = LittleEndian;
 
         let mut landmarks = Landmarks::new();
 
         landmarks.request(Bit32, Start, stream)?;
         stream.write_u32::<Endian>(0)?; // program_table_position:
+        landmarks.request(Bit32, SectionStart, stream)?;
         stream.write_u32::<Endian>(0)?; // section_table_point:
-        stream.write_u32::<Endian>(0)?; // flags:
+        stream.write_u32::<Endian>(flags)?; // flags:
         stream.write_u16::<Endian>(HEADER_SIZE)?; // header_size:
         stream.write_u16::<Endian>(PROGRAM_HEADER_SIZE)?; // program_entry_size:
         landmarks.request(Bit16, Count, stream)?;
         stream.write_u16::<Endian>(0)?; // program_entry_count:
+        landmarks.request(Bit16, SectionEntrySize, stream)?;
         stream.write_u16::<Endian>(0)?; // section_entry_size:
+        landmarks.request(Bit16, SectionCount, stream)?;
         stream.write_u16::<Endian>(0)?; // section_entry_count:
+        landmarks.request(Bit16, SectionNames, stream)?;
         stream.write_u16::<Endian>(0)?; // names_point:
 
         Ok(landmarks)
@@ -8,6 +8,11 @@ pub enum Landmark {
     Count,
     Start,
     Data(usize), // index
+    SectionStart,
+    SectionCount,
+    SectionEntrySize,
+    SectionNames,
+    SectionData(usize), // index
 }
 
 pub enum PointerSize {
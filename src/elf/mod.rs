@@ -3,6 +3,7 @@ pub mod error;
 pub mod header;
 mod landmark;
 pub mod program;
+pub mod section;
 
 pub use crate::elf::core::Elf;
 pub use crate::elf::header::Header;
@@ -0,0 +1,136 @@
+use crate::elf::error::Error::InvalidHeaderType;
+use crate::elf::error::Result;
+use crate::elf::header::SECTION_HEADER_SIZE;
+use crate::elf::landmark::Landmark::SectionData;
+use crate::elf::landmark::Landmarks;
+use crate::elf::landmark::PointerSize::Bit32;
+use bitflags::bitflags;
+use byteorder::{LittleEndian, WriteBytesExt};
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::ToPrimitive;
+use std::io::{Seek, Write};
+
+#[derive(ToPrimitive, FromPrimitive, Copy, Clone, Debug)]
+pub enum SectionHeaderType {
+    Null = 0,
+    ProgramData = 1,
+    SymbolTable = 2,
+    StringTable = 3,
+    Rel = 9,
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct SectionHeaderFlags: u32 {
+        const WRITABLE = 1 << 0;
+        const ALLOCATED = 1 << 1;
+        const EXECUTABLE = 1 << 2;
+    }
+}
+
+pub const SYMBOL_SIZE: u32 = 16;
+pub const REL_SIZE: u32 = 8;
+
+// Binding goes in the top 4 bits of a symbol's info byte.
+pub const SYMBOL_LOCAL: u8 = 0;
+pub const SYMBOL_GLOBAL: u8 = 1;
+
+pub const SECTION_UNDEFINED: u16 = 0;
+pub const SECTION_ABSOLUTE: u16 = 0xFFF1;
+
+// Section indices (in link and info) count the null section the writer puts first, so sections[0] is index 1.
+#[derive(Debug)]
+pub struct SectionHeader {
+    pub name: String,
+    pub header_type: SectionHeaderType,
+    pub flags: SectionHeaderFlags,
+    pub address: u32,
+    pub link: u32,
+    pub info: u32,
+    pub alignment: u32,
+    pub entry_size: u32,
+    pub data: Vec<u8>,
+}
+
+// Null terminated names, starting with the empty name at 0.
+pub struct StringTable {
+    pub data: Vec<u8>,
+}
+
+impl StringTable {
+    pub fn new() -> StringTable {
+        StringTable { data: vec![0] }
+    }
+
+    pub fn add(&mut self, value: &str) -> u32 {
+        if value.is_empty() {
+            return 0
+        }
+
+        let offset = self.data.len() as u32;
+
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+
+        offset
+    }
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Symbol {
+    pub name: u32, // offset into the string table
+    pub value: u32,
+    pub binding: u8,
+    pub section: u16,
+}
+
+impl Symbol {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        output.write_u32::<LittleEndian>(self.name).unwrap();
+        output.write_u32::<LittleEndian>(self.value).unwrap();
+        output.write_u32::<LittleEndian>(0).unwrap(); // size
+        output.write_u8(self.binding << 4).unwrap(); // type is left as none
+        output.write_u8(0).unwrap(); // other
+        output.write_u16::<LittleEndian>(self.section).unwrap();
+    }
+}
+
+impl SectionHeader {
+    pub fn write_null<T: Write + Seek>(stream: &mut T) -> Result<()> {
+        stream.write_all(&[0; SECTION_HEADER_SIZE as usize])?;
+
+        Ok(())
+    }
+
+    pub fn write<T: Write + Seek>(
+        &self,
+        stream: &mut T,
+        name: u32,
+        landmark_index: usize,
+    ) -> Result<Landmarks> {
+        type Endian = LittleEndian;
+
+        let mut landmarks = Landmarks::new();
+
+        stream.write_u32::<Endian>(name)?;
+        stream.write_u32::<Endian>(self.header_type.to_u32().ok_or(InvalidHeaderType)?)?;
+        stream.write_u32::<Endian>(self.flags.bits())?;
+        stream.write_u32::<Endian>(self.address)?;
+
+        landmarks.request(Bit32, SectionData(landmark_index), stream)?;
+        stream.write_u32::<Endian>(0)?;
+
+        stream.write_u32::<Endian>(self.data.len() as u32)?;
+        stream.write_u32::<Endian>(self.link)?;
+        stream.write_u32::<Endian>(self.info)?;
+        stream.write_u32::<Endian>(self.alignment)?;
+        stream.write_u32::<Endian>(self.entry_size)?;
+
+        Ok(landmarks)
+    }
+}
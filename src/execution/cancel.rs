@@ -0,0 +1,173 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use parking_lot::{Condvar, Mutex};
+
+struct CancellationInner {
+    cancelled: AtomicBool,
+    lock: Mutex<()>,
+    wake: Condvar, // wakes anyone in wait_timeout
+}
+
+// A flag that can be set from any thread without waiting on whoever checks it.
+// Checking is a single atomic load, so runners can poll it every instruction.
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<CancellationInner>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken {
+            inner: Arc::new(CancellationInner {
+                cancelled: AtomicBool::new(false),
+                lock: Mutex::new(()),
+                wake: Condvar::new(),
+            })
+        }
+    }
+
+    pub fn cancel(&self) {
+        let _lock = self.inner.lock.lock();
+
+        self.inner.cancelled.store(true, Ordering::Release);
+        self.inner.wake.notify_all();
+    }
+
+    pub fn reset(&self) {
+        self.inner.cancelled.store(false, Ordering::Release)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    // Blocks until the token is cancelled or duration passes. Returns true if it was cancelled.
+    pub fn wait_timeout(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut lock = self.inner.lock.lock();
+
+        while !self.is_cancelled() {
+            if self.inner.wake.wait_until(&mut lock, deadline).timed_out() {
+                return self.is_cancelled()
+            }
+        }
+
+        true
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        CancellationToken::new()
+    }
+}
+
+// Calls f once duration passes, unless the timer is dropped first.
+// Dropping wakes the timer thread and joins it, so no thread outlives the timer.
+pub struct Timer {
+    stop: CancellationToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Timer {
+    pub fn start<F: FnOnce () + Send + 'static>(duration: Duration, f: F) -> Timer {
+        let stop = CancellationToken::new();
+        let waiting = stop.clone();
+
+        let thread = thread::spawn(move || {
+            if !waiting.wait_timeout(duration) {
+                f()
+            }
+        });
+
+        Timer { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.stop.cancel();
+
+        if let Some(thread) = self.thread.take() {
+            // If f panicked, that only ended the timer's thread. Don't panic again while dropping.
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::assembler::string::assemble_from;
+    use crate::execution::cancel::{CancellationToken, Timer};
+    use crate::execution::executor::ExecutorMode::{Paused, Running};
+    use crate::unit::device::UnitDevice;
+
+    #[test]
+    fn timers_fire_once_the_duration_passes() {
+        let fired = Arc::new(AtomicBool::new(false));
+        let set = fired.clone();
+
+        let timer = Timer::start(Duration::from_millis(10), move || set.store(true, Ordering::Relaxed));
+
+        thread::sleep(Duration::from_millis(200));
+        drop(timer);
+
+        assert!(fired.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn dropped_timers_return_right_away_without_firing() {
+        let fired = Arc::new(AtomicBool::new(false));
+        let set = fired.clone();
+
+        let start = Instant::now();
+
+        drop(Timer::start(Duration::from_secs(60), move || set.store(true, Ordering::Relaxed)));
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!fired.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn cancelling_wakes_waiters() {
+        let token = CancellationToken::new();
+        let waiting = token.clone();
+
+        let waiter = thread::spawn(move || waiting.wait_timeout(Duration::from_secs(60)));
+
+        token.cancel();
+
+        assert!(waiter.join().unwrap());
+        assert!(!CancellationToken::new().wait_timeout(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn pause_stops_a_run_on_another_thread() {
+        let device = UnitDevice::new(assemble_from("spin: b spin\n").unwrap());
+        let executor = device.executor.clone();
+
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+
+            executor.pause()
+        });
+
+        assert_eq!(device.executor.run(false).mode, Paused);
+        stopper.join().unwrap();
+
+        // A pause requested before run starts is kept until resume.
+        device.executor.pause();
+        assert_eq!(device.executor.run(false).mode, Paused);
+
+        device.executor.resume();
+        assert_eq!(device.executor.frame().mode, Running);
+        assert!(!device.executor.pause_token().is_cancelled());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::cpu::Memory;
+use crate::cpu::state::Registers;
+use crate::execution::executor::ExecutorMode;
+use crate::execution::trackers::Tracker;
+use crate::execution::Executor;
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+use crate::unit::register::RegisterName;
+use num_traits::FromPrimitive;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug)]
+pub struct LockstepOptions {
+    pub limit: u64, // instructions per executor before giving up
+    pub check_memory: bool, // compare the word each store wrote, not just registers
+    pub ignore_registers: Vec<RegisterName>, // ex. $at, which pseudo-instructions are free to use differently
+    pub ignore_hi_lo: bool,
+}
+
+impl Default for LockstepOptions {
+    fn default() -> Self {
+        LockstepOptions {
+            limit: 1_000_000,
+            check_memory: true,
+            ignore_registers: vec![],
+            ignore_hi_lo: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    Pc(u32, u32), // a, b
+    Register(RegisterName, u32, u32),
+    Hi(u32, u32),
+    Lo(u32, u32),
+    Memory { address: u32, a: Option<u32>, b: Option<u32> }, // the aligned word, None if unmapped
+    Mode(ExecutorMode, ExecutorMode),
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let word = |value: &Option<u32>| value.map_or("unmapped".to_string(), |value| format!("0x{value:08x}"));
+
+        match self {
+            Difference::Pc(a, b) => write!(f, "pc 0x{a:08x} != 0x{b:08x}"),
+            Difference::Register(name, a, b) => write!(f, "{name} 0x{a:08x} != 0x{b:08x}"),
+            Difference::Hi(a, b) => write!(f, "hi 0x{a:08x} != 0x{b:08x}"),
+            Difference::Lo(a, b) => write!(f, "lo 0x{a:08x} != 0x{b:08x}"),
+            Difference::Memory { address, a, b } => write!(f, "memory at 0x{address:08x} {} != {}", word(a), word(b)),
+            Difference::Mode(a, b) => write!(f, "stopped {a:?} != {b:?}"),
+        }
+    }
+}
+
+// The first step after which the two executors disagreed.
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    pub step: u64, // instructions run by each before this one, 0 if they differed from the start
+    pub pc: u32, // where the instruction ran, in a
+    pub word: Option<u32>,
+    pub instruction: Option<Instruction>,
+    pub differences: Vec<Difference>,
+}
+
+#[derive(Clone, Debug)]
+pub enum DivergenceReport {
+    Diverged(Divergence),
+    // Both stopped the same way, in the same state (ex. on a syscall). Handle it on both and call again to continue.
+    Stopped { steps: u64, mode: ExecutorMode },
+    StepLimit { steps: u64 },
+}
+
+fn compare_registers(a: &Registers, b: &Registers, options: &LockstepOptions) -> Vec<Difference> {
+    let mut differences = vec![];
+
+    if a.pc != b.pc {
+        differences.push(Difference::Pc(a.pc, b.pc))
+    }
+
+    for (index, (left, right)) in a.line.iter().zip(b.line.iter()).enumerate() {
+        let name = RegisterName::from_usize(index).unwrap();
+
+        if left != right && !options.ignore_registers.contains(&name) {
+            differences.push(Difference::Register(name, *left, *right))
+        }
+    }
+
+    if !options.ignore_hi_lo {
+        if a.hi != b.hi {
+            differences.push(Difference::Hi(a.hi, b.hi))
+        }
+
+        if a.lo != b.lo {
+            differences.push(Difference::Lo(a.lo, b.lo))
+        }
+    }
+
+    differences
+}
+
+// The word a store is about to write into, given the registers before it runs.
+fn store_address(instruction: &Instruction, registers: &Registers) -> Option<u32> {
+    let (s, imm) = match instruction {
+        Instruction::Sb { s, imm, .. } | Instruction::Sh { s, imm, .. } | Instruction::Sw { s, imm, .. }
+        | Instruction::Sc { s, imm, .. } => (s, imm),
+        _ => return None,
+    };
+
+    Some(registers.get(*s).wrapping_add(*imm as i16 as u32) & !3)
+}
+
+fn observe<Mem: Memory, Track: Tracker<Mem>>(executor: &Executor<Mem, Track>) -> (Registers, Option<u32>) {
+    executor.with_state(|state| {
+        let pc = state.registers.pc;
+
+        (state.registers, state.memory.peek_u32(pc).ok())
+    })
+}
+
+fn stopped_mode<Mem: Memory, Track: Tracker<Mem>>(executor: &Executor<Mem, Track>, interrupted: bool) -> Option<ExecutorMode> {
+    interrupted.then(|| executor.frame().mode)
+}
+
+// Steps a and b one instruction at a time (breakpoints are ignored) and stops at the first difference in
+// architectural state: registers always, and with check_memory, the word written by each store.
+// Both should start from equivalent states, ex. the same binary or a reference and a submission.
+pub fn run_lockstep<MemA: Memory, TrackA: Tracker<MemA>, MemB: Memory, TrackB: Tracker<MemB>>(
+    a: &Executor<MemA, TrackA>, b: &Executor<MemB, TrackB>, options: &LockstepOptions
+) -> DivergenceReport {
+    let (registers_a, word) = observe(a);
+    let (registers_b, _) = observe(b);
+
+    let differences = compare_registers(&registers_a, &registers_b, options);
+
+    if !differences.is_empty() {
+        return DivergenceReport::Diverged(Divergence {
+            step: 0,
+            pc: registers_a.pc,
+            word,
+            instruction: word.and_then(|word| InstructionDecoder::decode(registers_a.pc, word)),
+            differences,
+        })
+    }
+
+    let mut registers = registers_a;
+
+    for step in 0..options.limit {
+        let pc = registers.pc;
+        let word = a.with_memory(|memory| memory.peek_u32(pc).ok());
+        let instruction = word.and_then(|word| InstructionDecoder::decode(pc, word));
+
+        let store = options.check_memory
+            .then(|| instruction.as_ref().and_then(|instruction| store_address(instruction, &registers)))
+            .flatten();
+
+        let mode_a = stopped_mode(a, a.cycle(true));
+        let mode_b = stopped_mode(b, b.cycle(true));
+
+        let (registers_a, _) = observe(a);
+        let (registers_b, _) = observe(b);
+
+        let mut differences = compare_registers(&registers_a, &registers_b, options);
+
+        if mode_a != mode_b {
+            differences.push(Difference::Mode(
+                mode_a.unwrap_or(ExecutorMode::Running), mode_b.unwrap_or(ExecutorMode::Running)
+            ))
+        }
+
+        if let Some(address) = store {
+            let value_a = a.with_memory(|memory| memory.peek_u32(address).ok());
+            let value_b = b.with_memory(|memory| memory.peek_u32(address).ok());
+
+            if value_a != value_b {
+                differences.push(Difference::Memory { address, a: value_a, b: value_b })
+            }
+        }
+
+        if !differences.is_empty() {
+            return DivergenceReport::Diverged(Divergence { step, pc, word, instruction, differences })
+        }
+
+        if let Some(mode) = mode_a {
+            return DivergenceReport::Stopped { steps: step, mode }
+        }
+
+        registers = registers_a;
+    }
+
+    DivergenceReport::StepLimit { steps: options.limit }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::error::Error::CpuSyscall;
+    use crate::execution::compare::{run_lockstep, Difference, DivergenceReport, LockstepOptions};
+    use crate::execution::executor::ExecutorMode::Invalid;
+    use crate::unit::device::UnitDevice;
+    use crate::unit::register::RegisterName::T2;
+
+    const PROGRAM: &str = "
+        .data
+        x: .word 0
+        .text
+        main:
+            li $t0, 5
+            li $t1, 7
+            add $t2, $t0, $t1
+            sw $t2, x
+            li $v0, 10
+            syscall
+    ";
+
+    fn lockstep(a: &str, b: &str, options: &LockstepOptions) -> DivergenceReport {
+        let a = UnitDevice::new(assemble_from(a).unwrap());
+        let b = UnitDevice::new(assemble_from(b).unwrap());
+
+        run_lockstep(&a.executor, &b.executor, options)
+    }
+
+    #[test]
+    fn the_same_program_stops_together() {
+        let report = lockstep(PROGRAM, PROGRAM, &LockstepOptions::default());
+
+        // sw to a label is three instructions (lui, ori, sw), the syscall hasn't run.
+        assert!(matches!(report, DivergenceReport::Stopped { steps: 7, mode: Invalid(CpuSyscall) }), "{report:?}");
+    }
+
+    #[test]
+    fn one_changed_instruction_is_pinpointed() {
+        let report = lockstep(PROGRAM, &PROGRAM.replace("add $t2", "sub $t2"), &LockstepOptions::default());
+
+        let DivergenceReport::Diverged(divergence) = report else { panic!("{report:?}") };
+
+        assert_eq!(divergence.step, 2);
+        assert_eq!(divergence.pc, 0x400008);
+        assert_eq!(divergence.word, Some(0x01095020));
+        assert_eq!(divergence.instruction.map(|instruction| instruction.to_string()).as_deref(), Some("add $t2, $t0, $t1"));
+        assert_eq!(divergence.differences, vec![Difference::Register(T2, 12, (-2i32) as u32)]);
+    }
+
+    #[test]
+    fn stores_are_compared_by_the_word_written() {
+        let changed = PROGRAM.replace("sw $t2", "sw $t1");
+
+        let DivergenceReport::Diverged(divergence) = lockstep(PROGRAM, &changed, &LockstepOptions::default()) else {
+            panic!("a different store wasn't noticed")
+        };
+
+        assert_eq!(divergence.step, 5);
+        assert_eq!(divergence.differences, vec![Difference::Memory { address: 0x10010000, a: Some(12), b: Some(7) }]);
+
+        // Registers alone agree.
+        let options = LockstepOptions { check_memory: false, ..Default::default() };
+
+        assert!(matches!(lockstep(PROGRAM, &changed, &options), DivergenceReport::Stopped { steps: 7, .. }));
+    }
+}
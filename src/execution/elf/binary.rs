@@ -1,8 +1,111 @@
-use crate::assembler::binary::{Binary, RegionFlags};
-use crate::elf::header::{BinaryType, Endian, InstructionSet, MAGIC};
+use crate::assembler::binary::{Binary, EntrySource, RawRegion, RegionFlags, RelocationKind};
+use crate::elf::error::Error::UnalignedEntry;
+use crate::elf::header::{
+    BinaryType, Endian, InstructionSet, MipsArch, OsAbi, ARCH_SHIFT, FLAG_CPIC, FLAG_NOREORDER, FLAG_PIC, MAGIC
+};
 use crate::elf::program::ProgramHeaderType::Load;
 use crate::elf::program::{ProgramHeader, ProgramHeaderFlags};
+use crate::elf::section::{
+    SectionHeader, SectionHeaderFlags, SectionHeaderType, StringTable, Symbol, REL_SIZE, SECTION_ABSOLUTE,
+    SECTION_UNDEFINED, SYMBOL_GLOBAL, SYMBOL_LOCAL, SYMBOL_SIZE,
+};
 use crate::elf::{Elf, Header};
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::collections::{BTreeSet, HashMap};
+
+// Header fields for create_elf_with. The defaults give what create_elf always wrote:
+// e_flags 0 (MIPS I, no PIC), the System V ABI and the binary's own entry.
+#[derive(Clone, Debug, Default)]
+pub struct ElfOptions {
+    arch: MipsArch,
+    pic: bool,
+    cpic: bool, // calls go through PIC conventions, set along with pic by most toolchains
+    noreorder: bool, // the code relies on delay slots as written
+    abi: OsAbi,
+    entry: Option<u32>,
+}
+
+impl ElfOptions {
+    pub fn new() -> ElfOptions {
+        ElfOptions::default()
+    }
+
+    pub fn with_arch(mut self, arch: MipsArch) -> Self {
+        self.arch = arch;
+        self
+    }
+
+    pub fn with_pic(mut self, pic: bool) -> Self {
+        self.pic = pic;
+        self
+    }
+
+    pub fn with_cpic(mut self, cpic: bool) -> Self {
+        self.cpic = cpic;
+        self
+    }
+
+    pub fn with_noreorder(mut self, noreorder: bool) -> Self {
+        self.noreorder = noreorder;
+        self
+    }
+
+    pub fn with_abi(mut self, abi: OsAbi) -> Self {
+        self.abi = abi;
+        self
+    }
+
+    // In place of the binary's entry, which the loader jumps to, so it has to be word aligned.
+    pub fn with_entry(mut self, entry: u32) -> crate::elf::error::Result<Self> {
+        if entry % 4 != 0 {
+            return Err(UnalignedEntry(entry))
+        }
+
+        self.entry = Some(entry);
+
+        Ok(self)
+    }
+
+    // e_flags.
+    pub fn flags(&self) -> u32 {
+        let bits = [(self.noreorder, FLAG_NOREORDER), (self.pic, FLAG_PIC), (self.cpic, FLAG_CPIC)];
+
+        bits.into_iter()
+            .filter(|(set, _)| *set)
+            .fold((self.arch as u32) << ARCH_SHIFT, |flags, (_, bit)| flags | bit)
+    }
+}
+
+// e_type, a binary with relocations is an object for a linker rather than something to run.
+const TYPE_RELOCATABLE: u16 = 1;
+
+impl From<RelocationKind> for u32 {
+    fn from(value: RelocationKind) -> Self {
+        match value {
+            RelocationKind::Full => 2, // R_MIPS_32
+            RelocationKind::Jump => 4, // R_MIPS_26
+            RelocationKind::Upper => 5, // R_MIPS_HI16
+            RelocationKind::Lower => 6, // R_MIPS_LO16
+            RelocationKind::Branch => 10, // R_MIPS_PC16
+        }
+    }
+}
+
+impl From<RegionFlags> for SectionHeaderFlags {
+    fn from(value: RegionFlags) -> Self {
+        let mut result = SectionHeaderFlags::ALLOCATED;
+
+        if value.contains(RegionFlags::EXECUTABLE) {
+            result |= SectionHeaderFlags::EXECUTABLE
+        }
+
+        if value.contains(RegionFlags::WRITABLE) {
+            result |= SectionHeaderFlags::WRITABLE
+        }
+
+        result
+    }
+}
 
 impl From<RegionFlags> for ProgramHeaderFlags {
     fn from(value: RegionFlags) -> Self {
@@ -18,26 +121,223 @@ impl From<RegionFlags> for ProgramHeaderFlags {
     }
 }
 
+impl From<ProgramHeaderFlags> for RegionFlags {
+    fn from(value: ProgramHeaderFlags) -> Self {
+        value.iter()
+            .map(|item| match item {
+                ProgramHeaderFlags::EXECUTABLE => RegionFlags::EXECUTABLE,
+                ProgramHeaderFlags::READABLE => RegionFlags::READABLE,
+                ProgramHeaderFlags::WRITABLE => RegionFlags::WRITABLE,
+                _ => RegionFlags::empty(),
+            })
+            .reduce(|x, y| x | y)
+            .unwrap_or(RegionFlags::empty())
+    }
+}
+
+impl Elf {
+    // The reverse of create_elf, for running an emitted file. Only what is loaded comes back,
+    // labels and breakpoints are left behind. One region per LOAD header, in the same order.
+    pub fn to_binary(&self) -> Binary {
+        let mut binary = Binary::new();
+
+        binary.entry = self.header.program_entry;
+        binary.entry_source = EntrySource::Directive;
+
+        for header in &self.program_headers {
+            if !matches!(header.header_type, Some(Load)) {
+                continue
+            }
+
+            // Anything past the file's data (ex. .bss) is zero in memory.
+            let mut data = header.data.clone();
+            data.resize(data.len().max(header.memory_size as usize), 0);
+
+            binary.regions.push(RawRegion {
+                flags: header.flags.into(),
+                address: header.virtual_address,
+                data,
+            })
+        }
+
+        binary
+    }
+}
+
 impl Binary {
-    fn default_header(&self) -> Header {
+    fn header(&self, options: &ElfOptions) -> Header {
         Header {
             magic: MAGIC,
             binary_type: BinaryType::Binary32,
             endian: Endian::Little,
             header_version: 1,
-            abi: 0,
+            abi: options.abi as u8,
             padding: [0; 8],
-            package: 0,
+            package: if self.relocations.is_empty() { 0 } else { TYPE_RELOCATABLE },
             cpu: InstructionSet::Mips,
             elf_version: 0,
-            program_entry: self.entry,
+            program_entry: options.entry.unwrap_or(self.entry),
+            flags: options.flags(),
+        }
+    }
+
+    // Binaries from the assembler never hold empty regions, but read ones might.
+    fn loaded_regions(&self) -> impl Iterator<Item = (usize, &RawRegion)> {
+        self.regions.iter().enumerate().filter(|(_, region)| !region.data.is_empty())
+    }
+
+    // Where a label lives: section index and value, relative to the section in relocatable objects.
+    fn symbol_place(&self, address: u32, sections: &HashMap<usize, u16>) -> (u16, u32) {
+        let relocatable = !self.relocations.is_empty();
+
+        match self.region_for(address) {
+            Some(index) => {
+                let value = if relocatable { address - self.regions[index].address } else { address };
+
+                (sections[&index], value)
+            }
+            None => (SECTION_ABSOLUTE, address),
+        }
+    }
+
+    // One section per region, then a REL section for each region with relocations, the symbols and their names.
+    fn sections(&self) -> Vec<SectionHeader> {
+        let mut result = vec![];
+        let mut indices = HashMap::new(); // region -> section index
+
+        for (index, region) in self.loaded_regions() {
+            result.push(SectionHeader {
+                name: region.section().directive().into(),
+                header_type: SectionHeaderType::ProgramData,
+                flags: region.flags.into(),
+                address: region.address,
+                link: 0,
+                info: 0,
+                alignment: 4,
+                entry_size: 0,
+                data: region.data.clone(),
+            });
+
+            indices.insert(index, result.len() as u16); // counting the null section
+        }
+
+        // Locals come before globals, undefined symbols are always global.
+        let undefined: BTreeSet<&str> = self.relocations.iter()
+            .map(|relocation| relocation.symbol.as_str())
+            .filter(|name| !self.labels.contains_key(*name))
+            .collect();
+
+        let mut locals: Vec<(&String, &u32)> = self.labels.iter()
+            .filter(|(name, _)| !self.globals.contains(*name))
+            .collect();
+        let mut globals: Vec<(&String, &u32)> = self.labels.iter()
+            .filter(|(name, _)| self.globals.contains(*name))
+            .collect();
+
+        locals.sort();
+        globals.sort();
+
+        let mut names = StringTable::new();
+        let mut symbols = vec![];
+        let mut symbol_indices = HashMap::new();
+
+        Symbol { name: 0, value: 0, binding: SYMBOL_LOCAL, section: SECTION_UNDEFINED }.write(&mut symbols);
+
+        let mut count = 1u32;
+        let mut add = |name: &str, value: u32, binding: u8, section: u16, symbols: &mut Vec<u8>| {
+            Symbol { name: names.add(name), value, binding, section }.write(symbols);
+            symbol_indices.insert(name.to_string(), count);
+
+            count += 1;
+        };
+
+        for (name, address) in locals {
+            let (section, value) = self.symbol_place(*address, &indices);
+
+            add(name, value, SYMBOL_LOCAL, section, &mut symbols);
+        }
+
+        let first_global = symbols.len() as u32 / SYMBOL_SIZE;
+
+        for (name, address) in globals {
+            let (section, value) = self.symbol_place(*address, &indices);
+
+            add(name, value, SYMBOL_GLOBAL, section, &mut symbols);
+        }
+
+        for name in undefined {
+            add(name, 0, SYMBOL_GLOBAL, SECTION_UNDEFINED, &mut symbols);
         }
+
+        let mut rels: Vec<SectionHeader> = vec![];
+
+        for (index, region) in self.loaded_regions() {
+            let mut data = vec![];
+
+            for relocation in self.relocations.iter().filter(|relocation| relocation.region == index) {
+                let symbol = symbol_indices[&relocation.symbol];
+
+                // Relocations make this an object file, where offsets count from the start of the section.
+                data.write_u32::<LittleEndian>(relocation.offset as u32).unwrap();
+                data.write_u32::<LittleEndian>(symbol << 8 | u32::from(relocation.kind)).unwrap();
+            }
+
+            if data.is_empty() {
+                continue
+            }
+
+            rels.push(SectionHeader {
+                name: format!(".rel{}", region.section().directive()),
+                header_type: SectionHeaderType::Rel,
+                flags: SectionHeaderFlags::empty(),
+                address: 0,
+                link: 0, // the symbol table, filled in below
+                info: indices[&index] as u32,
+                alignment: 4,
+                entry_size: REL_SIZE,
+                data,
+            })
+        }
+
+        let symbols_index = (result.len() + rels.len() + 1) as u32;
+
+        for rel in &mut rels {
+            rel.link = symbols_index
+        }
+
+        result.extend(rels);
+
+        result.push(SectionHeader {
+            name: ".symtab".into(),
+            header_type: SectionHeaderType::SymbolTable,
+            flags: SectionHeaderFlags::empty(),
+            address: 0,
+            link: symbols_index + 1, // the string table right after
+            info: first_global,
+            alignment: 4,
+            entry_size: SYMBOL_SIZE,
+            data: symbols,
+        });
+
+        result.push(SectionHeader {
+            name: ".strtab".into(),
+            header_type: SectionHeaderType::StringTable,
+            flags: SectionHeaderFlags::empty(),
+            address: 0,
+            link: 0,
+            info: 0,
+            alignment: 1,
+            entry_size: 0,
+            data: names.data,
+        });
+
+        result
     }
 
     fn program_headers(&self) -> Vec<ProgramHeader> {
         let mut result = vec![];
 
-        for region in &self.regions {
+        for (_, region) in self.loaded_regions() {
             let header = ProgramHeader {
                 header_type: Some(Load),
                 virtual_address: region.address,
@@ -55,12 +355,220 @@ impl Binary {
     }
 
     pub fn create_elf(&self) -> Elf {
-        let header = self.default_header();
+        self.create_elf_with(&ElfOptions::default())
+    }
+
+    pub fn create_elf_with(&self, options: &ElfOptions) -> Elf {
+        let header = self.header(options);
         let program_headers = self.program_headers();
+        let sections = self.sections();
 
         Elf {
             header,
             program_headers,
+            sections,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::binary::{Binary, RawRegion, RegionFlags, RelocationKind};
+    use crate::assembler::core::AssembleOptions;
+    use crate::assembler::string::{assemble_from, assemble_from_with_options};
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::cpu::{Memory, State};
+    use crate::elf::section::{SECTION_UNDEFINED, SYMBOL_GLOBAL, SYMBOL_LOCAL};
+    use crate::elf::Elf;
+    use crate::execution::elf::setup::create_simple_state;
+    use std::io::Cursor;
+
+    fn relocatable(source: &str) -> Binary {
+        assemble_from_with_options(source, AssembleOptions { relocatable: true, ..Default::default() }).unwrap()
+    }
+
+    // Reads back one 16 byte symbol: name, value, binding and section index.
+    fn symbol(symbols: &[u8], names: &[u8], index: u32) -> (String, u32, u8, u16) {
+        let entry = &symbols[index as usize * 16..][..16];
+        let word = |at: usize| u32::from_le_bytes(entry[at..at + 4].try_into().unwrap());
+
+        let name = &names[word(0) as usize..];
+        let name = String::from_utf8(name[..name.iter().position(|byte| *byte == 0).unwrap()].to_vec()).unwrap();
+
+        (name, word(4), entry[12] >> 4, u16::from_le_bytes([entry[14], entry[15]]))
+    }
+
+    // Offset and info (symbol << 8 | type) of each entry in a REL section.
+    fn entries(data: &[u8]) -> Vec<(u32, u32)> {
+        data.chunks(8)
+            .map(|entry| (
+                u32::from_le_bytes(entry[..4].try_into().unwrap()),
+                u32::from_le_bytes(entry[4..].try_into().unwrap()),
+            ))
+            .collect()
+    }
+
+    const SOURCE: &str = "
+        main:
+            nop
+            jal external_fn
+            la $t0, value
+        .data
+        value: .word 3
+        .word external_fn
+    ";
+
+    #[test]
+    fn references_become_relocations() {
+        let binary = relocatable(SOURCE);
+
+        let relocations: Vec<_> = binary.relocations.iter()
+            .map(|relocation| (relocation.region, relocation.offset, relocation.kind, relocation.symbol.as_str()))
+            .collect();
+
+        assert_eq!(relocations, [
+            (0, 4, RelocationKind::Jump, "external_fn"),
+            (0, 8, RelocationKind::Upper, "value"),
+            (0, 12, RelocationKind::Lower, "value"),
+            (1, 4, RelocationKind::Full, "external_fn"),
+        ]);
+
+        // Fields hold the addend, here 0, for the linker to add the symbol to.
+        assert_eq!(binary.regions[0].data[4..], [0, 0, 0, 0x0c, 0, 0, 0x08, 0x3c, 0, 0, 0x08, 0x35]);
+
+        // Without relocatable, an undefined label is still an error.
+        assert!(assemble_from_with_options(SOURCE, AssembleOptions::default()).is_err());
+    }
+
+    #[test]
+    fn undefined_calls_get_an_r_mips_26_record() {
+        let elf = relocatable(SOURCE).create_elf();
+
+        let names: Vec<_> = elf.sections.iter().map(|section| section.name.as_str()).collect();
+
+        assert_eq!(names, [".text", ".data", ".rel.text", ".rel.data", ".symtab", ".strtab"]);
+
+        let [_, _, text, data, symbols, strings] = &elf.sections[..] else { unreachable!() };
+
+        // Indices count the null section, so .text is 1 and .symtab is 5.
+        assert_eq!((text.info, text.link, data.info, data.link), (1, 5, 2, 5));
+        assert_eq!(symbols.link, 6);
+
+        let text_entries = entries(&text.data);
+        let (offset, info) = text_entries[0];
+
+        assert_eq!(offset, 4);
+        assert_eq!(info & 0xFF, 4); // R_MIPS_26
+        assert_eq!(symbol(&symbols.data, &strings.data, info >> 8), ("external_fn".into(), 0, SYMBOL_GLOBAL, SECTION_UNDEFINED));
+
+        let types: Vec<u32> = text_entries.iter().chain(&entries(&data.data)).map(|(_, info)| info & 0xFF).collect();
+
+        assert_eq!(types, [4, 5, 6, 2]); // R_MIPS_26, HI16, LO16, 32
+
+        // Locals come first (info is the first global), with values relative to their section.
+        assert_eq!(symbols.info, 3);
+        assert_eq!(symbol(&symbols.data, &strings.data, 1), ("main".into(), 0, SYMBOL_LOCAL, 1));
+        assert_eq!(symbol(&symbols.data, &strings.data, 2), ("value".into(), 0, SYMBOL_LOCAL, 2));
+    }
+
+    #[test]
+    fn relocatable_files_are_objects() {
+        let mut bytes = Cursor::new(vec![]);
+
+        relocatable(SOURCE).create_elf().write(&mut bytes).unwrap();
+
+        let bytes = bytes.into_inner();
+
+        assert_eq!(u16::from_le_bytes([bytes[16], bytes[17]]), 1); // ET_REL
+        assert_eq!(u16::from_le_bytes([bytes[48], bytes[49]]), 8); // null, six sections and .shstrtab
+
+        let mut bytes = Cursor::new(vec![]);
+
+        assemble_from("main: nop\n").unwrap().create_elf().write(&mut bytes).unwrap();
+
+        let bytes = bytes.into_inner();
+
+        assert_eq!(u16::from_le_bytes([bytes[16], bytes[17]]), 0);
+    }
+
+    const PROGRAM: &str = "
+        .text
+        helper: jr $ra
+        main:
+            jal helper
+            la $t0, bytes
+            li $v0, 10
+            syscall
+        .data
+        bytes: .byte 1, 2, 3
+        .space 9
+        .ktext
+            nop
+    ";
+
+    // Written out and read back, as titan build --emit and titan run do.
+    fn round_trip(binary: &Binary) -> Elf {
+        let mut bytes = Cursor::new(vec![]);
+
+        binary.create_elf().write(&mut bytes).unwrap();
+        bytes.set_position(0);
+
+        Elf::read(&mut bytes).unwrap()
+    }
+
+    #[test]
+    fn emitted_files_load_the_same_regions() {
+        let binary = assemble_from(PROGRAM).unwrap();
+        let loaded = round_trip(&binary).to_binary();
+
+        assert_eq!(loaded.entry, binary.entry);
+        assert_eq!(loaded.entry, 0x400004);
+
+        // Same regions, nothing merged or padded.
+        let layout = |binary: &Binary| -> Vec<_> {
+            binary.regions.iter()
+                .map(|region| (region.address, region.flags, region.data.clone()))
+                .collect()
+        };
+
+        assert_eq!(layout(&loaded), layout(&binary));
+        assert_eq!(loaded.regions.len(), 3);
+        assert_eq!(loaded.regions[1].data.len(), 12);
+    }
+
+    #[test]
+    fn emitted_files_start_in_the_same_state() {
+        let binary = assemble_from(PROGRAM).unwrap();
+
+        let direct: State<SectionMemory<DefaultResponder>> = create_simple_state(&binary.create_elf(), 0x100000);
+        let emitted: State<SectionMemory<DefaultResponder>> =
+            create_simple_state(&round_trip(&binary).to_binary().create_elf(), 0x100000);
+
+        assert_eq!(emitted.registers.pc, direct.registers.pc);
+        assert_eq!(emitted.registers.line, direct.registers.line);
+
+        for region in &binary.regions {
+            for address in region.address..region.address + region.data.len() as u32 {
+                assert_eq!(emitted.memory.get(address), direct.memory.get(address), "{address:#x}");
+            }
         }
     }
+
+    #[test]
+    fn empty_regions_are_not_loaded() {
+        let mut binary = assemble_from(PROGRAM).unwrap();
+
+        // The assembler drops them, but a binary read from elsewhere can have them.
+        binary.regions.insert(1, RawRegion { address: 0x500000, flags: RegionFlags::EXECUTABLE, data: vec![] });
+
+        let elf = binary.create_elf();
+
+        assert_eq!(elf.program_headers.len(), 3);
+        assert!(elf.program_headers.iter().all(|header| header.virtual_address != 0x500000));
+
+        let loaded = round_trip(&binary).to_binary();
+
+        assert_eq!(loaded.regions.len(), 3);
+        assert!(loaded.regions.iter().all(|region| !region.data.is_empty()));
+    }
 }
@@ -1,3 +1,4 @@
+use crate::assembler::binary::Binary;
 use crate::cpu::decoder::Decoder;
 use crate::cpu::disassemble::{Disassembler, LabelProvider};
 use crate::elf::header::{BinaryType, Endian};
@@ -5,6 +6,7 @@ use crate::elf::program::{ProgramHeader, ProgramHeaderFlags, ProgramHeaderType};
 use crate::elf::Elf;
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::io::Cursor;
 
 struct LabelManager {
@@ -48,6 +50,20 @@ pub struct Inspection {
     pub lines: Vec<String>,
 }
 
+pub const DEFAULT_NOP_RUN: usize = 8;
+
+#[derive(Copy, Clone, Debug)]
+pub struct InspectionOptions {
+    pub raw_nop: bool, // sll $zero, $zero, 0 instead of nop
+    pub collapse_nops: Option<usize>, // runs of more nops than this become one "... N x nop ..." line
+}
+
+impl Default for InspectionOptions {
+    fn default() -> Self {
+        InspectionOptions { raw_nop: false, collapse_nops: Some(DEFAULT_NOP_RUN) }
+    }
+}
+
 impl Inspection {
     fn program_header_flags(flags: ProgramHeaderFlags) -> String {
         let entries = [
@@ -135,7 +151,7 @@ impl Inspection {
     }
 
     // Assumption: Every instruction is the same size.
-    fn disassemble(address: u32, data: &Vec<u8>, manager: &mut LabelManager) -> Vec<String> {
+    fn disassemble(address: u32, data: &Vec<u8>, manager: &mut LabelManager, raw_nop: bool) -> Vec<(u32, String)> {
         let mut instructions = Cursor::new(data);
 
         let mut result = vec![];
@@ -143,6 +159,7 @@ impl Inspection {
         let mut disassembler = Disassembler {
             pc: address,
             labels: manager,
+            raw_nop,
         };
 
         while let Ok(instruction) = instructions.read_u32::<LittleEndian>() {
@@ -150,15 +167,31 @@ impl Inspection {
                 .dispatch(instruction)
                 .unwrap_or_else(|| format!("INVALID # 0x{instruction:08x}"));
 
-            disassembler.pc += 4;
+            disassembler.pc = disassembler.pc.wrapping_add(4);
 
-            result.push(text)
+            result.push((instruction, text))
         }
 
         result
     }
 
+    // Zero words starting at pc, up to the next label (which has to stay visible).
+    fn nop_run(instructions: &[(u32, String)], pc: u32, manager: &LabelManager) -> usize {
+        instructions.iter()
+            .enumerate()
+            .take_while(|(index, (word, _))| {
+                let address = pc.wrapping_add(4 * *index as u32);
+
+                *word == 0 && (*index == 0 || !(manager.labels.contains(&address) || manager.entry == Some(address)))
+            })
+            .count()
+    }
+
     pub fn new(named: Option<&str>, elf: &Elf) -> Inspection {
+        Inspection::with_options(named, elf, InspectionOptions::default())
+    }
+
+    pub fn with_options(named: Option<&str>, elf: &Elf, options: InspectionOptions) -> Inspection {
         let mut lines: Vec<String> = Inspection::description(named, elf)
             .iter()
             .map(|text| format!("# {text}"))
@@ -168,14 +201,14 @@ impl Inspection {
 
         let mut manager = LabelManager::new(Some(elf.header.program_entry));
 
-        let executables: Vec<(&ProgramHeader, Vec<String>)> = elf
+        let executables: Vec<(&ProgramHeader, Vec<(u32, String)>)> = elf
             .program_headers
             .iter()
             .filter(|header| header.flags.contains(ProgramHeaderFlags::EXECUTABLE))
             .map(|head| {
                 (
                     head,
-                    Inspection::disassemble(head.virtual_address, &head.data, &mut manager),
+                    Inspection::disassemble(head.virtual_address, &head.data, &mut manager, options.raw_nop),
                 )
             })
             .collect();
@@ -191,20 +224,249 @@ impl Inspection {
             ]);
 
             let mut pc = header.virtual_address;
+            let mut index = 0;
 
-            for instruction in instructions {
+            while index < instructions.len() {
                 if manager.labels.contains(&pc) || manager.entry == Some(pc) {
                     lines.push(format!("{}:", manager.label_string(pc)));
                 }
 
+                let run = Inspection::nop_run(&instructions[index..], pc, &manager);
+
+                // Every pc in the run breaks on the one line standing in for it.
+                if options.collapse_nops.is_some_and(|limit| run > limit) {
+                    for offset in 0..run {
+                        breakpoints.insert(pc.wrapping_add(4 * offset as u32), lines.len());
+                    }
+
+                    lines.push(format!("    ... {run} x nop ..."));
+
+                    pc = pc.wrapping_add(4 * run as u32);
+                    index += run;
+
+                    continue
+                }
+
                 breakpoints.insert(pc, lines.len());
 
-                lines.push(format!("    {instruction}"));
+                lines.push(format!("    {}", instructions[index].1));
 
-                pc += 4;
+                pc = pc.wrapping_add(4);
+                index += 1;
             }
         }
 
         Inspection { breakpoints, lines }
     }
 }
+
+// Where each label is, as a region and a byte offset into it. A label written right before data that
+// gets padding keeps the address from before the padding, so it can sit a few bytes ahead of its data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelOffset {
+    pub name: String,
+    pub address: u32,
+    pub region: usize, // index into the binary's regions
+    pub offset: u32, // bytes from the start of the region
+}
+
+impl Binary {
+    // By address, then name. Labels outside every region (ex. an alias past the end) are left out.
+    pub fn label_offsets(&self) -> Vec<LabelOffset> {
+        let mut result: Vec<LabelOffset> = self.labels.iter()
+            .filter_map(|(name, address)| {
+                let region = self.region_for(*address)?;
+
+                Some(LabelOffset {
+                    name: name.clone(),
+                    address: *address,
+                    region,
+                    offset: address - self.regions[region].address,
+                })
+            })
+            .collect();
+
+        result.sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+
+        result
+    }
+}
+
+// The statement a word of code came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WordOrigin<'a> {
+    pub mnemonic: &'a str,
+    pub expansion: bool, // see BinaryBreakpoint::is_expansion
+    pub index: usize, // which of the statement's words this is
+    pub words: usize, // words the statement became
+}
+
+// Statements written with one mnemonic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MnemonicCount {
+    pub mnemonic: String,
+    pub statements: usize,
+    pub words: usize,
+    pub expansions: usize, // statements that expanded, see BinaryBreakpoint::is_expansion
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeReport {
+    pub words: usize, // of code, every statement's words
+    pub expansion_words: usize, // words beyond one per statement, what pseudo-instructions cost over real ones
+    pub mnemonics: Vec<MnemonicCount>, // by words, highest first
+}
+
+impl Binary {
+    // Words of code by pc, annotated with the statement they came from.
+    pub fn word_origins(&self) -> HashMap<u32, WordOrigin<'_>> {
+        let mut result = HashMap::new();
+
+        for breakpoint in &self.breakpoints {
+            for (index, pc) in breakpoint.pcs.iter().enumerate() {
+                result.insert(*pc, WordOrigin {
+                    mnemonic: &breakpoint.mnemonic,
+                    expansion: breakpoint.is_expansion(),
+                    index,
+                    words: breakpoint.pcs.len(),
+                });
+            }
+        }
+
+        result
+    }
+
+    pub fn size_report(&self) -> SizeReport {
+        let mut counts: HashMap<&str, MnemonicCount> = HashMap::new();
+
+        for breakpoint in &self.breakpoints {
+            let count = counts.entry(&breakpoint.mnemonic).or_insert_with(|| MnemonicCount {
+                mnemonic: breakpoint.mnemonic.clone(),
+                statements: 0,
+                words: 0,
+                expansions: 0,
+            });
+
+            count.statements += 1;
+            count.words += breakpoint.pcs.len();
+            count.expansions += breakpoint.is_expansion() as usize;
+        }
+
+        let mut mnemonics: Vec<MnemonicCount> = counts.into_values().collect();
+        mnemonics.sort_by(|a, b| b.words.cmp(&a.words).then_with(|| a.mnemonic.cmp(&b.mnemonic)));
+
+        let words = mnemonics.iter().map(|count| count.words).sum();
+        let statements: usize = mnemonics.iter().map(|count| count.statements).sum();
+
+        SizeReport { words, expansion_words: words - statements, mnemonics }
+    }
+}
+
+impl Display for SizeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:>10} {:>8} {:>10}  instruction", "statements", "words", "expanded")?;
+
+        for count in &self.mnemonics {
+            writeln!(
+                f, "{:>10} {:>8} {:>10}  {}",
+                count.statements, count.words, count.expansions, count.mnemonic
+            )?;
+        }
+
+        writeln!(f, "{} words, {} of them from expansions", self.words, self.expansion_words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::decoder::Decoder;
+    use crate::cpu::disassemble::{DisassembledLine, Disassembler, HexLabelProvider};
+    use crate::execution::elf::inspection::{Inspection, InspectionOptions};
+    use crate::unit::instruction::InstructionDecoder;
+
+    fn disassembled(word: u32, raw_nop: bool) -> String {
+        let mut disassembler = Disassembler { pc: 0x400000, labels: HexLabelProvider::default(), raw_nop };
+        let line: DisassembledLine = disassembler.dispatch(word).unwrap();
+
+        line.text()
+    }
+
+    #[test]
+    fn the_zero_word_is_nop() {
+        assert_eq!(disassembled(0, false), "nop");
+        assert_eq!(disassembled(0, true), "sll $zero, $zero, 0");
+
+        // Only the zero word, other shifts into $zero stay as they are.
+        assert_eq!(disassembled(0x00000040, false), "sll $zero, $zero, 1");
+
+        let instruction = InstructionDecoder::decode(0x400000, 0).unwrap();
+
+        assert_eq!(instruction.to_string(), "nop");
+        assert_eq!(format!("{instruction:#}"), "sll $zero, $zero, 0");
+    }
+
+    // The section's lines, after the header comments.
+    fn listing(source: &str, options: InspectionOptions) -> Vec<String> {
+        let elf = assemble_from(source).unwrap().create_elf();
+        let lines = Inspection::with_options(None, &elf, options).lines;
+
+        let start = lines.iter().position(|line| line.starts_with("# Section")).unwrap();
+
+        lines[start + 1..].to_vec()
+    }
+
+    const PADDED: &str = "
+        main:
+            addi $t0, $t0, 1
+            nop
+            nop
+            nop
+            j end
+            .space 40
+        end:
+            addi $t0, $t0, 2
+    ";
+
+    #[test]
+    fn long_nop_runs_collapse() {
+        assert_eq!(listing(PADDED, InspectionOptions::default()), [
+            "entry_400000:",
+            "    addi $t0, $t0, 1",
+            "    nop",
+            "    nop",
+            "    nop",
+            "    j address_40003c",
+            "    ... 10 x nop ...",
+            "address_40003c:",
+            "    addi $t0, $t0, 2",
+        ]);
+    }
+
+    #[test]
+    fn nop_runs_can_stay_expanded() {
+        let options = InspectionOptions { raw_nop: true, collapse_nops: None };
+        let lines = listing(PADDED, options);
+
+        assert_eq!(lines.len(), 1 + 5 + 10 + 1 + 1);
+        assert_eq!(lines[2], "    sll $zero, $zero, 0");
+        assert!(lines[6..16].iter().all(|line| line == "    sll $zero, $zero, 0"));
+
+        // Runs of exactly the limit aren't collapsed.
+        let options = InspectionOptions { raw_nop: false, collapse_nops: Some(10) };
+
+        assert_eq!(listing(PADDED, options)[6], "    nop");
+    }
+
+    #[test]
+    fn collapsed_runs_keep_breakpoints() {
+        let elf = assemble_from(PADDED).unwrap().create_elf();
+        let inspection = Inspection::new(None, &elf);
+
+        let line = inspection.breakpoints[&0x400014];
+
+        assert_eq!(inspection.lines[line], "    ... 10 x nop ...");
+        assert_eq!(inspection.breakpoints[&0x400038], line);
+        assert_eq!(inspection.lines[inspection.breakpoints[&0x40003c]], "    addi $t0, $t0, 2");
+    }
+}
@@ -1,37 +1,267 @@
-use crate::cpu::memory::section::{ListenResponder, SectionMemory};
+use crate::cpu::memory::section::{InitPolicy, ListenResponder, SectionMemory};
 use crate::cpu::memory::Mountable;
 use crate::cpu::memory::Region;
-use crate::cpu::State;
+use crate::cpu::{Memory, State};
 use crate::elf::Elf;
 
 pub const SMALL_HEAP_SIZE: u32 = 0x10000u32;
 
+pub const STACK_TOP: u32 = 0x7FFFFFFCu32;
+
+// Bytes of argument strings and pointers (see StateBuilder::argument_block), like a kernel's ARG_MAX.
+// Arguments past it are dropped, so the block always fits under STACK_TOP.
+pub const ARGUMENT_LIMIT: usize = 0x100000;
+
+pub struct StateBuilder {
+    entry: u32,
+    regions: Vec<Region>,
+    heap_size: u32,
+    heap_init: InitPolicy,
+    args: Vec<String>,
+}
+
+impl StateBuilder {
+    pub fn new(entry: u32) -> StateBuilder {
+        StateBuilder {
+            entry,
+            regions: vec![],
+            heap_size: SMALL_HEAP_SIZE,
+            heap_init: InitPolicy::Zero,
+            args: vec![],
+        }
+    }
+
+    pub fn from_elf(elf: &Elf) -> StateBuilder {
+        let regions = elf
+            .program_headers
+            .iter()
+            .map(|header| Region {
+                start: header.virtual_address,
+                data: header.data.clone(),
+            })
+            .collect();
+
+        StateBuilder {
+            regions,
+            ..Self::new(elf.header.program_entry)
+        }
+    }
+
+    pub fn with_region(mut self, region: Region) -> Self {
+        self.regions.push(region);
+
+        self
+    }
+
+    pub fn with_heap_size(mut self, heap_size: u32) -> Self {
+        self.heap_size = heap_size;
+
+        self
+    }
+
+    pub fn with_heap_init(mut self, heap_init: InitPolicy) -> Self {
+        self.heap_init = heap_init;
+
+        self
+    }
+
+    // Keeps arguments in order while the block stays within ARGUMENT_LIMIT.
+    pub fn with_args<S: AsRef<str>>(mut self, args: &[S]) -> Self {
+        let mut size = 4 + 3; // argv's null word, and at most 3 bytes of padding
+
+        self.args = args.iter()
+            .map(|arg| arg.as_ref())
+            .take_while(|arg| {
+                size += 4 + arg.len() + 1; // pointer, string and null
+
+                size <= ARGUMENT_LIMIT
+            })
+            .map(|arg| arg.to_string())
+            .collect();
+
+        self
+    }
+
+    // Program arguments sit at the top of the stack, like MARS:
+    //
+    //   STACK_TOP ->  (end of heap, unmapped)
+    //                 argument strings, null terminated, in order
+    //                 padding to a word boundary
+    //                 argv[argc] = 0
+    //                 argv[0..argc] = string pointers
+    //   argv      ->  (= $a1, also the initial $sp)
+    //
+    // $a0 holds argc. With no arguments, argv is a single null word.
+    fn argument_block(&self) -> (u32, Region) {
+        let strings_size: usize = self.args.iter().map(|arg| arg.len() + 1).sum();
+
+        let strings_start = (STACK_TOP - strings_size as u32) & !3;
+        let argv = strings_start - 4 * (self.args.len() as u32 + 1);
+
+        let mut data = vec![0u8; (STACK_TOP - argv) as usize];
+        let mut address = strings_start;
+
+        for (i, arg) in self.args.iter().enumerate() {
+            let pointer = 4 * i;
+            data[pointer..pointer + 4].copy_from_slice(&address.to_le_bytes());
+
+            let offset = (address - argv) as usize;
+            data[offset..offset + arg.len()].copy_from_slice(arg.as_bytes());
+
+            address += arg.len() as u32 + 1;
+        }
+
+        (argv, Region { start: argv, data })
+    }
+
+    pub fn build<Mem: Memory + Mountable>(self, mut memory: Mem) -> State<Mem> {
+        let (argv, arguments) = self.argument_block();
+
+        for region in self.regions {
+            memory.mount(region)
+        }
+
+        // Heap stops where the arguments begin, so the two regions are adjacent.
+        let heap_start = STACK_TOP.saturating_sub(self.heap_size).min(argv);
+
+        let mut data = vec![0; (argv - heap_start) as usize];
+        self.heap_init.fill(heap_start, &mut data);
+
+        let heap = Region { start: heap_start, data };
+
+        memory.mount(heap);
+        memory.mount(arguments);
+
+        let mut state = State::new(self.entry, memory);
+        state.registers.line[4] = self.args.len() as u32;
+        state.registers.line[5] = argv;
+        state.registers.line[29] = argv;
+
+        state
+    }
+}
+
 pub fn create_simple_state<T: ListenResponder>(
     elf: &Elf,
     heap_size: u32,
 ) -> State<SectionMemory<T>> {
-    let mut memory = SectionMemory::new();
+    create_state_with_args(elf, heap_size, &[] as &[&str])
+}
+
+// argc in $a0, argv in $a1 and $sp, laid out as in StateBuilder::argument_block.
+pub fn create_state_with_args<T: ListenResponder, S: AsRef<str>>(
+    elf: &Elf,
+    heap_size: u32,
+    args: &[S],
+) -> State<SectionMemory<T>> {
+    StateBuilder::from_elf(elf)
+        .with_heap_size(heap_size)
+        .with_args(args)
+        .build(SectionMemory::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::cpu::{Memory, State};
+    use crate::assembler::string::assemble_from;
+    use crate::execution::elf::setup::{create_simple_state, create_state_with_args, StateBuilder, ARGUMENT_LIMIT, STACK_TOP};
+    use crate::unit::device::UnitDevice;
+    use crate::unit::terminal::OutputWait;
+
+    fn build(args: &[&str]) -> State<SectionMemory<DefaultResponder>> {
+        StateBuilder::new(0x00400000).with_args(args).build(SectionMemory::new())
+    }
 
-    for header in &elf.program_headers {
-        let region = Region {
-            start: header.virtual_address,
-            data: header.data.clone(),
-        };
+    #[test]
+    fn arguments_at_stack_top() {
+        let state = build(&["a", "bc"]);
+        let argv = state.registers.line[5];
 
-        memory.mount(region)
+        assert_eq!(state.registers.line[4], 2);
+        assert_eq!(argv, 0x7FFFFFE8);
+
+        let pointer = state.memory.get_u32(argv + 4).unwrap();
+
+        assert_eq!(state.memory.get(pointer), Ok(b'b'));
+        assert_eq!(state.memory.get_u32(argv + 8), Ok(0));
     }
 
-    let heap_end = 0x7FFFFFFCu32;
+    #[test]
+    fn arguments_past_the_limit_are_dropped() {
+        let long = "x".repeat(4096);
+        let args: Vec<&str> = std::iter::repeat(long.as_str()).take(1 << 20).collect();
+
+        let state = build(&args);
+        let argc = state.registers.line[4] as usize;
+        let argv = state.registers.line[5];
+
+        assert!(argc > 0 && argc < args.len());
+        assert!((STACK_TOP - argv) as usize <= ARGUMENT_LIMIT);
+    }
 
-    let heap = Region {
-        start: heap_end - heap_size,
-        data: vec![0; heap_size as usize],
-    };
+    // Prints the total length of its arguments.
+    const SUM_LENGTHS: &str = "
+        main:
+            li $t0, 0
+            li $t1, 0
+        arguments:
+            beq $t1, $a0, done
+            sll $t2, $t1, 2
+            add $t2, $t2, $a1
+            lw $t3, 0($t2)
+        characters:
+            lb $t4, 0($t3)
+            beqz $t4, next
+            addi $t0, $t0, 1
+            addi $t3, $t3, 1
+            b characters
+        next:
+            addi $t1, $t1, 1
+            b arguments
+        done:
+            move $a0, $t0
+            li $v0, 1
+            syscall
+            li $v0, 10
+            syscall
+    ";
 
-    memory.mount(heap);
+    #[test]
+    fn programs_sum_their_argument_lengths() {
+        let binary = assemble_from(SUM_LENGTHS).unwrap();
 
-    let mut state = State::new(elf.header.program_entry, memory);
-    state.registers.line[29] = heap_end;
+        let device = UnitDevice::with_args(binary.clone(), &["abc", "de", ""]);
 
-    state
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert_eq!(device.stdout(), "5");
+
+        let device = UnitDevice::new(binary);
+
+        device.run_until_exit(&[]).unwrap();
+
+        assert_eq!(device.stdout(), "0");
+    }
+
+    #[test]
+    fn elf_states_take_arguments() {
+        let elf = assemble_from(SUM_LENGTHS).unwrap().create_elf();
+        let state: State<SectionMemory<DefaultResponder>> = create_state_with_args(&elf, 0x1000, &["hello", "xy"]);
+
+        let argv = state.registers.line[5];
+
+        assert_eq!(state.registers.line[4], 2);
+        assert_eq!(state.registers.line[29], argv);
+        assert_eq!(state.registers.pc, elf.header.program_entry);
+
+        let second = state.memory.get_u32(argv + 4).unwrap();
+
+        assert_eq!((state.memory.get(second), state.memory.get(second + 2)), (Ok(b'x'), Ok(0)));
+
+        // Without any, argc is 0 and argv holds only the null entry.
+        let state: State<SectionMemory<DefaultResponder>> = create_simple_state(&elf, 0x1000);
+
+        assert_eq!(state.registers.line[4], 0);
+        assert_eq!(state.memory.get_u32(state.registers.line[5]), Ok(0));
+    }
 }
@@ -1,11 +1,20 @@
 use crate::cpu::error::Error;
+use crate::cpu::error::Error::{CpuBreak, CpuInvalid, CpuSyscall, CpuTrap, MemoryAlign, MemoryUnmapped};
+use crate::cpu::error::{describe_invalid, MemoryAlignment};
+use crate::cpu::memory::{Mountable, Region};
 use crate::cpu::state::Registers;
 use crate::cpu::{Memory, State};
-use crate::execution::executor::ExecutorMode::{Breakpoint, Invalid, Paused, Running};
-use std::collections::HashSet;
-use std::fmt::Debug;
+use crate::execution::executor::ExecutorMode::{Breakpoint, Finished, Invalid, Paused, Running};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::{Deref, DerefMut};
 use crate::execution::trackers::empty::EmptyTracker;
 use crate::execution::trackers::Tracker;
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+use crate::assembler::binary::Binary;
+use crate::execution::profile::{ProfileReport, Profiler};
+use crate::execution::stack::StackGrowth;
+use crate::execution::cancel::CancellationToken;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ExecutorMode {
@@ -13,10 +22,49 @@ pub enum ExecutorMode {
     Invalid(Error),
     Paused,
     Breakpoint,
+    Finished, // the pc reached one of the end pcs (ex. the end of the text), nothing there ran
 }
 
-// Addresses
-type Breakpoints = HashSet<u32>;
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BreakpointInfo {
+    pub enabled: bool, // disabled breakpoints neither stop nor count hits
+    pub hit_count: u64, // every time the pc reached it, stopped or not
+    pub ignore_remaining: u64, // hits left to pass through before it stops again
+}
+
+impl BreakpointInfo {
+    pub fn new() -> BreakpointInfo {
+        BreakpointInfo { enabled: true, hit_count: 0, ignore_remaining: 0 }
+    }
+
+    pub fn ignoring(count: u64) -> BreakpointInfo {
+        BreakpointInfo { ignore_remaining: count, ..BreakpointInfo::new() }
+    }
+}
+
+impl Default for BreakpointInfo {
+    fn default() -> Self {
+        BreakpointInfo::new()
+    }
+}
+
+// Address -> info
+type Breakpoints = HashMap<u32, BreakpointInfo>;
+
+pub struct RetiredInstruction {
+    pub pc: u32,
+    pub word: u32,
+    pub instruction: Option<Instruction>, // None if the word doesn't decode
+    pub next: u32, // pc after it
+}
+
+pub type RetireHook = Box<dyn FnMut(&RetiredInstruction) + Send>;
+
+type Mount<Mem> = fn(&mut Mem, Region);
+
+fn mount<Mem: Mountable>(memory: &mut Mem, region: Region) {
+    memory.mount(region)
+}
 
 pub struct ExecutorState<Mem: Memory, Track: Tracker<Mem>> {
     mode: ExecutorMode,
@@ -25,17 +73,145 @@ pub struct ExecutorState<Mem: Memory, Track: Tracker<Mem>> {
     breakpoints: Breakpoints,
     batch: usize,
 
-    tracker: Track
+    tracker: Track,
+    hooks: Vec<RetireHook>, // called after every instruction that completes
+    profiler: Option<Profiler>,
+    retired: u64, // instructions completed so far, back-stepping doesn't take any off
+    stack: Option<(StackGrowth, Mount<Mem>)>,
+    budget: bool, // the last run stopped because it ran every instruction it was given, not on a breakpoint
+    end_pcs: Vec<u32>, // reaching one is ExecutorMode::Finished, see Binary::end_pcs
 }
 
+// Locking: every method locks the executor for as long as it runs, and with_state, with_memory and with_tracker
+// hold it while their closure runs. The lock is not reentrant, so nothing that runs under it (those closures,
+// retire hooks, UnitDevice syscall handlers) may call back into the same executor, or any UnitDevice method
+// that does (get, set, get_data, set_data, ...). Work on what you were handed instead.
+// Debug builds panic on the second lock rather than deadlocking, see lock().
 pub struct Executor<Mem: Memory, Track: Tracker<Mem>> {
     mutex: parking_lot::Mutex<ExecutorState<Mem, Track>>,
+    pause: CancellationToken, // set by pause() without taking the lock, the runner checks it every instruction
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    // Executors (by address) locked by this thread right now.
+    static HELD: std::cell::RefCell<Vec<usize>> = const { std::cell::RefCell::new(vec![]) };
+}
+
+struct ExecutorGuard<'a, Mem: Memory, Track: Tracker<Mem>> {
+    guard: parking_lot::MutexGuard<'a, ExecutorState<Mem, Track>>,
+    #[cfg(debug_assertions)]
+    owner: usize,
+}
+
+impl<Mem: Memory, Track: Tracker<Mem>> Deref for ExecutorGuard<'_, Mem, Track> {
+    type Target = ExecutorState<Mem, Track>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<Mem: Memory, Track: Tracker<Mem>> DerefMut for ExecutorGuard<'_, Mem, Track> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<Mem: Memory, Track: Tracker<Mem>> Drop for ExecutorGuard<'_, Mem, Track> {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+
+            if let Some(index) = held.iter().position(|owner| *owner == self.owner) {
+                held.swap_remove(index);
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Running,
+    Paused,
+    Breakpoint { pc: u32, label: Option<String> }, // the label is filled in by UnitDevice::stop_reason
+    StepBudget { pc: u32 }, // a step, or a run limited to some number of instructions, finished
+    Syscall { pc: u32, code: u32 }, // waiting to be handled, code is $v0
+    // The pc is left on the instruction that failed. Nearby is the disassembly around it for invalid instructions.
+    Fault { error: Error, pc: u32, word: Option<u32>, nearby: Vec<String> },
+    Finished { pc: u32 },
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Running => write!(f, "Running"),
+            StopReason::Paused => write!(f, "Paused"),
+            StopReason::Breakpoint { pc, label: Some(label) } => write!(f, "Stopped at breakpoint 0x{pc:08x} ({label})"),
+            StopReason::Breakpoint { pc, label: None } => write!(f, "Stopped at breakpoint 0x{pc:08x}"),
+            StopReason::StepBudget { pc } => write!(f, "Stopped after stepping, at 0x{pc:08x}"),
+            StopReason::Syscall { pc, code } => write!(f, "Syscall {code} waiting to be handled at pc 0x{pc:08x}"),
+            StopReason::Finished { pc } => write!(f, "Program finished, the pc reached the end of the code at 0x{pc:08x}"),
+            StopReason::Fault { error, pc, word, nearby } => {
+                match error {
+                    MemoryUnmapped(address) => write!(f, "Memory access fault: unmapped address 0x{address:08x}")?,
+                    MemoryAlign(alignment, address) => {
+                        let size = match alignment {
+                            MemoryAlignment::Half => 2,
+                            MemoryAlignment::Word => 4,
+                        };
+
+                        write!(f, "Memory access fault: address 0x{address:08x} is not a multiple of {size}")?
+                    }
+                    CpuInvalid(_, word) => write!(f, "Invalid instruction 0x{word:08x} ({})", describe_invalid(*word))?,
+                    CpuTrap => write!(f, "Trap")?,
+                    CpuSyscall => write!(f, "Unhandled syscall")?,
+                    CpuBreak(code) => write!(f, "Break (code {code})")?,
+                }
+
+                write!(f, " at pc 0x{pc:08x}")?;
+
+                if let Some(instruction) = word.and_then(|word| InstructionDecoder::decode(*pc, word)) {
+                    write!(f, " ({instruction})")?
+                }
+
+                for line in nearby {
+                    write!(f, "\n{line}")?
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+// Instructions around pc, two on each side, with the one at pc marked.
+pub const DISASSEMBLY_WINDOW: u32 = 2;
+
+fn disassembly_window<Mem: Memory>(memory: &Mem, pc: u32) -> Vec<String> {
+    let start = pc.wrapping_sub(4 * DISASSEMBLY_WINDOW);
+
+    (0 ..= 2 * DISASSEMBLY_WINDOW).map(|index| {
+        let address = start.wrapping_add(4 * index);
+        let marker = if address == pc { ">" } else { " " };
+
+        let text = match memory.peek_u32(address) {
+            Ok(word) => InstructionDecoder::decode(address, word)
+                .map_or_else(|| format!(".word 0x{word:08x}"), |instruction| instruction.to_string()),
+            Err(_) => "(unmapped)".to_string(),
+        };
+
+        format!("{marker} 0x{address:08x}  {text}")
+    }).collect()
 }
 
 #[derive(Debug)]
 pub struct DebugFrame {
     pub mode: ExecutorMode,
     pub registers: Registers,
+    pub breakpoint: Option<BreakpointInfo>, // the breakpoint at pc, if that is what stopped execution
+    pub stop: StopReason,
 }
 
 impl<Mem: Memory, Track: Tracker<Mem>> ExecutorState<Mem, Track> {
@@ -43,30 +219,130 @@ impl<Mem: Memory, Track: Tracker<Mem>> ExecutorState<Mem, Track> {
         ExecutorState {
             mode: Paused,
             state,
-            breakpoints: HashSet::new(),
+            breakpoints: HashMap::new(),
             batch: 140,
-            tracker
+            tracker,
+            hooks: vec![],
+            profiler: None,
+            retired: 0,
+            stack: None,
+            budget: false,
+            end_pcs: vec![],
         }
     }
 
     pub fn frame(&self) -> DebugFrame {
+        let breakpoint = if self.mode == Breakpoint && !self.budget {
+            self.breakpoints.get(&self.state.registers.pc).copied()
+        } else {
+            None
+        };
+
         DebugFrame {
             mode: self.mode,
             registers: self.state.registers,
+            breakpoint,
+            stop: self.stop_reason(),
+        }
+    }
+
+    fn stop_reason(&self) -> StopReason {
+        let pc = self.state.registers.pc;
+
+        match self.mode {
+            Running => StopReason::Running,
+            Paused => StopReason::Paused,
+            Breakpoint if self.budget => StopReason::StepBudget { pc },
+            Breakpoint => StopReason::Breakpoint { pc, label: None },
+            Invalid(CpuSyscall) => StopReason::Syscall { pc, code: self.state.registers.line[2] },
+            Invalid(error) => {
+                let nearby = match error {
+                    CpuInvalid(..) => disassembly_window(&self.state.memory, pc),
+                    _ => vec![],
+                };
+
+                StopReason::Fault { error, pc, word: self.state.memory.peek_u32(pc).ok(), nearby }
+            }
+            Finished => StopReason::Finished { pc },
         }
     }
 
+    fn is_hooked(&self) -> bool {
+        !self.hooks.is_empty() || self.profiler.is_some()
+    }
+
+    fn retire(&mut self, pc: u32, word: u32, next: u32) {
+        let retired = RetiredInstruction {
+            pc, word, instruction: InstructionDecoder::decode(pc, word), next
+        };
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.retire(&retired)
+        }
+
+        for hook in &mut self.hooks {
+            hook(&retired)
+        }
+    }
+
+    fn grow_stack(&mut self, address: u32) -> bool {
+        let Some((growth, mount)) = &self.stack else { return false };
+        let Some(page) = growth.page_for(&self.state.memory, address) else { return false };
+
+        mount(&mut self.state.memory, page);
+
+        true
+    }
+
+    // True if the breakpoint at pc should stop execution, counting the hit either way.
+    fn hit_breakpoint(&mut self) -> bool {
+        let Some(info) = self.breakpoints.get_mut(&self.state.registers.pc) else { return false };
+
+        if !info.enabled {
+            return false
+        }
+
+        info.hit_count += 1;
+
+        if info.ignore_remaining > 0 {
+            info.ignore_remaining -= 1;
+
+            return false
+        }
+
+        true
+    }
+
     // Returns true if the CPU was interrupted.
     // If true, see self.frame() for details (ex. the mode)
     pub fn cycle(&mut self, no_breakpoints: bool) -> bool {
-        if !no_breakpoints && self.breakpoints.contains(&self.state.registers.pc) {
+        if !no_breakpoints && self.hit_breakpoint() {
             self.mode = Breakpoint;
 
             return true
         }
 
+        // Only a few of these, usually the end of the text.
+        if self.end_pcs.contains(&self.state.registers.pc) {
+            self.mode = Finished;
+
+            return true
+        }
+
+        // Fetching the word again is only worth it if someone is listening.
+        let hooked = self.is_hooked();
+        let pc = self.state.registers.pc;
+        let word = if hooked { self.state.memory.peek_u32(pc).ok() } else { None };
+
         self.tracker.pre_track(&mut self.state);
-        let result = self.state.step();
+        let mut result = self.state.step();
+
+        // The pc is left on a faulting instruction, so it runs again once the stack has grown.
+        if let Err(MemoryUnmapped(address)) = result {
+            if self.grow_stack(address) {
+                result = self.state.step()
+            }
+        }
 
         if let Err(err) = result {
             self.mode = Invalid(err);
@@ -76,6 +352,12 @@ impl<Mem: Memory, Track: Tracker<Mem>> ExecutorState<Mem, Track> {
             // Only track the instruction if it did not fail.
             // This means back-stepping will not go back to your instruction.
             self.tracker.post_track(&mut self.state);
+            self.state.memory.tick();
+            self.retired += 1;
+
+            if let Some(word) = word {
+                self.retire(pc, word, self.state.registers.pc)
+            }
 
             false
         }
@@ -87,81 +369,252 @@ pub struct BatchResult {
     pub interrupted: bool
 }
 
+// A statement that branches back into itself gives up after this many instructions.
+pub const STATEMENT_STEP_LIMIT: u64 = 100000;
+
+pub struct StatementStep {
+    pub frame: DebugFrame,
+    pub statement: Option<usize>, // index into Binary::breakpoints where the pc landed, None outside any statement
+    pub instructions_executed: u64,
+}
+
 impl<Mem: Memory, Track: Tracker<Mem>> Executor<Mem, Track> {
     pub fn new(state: State<Mem>, tracker: Track) -> Executor<Mem, Track> {
         Executor {
             mutex: parking_lot::Mutex::new(ExecutorState::new(state, tracker)),
+            pause: CancellationToken::new(),
         }
     }
 
     pub fn from_state(state: State<Mem>) -> Executor<Mem, EmptyTracker> {
         Executor {
-            mutex: parking_lot::Mutex::new(ExecutorState::new(state, EmptyTracker { }))
+            mutex: parking_lot::Mutex::new(ExecutorState::new(state, EmptyTracker { })),
+            pause: CancellationToken::new(),
+        }
+    }
+
+    fn lock(&self) -> ExecutorGuard<'_, Mem, Track> {
+        #[cfg(debug_assertions)]
+        let owner = self as *const Self as usize;
+
+        #[cfg(debug_assertions)]
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+
+            assert!(
+                !held.contains(&owner),
+                "Executor locked again by the thread already holding it, this would deadlock. \
+                Code running under the lock (with_state closures, retire hooks, syscall handlers) \
+                must use the state it was given instead of calling back into the executor or the device."
+            );
+
+            held.push(owner);
+        });
+
+        ExecutorGuard {
+            guard: self.mutex.lock(),
+            #[cfg(debug_assertions)]
+            owner,
         }
     }
 
     pub fn frame(&self) -> DebugFrame {
-        self.mutex.lock().frame()
+        self.lock().frame()
     }
 
+    // Independent copy for speculative runs, with its own tracker.
+    // Nothing is shared: memory is deep copied (SectionMemory copies every mapped section), retire hooks are not copied.
+    pub fn fork<T: Tracker<Mem>>(&self, tracker: T) -> Executor<Mem, T> where Mem: Clone {
+        let lock = self.lock();
+
+        Executor {
+            mutex: parking_lot::Mutex::new(ExecutorState {
+                mode: lock.mode,
+                state: lock.state.clone(),
+                breakpoints: lock.breakpoints.clone(),
+                batch: lock.batch,
+                tracker,
+                hooks: vec![],
+                profiler: None,
+                retired: lock.retired,
+                stack: lock.stack,
+                budget: lock.budget,
+                end_pcs: lock.end_pcs.clone(),
+            }),
+            pause: CancellationToken::new(),
+        }
+    }
+
+    // Pause and resume, safe to call from any thread (a Stop button) while another thread runs the executor:
+    // - pause() never waits on the runner. run and run_batched stop before their next instruction and leave the
+    //   mode as Paused. If nothing is running, the mode becomes Paused right away.
+    // - A pause stays requested until the runner sees it or resume() is called, so one that lands between
+    //   batches, or just before run starts, is not lost.
+    // - resume() drops a pending pause and sets the mode back to Running, call it before run to continue.
     pub fn pause(&self) {
-        self.mutex.lock().mode = Paused
+        self.pause.cancel();
+
+        if let Some(mut lock) = self.mutex.try_lock() {
+            lock.mode = Paused
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut lock = self.lock();
+
+        self.pause.reset();
+        lock.mode = Running
+    }
+
+    // The token pause() sets, for embedders that want to wait on or poll it themselves.
+    pub fn pause_token(&self) -> CancellationToken {
+        self.pause.clone()
     }
     
     pub fn override_mode(&self, mode: ExecutorMode) {
-        self.mutex.lock().mode = mode
+        self.lock().mode = mode
     }
 
     pub fn with_state<T, F: FnOnce (&mut State<Mem>) -> T>(&self, f: F) -> T {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
 
         f(&mut lock.state)
     }
 
     pub fn with_memory<T, F: FnOnce (&mut Mem) -> T>(&self, f: F) -> T {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
 
         f(&mut lock.state.memory)
     }
 
     pub fn with_tracker<T, F: FnOnce (&mut Track) -> T>(&self, f: F) -> T {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
 
         f(&mut lock.tracker)
     }
 
     pub fn syscall_handled(&self) {
-        let mut lock = self.mutex.lock();
+        let mut lock = self.lock();
 
         if let Invalid(_) = lock.mode {
             lock.mode = Running
         }
-        
-        lock.state.registers.pc += 4;
+
+        // The syscall only completes now that it's been handled.
+        let pc = lock.state.registers.pc;
+        lock.retired += 1;
+
+        if lock.is_hooked() {
+            if let Ok(word) = lock.state.memory.peek_u32(pc) {
+                lock.retire(pc, word, pc.wrapping_add(4))
+            }
+        }
+
+        lock.state.registers.pc = lock.state.registers.pc.wrapping_add(4);
+    }
+
+    // Called with every instruction that completes, from whichever thread is running the executor.
+    // The executor is locked while hooks run, so they must not call back into it (see Executor).
+    pub fn add_retire_hook<F: FnMut(&RetiredInstruction) + Send + 'static>(&self, hook: F) {
+        self.lock().hooks.push(Box::new(hook))
+    }
+
+    pub fn clear_retire_hooks(&self) {
+        self.lock().hooks.clear()
+    }
+
+    // Profiles from the current pc on, which is taken as the entry of the outermost function.
+    pub fn start_profile(&self, binary: &Binary) {
+        let mut lock = self.lock();
+
+        lock.profiler = Some(Profiler::new(binary, lock.state.registers.pc))
+    }
+
+    // None unless start_profile was called.
+    pub fn profile(&self) -> Option<ProfileReport> {
+        self.lock().profiler.as_ref().map(|profiler| profiler.report())
+    }
+
+    pub fn stop_profile(&self) -> Option<ProfileReport> {
+        self.lock().profiler.take().map(|profiler| profiler.report())
+    }
+
+    // None keeps the stack at whatever was mounted up front.
+    pub fn set_stack_growth(&self, growth: Option<StackGrowth>) where Mem: Mountable {
+        self.lock().stack = growth.map(|growth| (growth, mount as Mount<Mem>))
+    }
+
+    // Reaching one of these addresses finishes the program (ExecutorMode::Finished).
+    pub fn set_end_pcs(&self, end_pcs: Vec<u32>) {
+        self.lock().end_pcs = end_pcs
+    }
+
+    // Breakpoints already set at one of these addresses keep their info (hit counts, ignore counts), others are removed.
+    pub fn set_breakpoints(&self, addresses: HashSet<u32>) {
+        let mut lock = self.lock();
+
+        lock.breakpoints.retain(|address, _| addresses.contains(address));
+
+        for address in addresses {
+            lock.breakpoints.entry(address).or_default();
+        }
+    }
+
+    pub fn set_breakpoint(&self, address: u32, info: BreakpointInfo) {
+        self.lock().breakpoints.insert(address, info);
+    }
+
+    pub fn remove_breakpoint(&self, address: u32) -> Option<BreakpointInfo> {
+        self.lock().breakpoints.remove(&address)
     }
 
-    pub fn set_breakpoints(&self, breakpoints: Breakpoints) {
-        let mut lock = self.mutex.lock();
+    pub fn breakpoint(&self, address: u32) -> Option<BreakpointInfo> {
+        self.lock().breakpoints.get(&address).copied()
+    }
+
+    pub fn breakpoints(&self) -> Breakpoints {
+        self.lock().breakpoints.clone()
+    }
+
+    // Returns false if there is no breakpoint at address.
+    pub fn update_breakpoint<F: FnOnce (&mut BreakpointInfo)>(&self, address: u32, f: F) -> bool {
+        let mut lock = self.lock();
 
-        lock.breakpoints = breakpoints
+        let Some(info) = lock.breakpoints.get_mut(&address) else { return false };
+
+        f(info);
+
+        true
     }
 
     // Returns true if CPU was interrupted.
     pub fn cycle(&self, no_breakpoints: bool) -> bool {
-        self.mutex.lock().cycle(no_breakpoints)
+        self.lock().cycle(no_breakpoints)
     }
     
+    // Instructions completed since the executor was created, handled syscalls included.
+    pub fn retired(&self) -> u64 {
+        self.lock().retired
+    }
+
     pub fn is_breakpoint(&self) -> bool {
-        self.mutex.lock().mode == Breakpoint
+        self.lock().mode == Breakpoint
     }
     
     // Returns true if the CPU was interrupted.
     pub fn run_batched(&self, batch: usize, mut skip_first_breakpoint: bool, allow_interrupt: bool) -> BatchResult {
-        let mut value = self.mutex.lock();
+        let mut value = self.lock();
 
         let mut instructions_executed = 0;
+
+        value.budget = false;
         
         for _ in 0..batch {
+            if allow_interrupt && self.pause.is_cancelled() {
+                self.pause.reset();
+                value.mode = Paused;
+            }
+
             if allow_interrupt && value.mode != Running {
                 return BatchResult {
                     instructions_executed,
@@ -181,14 +634,62 @@ impl<Mem: Memory, Track: Tracker<Mem>> Executor<Mem, Track> {
             skip_first_breakpoint = false
         }
 
+        value.budget = true;
+
         BatchResult {
             instructions_executed,
             interrupted: false
         }
     }
 
+    // Runs every word of the statement at pc (all of a pseudo-instruction's expansion), stopping once the pc
+    // is outside of it. Breakpoints past the first word, syscalls and errors stop it early, see the frame's mode.
+    // A pc outside any statement steps a single instruction.
+    pub fn step_statement(&self, binary: &Binary) -> StatementStep {
+        let mut value = self.lock();
+
+        let start = value.state.registers.pc;
+        let pcs: HashSet<u32> = binary.statement_for(start)
+            .map(|index| binary.breakpoints[index].pcs.iter().copied().collect())
+            .unwrap_or_else(|| HashSet::from([start]));
+
+        let mut instructions_executed = 0;
+
+        value.budget = false;
+
+        loop {
+            if self.pause.is_cancelled() {
+                self.pause.reset();
+                value.mode = Paused;
+
+                break
+            }
+
+            if value.cycle(instructions_executed == 0) {
+                break
+            }
+
+            instructions_executed += 1;
+
+            let pc = value.state.registers.pc;
+
+            if !pcs.contains(&pc) || instructions_executed >= STATEMENT_STEP_LIMIT {
+                value.mode = Breakpoint; // like a single step, so running again goes past a breakpoint here
+                value.budget = true;
+
+                break
+            }
+        }
+
+        StatementStep {
+            frame: value.frame(),
+            statement: binary.statement_for(value.state.registers.pc),
+            instructions_executed,
+        }
+    }
+
     pub fn run(&self, mut skip_first_breakpoint: bool) -> DebugFrame {
-        let batch = self.mutex.lock().batch;
+        let batch = self.lock().batch;
         
         while !self.run_batched(batch, skip_first_breakpoint, true).interrupted {
             skip_first_breakpoint = false
@@ -197,3 +698,207 @@ impl<Mem: Memory, Track: Tracker<Mem>> Executor<Mem, Track> {
         self.frame()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::error::Error::MemoryUnmapped;
+    use crate::execution::executor::{BreakpointInfo, StopReason, DISASSEMBLY_WINDOW, STATEMENT_STEP_LIMIT};
+    use crate::execution::executor::ExecutorMode::{Breakpoint, Finished};
+    use crate::unit::device::{StopCondition, UnitDevice};
+    use crate::unit::register::RegisterName::{T0, T2};
+
+    fn counting_loop() -> (UnitDevice, u32) {
+        let device = UnitDevice::new(assemble_from("
+            li $t0, 1000
+            body:
+                addi $t0, $t0, -1
+                bnez $t0, body
+        ").unwrap());
+
+        let body = device.binary.labels["body"];
+
+        (device, body)
+    }
+
+    #[test]
+    fn ignored_hits_pass_through() {
+        let (device, body) = counting_loop();
+
+        let mut stops = vec![];
+
+        loop {
+            device.execute_until([StopCondition::Address(body, Some(998)), StopCondition::Complete]).unwrap();
+
+            let frame = device.executor.frame();
+
+            if frame.mode == Finished {
+                break
+            }
+
+            assert_eq!(frame.mode, Breakpoint);
+
+            stops.push((device.get(T0), frame.breakpoint.unwrap().hit_count));
+        }
+
+        // Hits 999 and 1000, with 2 and 1 iterations left.
+        assert_eq!(stops, [(2, 999), (1, 1000)]);
+        assert_eq!(device.executor.breakpoint(body).unwrap().hit_count, 1000);
+    }
+
+    #[test]
+    fn breakpoints_can_be_disabled_and_updated() {
+        let (device, body) = counting_loop();
+
+        device.executor.set_breakpoint(body, BreakpointInfo { enabled: false, ..BreakpointInfo::new() });
+
+        device.executor.resume();
+        let frame = device.executor.run(false);
+
+        // Disabled breakpoints don't stop or count.
+        assert_eq!(frame.mode, Finished);
+        assert_eq!(device.executor.breakpoint(body).unwrap().hit_count, 0);
+
+        assert!(device.executor.update_breakpoint(body, |info| info.enabled = true));
+        assert!(!device.executor.update_breakpoint(body + 4, |info| info.enabled = true));
+        assert!(device.executor.remove_breakpoint(body).unwrap().enabled);
+        assert!(device.executor.breakpoint(body).is_none());
+    }
+
+    const STATEMENTS: &str = "
+        li $t0, 5
+        li $t1, 3
+        bge $t0, $t1, greater
+        nop
+        greater:
+        add $t2, $t0, $t1
+        b after
+        nop
+        after:
+        spin: b spin
+    ";
+
+    #[test]
+    fn statements_step_as_a_whole() {
+        let device = UnitDevice::new(assemble_from(STATEMENTS).unwrap());
+        let binary = &device.binary;
+        let statement = |label: &str| binary.statement_for(binary.labels[label]);
+
+        device.step().unwrap();
+        device.step().unwrap();
+
+        // bge is slt and beq, both run and the branch is taken.
+        let step = device.executor.step_statement(binary);
+
+        assert_eq!(step.instructions_executed, 2);
+        assert_eq!(step.statement, statement("greater"));
+        assert_eq!(step.frame.mode, Breakpoint);
+
+        // A plain add is one instruction.
+        let step = device.executor.step_statement(binary);
+
+        assert_eq!(step.instructions_executed, 1);
+        assert_eq!(device.get(T2), 8);
+
+        // A taken branch lands on the line it names, not the next one.
+        let step = device.executor.step_statement(binary);
+
+        assert_eq!(step.instructions_executed, 1);
+        assert_eq!(step.frame.registers.pc, binary.labels["after"]);
+        assert_eq!(step.statement, statement("after"));
+    }
+
+    #[test]
+    fn statements_branching_into_themselves_stop_at_the_limit() {
+        let device = UnitDevice::new(assemble_from(STATEMENTS).unwrap());
+        let spin = device.binary.labels["spin"];
+
+        while device.registers().pc != spin {
+            device.executor.step_statement(&device.binary);
+        }
+
+        let step = device.executor.step_statement(&device.binary);
+
+        assert_eq!(step.instructions_executed, STATEMENT_STEP_LIMIT);
+        assert_eq!(step.frame.registers.pc, spin);
+        assert_eq!(step.statement, device.binary.statement_for(spin));
+
+        // A breakpoint there stops it on the next lap instead.
+        device.executor.set_breakpoint(spin, BreakpointInfo::new());
+
+        let step = device.executor.step_statement(&device.binary);
+
+        assert_eq!(step.instructions_executed, 1);
+        assert_eq!(step.frame.mode, Breakpoint);
+        assert!(step.frame.breakpoint.is_some());
+    }
+
+    #[test]
+    fn breakpoints_and_faults_say_where() {
+        let device = UnitDevice::new(assemble_from("
+            li $t0, 2
+            loop_top:
+                addi $t0, $t0, -1
+                bnez $t0, loop_top
+            lw $t1, 16($zero)
+        ").unwrap());
+
+        device.execute_until([StopCondition::Label("loop_top".into())]).unwrap();
+
+        assert_eq!(device.stop_reason(), StopReason::Breakpoint { pc: 0x400004, label: Some("loop_top".into()) });
+        assert_eq!(device.stop_reason().to_string(), "Stopped at breakpoint 0x00400004 (loop_top)");
+
+        assert!(device.execute_until([StopCondition::Complete]).is_err());
+
+        let StopReason::Fault { error, pc, word, .. } = device.stop_reason() else { panic!() };
+
+        assert_eq!((error, pc, word), (MemoryUnmapped(16), 0x40000c, Some(0x8C090010)));
+        assert_eq!(
+            device.stop_reason().to_string(),
+            "Memory access fault: unmapped address 0x00000010 at pc 0x0040000c (lw $t1, 0x10($zero))"
+        );
+    }
+
+    #[test]
+    fn steps_syscalls_and_the_end_say_where() {
+        let device = UnitDevice::new(assemble_from("li $v0, 5\nsyscall\n").unwrap());
+
+        device.step().unwrap();
+
+        assert_eq!(device.stop_reason(), StopReason::StepBudget { pc: 0x400004 });
+
+        device.execute_until([StopCondition::SyscallInvoked(None)]).unwrap();
+
+        assert_eq!(device.stop_reason(), StopReason::Syscall { pc: 0x400004, code: 5 });
+        assert_eq!(device.stop_reason().to_string(), "Syscall 5 waiting to be handled at pc 0x00400004");
+
+        device.executor.syscall_handled();
+        device.execute_until([StopCondition::Complete]).unwrap();
+
+        assert_eq!(device.stop_reason(), StopReason::Finished { pc: 0x400008 });
+    }
+
+    #[test]
+    fn invalid_instructions_show_their_neighbours() {
+        let device = UnitDevice::new(assemble_from("li $v0, 5\nlw $t0, 2($sp)\n.word 0xFC000000\nnop\n").unwrap());
+
+        device.step().unwrap();
+
+        assert!(device.execute_until([StopCondition::Complete]).is_err());
+        assert_eq!(
+            device.stop_reason().to_string(),
+            "Memory access fault: address 0x7ffffffa is not a multiple of 4 at pc 0x00400004 (lw $t0, 2($sp))"
+        );
+
+        device.jump_to(0x400008);
+
+        assert!(device.execute_until([StopCondition::Complete]).is_err());
+
+        let text = device.stop_reason().to_string();
+        let lines: Vec<_> = text.lines().collect();
+
+        assert_eq!(lines[0], "Invalid instruction 0xfc000000 (unknown opcode 0x3f) at pc 0x00400008");
+        assert_eq!(lines[3], "> 0x00400008  .word 0xfc000000");
+        assert_eq!(lines.len(), 1 + 2 * DISASSEMBLY_WINDOW as usize + 1);
+    }
+}
@@ -1,5 +1,9 @@
 pub mod executor;
 pub mod elf;
 pub mod trackers;
+pub mod profile;
+pub mod stack;
+pub mod cancel;
+pub mod compare;
 
 pub use executor::Executor;
@@ -0,0 +1,483 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use crate::assembler::binary::Binary;
+use crate::execution::executor::RetiredInstruction;
+use crate::unit::register::RegisterName;
+
+// Exact flat profile. A shadow call stack follows jal/jalr (and taken bltzal/bgezal) and pops on a jump back
+// to a return address on it. Each retired instruction counts for the function on top of the stack.
+// Only retired instructions count, so time the host spends handling a syscall never shows up.
+// Tail calls through j stay with the caller.
+struct Frame {
+    function: u32,
+    return_address: u32,
+    call_site: Option<u32>, // None for the entry frame
+    start: u64, // instructions retired before the call
+}
+
+#[derive(Default)]
+struct FunctionCounts {
+    self_count: u64,
+    cumulative: u64, // finished outermost activations only, the rest is added in report()
+    calls: u64,
+    active: usize, // activations on the stack, recursion only counts the outermost one
+}
+
+#[derive(Default)]
+struct CallSiteCounts {
+    calls: u64,
+    cumulative: u64,
+}
+
+struct MnemonicCounts {
+    mnemonic: String,
+    retired: u64,
+    words: (usize, usize), // fewest and most words one statement became
+    expansion: bool,
+}
+
+pub struct Profiler {
+    globals: BTreeMap<u32, String>,
+    locals: BTreeMap<u32, String>,
+    stack: Vec<Frame>,
+    functions: HashMap<u32, FunctionCounts>,
+    call_sites: HashMap<(u32, u32), CallSiteCounts>, // (site, callee)
+    origins: HashMap<u32, usize>, // pc -> index into mnemonics
+    mnemonics: Vec<MnemonicCounts>,
+    retired: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct FunctionProfile {
+    pub address: u32,
+    pub name: String,
+    pub self_count: u64,
+    pub cumulative: u64,
+    pub calls: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct CallSiteProfile {
+    pub site: u32,
+    pub caller: String,
+    pub callee: u32,
+    pub callee_name: String,
+    pub calls: u64,
+    pub cumulative: u64, // instructions spent in calls made here, nested ones included
+}
+
+// Retired instructions by the mnemonic of the statement they were assembled from, so `li` counts both words of
+// an expanded li. Instructions with no statement (ex. code written to memory at runtime) are left out.
+#[derive(Clone, Debug)]
+pub struct MnemonicProfile {
+    pub mnemonic: String,
+    pub retired: u64,
+    pub words: (usize, usize), // fewest and most words a statement became, ex. (2, 3) for bge
+    pub expansion: bool, // some statement was a pseudo-instruction or expanded, see BinaryBreakpoint::is_expansion
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfileReport {
+    pub total: u64,
+    pub functions: Vec<FunctionProfile>, // by self count, highest first
+    pub call_sites: Vec<CallSiteProfile>, // by call count, highest first
+    pub mnemonics: Vec<MnemonicProfile>, // by retired count, highest first, only those that ran
+}
+
+// Function names: a label at the function's own address (a global one first), otherwise the nearest
+// global label before it, otherwise the nearest label before it. Aliases are left out.
+fn function_names(binary: &Binary) -> (BTreeMap<u32, String>, BTreeMap<u32, String>) {
+    let mut globals = BTreeMap::new();
+    let mut locals = BTreeMap::new();
+
+    let mut labels: Vec<(&String, &u32)> = binary.labels.iter()
+        .filter(|(name, _)| !binary.aliases.contains_key(*name))
+        .collect();
+    labels.sort();
+
+    for (name, address) in labels {
+        let names = if binary.globals.contains(name) { &mut globals } else { &mut locals };
+
+        names.entry(*address).or_insert_with(|| name.clone());
+    }
+
+    (globals, locals)
+}
+
+fn mnemonic_origins(binary: &Binary) -> (HashMap<u32, usize>, Vec<MnemonicCounts>) {
+    let mut origins = HashMap::new();
+    let mut mnemonics: Vec<MnemonicCounts> = vec![];
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+
+    for breakpoint in &binary.breakpoints {
+        let words = breakpoint.pcs.len();
+
+        let index = *indices.entry(&breakpoint.mnemonic).or_insert_with(|| {
+            mnemonics.push(MnemonicCounts {
+                mnemonic: breakpoint.mnemonic.clone(),
+                retired: 0,
+                words: (words, words),
+                expansion: false,
+            });
+
+            mnemonics.len() - 1
+        });
+
+        let counts = &mut mnemonics[index];
+        counts.words = (counts.words.0.min(words), counts.words.1.max(words));
+        counts.expansion |= breakpoint.is_expansion();
+
+        for pc in &breakpoint.pcs {
+            origins.insert(*pc, index);
+        }
+    }
+
+    (origins, mnemonics)
+}
+
+impl Profiler {
+    // entry is where the outermost function starts, usually binary.entry.
+    pub fn new(binary: &Binary, entry: u32) -> Profiler {
+        let (globals, locals) = function_names(binary);
+        let (origins, mnemonics) = mnemonic_origins(binary);
+
+        let mut profiler = Profiler {
+            globals,
+            locals,
+            stack: vec![],
+            functions: HashMap::new(),
+            call_sites: HashMap::new(),
+            origins,
+            mnemonics,
+            retired: 0,
+        };
+
+        profiler.push(entry, 0, None);
+
+        profiler
+    }
+
+    pub fn name_for(&self, address: u32) -> String {
+        if let Some(name) = self.globals.get(&address).or_else(|| self.locals.get(&address)) {
+            return name.clone()
+        }
+
+        let before = self.globals.range(..address).next_back()
+            .or_else(|| self.locals.range(..address).next_back());
+
+        match before {
+            Some((start, name)) => format!("{name}+0x{:x}", address - start),
+            None => format!("0x{address:08x}"),
+        }
+    }
+
+    fn push(&mut self, function: u32, return_address: u32, call_site: Option<u32>) {
+        let counts = self.functions.entry(function).or_default();
+
+        counts.calls += 1;
+        counts.active += 1;
+
+        if let Some(site) = call_site {
+            self.call_sites.entry((site, function)).or_default().calls += 1;
+        }
+
+        self.stack.push(Frame { function, return_address, call_site, start: self.retired })
+    }
+
+    fn pop(&mut self) {
+        let Some(frame) = self.stack.pop() else { return };
+        let spent = self.retired - frame.start;
+
+        let counts = self.functions.entry(frame.function).or_default();
+        counts.active -= 1;
+
+        if counts.active == 0 {
+            counts.cumulative += spent;
+        }
+
+        if let Some(site) = frame.call_site {
+            self.call_sites.entry((site, frame.function)).or_default().cumulative += spent;
+        }
+    }
+
+    pub fn retire(&mut self, retired: &RetiredInstruction) {
+        self.retired += 1;
+
+        if let Some(frame) = self.stack.last() {
+            self.functions.entry(frame.function).or_default().self_count += 1;
+        }
+
+        if let Some(index) = self.origins.get(&retired.pc) {
+            self.mnemonics[*index].retired += 1;
+        }
+
+        let Some(instruction) = &retired.instruction else { return };
+
+        let sequential = retired.next == retired.pc.wrapping_add(4);
+
+        if instruction.is_control() && instruction.writes() == Some(RegisterName::RA) && !sequential {
+            self.push(retired.next, retired.pc.wrapping_add(4), Some(retired.pc));
+        } else if instruction.is_control() {
+            // Skip frames a function left without returning (ex. it jumped back further up).
+            // The entry frame (index 0) has nowhere to return to, so it stays.
+            let index = self.stack.iter().skip(1).rposition(|frame| frame.return_address == retired.next);
+
+            if let Some(index) = index {
+                while self.stack.len() > index + 1 {
+                    self.pop()
+                }
+            }
+        }
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        // Activations still running count up to now.
+        let mut running: HashMap<u32, u64> = HashMap::new();
+        let mut sites: HashMap<(u32, u32), u64> = HashMap::new();
+        let mut seen = HashSet::new();
+
+        for frame in &self.stack {
+            let spent = self.retired - frame.start;
+
+            if seen.insert(frame.function) {
+                *running.entry(frame.function).or_default() += spent;
+            }
+
+            if let Some(site) = frame.call_site {
+                *sites.entry((site, frame.function)).or_default() += spent;
+            }
+        }
+
+        let mut functions: Vec<FunctionProfile> = self.functions.iter()
+            .map(|(address, counts)| FunctionProfile {
+                address: *address,
+                name: self.name_for(*address),
+                self_count: counts.self_count,
+                cumulative: counts.cumulative + running.get(address).copied().unwrap_or(0),
+                calls: counts.calls,
+            })
+            .collect();
+
+        functions.sort_by(|a, b| b.self_count.cmp(&a.self_count).then(a.address.cmp(&b.address)));
+
+        let mut call_sites: Vec<CallSiteProfile> = self.call_sites.iter()
+            .map(|((site, callee), counts)| CallSiteProfile {
+                site: *site,
+                caller: self.name_for(*site),
+                callee: *callee,
+                callee_name: self.name_for(*callee),
+                calls: counts.calls,
+                cumulative: counts.cumulative + sites.get(&(*site, *callee)).copied().unwrap_or(0),
+            })
+            .collect();
+
+        call_sites.sort_by(|a, b| b.calls.cmp(&a.calls).then(a.site.cmp(&b.site)));
+
+        let mut mnemonics: Vec<MnemonicProfile> = self.mnemonics.iter()
+            .filter(|counts| counts.retired > 0)
+            .map(|counts| MnemonicProfile {
+                mnemonic: counts.mnemonic.clone(),
+                retired: counts.retired,
+                words: counts.words,
+                expansion: counts.expansion,
+            })
+            .collect();
+
+        mnemonics.sort_by(|a, b| b.retired.cmp(&a.retired).then_with(|| a.mnemonic.cmp(&b.mnemonic)));
+
+        ProfileReport { total: self.retired, functions, call_sites, mnemonics }
+    }
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:>10} {:>10} {:>8}  function", "self", "cumulative", "calls")?;
+
+        for function in &self.functions {
+            writeln!(
+                f, "{:>10} {:>10} {:>8}  {}",
+                function.self_count, function.cumulative, function.calls, function.name
+            )?;
+        }
+
+        writeln!(f, "{} instructions", self.total)?;
+
+        if !self.call_sites.is_empty() {
+            writeln!(f, "\n{:>8} {:>10}  call site", "calls", "cumulative")?;
+
+            for site in &self.call_sites {
+                writeln!(
+                    f, "{:>8} {:>10}  {} (0x{:08x}) -> {}",
+                    site.calls, site.cumulative, site.caller, site.site, site.callee_name
+                )?;
+            }
+        }
+
+        if !self.mnemonics.is_empty() {
+            writeln!(f, "\n{:>10} {:>6}  instruction", "retired", "words")?;
+
+            for mnemonic in &self.mnemonics {
+                let words = match mnemonic.words {
+                    (fewest, most) if fewest == most => fewest.to_string(),
+                    (fewest, most) => format!("{fewest}-{most}"),
+                };
+
+                let note = if mnemonic.expansion { " (expanded)" } else { "" };
+
+                writeln!(f, "{:>10} {:>6}  {}{note}", mnemonic.retired, words, mnemonic.mnemonic)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::unit::device::{StopCondition, UnitDevice};
+
+    // Mostly pseudo-instructions: li, la and move each run ten times, blt nine times taken.
+    const EXPANDED: &str = "
+        main:
+            li $t0, 0
+            li $t1, 0x12345
+        loop:
+            la $t2, main
+            move $t3, $t2
+            addi $t0, $t0, 1
+            blt $t0, 10, loop
+            li $v0, 10
+            syscall
+    ";
+
+    // Sum of squares from 3 down to 1, each square is a call.
+    const FUNCTIONS: &str = "
+        .globl main
+        .globl sum
+        .globl square
+        main:
+            li $a0, 3
+            jal sum
+            li $v0, 10
+            syscall
+        sum:
+            addi $sp, $sp, -4
+            sw $ra, 0($sp)
+            move $s0, $a0
+            li $s1, 0
+        sum_loop:
+            move $a0, $s0
+            jal square
+            add $s1, $s1, $v0
+            addi $s0, $s0, -1
+            bnez $s0, sum_loop
+            move $v0, $s1
+            lw $ra, 0($sp)
+            addi $sp, $sp, 4
+            jr $ra
+        square:
+            mult $a0, $a0
+            mflo $v0
+            jr $ra
+    ";
+
+    #[test]
+    fn functions_count_self_and_cumulative() {
+        let device = UnitDevice::new(assemble_from(FUNCTIONS).unwrap());
+
+        device.executor.start_profile(&device.binary);
+        device.execute_until([StopCondition::SyscallInvoked(Some(10))]).unwrap();
+
+        let report = device.executor.profile().unwrap();
+
+        let functions: Vec<_> = report.functions.iter()
+            .map(|function| (function.name.as_str(), function.self_count, function.cumulative, function.calls))
+            .collect();
+
+        // sum is 4 words in, 5 a lap for 3 laps and 4 out. The syscall hasn't run yet, so it isn't counted.
+        assert_eq!(functions, [("sum", 23, 32, 1), ("square", 9, 9, 3), ("main", 3, 35, 1)]);
+        assert_eq!(report.total, 35);
+
+        let call_sites: Vec<_> = report.call_sites.iter()
+            .map(|site| (site.caller.as_str(), site.site, site.callee_name.as_str(), site.calls, site.cumulative))
+            .collect();
+
+        // The jal in sum's loop is named from sum, the nearest global label.
+        assert_eq!(call_sites, [
+            ("sum+0x14", 0x400024, "square", 3, 9),
+            ("main+0x4", 0x400004, "sum", 1, 32),
+        ]);
+
+        let text = report.to_string();
+        let lines: Vec<_> = text.lines().take(9).collect();
+
+        assert_eq!(lines, [
+            "      self cumulative    calls  function",
+            "        23         32        1  sum",
+            "         9          9        3  square",
+            "         3         35        1  main",
+            "35 instructions",
+            "",
+            "   calls cumulative  call site",
+            "       3          9  sum+0x14 (0x00400024) -> square",
+            "       1         32  main+0x4 (0x00400004) -> sum",
+        ]);
+    }
+
+    #[test]
+    fn retired_instructions_count_for_their_statement() {
+        let device = UnitDevice::new(assemble_from(EXPANDED).unwrap());
+
+        device.executor.start_profile(&device.binary);
+        device.execute_until([StopCondition::SyscallInvoked(Some(10))]).unwrap();
+
+        let report = device.executor.profile().unwrap();
+
+        let mnemonics: Vec<_> = report.mnemonics.iter()
+            .map(|mnemonic| (mnemonic.mnemonic.as_str(), mnemonic.retired, mnemonic.words, mnemonic.expansion))
+            .collect();
+
+        // blt with a constant is addi $at, slt and bne. The two li 0s are one word, li 0x12345 is two.
+        assert_eq!(mnemonics, [
+            ("blt", 30, (3, 3), true),
+            ("la", 20, (2, 2), true),
+            ("addi", 10, (1, 1), false),
+            ("move", 10, (1, 1), true),
+            ("li", 4, (1, 2), true),
+        ]);
+
+        assert_eq!(report.total, 74);
+        assert_eq!(report.mnemonics.iter().map(|mnemonic| mnemonic.retired).sum::<u64>(), report.total);
+
+        assert!(report.to_string().contains("        30      3  blt (expanded)\n"));
+        assert!(report.to_string().contains("         4    1-2  li (expanded)\n"));
+        assert!(report.to_string().contains("        10      1  addi\n"));
+    }
+
+    #[test]
+    fn static_counts_separate_expansions() {
+        let binary = assemble_from(EXPANDED).unwrap();
+        let report = binary.size_report();
+
+        assert_eq!((report.words, report.expansion_words), (12, 4));
+
+        let li = report.mnemonics.iter().find(|count| count.mnemonic == "li").unwrap();
+
+        assert_eq!((li.statements, li.words, li.expansions), (3, 4, 3));
+
+        // Every word of the la in the loop points back to it.
+        let origins = binary.word_origins();
+        let la = binary.labels["loop"];
+
+        for (index, pc) in [la, la + 4].into_iter().enumerate() {
+            let origin = &origins[&pc];
+
+            assert_eq!((origin.mnemonic, origin.expansion, origin.index, origin.words), ("la", true, index, 2));
+        }
+
+        let addi = &origins[&(la + 12)];
+
+        assert_eq!((addi.mnemonic, addi.expansion), ("addi", false));
+    }
+}
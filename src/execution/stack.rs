@@ -0,0 +1,121 @@
+use crate::cpu::Memory;
+use crate::cpu::memory::Region;
+use crate::execution::elf::setup::STACK_TOP;
+
+pub const STACK_PAGE_SIZE: u32 = 0x1000;
+
+// Faults further below mapped memory than this are treated as bad pointers, not stack growth.
+pub const DEFAULT_GROWTH_WINDOW: u32 = 0x10000;
+
+// Demand paged stack: an access that faults a little below mapped stack memory mounts the page
+// it landed in, then the instruction runs again. Stack memory is contiguous, so "a little below"
+// means the byte window bytes above the fault is already mapped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StackGrowth {
+    pub limit: u32, // the stack never grows below this address
+    pub window: u32,
+}
+
+impl StackGrowth {
+    // Lets the stack reach max_size bytes below STACK_TOP, heap included.
+    pub fn new(max_size: u32) -> StackGrowth {
+        StackGrowth {
+            limit: STACK_TOP.saturating_sub(max_size),
+            window: DEFAULT_GROWTH_WINDOW,
+        }
+    }
+
+    // The page to mount for a fault at address, if the stack should grow into it.
+    pub fn page_for<Mem: Memory>(&self, memory: &Mem, address: u32) -> Option<Region> {
+        if address < self.limit || address >= STACK_TOP {
+            return None
+        }
+
+        let above = address.checked_add(self.window)?;
+
+        if memory.peek(above.min(STACK_TOP - 1)).is_err() {
+            return None
+        }
+
+        let start = (address & !(STACK_PAGE_SIZE - 1)).max(self.limit);
+        let end = (start + STACK_PAGE_SIZE) & !(STACK_PAGE_SIZE - 1);
+
+        Some(Region { start, data: vec![0; (end - start) as usize] })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::unit::device::UnitDevice;
+    use crate::unit::terminal::OutputWait;
+
+    // Sums 0..=depth recursively with 64 byte frames, 20000 deep is about 1.25MB of stack.
+    fn recursion(depth: u32) -> UnitDevice {
+        UnitDevice::new(assemble_from(&format!("
+            main:
+                li $a0, {depth}
+                jal sum
+                move $a0, $v0
+                li $v0, 1
+                syscall
+                li $v0, 10
+                syscall
+            sum:
+                addiu $sp, $sp, -64
+                sw $ra, 0($sp)
+                sw $a0, 4($sp)
+                beqz $a0, base
+                addiu $a0, $a0, -1
+                jal sum
+                lw $a0, 4($sp)
+                addu $v0, $v0, $a0
+                b done
+            base:
+                li $v0, 0
+            done:
+                lw $ra, 0($sp)
+                addiu $sp, $sp, 64
+                jr $ra
+        ")).unwrap())
+    }
+
+    #[test]
+    fn deep_recursion_faults_without_growth() {
+        let device = recursion(20000);
+
+        assert!(device.run_until_exit(&[]).is_err());
+    }
+
+    #[test]
+    fn deep_recursion_grows_the_stack() {
+        let device = recursion(20000);
+
+        device.grow_stack(0x200000);
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert_eq!(device.stdout(), "200010000");
+    }
+
+    #[test]
+    fn growth_stops_at_the_limit() {
+        let device = recursion(20000);
+
+        // Room for the heap and a bit, not the whole recursion.
+        device.grow_stack(0x110000);
+
+        assert!(device.run_until_exit(&[]).is_err());
+    }
+
+    #[test]
+    fn far_below_the_stack_is_not_growth() {
+        let device = UnitDevice::new(assemble_from("
+            lui $t0, 0x7fe0
+            sw $zero, 0($t0)
+        ").unwrap());
+
+        device.grow_stack(0x1000000);
+
+        assert!(device.run_until_exit(&[]).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use crate::assembler::binary::{Binary, RegionFlags};
+use crate::cpu::Memory;
+use crate::execution::Executor;
+use crate::execution::trackers::Tracker;
+
+// Instructions from one label up to the next label (or the end of its region).
+#[derive(Clone, Debug)]
+pub struct LabelCoverage {
+    pub label: Option<String>, // None for code in front of the first label of a region
+    pub start: u32,
+    pub end: u32,
+    pub unexecuted: Vec<u32>,
+}
+
+// Records every pc an executor retires, through a retire hook.
+#[derive(Clone, Default)]
+pub struct CoverageTracker {
+    executed: Arc<parking_lot::Mutex<BTreeSet<u32>>>,
+}
+
+impl CoverageTracker {
+    pub fn new() -> CoverageTracker {
+        CoverageTracker::default()
+    }
+
+    // Starts recording everything executor runs from now on.
+    pub fn attach<Mem: Memory, Track: Tracker<Mem>>(&self, executor: &Executor<Mem, Track>) {
+        let executed = self.executed.clone();
+
+        executor.add_retire_hook(move |retired| {
+            executed.lock().insert(retired.pc);
+        })
+    }
+
+    pub fn executed(&self) -> BTreeSet<u32> {
+        self.executed.lock().clone()
+    }
+
+    pub fn is_executed(&self, pc: u32) -> bool {
+        self.executed.lock().contains(&pc)
+    }
+
+    pub fn clear(&self) {
+        self.executed.lock().clear()
+    }
+
+    // Every label in an executable region, with the instructions under it that never ran.
+    // Aliases are skipped, so each instruction shows up once.
+    pub fn report(&self, binary: &Binary) -> Vec<LabelCoverage> {
+        let executed = self.executed.lock();
+        let mut result = vec![];
+
+        for region in &binary.regions {
+            if !region.flags.contains(RegionFlags::EXECUTABLE) || region.data.is_empty() {
+                continue
+            }
+
+            let end = region.wrapping_pc();
+            let inside = |address: u32| address >= region.address && address < end;
+
+            let mut labels: Vec<(u32, &String)> = binary.labels.iter()
+                .filter(|(name, address)| inside(**address) && !binary.aliases.contains_key(*name))
+                .map(|(name, address)| (*address, name))
+                .collect();
+
+            labels.sort();
+            labels.dedup_by_key(|(address, _)| *address);
+
+            let mut starts: Vec<(u32, Option<String>)> = labels.into_iter()
+                .map(|(address, name)| (address, Some(name.clone())))
+                .collect();
+
+            if starts.first().map_or(true, |(address, _)| *address != region.address) {
+                starts.insert(0, (region.address, None));
+            }
+
+            for (i, (start, label)) in starts.iter().enumerate() {
+                let stop = starts.get(i + 1).map(|(address, _)| *address).unwrap_or(end);
+
+                let unexecuted = (*start .. stop).step_by(4)
+                    .filter(|pc| !executed.contains(pc))
+                    .collect();
+
+                result.push(LabelCoverage { label: label.clone(), start: *start, end: stop, unexecuted })
+            }
+        }
+
+        result
+    }
+
+    // Just the labels with something left to run.
+    pub fn unexecuted(&self, binary: &Binary) -> Vec<LabelCoverage> {
+        self.report(binary).into_iter()
+            .filter(|coverage| !coverage.unexecuted.is_empty())
+            .collect()
+    }
+}
@@ -6,13 +6,16 @@ use crate::cpu::state::Registers;
 use crate::execution::trackers::Tracker;
 
 pub struct HistoryEntry {
+    // The whole set from before the step, so an instruction that writes several (ex. mult, hi and lo) undoes at once.
     pub registers: Registers,
+    pub link: bool, // the ll reservation from before the step, State::link
     pub edits: SmallVec<[WatchEntry; LOG_SIZE]>
 }
 
 impl HistoryEntry {
-    pub fn apply<Mem: Memory>(self, registers: &mut Registers, memory: &mut Mem) {
+    pub fn apply<Mem: Memory>(self, registers: &mut Registers, link: &mut bool, memory: &mut Mem) {
         *registers = self.registers;
+        *link = self.link;
 
         for entry in self.edits {
             entry.apply(memory).ok(); // ignore error
@@ -22,7 +25,7 @@ impl HistoryEntry {
 
 pub struct HistoryTracker {
     buffer: VecDeque<HistoryEntry>,
-    registers: Option<Registers>
+    registers: Option<(Registers, bool)> // and link
 }
 
 impl HistoryTracker {
@@ -59,12 +62,12 @@ impl HistoryTracker {
 
 impl<Mem: Memory> Tracker<WatchedMemory<Mem>> for HistoryTracker {
     fn pre_track(&mut self, state: &mut State<WatchedMemory<Mem>>) {
-        self.registers = Some(state.registers.clone())
+        self.registers = Some((state.registers, state.link))
     }
 
     fn post_track(&mut self, state: &mut State<WatchedMemory<Mem>>) {
-        let Some(registers) = self.registers else { return };
-        let entry = HistoryEntry { registers, edits: state.memory.take() };
+        let Some((registers, link)) = self.registers else { return };
+        let entry = HistoryEntry { registers, link, edits: state.memory.take_step_delta() };
 
         self.push(entry);
     }
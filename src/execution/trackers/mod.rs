@@ -1,5 +1,7 @@
 pub mod tracker;
 pub mod empty;
 pub mod history;
+pub mod pipeline;
+pub mod coverage;
 
 pub use tracker::Tracker;
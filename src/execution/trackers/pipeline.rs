@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+use crate::cpu::{Memory, State};
+use crate::execution::trackers::Tracker;
+use crate::unit::instruction::{Instruction, InstructionDecoder};
+use crate::unit::register::RegisterName;
+
+// Classic IF ID EX MEM WB pipeline with full forwarding. Only timing is modelled, execution is untouched.
+//  - Load-use: an instruction that needs a loaded register in EX right after the load waits.
+//    A store's data register isn't needed until MEM, so `lw $t0; sw $t0, 0($t1)` doesn't stall.
+//  - Branch flush: a taken branch or jump throws away the instructions fetched behind it.
+// hi/lo, multiply/divide latency and memory latency aren't modelled.
+#[derive(Clone, Debug)]
+pub struct PipelineConfig {
+    pub load_use_penalty: u64,
+    pub branch_penalty: u64, // 2 when branches resolve in EX, 1 if they resolve in ID
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            load_use_penalty: 1,
+            branch_penalty: 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StallReason {
+    LoadUse(RegisterName), // register the previous load writes
+    BranchFlush(u32), // pc of the taken branch
+}
+
+#[derive(Clone, Debug)]
+pub struct PipelineRecord {
+    pub pc: u32,
+    pub instruction: Option<Instruction>, // None if the word doesn't decode
+    pub issue: u64, // cycle it enters IF (stalls push this back), it writes back 4 cycles later
+    pub stalls: u64, // bubbles in front of it
+    pub reason: Option<StallReason>,
+}
+
+pub struct PipelineTracker {
+    pub config: PipelineConfig,
+    records: VecDeque<PipelineRecord>,
+    capacity: usize,
+
+    pending: Option<(u32, Option<Instruction>)>, // fetched in pre_track, retired in post_track
+    next_issue: u64,
+    hazard: Option<StallReason>, // what the previous instruction leaves for the next one
+    load: Option<RegisterName>,
+
+    instructions: u64,
+    stalls: u64,
+    last_issue: Option<u64>,
+}
+
+impl PipelineTracker {
+    // Keeps the last `capacity` records, the totals cover everything.
+    pub fn new(config: PipelineConfig, capacity: usize) -> PipelineTracker {
+        PipelineTracker {
+            config,
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+            pending: None,
+            next_issue: 0,
+            hazard: None,
+            load: None,
+            instructions: 0,
+            stalls: 0,
+            last_issue: None,
+        }
+    }
+
+    pub fn records(&self) -> &VecDeque<PipelineRecord> {
+        &self.records
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    // Including the 4 cycles it takes to fill the pipeline.
+    pub fn cycles(&self) -> u64 {
+        self.last_issue.map(|issue| issue + 5).unwrap_or(0)
+    }
+
+    // Steady state: 1 + stalls per instruction.
+    pub fn cpi(&self) -> f64 {
+        if self.instructions == 0 {
+            return 0.0
+        }
+
+        (self.instructions + self.stalls) as f64 / self.instructions as f64
+    }
+
+    pub fn clear(&mut self) {
+        *self = PipelineTracker::new(self.config.clone(), self.capacity)
+    }
+
+    fn load_use(&self, instruction: &Instruction) -> Option<RegisterName> {
+        let load = self.load.filter(|register| *register != RegisterName::Zero)?;
+
+        let reads = instruction.reads();
+        let needed = if instruction.is_store() { &reads[..1] } else { &reads[..] };
+
+        needed.contains(&load).then_some(load)
+    }
+
+    fn push(&mut self, record: PipelineRecord) {
+        if self.capacity == 0 {
+            return
+        }
+
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(record);
+    }
+}
+
+impl<Mem: Memory> Tracker<Mem> for PipelineTracker {
+    fn pre_track(&mut self, state: &mut State<Mem>) {
+        let pc = state.registers.pc;
+        let instruction = state.memory.peek_u32(pc).ok()
+            .and_then(|word| InstructionDecoder::decode(pc, word));
+
+        self.pending = Some((pc, instruction));
+    }
+
+    fn post_track(&mut self, state: &mut State<Mem>) {
+        let Some((pc, instruction)) = self.pending.take() else { return };
+
+        let reason = match (&self.hazard, &instruction) {
+            (Some(flush @ StallReason::BranchFlush(_)), _) => Some(*flush),
+            (_, Some(instruction)) => self.load_use(instruction).map(StallReason::LoadUse),
+            _ => None,
+        };
+
+        let stalls = match reason {
+            Some(StallReason::LoadUse(_)) => self.config.load_use_penalty,
+            Some(StallReason::BranchFlush(_)) => self.config.branch_penalty,
+            None => 0,
+        };
+
+        let issue = self.next_issue + stalls;
+
+        let taken = instruction.as_ref().is_some_and(|instruction| instruction.is_control())
+            && state.registers.pc != pc.wrapping_add(4);
+
+        self.hazard = taken.then_some(StallReason::BranchFlush(pc));
+        self.load = instruction.as_ref()
+            .filter(|instruction| instruction.is_load())
+            .and_then(|instruction| instruction.writes());
+
+        self.next_issue = issue + 1;
+        self.last_issue = Some(issue);
+        self.instructions += 1;
+        self.stalls += stalls;
+
+        self.push(PipelineRecord { pc, instruction, issue, stalls, reason });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
+    use crate::cpu::memory::Region;
+    use crate::execution::elf::setup::StateBuilder;
+    use crate::execution::executor::Executor;
+    use crate::execution::executor::ExecutorMode::Finished;
+    use crate::execution::trackers::pipeline::{PipelineConfig, PipelineTracker, StallReason};
+    use crate::unit::register::RegisterName::{T0, T1};
+
+    type PipelineExecutor = Executor<SectionMemory<DefaultResponder>, PipelineTracker>;
+
+    fn run(source: &str, config: PipelineConfig) -> PipelineExecutor {
+        let binary = assemble_from(source).unwrap();
+
+        let state = binary.regions.iter()
+            .fold(StateBuilder::new(binary.entry), |builder, region| {
+                builder.with_region(Region { start: region.address, data: region.data.clone() })
+            })
+            .build(SectionMemory::new());
+
+        let executor = Executor::new(state, PipelineTracker::new(config, 100));
+
+        executor.set_end_pcs(binary.end_pcs());
+        executor.resume();
+
+        assert_eq!(executor.run(false).mode, Finished);
+
+        executor
+    }
+
+    // (pc, issue, stalls, reason) for each instruction.
+    fn records(executor: &PipelineExecutor) -> Vec<(u32, u64, u64, Option<StallReason>)> {
+        executor.with_tracker(|tracker| {
+            tracker.records().iter().map(|record| (record.pc, record.issue, record.stalls, record.reason)).collect()
+        })
+    }
+
+    #[test]
+    fn load_use_stalls_once() {
+        let executor = run("
+            la $t1, value
+            lw $t0, 0($t1)
+            addi $t0, $t0, 1
+            .data
+            value: .word 5
+        ", PipelineConfig::default());
+
+        assert_eq!(records(&executor), [
+            (0x400000, 0, 0, None),
+            (0x400004, 1, 0, None),
+            (0x400008, 2, 0, None),
+            (0x40000c, 4, 1, Some(StallReason::LoadUse(T0))),
+        ]);
+
+        executor.with_tracker(|tracker| {
+            assert_eq!(tracker.instructions(), 4);
+            assert_eq!(tracker.stalls(), 1);
+            assert_eq!(tracker.cycles(), 9);
+            assert_eq!(tracker.cpi(), 1.25);
+        });
+    }
+
+    #[test]
+    fn stores_only_wait_for_their_base() {
+        // value points at itself, so loading it into $t1 keeps the store in bounds.
+        let source = |store: &str| format!("la $t1, value\nlw $t0, 0($t1)\n{store}\n.data\nvalue: .word value\n");
+
+        let executor = run(&source("sw $t0, 4($t1)"), PipelineConfig::default());
+
+        assert_eq!(executor.with_tracker(|tracker| tracker.stalls()), 0);
+
+        let executor = run(&source("lw $t1, 0($t1)\nsw $t0, 0($t1)"), PipelineConfig::default());
+
+        assert_eq!(records(&executor)[4], (0x400010, 5, 1, Some(StallReason::LoadUse(T1))));
+    }
+
+    #[test]
+    fn taken_branches_flush() {
+        let source = "
+            li $t0, 1
+            beq $t0, $zero, skip
+            beq $t0, $t0, skip
+            nop
+            skip: addi $t2, $t2, 1
+        ";
+
+        // Only the taken one costs anything.
+        let executor = run(source, PipelineConfig::default());
+
+        assert_eq!(records(&executor), [
+            (0x400000, 0, 0, None),
+            (0x400004, 1, 0, None),
+            (0x400008, 2, 0, None),
+            (0x400010, 5, 2, Some(StallReason::BranchFlush(0x400008))),
+        ]);
+
+        let config = PipelineConfig { branch_penalty: 1, ..PipelineConfig::default() };
+        let executor = run(source, config);
+
+        assert_eq!(records(&executor)[3], (0x400010, 4, 1, Some(StallReason::BranchFlush(0x400008))));
+        assert_eq!(executor.with_tracker(|tracker| tracker.cpi()), 1.25);
+    }
+}
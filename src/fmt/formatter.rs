@@ -0,0 +1,326 @@
+use crate::assembler::lexer::TokenKind::{
+    Colon, Comma, Comment, Directive, IntegerLiteral, LeftBrace, NewLine, Register, Symbol,
+};
+use crate::assembler::lexer::{lex, LexerError, Token};
+
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    pub indent: usize,         // column for mnemonics and directives
+    pub operand_column: usize, // operands start here (or one space after a long mnemonic)
+    pub comment_column: usize, // trailing comments start here (or one space after long code)
+    pub labels_on_own_line: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: 4,
+            operand_column: 12,
+            comment_column: 40,
+            labels_on_own_line: true,
+        }
+    }
+}
+
+// Source text of each token. A token's location is where the lexer started on it, before skipping spaces,
+// so the text runs from there to the next token and may start with the spaces that separated them.
+fn token_texts<'a>(source: &'a str, tokens: &[Token]) -> Vec<&'a str> {
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let end = tokens.get(i + 1).map(|next| next.location.index).unwrap_or(source.len());
+
+            &source[token.location.index..end]
+        })
+        .collect()
+}
+
+fn trimmed(text: &str) -> &str {
+    text.trim_matches([' ', '\t', '\r'])
+}
+
+struct Line<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    texts: &'b [&'a str],
+    start: usize, // source offset of the line
+    end: usize,   // source offset of the newline (or end of source)
+}
+
+fn pad_to(line: &mut String, column: usize) {
+    let width = line.chars().count();
+
+    if width < column {
+        line.extend(std::iter::repeat(' ').take(column - width));
+    } else if !line.is_empty() && !line.ends_with(' ') {
+        line.push(' ');
+    }
+}
+
+// Operands keep their original spacing, except commas, which get no space before and one after.
+// Registers are written in lower case.
+fn push_operands(line: &mut String, tokens: &[Token], texts: &[&str]) {
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && token.kind != Comma {
+            let gap = texts[i].starts_with([' ', '\t']);
+
+            if tokens[i - 1].kind == Comma || gap {
+                line.push(' ');
+            }
+        }
+
+        match token.kind {
+            Register(_) => line.push_str(&trimmed(texts[i]).to_ascii_lowercase()),
+            _ => line.push_str(trimmed(texts[i])),
+        }
+    }
+}
+
+fn format_line(source: &str, line: &Line, options: &FormatOptions, output: &mut Vec<String>) {
+    let original = source[line.start..line.end].trim_end_matches([' ', '\t', '\r']);
+
+    let (code, comment) = match line.tokens.last() {
+        Some(Token { kind: Comment(_), .. }) => {
+            let count = line.tokens.len() - 1;
+
+            (&line.tokens[..count], Some(trimmed(line.texts[count])))
+        }
+        _ => (line.tokens, None),
+    };
+
+    // Blank and comment-only lines are left as they were.
+    if code.is_empty() {
+        output.push(original.to_string());
+
+        return
+    }
+
+    let mut labels = vec![];
+    let mut index = 0;
+
+    // Numeric labels (1:) count too.
+    while let (Some(Token { kind: Symbol(_) | IntegerLiteral(_), .. }), Some(Token { kind: Colon, .. })) =
+        (code.get(index), code.get(index + 1)) {
+        labels.push(trimmed(line.texts[index]));
+
+        index += 2;
+    }
+
+    let statement = &code[index..];
+
+    // Anything not shaped like "label: mnemonic operands" (a stray integer, a brace) passes through.
+    if let Some(first) = statement.first() {
+        if !matches!(first.kind, Symbol(_) | Directive(_)) {
+            output.push(original.to_string());
+
+            return
+        }
+    }
+
+    let mut current = String::new();
+
+    for label in &labels {
+        if options.labels_on_own_line && !current.is_empty() {
+            output.push(std::mem::take(&mut current));
+        }
+
+        if !current.is_empty() {
+            current.push(' ');
+        }
+
+        current.push_str(label);
+        current.push(':');
+    }
+
+    if options.labels_on_own_line && !statement.is_empty() && !current.is_empty() {
+        output.push(std::mem::take(&mut current));
+    }
+
+    if !statement.is_empty() {
+        pad_to(&mut current, options.indent);
+        current.push_str(trimmed(line.texts[index]));
+
+        let operands = &line.texts[index + 1..index + statement.len()];
+
+        // A macro call like print(x) keeps its brace against the name.
+        let attached = statement.get(1).is_some_and(|token| token.kind == LeftBrace)
+            && !operands[0].starts_with([' ', '\t']);
+
+        if statement.len() > 1 && !attached {
+            pad_to(&mut current, options.operand_column);
+        }
+
+        if statement.len() > 1 {
+            push_operands(&mut current, &statement[1..], operands);
+        }
+    }
+
+    if let Some(comment) = comment {
+        pad_to(&mut current, options.comment_column);
+        current.push_str(comment);
+    }
+
+    output.push(current);
+}
+
+// Formats assembly source. Only whitespace changes, so the output assembles the same.
+pub fn format(source: &str, options: &FormatOptions) -> Result<String, LexerError> {
+    let tokens = lex(source)?;
+    let texts = token_texts(source, &tokens);
+
+    let mut output = vec![];
+    let mut start = 0;
+    let mut first = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != NewLine {
+            continue
+        }
+
+        // The token starts before the spaces in front of the newline, lines split on the newline itself.
+        let end = source[token.location.index..].find('\n')
+            .map_or(source.len(), |offset| token.location.index + offset);
+
+        let line = Line {
+            tokens: &tokens[first..i],
+            texts: &texts[first..i],
+            start,
+            end,
+        };
+
+        format_line(source, &line, options, &mut output);
+
+        start = end + 1;
+        first = i + 1;
+    }
+
+    if start < source.len() {
+        let line = Line {
+            tokens: &tokens[first..],
+            texts: &texts[first..],
+            start,
+            end: source.len(),
+        };
+
+        format_line(source, &line, options, &mut output);
+    }
+
+    let mut result = output.join("\n");
+
+    if !output.is_empty() {
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fmt::formatter::{format, FormatOptions};
+
+    // Regression corpus, each one a shape the formatter has gotten wrong or has to pass through.
+    const CORPUS: &[&str] = &[
+        "main:\n  li $v0, 10\n  syscall\n",
+        "main: add $t0,$t1 ,$t2   # sum\n\n\nloop: beq $t0, $zero, loop\n",
+        "# header comment, left alone\n\n.data\nvalues: .word 1,2,3\nmsg: .asciiz \"a,  b\"\n",
+        ".macro print (%x)\nli $a0, %x\nli $v0, 1\nsyscall\n.end_macro\nmain: print(5)\n",
+        "first: second: jr $ra\n1: b 1b\n",
+        "lw $t0, 4($sp)\nsw $t0, -8( $sp )\nla $a0, table + 4 * 2\n",
+        "main:   \n\t\n  nop   \n",
+        "main:\r\n  ADDI $T0, $ZERO, 1\r\n\r\n  jr $RA\r\n",
+        "  .text\n  .globl main\nmain: nop",
+        "(stray brace\n5 +  3\n",
+        "",
+    ];
+
+    // Whitespace a file might pick up anywhere, none of which should change what comes out twice.
+    fn variants(source: &str) -> Vec<String> {
+        vec![
+            source.to_string(),
+            source.replace('\n', "   \n"),
+            source.replace('\n', "\r\n"),
+            source.replace("  ", "\t"),
+            source.replace('\n', "\n\n"),
+        ]
+    }
+
+    fn options() -> Vec<FormatOptions> {
+        vec![
+            FormatOptions::default(),
+            FormatOptions { labels_on_own_line: false, ..FormatOptions::default() },
+            FormatOptions { indent: 8, operand_column: 16, comment_column: 20, labels_on_own_line: true },
+        ]
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for source in CORPUS.iter().flat_map(|source| variants(source)) {
+            for options in options() {
+                let once = format(&source, &options).unwrap();
+                let twice = format(&once, &options).unwrap();
+
+                assert_eq!(once, twice, "formatting {source:?} twice");
+                assert!(!once.lines().any(|line| line.ends_with([' ', '\t', '\r'])), "trailing space in {once:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn columns_and_commas() {
+        let source = "main: add $t0,$t1 ,$t2   # sum\nloop:beq $t0, $zero, loop\n";
+
+        assert_eq!(format(source, &FormatOptions::default()).unwrap(), concat!(
+            "main:\n",
+            "    add     $t0, $t1, $t2               # sum\n",
+            "loop:\n",
+            "    beq     $t0, $zero, loop\n",
+        ));
+
+        let inline = FormatOptions { labels_on_own_line: false, ..FormatOptions::default() };
+
+        assert_eq!(format(source, &inline).unwrap(), concat!(
+            "main: add   $t0, $t1, $t2               # sum\n",
+            "loop: beq   $t0, $zero, loop\n",
+        ));
+    }
+
+    #[test]
+    fn blank_lines_and_comments_are_kept() {
+        let source = "# header   comment\n\n\n   # indented comment\nmain:   \n\n  nop   \n";
+
+        assert_eq!(format(source, &FormatOptions::default()).unwrap(), concat!(
+            "# header   comment\n",
+            "\n",
+            "\n",
+            "   # indented comment\n",
+            "main:\n",
+            "\n",
+            "    nop\n",
+        ));
+    }
+
+    #[test]
+    fn crlf_lines_and_register_case() {
+        let source = "main:\r\n  ADDI $T0, $ZERO, 1\r\n\r\n  jr $Ra\r\n";
+
+        assert_eq!(format(source, &FormatOptions::default()).unwrap(), concat!(
+            "main:\n",
+            "    ADDI    $t0, $zero, 1\n",
+            "\n",
+            "    jr      $ra\n",
+        ));
+    }
+
+    #[test]
+    fn unknown_shapes_pass_through() {
+        let source = "(stray brace  \n5 +  3\nprint(5)\n1: b 1b\n";
+
+        assert_eq!(format(source, &FormatOptions::default()).unwrap(), concat!(
+            "(stray brace\n",
+            "5 +  3\n",
+            "    print(5)\n",
+            "1:\n",
+            "    b       1b\n",
+        ));
+    }
+}
@@ -0,0 +1,3 @@
+mod formatter;
+
+pub use formatter::{format, FormatOptions};
@@ -2,4 +2,5 @@ pub mod assembler;
 pub mod cpu;
 pub mod execution;
 pub mod elf;
+pub mod fmt;
 pub mod unit;
@@ -0,0 +1,134 @@
+use crate::cpu::error::Error::MemoryUnmapped;
+use crate::cpu::error::Result;
+use crate::cpu::memory::section::ListenResponder;
+
+// MARS keyboard and display MMIO, all in one section.
+pub const CONSOLE_SELECTOR: usize = 0xFFFF;
+
+pub const RECEIVER_CONTROL: u32 = 0xFFFF0000;
+pub const RECEIVER_DATA: u32 = 0xFFFF0004;
+pub const TRANSMITTER_CONTROL: u32 = 0xFFFF0008;
+pub const TRANSMITTER_DATA: u32 = 0xFFFF000C;
+
+const CONSOLE_SIZE: usize = 0x100;
+
+const READY: u8 = 1;
+const INTERRUPT_ENABLE: u8 = 2;
+
+// Display transmitter with MARS timing. Storing a byte to TRANSMITTER_DATA while ready sends it to the
+// output right away, then the ready bit (bit 0 of TRANSMITTER_CONTROL) reads 0 for the next `delay`
+// instructions. A byte stored while not ready is lost: it never reaches the output, only `lost` counts it.
+// Bit 1 of TRANSMITTER_CONTROL is interrupt enable. There are no CPU interrupts, so when ready comes back
+// with it set, the request is latched for the host to pick up with take_interrupt.
+// Everything else in the console range (the receiver registers) is plain memory.
+#[derive(Clone)]
+pub struct ConsoleTransmitterResponder {
+    delay: u64,
+    clock: u64,    // instructions executed since mounting
+    ready_at: u64, // clock value at which the transmitter is ready again
+    interrupt_enable: bool,
+    interrupt_requested: bool,
+    pub output: Vec<u8>,
+    pub lost: usize,
+    data: Vec<u8>,
+}
+
+impl ConsoleTransmitterResponder {
+    pub fn new(delay: u64) -> ConsoleTransmitterResponder {
+        ConsoleTransmitterResponder {
+            delay,
+            clock: 0,
+            ready_at: 0,
+            interrupt_enable: false,
+            interrupt_requested: false,
+            output: vec![],
+            lost: 0,
+            data: vec![0; CONSOLE_SIZE],
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.clock >= self.ready_at
+    }
+
+    // True once per interrupt request.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_requested)
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn transmit(&mut self, value: u8) {
+        if !self.is_ready() {
+            self.lost += 1;
+
+            return
+        }
+
+        self.output.push(value);
+
+        // The store itself gets ticked too, hence the extra instruction.
+        self.ready_at = self.clock + 1 + self.delay;
+
+        if self.delay == 0 && self.interrupt_enable {
+            self.interrupt_requested = true
+        }
+    }
+
+    fn offset(address: u32) -> Result<usize> {
+        let offset = address.wrapping_sub(RECEIVER_CONTROL) as usize;
+
+        if offset < CONSOLE_SIZE {
+            Ok(offset)
+        } else {
+            Err(MemoryUnmapped(address))
+        }
+    }
+}
+
+impl ListenResponder for ConsoleTransmitterResponder {
+    fn read(&self, address: u32) -> Result<u8> {
+        let offset = Self::offset(address)?;
+
+        if address == TRANSMITTER_CONTROL {
+            let ready = if self.is_ready() { READY } else { 0 };
+            let enable = if self.interrupt_enable { INTERRUPT_ENABLE } else { 0 };
+
+            return Ok(ready | enable)
+        }
+
+        // The rest of the control word reads as zero, the ready bit can't be written.
+        if (TRANSMITTER_CONTROL + 1 .. TRANSMITTER_DATA).contains(&address) {
+            return Ok(0)
+        }
+
+        Ok(self.data[offset])
+    }
+
+    fn write(&mut self, address: u32, value: u8) -> Result<()> {
+        let offset = Self::offset(address)?;
+
+        match address {
+            TRANSMITTER_CONTROL => self.interrupt_enable = value & INTERRUPT_ENABLE != 0,
+            TRANSMITTER_DATA => {
+                self.data[offset] = value;
+
+                self.transmit(value)
+            }
+            _ if (TRANSMITTER_CONTROL + 1 .. TRANSMITTER_DATA).contains(&address) => {}
+            _ => self.data[offset] = value,
+        }
+
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.clock += 1;
+
+        if self.clock == self.ready_at && self.interrupt_enable {
+            self.interrupt_requested = true
+        }
+    }
+}
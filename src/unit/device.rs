@@ -1,33 +1,47 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::{fs, thread};
 use std::panic::{catch_unwind, RefUnwindSafe};
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, Instant};
-use crate::assembler::binary::{Binary, RawRegion, RegionFlags};
+use std::time::Duration;
+use crate::assembler::binary::{Binary, BinarySection, RawRegion, RegionFlags};
+use crate::assembler::encode::{encode, PatchError};
+use crate::assembler::registers::{RegisterSlot, UnknownRegisterError};
 use crate::assembler::string::{assemble_from_path, SourceError};
 use crate::cpu::memory::{Mountable, Region};
-use crate::cpu::memory::section::{DefaultResponder, SectionMemory};
-use crate::cpu::memory::watched::WatchedMemory;
+use crate::cpu::memory::section::{InitPolicy, SectionKind, SectionMemory};
+use crate::cpu::memory::watched::{Framebuffer, Rect, WatchedMemory};
 use crate::cpu::{Memory, State};
-use crate::cpu::state::Registers;
-use crate::execution::executor::{DebugFrame, Executor, ExecutorMode};
+use crate::cpu::state::{HazardMode, HiLoHazard, HiLoHazards, Registers};
+use crate::cpu::snapshot::StateFormatError;
+use crate::execution::executor::{BreakpointInfo, DebugFrame, Executor, ExecutorMode, StopReason};
+use crate::execution::elf::setup::{StateBuilder, STACK_TOP};
+use crate::execution::stack::StackGrowth;
+use crate::execution::cancel::Timer;
 use crate::execution::trackers::history::HistoryTracker;
 use crate::unit::device::MakeUnitDeviceError::{CompileFailed, FileMissing};
-use crate::unit::device::UnitDeviceError::{ExecutionTimedOut, InvalidInstruction, MissingLabel, ProgramCompleted};
+use crate::unit::device::UnitDeviceError::{
+    ExecutionTimedOut, InvalidDialogResponse, InvalidInput, InvalidInstruction, MissingLabel, NoPendingDialog,
+    ProgramCompleted, ReadPastEndOfInput, UnsupportedSyscall
+};
 use num::{ToPrimitive, FromPrimitive};
 use StopCondition::{Label, MaybeLabel};
 use crate::execution::executor::ExecutorMode::{Invalid, Running};
-use crate::unit::device::StopCondition::{Address, Steps, Timeout};
+use crate::unit::device::StopCondition::{Address, Steps, SyscallInvoked, Timeout};
 use crate::cpu::error::Error as CpuError;
 use crate::unit::instruction::{Instruction, InstructionDecoder};
 use crate::unit::register::RegisterName;
-use crate::unit::register::RegisterName::{A0, RA, V0};
+use crate::unit::dialog::{DialogBehavior, DialogResponse};
+use crate::unit::terminal::Terminal;
+use crate::unit::console::{ConsoleTransmitterResponder, CONSOLE_SELECTOR};
+use crate::unit::register::RegisterName::{A0, A1, RA, V0};
 
-pub type MemoryType = WatchedMemory<SectionMemory<DefaultResponder>>;
+pub type MemoryType = WatchedMemory<SectionMemory<ConsoleTransmitterResponder>>;
 pub type TrackerType = HistoryTracker;
 
 #[derive(Debug)]
@@ -47,12 +61,95 @@ impl Display for MakeUnitDeviceError {
 
 impl Error for MakeUnitDeviceError { }
 
+pub type SyscallHandler = Box<dyn Fn(&mut SyscallContext)>;
+
 pub struct UnitDevice {
     pub executor: Arc<Executor<MemoryType, TrackerType>>,
     pub binary: Binary,
-    pub finished_pcs: Vec<u32>,
-    pub syscall_handler: Option<Box<dyn Fn()>>,
-    handlers: HashMap<u32, Box<dyn Fn ()>>,
+    finished_pcs: BTreeSet<u32>, // reaching one finishes the program (ExecutorMode::Finished)
+    pub syscall_handler: Option<SyscallHandler>,
+    handlers: HashMap<u32, SyscallHandler>,
+    pub terminal: RefCell<Terminal>, // console syscalls, see handle_terminal_syscall
+    pub dialogs: RefCell<DialogBehavior>, // dialog syscalls, see provide_dialog_result
+}
+
+// A mounted range of memory, split wherever the binary's regions start and end (see UnitDevice::memory_map).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryMapEntry {
+    pub start: u32,
+    pub len: u64, // bytes
+    pub kind: SectionKind,
+    pub region: Option<(BinarySection, RegionFlags)>, // the binary region loaded here, None for memory it didn't place
+}
+
+// Words read from memory per lock, so a long search doesn't hold up the executor.
+const MATCHING_CHUNK: u32 = 1024;
+
+// See UnitDevice::matching_addresses. Regions are visited by address, and the executor is only
+// locked while a chunk of words is copied out, never while the caller holds an address.
+pub struct MatchingAddresses<'a, F: FnMut(Instruction) -> bool> {
+    device: &'a UnitDevice,
+    matching: F,
+    regions: Vec<(u32, u32)>, // start, end, highest first
+    chunk: VecDeque<(u32, Option<u32>)>, // address, word (None if unmapped)
+    scanned: usize,
+}
+
+impl<F: FnMut(Instruction) -> bool> MatchingAddresses<'_, F> {
+    // Instructions passed to matching so far.
+    pub fn scanned(&self) -> usize {
+        self.scanned
+    }
+
+    // False once every region has been read.
+    fn fill(&mut self) -> bool {
+        let Some((start, end)) = self.regions.last_mut() else { return false };
+
+        let count = (*end - *start).div_ceil(4).min(MATCHING_CHUNK);
+        let first = *start;
+
+        *start = start.saturating_add(count * 4);
+
+        if count == 0 || *start >= *end {
+            self.regions.pop();
+        }
+
+        self.device.executor.with_memory(|memory| {
+            for index in 0..count {
+                let address = first.wrapping_add(index * 4);
+
+                self.chunk.push_back((address, memory.peek_u32(address).ok()))
+            }
+        });
+
+        true
+    }
+}
+
+impl<F: FnMut(Instruction) -> bool> Iterator for MatchingAddresses<'_, F> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        loop {
+            let Some((address, word)) = self.chunk.pop_front() else {
+                if !self.fill() {
+                    return None
+                }
+
+                continue
+            };
+
+            let Some(instruction) = word.and_then(|word| InstructionDecoder::decode(address, word)) else {
+                continue
+            };
+
+            self.scanned += 1;
+
+            if (self.matching)(instruction) {
+                return Some(address)
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -69,18 +166,20 @@ impl From<&str> for LabelIdentifier {
 
 #[derive(Clone, Debug)]
 pub enum StopCondition {
-    Address(u32), // PC Address
+    Address(u32, Option<u64>), // PC Address, and how many hits to pass through before stopping there
     MaybeLabel(LabelIdentifier), // Label (if it exists)
     Label(LabelIdentifier), // Label (fail if it doesn't exist)
     Steps(usize), // Number of Instructions to Execute
     Timeout(Duration), // Timeout
+    SyscallInvoked(Option<u32>), // Syscall about to run, for any or a specific $v0 (left unhandled)
     Complete,
 }
 
 struct StopConditionParameters {
     timeout: Option<Duration>,
     steps: Option<usize>,
-    breakpoints: Vec<u32>,
+    breakpoints: Vec<(u32, Option<u64>)>, // address, ignore count
+    syscalls: Vec<Option<u32>>,
     complete_error: bool
 }
 
@@ -124,17 +223,27 @@ impl StopConditionParameters {
         let breakpoints = conditions.iter()
             .filter_map(|c| {
                 match c {
-                    Address(pc) => Some(*pc),
+                    Address(pc, ignore) => Some((*pc, *ignore)),
                     MaybeLabel(identifier)
                         | Label(identifier) => {
                         get_label(&identifier.name)
-                            .map(|x| (x as i64 + identifier.offset) as u32)
+                            .map(|x| ((x as i64 + identifier.offset) as u32, None))
                     }
                     _ => None
                 }
             })
             .collect();
 
+        let syscalls = conditions.iter()
+            .filter_map(|c| {
+                if let SyscallInvoked(v0) = c {
+                    Some(*v0)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
         let complete_error = !conditions.iter()
             .any(|c| matches!(c, StopCondition::Complete));
 
@@ -142,6 +251,7 @@ impl StopConditionParameters {
             timeout,
             steps,
             breakpoints,
+            syscalls,
             complete_error
         })
     }
@@ -151,8 +261,13 @@ impl StopConditionParameters {
 pub enum UnitDeviceError {
     MissingLabel(String),
     ExecutionTimedOut,
-    InvalidInstruction(CpuError),
-    ProgramCompleted
+    InvalidInstruction(CpuError, Vec<String>), // with the disassembly around the pc, for invalid instructions
+    ProgramCompleted(u32, Option<String>), // pc, nearest_label
+    UnsupportedSyscall(u32), // $v0
+    InvalidInput(String), // not an integer, for a read integer syscall
+    ReadPastEndOfInput, // a read syscall after all of provide_stdin's input was used
+    NoPendingDialog,
+    InvalidDialogResponse(DialogResponse), // ex. Yes for an input dialog
 }
 
 impl Display for UnitDeviceError {
@@ -160,31 +275,31 @@ impl Display for UnitDeviceError {
         match self {
             MissingLabel(label) => write!(f, "Could not find label {} in program", label),
             ExecutionTimedOut => write!(f, "Execution timed out (by stop condition)"),
-            InvalidInstruction(error) => write!(f, "Cpu execution failed with error {}", error),
-            ProgramCompleted => write!(f, "Program completed and this was not caught")
-        }
-    }
-}
+            InvalidInstruction(error, nearby) => {
+                write!(f, "Cpu execution failed with error {}", error)?;
 
-fn make_timeout<F: FnOnce () + Send + 'static>(f: F, duration: Duration) -> Arc<AtomicBool> {
-    let stop = Arc::new(AtomicBool::new(false));
-    let result = stop.clone();
-
-    let start = Instant::now();
+                for line in nearby {
+                    write!(f, "\n{line}")?
+                }
 
-    thread::spawn(move || {
-        while start.elapsed() < duration {
-            if stop.load(Ordering::Relaxed) {
-                return
+                Ok(())
             }
+            ProgramCompleted(pc, label) => {
+                write!(f, "Program completed at 0x{pc:08x}")?;
 
-            thread::sleep(Duration::from_millis(100));
-        }
-
-        f()
-    });
+                if let Some(label) = label {
+                    write!(f, " (after {label})")?;
+                }
 
-    result
+                write!(f, " and this was not caught")
+            }
+            UnsupportedSyscall(v0) => write!(f, "Syscall {} is not supported", v0),
+            InvalidInput(input) => write!(f, "Expected an integer as input, but found \"{}\"", input),
+            ReadPastEndOfInput => write!(f, "The program tried to read past the end of its input"),
+            NoPendingDialog => write!(f, "The program is not waiting on a dialog"),
+            InvalidDialogResponse(response) => write!(f, "{:?} is not an answer this dialog accepts", response),
+        }
+    }
 }
 
 impl Error for UnitDeviceError { }
@@ -302,50 +417,114 @@ impl Registers {
 
 pub type UnitTest = fn (UnitDevice) -> ();
 
-impl UnitDevice {
-    pub fn new(binary: Binary) -> UnitDevice {
-        let mut memory = WatchedMemory::new(SectionMemory::new());
+// What a syscall handler gets to work with. The executor stays locked while the handler runs, so the
+// handler must go through this and not the device (device.get, device.set_data, ...), see Executor.
+pub struct SyscallContext<'a> {
+    pub state: &'a mut State<MemoryType>,
+}
 
-        let heap_size = 0x100000;
+impl SyscallContext<'_> {
+    pub fn get(&self, name: RegisterName) -> u32 {
+        self.state.registers.get(name)
+    }
 
-        for header in &binary.regions {
-            let region = Region {
-                start: header.address,
-                data: header.data.clone(),
-            };
+    pub fn set(&mut self, name: RegisterName, value: u32) {
+        self.state.registers.set(name, value)
+    }
 
-            memory.mount(region)
+    pub fn get_data(&self, address: u32, count: u32) -> Result<Vec<u8>, crate::cpu::error::Error> {
+        (0 .. count).map(|i| self.state.memory.get(address.wrapping_add(i))).collect()
+    }
+
+    pub fn set_data(&mut self, address: u32, data: &[u8]) -> Result<(), crate::cpu::error::Error> {
+        for (i, value) in data.iter().enumerate() {
+            self.state.memory.set(address.wrapping_add(i as u32), *value)?
         }
 
-        let heap_end = 0x7FFFFFFCu32;
+        Ok(())
+    }
+}
 
-        let heap = Region {
-            start: heap_end - heap_size,
-            data: vec![0; heap_size as usize],
-        };
+impl UnitDevice {
+    pub fn new(binary: Binary) -> UnitDevice {
+        Self::with_args(binary, &[] as &[&str])
+    }
 
-        memory.mount(heap);
+    pub fn with_args<S: AsRef<str>>(binary: Binary, args: &[S]) -> UnitDevice {
+        Self::with_memory_init(binary, args, None)
+    }
 
-        let mut state = State::new(binary.entry, memory);
-        state.registers.line[29] = heap_end;
+    // init fills fresh sections and the heap alike, ex. RandomSeeded to shake out reads of
+    // uninitialized memory. None keeps the defaults (0xCC sections, zeroed heap).
+    pub fn with_memory_init<S: AsRef<str>>(binary: Binary, args: &[S], init: Option<InitPolicy>) -> UnitDevice {
+        let builder = binary
+            .regions
+            .iter()
+            .fold(StateBuilder::new(binary.entry), |builder, header| {
+                builder.with_region(Region {
+                    start: header.address,
+                    data: header.data.clone(),
+                })
+            });
+
+        let state = builder
+            .with_heap_size(0x100000)
+            .with_heap_init(init.unwrap_or(InitPolicy::Zero))
+            .with_args(args)
+            .build(WatchedMemory::new(SectionMemory::with_init(init.unwrap_or_default())));
 
         let tracker = HistoryTracker::new(1000);
 
         let executor = Arc::new(Executor::new(state, tracker));
 
-        let finished_pcs = binary
-            .regions
-            .iter()
-            .map(|region| region.address + region.data.len() as u32)
-            .collect();
-
-        UnitDevice {
+        let mut device = UnitDevice {
             executor,
             binary,
             syscall_handler: None,
             handlers: HashMap::new(),
-            finished_pcs
+            finished_pcs: BTreeSet::new(),
+            terminal: RefCell::new(Terminal::default()),
+            dialogs: RefCell::new(DialogBehavior::default()),
+        };
+
+        // Falling off the end of a data region is a bug, not a finished program.
+        device.finished_pcs.extend(device.binary.end_pcs());
+        device.sync_completion_pcs();
+
+        device
+    }
+
+    fn sync_completion_pcs(&self) {
+        self.executor.set_end_pcs(self.finished_pcs.iter().copied().collect())
+    }
+
+    pub fn completion_pcs(&self) -> &BTreeSet<u32> {
+        &self.finished_pcs
+    }
+
+    pub fn is_completion_pc(&self, pc: u32) -> bool {
+        self.finished_pcs.contains(&pc)
+    }
+
+    pub fn add_completion_pc(&mut self, pc: u32) {
+        self.finished_pcs.insert(pc);
+        self.sync_completion_pcs();
+    }
+
+    // Adds the end of every region with any of these flags, e.g. RegionFlags::all() for the old behaviour.
+    pub fn add_completion_regions(&mut self, flags: RegionFlags) {
+        for region in &self.binary.regions {
+            if region.flags.intersects(flags) {
+                self.finished_pcs.insert(region.address.wrapping_add(region.data.len() as u32));
+            }
         }
+
+        self.sync_completion_pcs();
+    }
+
+    pub fn clear_completion_pcs(&mut self) {
+        self.finished_pcs.clear();
+        self.sync_completion_pcs();
     }
 
     pub fn binary(path: PathBuf) -> Result<Binary, MakeUnitDeviceError> {
@@ -371,10 +550,32 @@ impl UnitDevice {
         self.executor.with_state(|s| s.registers.set(name, value))
     }
 
+    // MARS style names: "$v0", "v0", "$2" or "2".
+    pub fn register_named(name: &str) -> Result<RegisterName, UnknownRegisterError> {
+        let slot = RegisterSlot::from_str(name)?;
+
+        Ok(FromPrimitive::from_u8(slot.to_u8().unwrap()).unwrap())
+    }
+
+    pub fn get_named(&self, name: &str) -> Result<u32, UnknownRegisterError> {
+        Ok(self.get(Self::register_named(name)?))
+    }
+
+    pub fn set_named(&self, name: &str, value: u32) -> Result<(), UnknownRegisterError> {
+        self.set(Self::register_named(name)?, value);
+
+        Ok(())
+    }
+
     pub fn has_label(&self, name: &str) -> bool {
         self.binary.labels.contains_key(name)
     }
 
+    pub fn is_alias(&self, name: &str) -> bool {
+        self.binary.aliases.contains_key(name)
+    }
+
+    // Prefers a label over any alias sharing its address.
     pub fn label_for(&self, address: u32) -> Option<&String> {
         self.binary.labels.iter()
             .filter_map(|(label, other)| {
@@ -384,7 +585,35 @@ impl UnitDevice {
                     None
                 }
             })
-            .next()
+            .min_by_key(|label| (self.is_alias(label), *label))
+    }
+
+    // "label+0x10" for the closest label at or before address (labels over aliases), "label" if it's right there.
+    pub fn nearest_label(&self, address: u32) -> Option<String> {
+        let (label, start) = self.binary.labels.iter()
+            .filter(|(_, start)| **start <= address)
+            .max_by_key(|(label, start)| (**start, !self.is_alias(label), std::cmp::Reverse(*label)))?;
+
+        Some(match address - start {
+            0 => label.clone(),
+            offset => format!("{label}+0x{offset:x}"),
+        })
+    }
+
+    // The label_for every labeled address, for Instruction::display_with and parameters_with_labels.
+    pub fn address_labels(&self) -> HashMap<u32, String> {
+        let mut result: HashMap<u32, String> = HashMap::new();
+
+        for (label, address) in &self.binary.labels {
+            let preferred = result.get(address)
+                .map_or(true, |other| (self.is_alias(label), label) < (self.is_alias(other), other));
+
+            if preferred {
+                result.insert(*address, label.clone());
+            }
+        }
+
+        result
     }
 
     pub fn arrived_at_label(&self, name: &str) -> bool {
@@ -395,34 +624,79 @@ impl UnitDevice {
 
     pub fn instruction_at(&self, address: u32) -> Option<Instruction> {
         self.executor.with_memory(|memory| {
-            memory.get_u32(address).ok()
+            memory.peek_u32(address).ok()
                 .and_then(|value| InstructionDecoder::decode(address, value))
         })
     }
 
-    pub fn addresses_for<F: FnMut(Instruction) -> bool>(&self, mut matching: F) -> Vec<u32> {
-        self.executor.with_memory(|memory| {
-            let mut result = vec![];
+    // Every address in the binary whose instruction matches, in ascending order.
+    pub fn addresses_for<F: FnMut(Instruction) -> bool>(&self, matching: F) -> Vec<u32> {
+        self.matching_addresses(matching).collect()
+    }
 
-            for region in &self.binary.regions {
-                for address in (region.address .. region.address + region.data.len() as u32).step_by(4) {
-                    let Some(instruction) = memory.get_u32(address).ok()
-                        .and_then(|value| InstructionDecoder::decode(address, value)) else {
-                        continue
-                    };
+    pub fn first_address_for<F: FnMut(Instruction) -> bool>(&self, matching: F) -> Option<u32> {
+        self.matching_addresses(matching).next()
+    }
 
-                    if matching(instruction) {
-                        result.push(address)
-                    }
-                }
-            }
+    // Like addresses_for, but only scans as far as the caller iterates.
+    pub fn matching_addresses<F: FnMut(Instruction) -> bool>(&self, matching: F) -> MatchingAddresses<'_, F> {
+        let mut regions: Vec<(u32, u32)> = self.binary.regions.iter()
+            .map(|region| (region.address, region.address.saturating_add(region.data.len() as u32)))
+            .collect();
 
-            result
-        })
+        regions.sort();
+        regions.reverse(); // popped from the back
+
+        MatchingAddresses { device: self, matching, regions, chunk: VecDeque::new(), scanned: 0 }
     }
 
     pub fn conditions_for_matching<F: FnMut(Instruction) -> bool>(&self, matching: F) -> Vec<StopCondition> {
-        self.addresses_for(matching).into_iter().map(|x| Address(x)).collect()
+        self.addresses_for(matching).into_iter().map(|address| Address(address, None)).collect()
+    }
+
+    // Every mounted range in address order, with the binary's regions marked, so a frontend can gray out
+    // unmapped memory without reading it.
+    pub fn memory_map(&self) -> Vec<MemoryMapEntry> {
+        let sections = self.executor.with_memory(|memory| memory.mounted_sections());
+
+        let mut regions: Vec<&RawRegion> = self.binary.regions.iter()
+            .filter(|region| !region.data.is_empty())
+            .collect();
+
+        regions.sort_by_key(|region| region.address);
+
+        let mut result = vec![];
+
+        for section in sections {
+            let start = section.start() as u64;
+            let end = start + section.len;
+
+            let mut piece = |from: u64, to: u64, region: Option<(BinarySection, RegionFlags)>| {
+                if to > from {
+                    result.push(MemoryMapEntry { start: from as u32, len: to - from, kind: section.kind, region })
+                }
+            };
+
+            let mut cursor = start;
+
+            for region in &regions {
+                let region_start = (region.address as u64).max(cursor);
+                let region_end = (region.address as u64 + region.data.len() as u64).min(end);
+
+                if region_end <= region_start {
+                    continue
+                }
+
+                piece(cursor, region_start, None);
+                piece(region_start, region_end, Some((region.section(), region.flags)));
+
+                cursor = region_end;
+            }
+
+            piece(cursor, end, None);
+        }
+
+        result
     }
 
     pub fn jump_to(&self, pc: u32) {
@@ -447,11 +721,28 @@ impl UnitDevice {
         self.executor.with_state(|s| *s = state)
     }
 
-    pub fn handle_syscall<F: Fn() + 'static>(&mut self, v0: u32, f: F) {
+    // Registers and memory (not devices), in the format State::to_bytes writes.
+    pub fn snapshot_bytes(&self) -> Vec<u8> {
+        self.executor.with_state(|s| s.to_bytes())
+    }
+
+    // Loads a state saved by snapshot_bytes, possibly from another run, into this device.
+    pub fn restore_from_bytes(&self, bytes: &[u8]) -> Result<(), StateFormatError> {
+        self.executor.with_state(|s| s.restore_bytes(bytes))
+    }
+
+    // The stack starts at 1MB (shared with the heap) and is mounted a page at a time past that,
+    // up to max_size bytes below the top.
+    pub fn grow_stack(&self, max_size: u32) {
+        self.executor.set_stack_growth(Some(StackGrowth::new(max_size)))
+    }
+
+    // Handlers run with the executor locked, see SyscallContext.
+    pub fn handle_syscall<F: Fn(&mut SyscallContext) + 'static>(&mut self, v0: u32, f: F) {
         self.handlers.insert(v0, Box::new(f));
     }
 
-    pub fn handle_any_syscall<F: Fn() + 'static>(&mut self, f: F) {
+    pub fn handle_any_syscall<F: Fn(&mut SyscallContext) + 'static>(&mut self, f: F) {
         self.syscall_handler = Some(Box::new(f))
     }
 
@@ -461,51 +752,90 @@ impl UnitDevice {
                 CpuError::CpuSyscall => {
                     let v0 = self.executor.with_state(|s| s.registers.get(V0));
 
-                    if let Some(handler) = self.handlers.get(&v0) {
-                        handler();
+                    let handler = self.handlers.get(&v0).or(self.syscall_handler.as_ref());
 
-                        self.executor.syscall_handled();
-
-                        Ok(false)
-                    } else if let Some(handler) = &self.syscall_handler {
-                        handler();
+                    if let Some(handler) = handler {
+                        self.executor.with_state(|state| handler(&mut SyscallContext { state }));
 
                         self.executor.syscall_handled();
 
                         Ok(false)
                     } else {
-                        Err(InvalidInstruction(error))
+                        Err(InvalidInstruction(error, vec![]))
                     }
                 }
 
                 _ => {
-                    if self.finished_pcs.contains(&frame.registers.pc) {
-                        if complete_error {
-                            Err(ProgramCompleted)
-                        } else {
-                            Ok(true)
-                        }
-                    } else {
-                        Err(InvalidInstruction(error))
-                    }
+                    let nearby = match &frame.stop {
+                        StopReason::Fault { nearby, .. } => nearby.clone(),
+                        _ => vec![],
+                    };
+
+                    Err(InvalidInstruction(error, nearby))
                 }
             },
 
+            ExecutorMode::Finished if complete_error => {
+                let pc = frame.registers.pc;
+
+                Err(ProgramCompleted(pc, self.nearest_label(pc)))
+            }
+
             _ => Ok(true)
         }
     }
 
+    // True if the frame is stopped on a syscall that one of the SyscallInvoked conditions asks for.
+    fn is_watched_syscall(&self, frame: &DebugFrame, syscalls: &[Option<u32>]) -> bool {
+        if frame.mode != Invalid(CpuError::CpuSyscall) {
+            return false
+        }
+
+        let v0 = frame.registers.get(V0);
+
+        syscalls.iter().any(|c| c.map_or(true, |value| value == v0))
+    }
+
+    // Why execution stopped, with the breakpoint's label if it has one.
+    pub fn stop_reason(&self) -> StopReason {
+        match self.executor.frame().stop {
+            StopReason::Breakpoint { pc, .. } => StopReason::Breakpoint { pc, label: self.label_for(pc).cloned() },
+            stop => stop,
+        }
+    }
+
     pub fn step(&self) -> Result<(), UnitDeviceError> {
         self.execute_until([Steps(1)])
     }
 
+    // One source statement, syscalls included. Returns the statement (index into binary.breakpoints) the pc
+    // landed on, see Executor::step_statement.
+    pub fn step_line(&self) -> Result<Option<usize>, UnitDeviceError> {
+        let frame = self.executor.frame();
+
+        // A syscall already stopped on is the rest of its statement.
+        if frame.mode == Invalid(CpuError::CpuSyscall) {
+            self.handle_frame(&frame, false)?;
+        } else {
+            let step = self.executor.step_statement(&self.binary);
+
+            self.handle_frame(&step.frame, false)?;
+        }
+
+        Ok(self.binary.statement_for(self.registers().pc))
+    }
+
     pub fn backstep(&self) -> bool {
         let Some(entry) = self.executor.with_tracker(|tracker| tracker.pop()) else {
             return false
         };
 
         self.executor.with_state(|state| {
-            entry.apply(&mut state.registers, &mut state.memory.backing);
+            for edit in &entry.edits {
+                state.memory.touch(edit.address, edit.size())
+            }
+
+            entry.apply(&mut state.registers, &mut state.link, &mut state.memory.backing);
         });
 
         true
@@ -535,7 +865,7 @@ impl UnitDevice {
 
         self.load_params(params);
 
-        let mut execution_conditions = vec![Address(return_address)];
+        let mut execution_conditions = vec![Address(return_address, None)];
         execution_conditions.extend_from_slice(conditions);
 
         self.execute_until_slice(&execution_conditions)?;
@@ -562,19 +892,40 @@ impl UnitDevice {
             conditions, |s| self.binary.labels.get(s).copied()
         )?;
 
-        self.executor.set_breakpoints(parameters.breakpoints.into_iter().collect());
+        // An ignore count only applies to a breakpoint that isn't set yet. Asking again with the same
+        // address keeps counting down what's left, so a loop can be stepped through one call at a time.
+        for &(address, ignore) in &parameters.breakpoints {
+            if let (Some(count), None) = (ignore, self.executor.breakpoint(address)) {
+                self.executor.set_breakpoint(address, BreakpointInfo::ignoring(count))
+            }
+        }
+
+        self.executor.set_breakpoints(parameters.breakpoints.iter().map(|(address, _)| *address).collect());
+
+        // A syscall left pending by a SyscallInvoked stop is handled before moving on.
+        let frame = self.executor.frame();
+
+        if frame.mode == Invalid(CpuError::CpuSyscall) {
+            self.handle_frame(&frame, parameters.complete_error)?;
+        }
+
+        let mut skip_breakpoint = self.executor.is_breakpoint();
+
+        // A pause from before this call was meant for the last run.
+        self.executor.resume();
 
         let did_timeout = Arc::new(AtomicBool::new(false));
         let did_timeout_clone = did_timeout.clone();
 
-        let cancel = parameters.timeout.map(move |duration| {
+        // Joined when this returns, however it returns.
+        let _timer = parameters.timeout.map(move |duration| {
             let executor = self.executor.clone();
 
-            make_timeout(move || {
+            Timer::start(duration, move || {
                 did_timeout_clone.store(true, Ordering::Relaxed);
 
                 executor.pause();
-            }, duration)
+            })
         });
 
         loop {
@@ -589,16 +940,18 @@ impl UnitDevice {
                 
                 self.executor.frame()
             } else {
-                self.executor.run(self.executor.is_breakpoint())
+                self.executor.run(skip_breakpoint)
             };
 
-            if self.handle_frame(&frame, parameters.complete_error)? {
+            skip_breakpoint = false;
+
+            if self.is_watched_syscall(&frame, &parameters.syscalls) {
                 break
             }
-        }
 
-        if let Some(cancel) = cancel {
-            cancel.store(true, Ordering::Relaxed)
+            if self.handle_frame(&frame, parameters.complete_error)? {
+                break
+            }
         }
 
         if did_timeout.load(Ordering::Relaxed) {
@@ -617,7 +970,7 @@ impl UnitDevice {
             let mut result = vec![];
 
             for i in 0 .. count {
-                result.push(memory.get(address.wrapping_add(i))?)
+                result.push(memory.peek(address.wrapping_add(i))?)
             }
 
             Ok(result)
@@ -634,6 +987,17 @@ impl UnitDevice {
         })
     }
 
+    // Binary::patch_instruction, then the same word in live memory. It's written past the watch log,
+    // so stepping back doesn't undo the patch.
+    pub fn patch_instruction(&mut self, address: u32, new: Instruction) -> Result<(), PatchError> {
+        let word = encode(address, &new)?;
+
+        self.binary.patch_instruction(address, new)?;
+
+        self.executor.with_memory(|memory| memory.backing.set_u32(address, word))
+            .map_err(PatchError::Memory)
+    }
+
     pub fn get_display_data(
         &self,
         line_byte_length: u32,
@@ -642,17 +1006,16 @@ impl UnitDevice {
         width: u32, height: u32
     ) -> Result<Vec<u32>, crate::cpu::error::Error> {
         self.executor.with_memory(|memory| {
-            let mut result = vec![];
-
-            result.reserve((width as usize) * (height as usize));
+            let mut result = Vec::with_capacity((width as usize) * (height as usize));
 
             for v in y .. (y + height) {
                 for h in x .. (x + width) {
-                    let point = address + line_byte_length
+                    let point = address.wrapping_add(line_byte_length
                         .wrapping_mul(v)
-                        .wrapping_add(h.wrapping_mul(4));
+                        .wrapping_add(h.wrapping_mul(4)));
 
-                    result.push(memory.get_u32(point)?)
+                    // A row can start anywhere. Peeked, pixels the program never drew aren't its reads.
+                    result.push(memory.read_u32_unaligned(point)?)
                 }
             }
 
@@ -660,6 +1023,68 @@ impl UnitDevice {
         })
     }
 
+    // Notes the first read of memory the program never wrote (the binary and the program arguments
+    // count as written). Call before running. Syscalls reading memory (ex. print string) count too.
+    pub fn track_uninitialized_reads(&self) {
+        let regions: Vec<(u32, u32)> = self.binary.regions.iter()
+            .map(|region| (region.address, region.data.len() as u32))
+            .collect();
+
+        self.executor.with_state(|state| {
+            let argv = state.registers.line[A1 as usize];
+            let memory = &mut state.memory.backing;
+
+            memory.track_uninitialized_reads();
+
+            for (address, length) in regions {
+                memory.mark_written(address, length)
+            }
+
+            memory.mark_written(argv, STACK_TOP.wrapping_sub(argv));
+        })
+    }
+
+    // Strict MIPS I timing for mfhi/mflo against mult/div (and anything else writing hi or lo).
+    pub fn check_hi_lo_hazards(&self, mode: HazardMode) {
+        self.executor.with_state(|state| state.hazards = Some(HiLoHazards::new(mode)))
+    }
+
+    // Found so far in HazardMode::Warn.
+    pub fn hi_lo_hazards(&self) -> Vec<HiLoHazard> {
+        self.executor.with_state(|state| {
+            state.hazards.as_ref().map(|hazards| hazards.found.clone()).unwrap_or_default()
+        })
+    }
+
+    pub fn first_uninitialized_read(&self) -> Option<u32> {
+        self.executor.with_memory(|memory| memory.backing.first_uninitialized_read())
+    }
+
+    // Tracks writes to a bitmap display (ex. 0x10008000, 512 bytes a row, 256 rows), see take_display_dirty.
+    // Everything starts out dirty so the first frame is drawn in full.
+    pub fn watch_display(&self, address: u32, line_byte_length: u32, height: u32) {
+        let mut framebuffer = Framebuffer::new(address, line_byte_length, height);
+        framebuffer.touch_all();
+
+        self.executor.with_memory(|memory| memory.framebuffer = Some(framebuffer))
+    }
+
+    // Pixels written since the last call, None if none were (or no display is watched).
+    pub fn take_display_dirty(&self) -> Option<Rect> {
+        self.executor.with_memory(|memory| memory.framebuffer.as_mut()?.take_dirty())
+    }
+
+    // Pixels of the watched display, row by row, for uploading what take_display_dirty returned.
+    pub fn read_rect(&self, rect: Rect) -> Result<Vec<u32>, crate::cpu::error::Error> {
+        let Some(framebuffer) = self.executor.with_memory(|memory| memory.framebuffer.clone()) else {
+            return Ok(vec![])
+        };
+
+        self.get_display_data(
+            framebuffer.line_byte_length, framebuffer.address, rect.x, rect.y, rect.width, rect.height
+        )
+    }
+
     pub fn mount_data(&mut self, address: u32, data: Vec<u8>) {
         self.executor.with_memory(|memory| {
             memory.mount(Region {
@@ -669,6 +1094,29 @@ impl UnitDevice {
         })
     }
 
+    // Replaces the keyboard/display range with a MARS style transmitter, busy for `delay` instructions per byte.
+    pub fn mount_console_mmio(&mut self, delay: u64) {
+        self.executor.with_memory(|memory| {
+            memory.backing.mount_listen(CONSOLE_SELECTOR, ConsoleTransmitterResponder::new(delay))
+        })
+    }
+
+    // None if mount_console_mmio was never called.
+    pub fn with_console<T, F: FnOnce (&mut ConsoleTransmitterResponder) -> T>(&self, f: F) -> Option<T> {
+        self.executor.with_memory(|memory| {
+            memory.backing.listener_mut(CONSOLE_SELECTOR).map(f)
+        })
+    }
+
+    pub fn console_output(&self) -> String {
+        self.with_console(|console| String::from_utf8_lossy(&console.output).to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn take_console_interrupt(&self) -> bool {
+        self.with_console(|console| console.take_interrupt()).unwrap_or(false)
+    }
+
     pub fn test<F: RefUnwindSafe + Fn() -> UnitDevice>(configure: F, tests: &[UnitTest]) -> thread::Result<()> {
         for test in tests {
             catch_unwind(|| {
@@ -681,3 +1129,297 @@ impl UnitDevice {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::binary::{BinarySection, RegionFlags};
+    use crate::assembler::registers::UnknownRegisterError;
+    use crate::assembler::string::assemble_from;
+    use crate::cpu::memory::section::SectionKind;
+    use crate::cpu::error::Error as CpuError;
+    use crate::unit::device::{StopCondition, UnitDevice, UnitDeviceError};
+    use crate::unit::instruction::Instruction;
+    use crate::unit::register::RegisterName::{A0, A1, V0};
+
+    // The 0x500000 section comes first in the binary, but is scanned last.
+    const SPLIT: &str = "
+        .text 0x500000
+        far: jal far
+        nop
+        .text 0x400000
+        near: nop
+        jal far
+        jal near
+    ";
+
+    fn is_jal(instruction: Instruction) -> bool {
+        matches!(instruction, Instruction::Jal { .. })
+    }
+
+    #[test]
+    fn matches_come_in_address_order() {
+        let device = UnitDevice::new(assemble_from(SPLIT).unwrap());
+
+        assert_eq!(device.binary.regions[0].address, 0x500000);
+
+        assert_eq!(device.addresses_for(is_jal), [0x400004, 0x400008, 0x500000]);
+        assert_eq!(device.first_address_for(is_jal), Some(0x400004));
+        assert_eq!(device.first_address_for(|instruction| matches!(instruction, Instruction::Jr { .. })), None);
+    }
+
+    #[test]
+    fn streaming_stops_at_the_first_match() {
+        let source = format!("jal main\nmain:\n{}", "nop\n".repeat(5000));
+        let device = UnitDevice::new(assemble_from(&source).unwrap());
+
+        let mut matching = device.matching_addresses(is_jal);
+
+        assert_eq!(matching.next(), Some(0x400000));
+        assert_eq!(matching.scanned(), 1);
+
+        // Going on scans the rest.
+        assert_eq!(matching.next(), None);
+        assert_eq!(matching.scanned(), 5001);
+
+        let device = UnitDevice::new(assemble_from(SPLIT).unwrap());
+        let mut split = device.matching_addresses(is_jal);
+
+        assert_eq!(split.next(), Some(0x400004));
+        assert_eq!(split.scanned(), 2);
+    }
+
+    #[test]
+    fn lines_step_through_syscalls() {
+        let device = tripler();
+
+        assert_eq!(device.step_line().unwrap(), Some(1));
+
+        // la is lui and ori, one line.
+        assert_eq!(device.step_line().unwrap(), Some(2));
+        assert_eq!(device.get(A1), device.binary.labels["buffer"]);
+
+        assert_eq!(device.step_line().unwrap(), Some(3));
+
+        // The syscall is handled as part of its line, then the program is past its last statement.
+        assert_eq!(device.step_line().unwrap(), None);
+        assert_eq!(device.get(V0), 1);
+    }
+
+    #[test]
+    fn address_labels_prefer_labels_over_aliases() {
+        let device = UnitDevice::new(assemble_from("
+            .alias alpha, start
+            start:
+            main: nop
+            j main
+        ").unwrap());
+
+        let labels = device.address_labels();
+
+        // main and start share an address, main wins by name, alpha is only an alias.
+        assert_eq!(labels[&0x400000], "main");
+        assert_eq!(labels.len(), 1);
+
+        let jump = device.instruction_at(0x400004).unwrap();
+
+        assert_eq!(jump.display_with(&labels).to_string(), "j main");
+    }
+
+    // Triples $a0 into the word at $a1 and answers 1 in $v0, all through the context.
+    fn tripler() -> UnitDevice {
+        let mut device = UnitDevice::new(assemble_from("
+            .data
+            buffer: .word 0
+            .text
+                li $a0, 7
+                la $a1, buffer
+                li $v0, 100
+                syscall
+        ").unwrap());
+
+        device.handle_syscall(100, |context| {
+            let value = context.get(A0) * 3;
+            let address = context.get(A1);
+
+            context.set_data(address, &value.to_le_bytes()).unwrap();
+            context.set(V0, 1);
+
+            assert_eq!(context.get_data(address, 4).unwrap(), value.to_le_bytes());
+        });
+
+        device
+    }
+
+    #[test]
+    fn handlers_work_through_the_context() {
+        let device = tripler();
+
+        device.execute_until([StopCondition::Complete]).unwrap();
+
+        let buffer = device.binary.labels["buffer"];
+
+        assert_eq!(device.get_data(buffer, 4).unwrap(), 21u32.to_le_bytes());
+        assert_eq!(device.get(V0), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Executor locked again by the thread already holding it")]
+    fn handlers_calling_back_into_the_executor_panic() {
+        let mut device = tripler();
+        let executor = device.executor.clone();
+
+        device.handle_syscall(100, move |_| {
+            executor.frame();
+        });
+
+        device.execute_until([StopCondition::Complete]).ok();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "this would deadlock")]
+    fn nested_with_state_panics() {
+        let device = tripler();
+
+        device.executor.with_state(|_| device.get(V0));
+    }
+
+    #[test]
+    fn separate_executors_can_be_locked_together() {
+        let first = tripler();
+        let second = tripler();
+
+        // Only the same executor twice is a problem.
+        let value = first.executor.with_state(|_| second.get(V0));
+
+        assert_eq!(value, 0);
+    }
+
+    #[test]
+    fn invalid_instructions_show_where_they_ran() {
+        let device = UnitDevice::new(assemble_from("nop\nnop\n.word 0xFC000000\nnop\n").unwrap());
+
+        let error = device.execute_until([StopCondition::Complete]).unwrap_err();
+
+        assert!(matches!(error, UnitDeviceError::InvalidInstruction(CpuError::CpuInvalid(0x400008, 0xfc000000), _)));
+
+        let text = error.to_string();
+        let lines: Vec<_> = text.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "Cpu execution failed with error Invalid CPU instruction 0xfc000000 at 0x00400008 (unknown opcode 0x3f)"
+        );
+        assert!(lines.contains(&"> 0x00400008  .word 0xfc000000"));
+        assert!(lines.contains(&"  0x00400004  nop"));
+    }
+
+    #[test]
+    fn memory_maps_mark_the_binary_regions() {
+        let mut device = UnitDevice::new(assemble_from("nop\nnop\n.data\n.word 1, 2\n.ktext\nnop\n").unwrap());
+
+        device.mount_console_mmio(0);
+
+        let map: Vec<_> = device.memory_map().iter()
+            .map(|entry| (entry.start, entry.len, entry.kind, entry.region.map(|(section, _)| section)))
+            .collect();
+
+        // Regions only cover what was assembled, the rest of their section is listed on its own.
+        assert_eq!(map, [
+            (0x00400000, 0x8, SectionKind::Data, Some(BinarySection::Text)),
+            (0x00400008, 0xfff8, SectionKind::Data, None),
+            (0x10010000, 0x8, SectionKind::Data, Some(BinarySection::Data)),
+            (0x10010008, 0xfff8, SectionKind::Data, None),
+            (0x7fef0000, 0x110000, SectionKind::Data, None), // the stack
+            (0x80000000, 0x4, SectionKind::Data, Some(BinarySection::KernelText)),
+            (0x80000004, 0xfffc, SectionKind::Data, None),
+            (0xffff0000, 0x10000, SectionKind::Listen, None),
+        ]);
+
+        let flags = device.memory_map()[2].region.unwrap().1;
+
+        assert!(flags.contains(RegionFlags::WRITABLE) && !flags.contains(RegionFlags::EXECUTABLE));
+    }
+
+    // Jumps to the word just past the data region.
+    const PAST_DATA: &str = "la $t0, end\njr $t0\n.data\n.word 1, 2\nend:\n";
+
+    #[test]
+    fn jumping_past_data_is_not_completion() {
+        let device = UnitDevice::new(assemble_from(PAST_DATA).unwrap());
+
+        assert_eq!(device.completion_pcs().iter().copied().collect::<Vec<_>>(), [0x40000c]);
+        assert!(!device.is_completion_pc(0x10010008));
+
+        let error = device.execute_until([StopCondition::Complete]).unwrap_err();
+
+        assert!(matches!(error, UnitDeviceError::InvalidInstruction(CpuError::CpuInvalid(0x10010008, _), _)));
+    }
+
+    #[test]
+    fn extra_completion_pcs_are_honored() {
+        let mut device = UnitDevice::new(assemble_from(PAST_DATA).unwrap());
+
+        device.add_completion_pc(0x10010008);
+
+        assert!(device.execute_until([StopCondition::Complete]).is_ok());
+        assert_eq!(device.registers().pc, 0x10010008);
+
+        // The old behaviour, the end of every region.
+        let mut device = UnitDevice::new(assemble_from(PAST_DATA).unwrap());
+
+        device.add_completion_regions(RegionFlags::all());
+
+        assert!(device.is_completion_pc(0x10010008));
+        assert!(device.execute_until([StopCondition::Complete]).is_ok());
+
+        // Without any, running off the end of the text is a fault too.
+        let mut device = UnitDevice::new(assemble_from("nop\nnop\n").unwrap());
+
+        device.clear_completion_pcs();
+
+        assert!(device.completion_pcs().is_empty());
+        assert!(matches!(
+            device.execute_until([StopCondition::Complete]),
+            Err(UnitDeviceError::InvalidInstruction(CpuError::CpuInvalid(0x400008, _), _))
+        ));
+    }
+
+    #[test]
+    fn routines_are_called_through_their_aliases() {
+        let device = UnitDevice::new(assemble_from("
+            .alias old_double, double
+            nop
+            double: add $v0, $a0, $a0
+            jr $ra
+        ").unwrap());
+
+        device.call("double", [5], None).unwrap();
+        assert_eq!(device.get(V0), 10);
+
+        device.call("old_double", [7], None).unwrap();
+        assert_eq!(device.get(V0), 14);
+
+        assert!(device.has_label("old_double"));
+        assert!(device.is_alias("old_double") && !device.is_alias("double"));
+        assert_eq!(device.label_for(0x400004).map(String::as_str), Some("double"));
+    }
+
+    #[test]
+    fn registers_are_read_and_written_by_name() {
+        let device = UnitDevice::new(assemble_from("add $v0, $a0, $a1\n").unwrap());
+
+        device.set_named("$a0", 3).unwrap();
+        device.set_named("a1", 4).unwrap();
+        device.step().unwrap();
+
+        assert_eq!(device.get_named("$v0"), Ok(7));
+        assert_eq!(device.get_named("$2"), Ok(7));
+        assert_eq!(device.get_named("V0"), Ok(7));
+        assert_eq!(device.get(A0), 3);
+
+        assert_eq!(device.get_named("$x9"), Err(UnknownRegisterError("x9".into())));
+        assert!(device.set_named("32", 1).is_err());
+    }
+}
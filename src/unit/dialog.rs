@@ -0,0 +1,336 @@
+use std::collections::VecDeque;
+use crate::cpu::error::Error as CpuError;
+use crate::execution::executor::ExecutorMode::Invalid;
+use crate::unit::device::{UnitDevice, UnitDeviceError};
+use crate::unit::register::RegisterName::{A0, A1, A2, V0};
+use crate::unit::terminal::{read_string, TerminalSyscall};
+
+// MARS dialog syscalls 50, 51, 54, 55, 56 and 59 for UnitDevice. There's nothing to show a dialog on,
+// so each one is answered by `dialogs` right away or left pending (like a read waiting for input)
+// until the frontend calls provide_dialog_result. 52, 53, 57 and 58 need floats, so they stay unsupported.
+pub const DIALOG_SYSCALLS: [u32; 6] = [50, 51, 54, 55, 56, 59];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Error,
+    Information,
+    Warning,
+    Question,
+    Plain, // any other $a1
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DialogKind {
+    Confirm, // 50, yes/no/cancel
+    InputInt, // 51
+    InputString { buffer: u32, capacity: u32 }, // 54, capacity counts the null
+    Message(MessageType), // 55
+    MessageInt(i32), // 56, shown after the message
+    MessageString(String), // 59, shown after the message
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DialogResponse {
+    Yes,
+    No,
+    Ok, // an input dialog accepted with nothing typed, or a message dismissed
+    Text(String), // typed into an input dialog, then accepted
+    Cancel,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DialogRequest {
+    pub kind: DialogKind,
+    pub message_address: u32,
+    pub message: String,
+    pub default: DialogResponse, // what closing the dialog answers
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum DialogBehavior {
+    #[default]
+    Pause, // left pending, see pending_dialog
+    Cancel, // every dialog gets its default
+    Scripted(VecDeque<DialogResponse>), // answered in order, then like Cancel
+}
+
+impl DialogBehavior {
+    fn next(&mut self, request: &DialogRequest) -> Option<DialogResponse> {
+        match self {
+            DialogBehavior::Pause => None,
+            DialogBehavior::Cancel => Some(request.default.clone()),
+            DialogBehavior::Scripted(responses) => Some(responses.pop_front().unwrap_or(request.default.clone())),
+        }
+    }
+}
+
+fn message_type(value: u32) -> MessageType {
+    match value {
+        0 => MessageType::Error,
+        1 => MessageType::Information,
+        2 => MessageType::Warning,
+        3 => MessageType::Question,
+        _ => MessageType::Plain,
+    }
+}
+
+// Status codes MARS leaves in $a1 for input dialogs.
+const STATUS_OK: i32 = 0;
+const STATUS_INVALID: i32 = -1;
+const STATUS_CANCELLED: i32 = -2;
+const STATUS_EMPTY: i32 = -3;
+const STATUS_TRUNCATED: i32 = -4;
+
+impl UnitDevice {
+    fn read_text(&self, address: u32) -> Result<String, UnitDeviceError> {
+        let bytes = read_string(self, address).map_err(|error| UnitDeviceError::InvalidInstruction(error, vec![]))?;
+
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    // The dialog the program is stopped on, if it is stopped on one.
+    pub fn pending_dialog(&self) -> Result<Option<DialogRequest>, UnitDeviceError> {
+        let frame = self.executor.frame();
+
+        if frame.mode != Invalid(CpuError::CpuSyscall) {
+            return Ok(None)
+        }
+
+        let (v0, a0, a1) = (frame.registers.get(V0), frame.registers.get(A0), frame.registers.get(A1));
+
+        let kind = match v0 {
+            50 => DialogKind::Confirm,
+            51 => DialogKind::InputInt,
+            54 => DialogKind::InputString { buffer: a1, capacity: frame.registers.get(A2) },
+            55 => DialogKind::Message(message_type(a1)),
+            56 => DialogKind::MessageInt(a1 as i32),
+            59 => DialogKind::MessageString(self.read_text(a1)?),
+            _ => return Ok(None),
+        };
+
+        let default = match kind {
+            DialogKind::Confirm | DialogKind::InputInt | DialogKind::InputString { .. } => DialogResponse::Cancel,
+            _ => DialogResponse::Ok,
+        };
+
+        Ok(Some(DialogRequest {
+            kind,
+            message_address: a0,
+            message: self.read_text(a0)?,
+            default,
+        }))
+    }
+
+    // Answers the pending dialog with MARS's results, then lets the program continue past the syscall.
+    pub fn provide_dialog_result(&self, response: DialogResponse) -> Result<(), UnitDeviceError> {
+        let request = self.pending_dialog()?.ok_or(UnitDeviceError::NoPendingDialog)?;
+
+        let invalid = || UnitDeviceError::InvalidDialogResponse(response.clone());
+
+        match &request.kind {
+            DialogKind::Confirm => {
+                let choice = match response {
+                    DialogResponse::Yes => 0,
+                    DialogResponse::No => 1,
+                    DialogResponse::Cancel => 2,
+                    _ => return Err(invalid()),
+                };
+
+                self.set(A0, choice)
+            }
+            DialogKind::InputInt => {
+                let status = match &response {
+                    DialogResponse::Cancel => STATUS_CANCELLED,
+                    DialogResponse::Ok => STATUS_EMPTY,
+                    DialogResponse::Text(text) if text.is_empty() => STATUS_EMPTY,
+                    DialogResponse::Text(text) => match text.trim().parse::<i32>() {
+                        Ok(value) => {
+                            self.set(A0, value as u32);
+
+                            STATUS_OK
+                        }
+                        Err(_) => STATUS_INVALID,
+                    },
+                    _ => return Err(invalid()),
+                };
+
+                self.set(A1, status as u32)
+            }
+            DialogKind::InputString { buffer, capacity } => {
+                let status = match &response {
+                    DialogResponse::Cancel => STATUS_CANCELLED,
+                    DialogResponse::Ok => STATUS_EMPTY,
+                    DialogResponse::Text(text) if text.is_empty() => STATUS_EMPTY,
+                    DialogResponse::Text(text) => {
+                        // Like MARS: at most capacity - 1 bytes, a newline if there's room, then a null.
+                        let room = capacity.saturating_sub(1) as usize;
+                        let mut bytes: Vec<u8> = text.bytes().take(room).collect();
+
+                        if text.len() < room {
+                            bytes.push(b'\n')
+                        }
+
+                        bytes.push(0);
+
+                        self.set_data(*buffer, bytes).map_err(|error| UnitDeviceError::InvalidInstruction(error, vec![]))?;
+
+                        if text.len() > room { STATUS_TRUNCATED } else { STATUS_OK }
+                    }
+                    _ => return Err(invalid()),
+                };
+
+                self.set(A1, status as u32)
+            }
+            // Nothing comes back from a message, however it was closed.
+            DialogKind::Message(_) | DialogKind::MessageInt(_) | DialogKind::MessageString(_) => {}
+        }
+
+        self.executor.syscall_handled();
+
+        Ok(())
+    }
+
+    // Runs through handle_terminal_syscall when $v0 is one of DIALOG_SYSCALLS.
+    pub fn handle_dialog_syscall(&self) -> Result<TerminalSyscall, UnitDeviceError> {
+        let Some(request) = self.pending_dialog()? else {
+            return Err(UnitDeviceError::NoPendingDialog)
+        };
+
+        let response = self.dialogs.borrow_mut().next(&request);
+
+        match response {
+            Some(response) => {
+                self.provide_dialog_result(response)?;
+
+                Ok(TerminalSyscall::Handled)
+            }
+            None => Ok(TerminalSyscall::AwaitingDialog),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use crate::assembler::string::assemble_from;
+    use crate::unit::device::{UnitDevice, UnitDeviceError};
+    use crate::unit::dialog::{DialogBehavior, DialogKind, DialogResponse};
+    use crate::unit::terminal::OutputWait;
+
+    // Shows a dialog, then prints $a0 and $a1 with a space between.
+    fn dialog(v0: u32, setup: &str) -> UnitDevice {
+        UnitDevice::new(assemble_from(&format!("
+            .data
+            question: .asciiz \"Continue?\"
+            buffer: .space 8
+            .text
+                la $a0, question
+                {setup}
+                li $v0, {v0}
+                syscall
+                move $t0, $a1
+                li $v0, 1
+                syscall
+                li $a0, ' '
+                li $v0, 11
+                syscall
+                move $a0, $t0
+                li $v0, 1
+                syscall
+                li $v0, 10
+                syscall
+        ")).unwrap())
+    }
+
+    fn scripted(responses: &[DialogResponse]) -> DialogBehavior {
+        DialogBehavior::Scripted(VecDeque::from(responses.to_vec()))
+    }
+
+    #[test]
+    fn confirm_waits_for_the_frontend() {
+        let device = dialog(50, "li $a1, 9");
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::AwaitingDialog);
+
+        let request = device.pending_dialog().unwrap().unwrap();
+
+        assert_eq!(request.kind, DialogKind::Confirm);
+        assert_eq!(request.message, "Continue?");
+        assert_eq!(request.message_address, device.binary.labels["question"]);
+        assert_eq!(request.default, DialogResponse::Cancel);
+
+        // Confirm only takes yes, no and cancel.
+        assert!(matches!(device.provide_dialog_result(DialogResponse::Ok), Err(UnitDeviceError::InvalidDialogResponse(_))));
+
+        device.provide_dialog_result(DialogResponse::No).unwrap();
+
+        assert!(device.pending_dialog().unwrap().is_none());
+        assert!(matches!(device.provide_dialog_result(DialogResponse::No), Err(UnitDeviceError::NoPendingDialog)));
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert_eq!(device.stdout(), "1 9");
+    }
+
+    #[test]
+    fn scripted_input_int() {
+        let device = dialog(51, "");
+
+        device.dialogs.replace(scripted(&[DialogResponse::Text("42".into())]));
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert_eq!(device.stdout(), "42 0");
+
+        // Without a value $a0 keeps the message address, only the status matters.
+        for (response, status) in [
+            (DialogResponse::Text("forty".into()), "-1"),
+            (DialogResponse::Text("".into()), "-3"),
+            (DialogResponse::Ok, "-3"),
+            (DialogResponse::Cancel, "-2"),
+        ] {
+            let device = dialog(51, "");
+
+            device.dialogs.replace(scripted(std::slice::from_ref(&response)));
+
+            assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+            assert!(device.stdout().ends_with(&format!(" {status}")), "{response:?}");
+        }
+    }
+
+    #[test]
+    fn input_strings_fill_the_buffer() {
+        for (text, status, stored) in [
+            ("hi", "0", &b"hi\n\0"[..]),
+            ("1234567", "0", &b"1234567\0"[..]),
+            ("123456789", "-4", &b"1234567\0"[..]),
+        ] {
+            let device = dialog(54, "la $a1, buffer\nli $a2, 8");
+
+            device.dialogs.replace(scripted(&[DialogResponse::Text(text.into())]));
+
+            assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+            assert!(device.stdout().ends_with(&format!(" {status}")), "{text}");
+
+            let buffer = device.binary.labels["buffer"];
+
+            assert_eq!(device.get_data(buffer, stored.len() as u32).unwrap(), stored, "{text}");
+        }
+    }
+
+    #[test]
+    fn cancel_answers_every_dialog_with_its_default() {
+        let device = dialog(51, "");
+
+        device.dialogs.replace(DialogBehavior::Cancel);
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert!(device.stdout().ends_with(" -2"));
+
+        // Scripts cancel once they run out.
+        let device = dialog(50, "");
+
+        device.dialogs.replace(scripted(&[]));
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert!(device.stdout().starts_with("2 "));
+    }
+}
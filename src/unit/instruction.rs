@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use crate::cpu::decoder::Decoder;
 use crate::unit::register::RegisterName;
@@ -62,12 +63,16 @@ pub enum Instruction {
     Sb { s: RegisterName, t: RegisterName, imm: u16 },
     Sh { s: RegisterName, t: RegisterName, imm: u16 },
     Sw { s: RegisterName, t: RegisterName, imm: u16 },
+    Ll { s: RegisterName, t: RegisterName, imm: u16 },
+    Sc { s: RegisterName, t: RegisterName, imm: u16 },
     Mfhi { d: RegisterName },
     Mflo { d: RegisterName },
     Mthi { s: RegisterName },
     Mtlo { s: RegisterName },
     Trap,
     Syscall,
+    Break { code: u32 },
+    Sync,
 }
 
 pub fn sig(imm: u16) -> String {
@@ -95,11 +100,11 @@ pub fn sig_u32(imm: u32) -> String {
 }
 
 fn jump_dest(pc: u32, imm: u32) -> u32 {
-    ((pc + 4) & 0xFC000000) | (imm << 2)
+    (pc.wrapping_add(4) & 0xF0000000) | (imm << 2)
 }
 
 fn rel_dest(pc: u32, imm: u16) -> u32 {
-    ((pc + 4) as i32 + ((imm as i16 as i32) << 2)) as u32
+    pc.wrapping_add(4).wrapping_add(((imm as i16 as i32) << 2) as u32)
 }
 
 impl From<u8> for RegisterName {
@@ -339,6 +344,14 @@ impl Decoder<Instruction> for InstructionDecoder {
         Instruction::Sw { s: s.into(), t: t.into(), imm }
     }
 
+    fn ll(&mut self, s: u8, t: u8, imm: u16) -> Instruction {
+        Instruction::Ll { s: s.into(), t: t.into(), imm }
+    }
+
+    fn sc(&mut self, s: u8, t: u8, imm: u16) -> Instruction {
+        Instruction::Sc { s: s.into(), t: t.into(), imm }
+    }
+
     fn mfhi(&mut self, d: u8) -> Instruction {
         Instruction::Mfhi { d: d.into() }
     }
@@ -362,8 +375,19 @@ impl Decoder<Instruction> for InstructionDecoder {
     fn syscall(&mut self) -> Instruction {
         Instruction::Syscall
     }
+
+    fn break_(&mut self, code: u32) -> Instruction {
+        Instruction::Break { code }
+    }
+
+    fn sync(&mut self) -> Instruction {
+        Instruction::Sync
+    }
 }
 
+// Address is always a resolved target (branches are decoded with their pc), never a raw offset,
+// so it's safe to navigate to. There's no FPU here, so no condition code parameters either.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum InstructionParameter {
     Register(RegisterName),
     Immediate(u16),
@@ -371,6 +395,12 @@ pub enum InstructionParameter {
     Offset(u16, RegisterName)
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolizedParameter {
+    Parameter(InstructionParameter),
+    Label(String, u32), // an address with a label right at it
+}
+
 impl From<RegisterName> for InstructionParameter {
     fn from(value: RegisterName) -> Self {
         Register(value)
@@ -435,12 +465,16 @@ impl Instruction {
             Instruction::Sb { .. } => "sb",
             Instruction::Sh { .. } => "sh",
             Instruction::Sw { .. } => "sw",
+            Instruction::Ll { .. } => "ll",
+            Instruction::Sc { .. } => "sc",
             Instruction::Mfhi { .. } => "mfhi",
             Instruction::Mflo { .. } => "mflo",
             Instruction::Mthi { .. } => "mthi",
             Instruction::Mtlo { .. } => "mtlo",
-            Instruction::Trap { .. } => "trap",
-            Instruction::Syscall { .. } => "syscall",
+            Instruction::Trap => "trap",
+            Instruction::Syscall => "syscall",
+            Instruction::Break { .. } => "break",
+            Instruction::Sync => "sync",
         }
     }
 
@@ -456,11 +490,11 @@ impl Instruction {
             Instruction::Nor { s, t, d } => vec![d.into(), s.into(), t.into()],
             Instruction::Or { s, t, d } => vec![d.into(), s.into(), t.into()],
             Instruction::Sll { t, d, sham } => vec![d.into(), t.into(), Immediate(sham as u16)],
-            Instruction::Sllv { s, t, d } =>  vec![d.into(), s.into(), t.into()],
+            Instruction::Sllv { s, t, d } => vec![d.into(), t.into(), s.into()],
             Instruction::Sra { t, d, sham } => vec![d.into(), t.into(), Immediate(sham as u16)],
-            Instruction::Srav { s, t, d } => vec![d.into(), s.into(), t.into()],
+            Instruction::Srav { s, t, d } => vec![d.into(), t.into(), s.into()],
             Instruction::Srl { t, d, sham } => vec![d.into(), t.into(), Immediate(sham as u16)],
-            Instruction::Srlv { s, t, d } => vec![d.into(), s.into(), t.into()],
+            Instruction::Srlv { s, t, d } => vec![d.into(), t.into(), s.into()],
             Instruction::Sub { s, t, d } => vec![d.into(), s.into(), t.into()],
             Instruction::Subu { s, t, d } => vec![d.into(), s.into(), t.into()],
             Instruction::Xor { s, t, d } => vec![d.into(), s.into(), t.into()],
@@ -493,27 +527,131 @@ impl Instruction {
             Instruction::Bgezal { s, address } => vec![s.into(), Address(address)],
             Instruction::J { address } => vec![Address(address)],
             Instruction::Jal { address } => vec![Address(address)],
-            Instruction::Lb { s, t, imm } => vec![s.into(), Offset(imm, t)],
-            Instruction::Lbu { s, t, imm } => vec![s.into(), Offset(imm, t)],
-            Instruction::Lh { s, t, imm } => vec![s.into(), Offset(imm, t)],
-            Instruction::Lhu { s, t, imm } => vec![s.into(), Offset(imm, t)],
-            Instruction::Lw { s, t, imm } => vec![s.into(), Offset(imm, t)],
-            Instruction::Sb { s, t, imm } => vec![s.into(), Offset(imm, t)],
-            Instruction::Sh { s, t, imm } => vec![s.into(), Offset(imm, t)],
-            Instruction::Sw { s, t, imm } => vec![s.into(), Offset(imm, t)],
+            Instruction::Lb { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Lbu { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Lh { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Lhu { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Lw { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Sb { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Sh { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Sw { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Ll { s, t, imm } => vec![t.into(), Offset(imm, s)],
+            Instruction::Sc { s, t, imm } => vec![t.into(), Offset(imm, s)],
             Instruction::Mfhi { d } => vec![d.into()],
             Instruction::Mflo { d } => vec![d.into()],
             Instruction::Mthi { s } => vec![s.into()],
             Instruction::Mtlo { s } => vec![s.into()],
             Instruction::Trap => vec![],
             Instruction::Syscall => vec![],
+            Instruction::Break { .. } => vec![],
+            Instruction::Sync => vec![],
+        }
+    }
+
+    // Branch or jump target.
+    pub fn address(&self) -> Option<u32> {
+        match self.clone().parameters().last() {
+            Some(Address(address)) => Some(*address),
+            _ => None,
+        }
+    }
+
+    // Same as parameters, with addresses that have a label named (ex. from UnitDevice::address_labels).
+    pub fn parameters_with_labels(&self, labels: &HashMap<u32, String>) -> Vec<SymbolizedParameter> {
+        self.clone().parameters().into_iter()
+            .map(|parameter| match parameter {
+                Address(address) if labels.contains_key(&address) => {
+                    SymbolizedParameter::Label(labels[&address].clone(), address)
+                }
+                parameter => SymbolizedParameter::Parameter(parameter),
+            })
+            .collect()
+    }
+
+    // Displays as `j main` instead of `j 0x400000` when the target has a label.
+    pub fn display_with<'a>(&'a self, labels: &'a HashMap<u32, String>) -> LabeledInstruction<'a> {
+        LabeledInstruction { instruction: self, labels }
+    }
+
+    // General purpose registers the instruction reads (hi/lo aren't included).
+    // Syscalls read $v0 and the argument registers.
+    pub fn reads(&self) -> Vec<RegisterName> {
+        use Instruction::*;
+
+        match self {
+            Add { s, t, .. } | Addu { s, t, .. } | And { s, t, .. } | Nor { s, t, .. } | Or { s, t, .. }
+            | Sllv { s, t, .. } | Srav { s, t, .. } | Srlv { s, t, .. } | Sub { s, t, .. } | Subu { s, t, .. }
+            | Xor { s, t, .. } | Slt { s, t, .. } | Sltu { s, t, .. } | Mul { s, t, .. }
+            | Div { s, t } | Divu { s, t } | Mult { s, t } | Multu { s, t }
+            | Madd { s, t } | Maddu { s, t } | Msub { s, t } | Msubu { s, t }
+            | Beq { s, t, .. } | Bne { s, t, .. }
+            | Sb { s, t, .. } | Sh { s, t, .. } | Sw { s, t, .. } | Sc { s, t, .. } => vec![*s, *t],
+
+            Sll { t, .. } | Sra { t, .. } | Srl { t, .. } => vec![*t],
+
+            Jr { s } | Jalr { s } | Mthi { s } | Mtlo { s }
+            | Addi { s, .. } | Addiu { s, .. } | Andi { s, .. } | Ori { s, .. } | Xori { s, .. }
+            | Slti { s, .. } | Sltiu { s, .. }
+            | Bgtz { s, .. } | Blez { s, .. } | Bltz { s, .. } | Bgez { s, .. } | Bltzal { s, .. } | Bgezal { s, .. }
+            | Lb { s, .. } | Lbu { s, .. } | Lh { s, .. } | Lhu { s, .. } | Lw { s, .. } | Ll { s, .. } => vec![*s],
+
+            Syscall => vec![
+                RegisterName::V0, RegisterName::A0, RegisterName::A1, RegisterName::A2, RegisterName::A3
+            ],
+
+            Lui { .. } | Lhi { .. } | Llo { .. } | J { .. } | Jal { .. } | Mfhi { .. } | Mflo { .. } | Trap
+            | Break { .. } | Sync => vec![],
+        }
+    }
+
+    // General purpose register the instruction writes, if any (links write $ra, even when not taken).
+    pub fn writes(&self) -> Option<RegisterName> {
+        use Instruction::*;
+
+        match self {
+            Add { d, .. } | Addu { d, .. } | And { d, .. } | Nor { d, .. } | Or { d, .. }
+            | Sll { d, .. } | Sllv { d, .. } | Sra { d, .. } | Srav { d, .. } | Srl { d, .. } | Srlv { d, .. }
+            | Sub { d, .. } | Subu { d, .. } | Xor { d, .. } | Slt { d, .. } | Sltu { d, .. } | Mul { d, .. }
+            | Mfhi { d } | Mflo { d } => Some(*d),
+
+            Addi { t, .. } | Addiu { t, .. } | Andi { t, .. } | Ori { t, .. } | Xori { t, .. }
+            | Slti { t, .. } | Sltiu { t, .. } | Lhi { t, .. } | Llo { t, .. }
+            | Lb { t, .. } | Lbu { t, .. } | Lh { t, .. } | Lhu { t, .. } | Lw { t, .. } | Ll { t, .. }
+            | Sc { t, .. } => Some(*t),
+
+            Lui { s, .. } => Some(*s),
+
+            Jal { .. } | Jalr { .. } | Bltzal { .. } | Bgezal { .. } => Some(RegisterName::RA),
+
+            _ => None,
         }
     }
+
+    pub fn is_load(&self) -> bool {
+        matches!(self,
+            Instruction::Lb { .. } | Instruction::Lbu { .. } | Instruction::Lh { .. }
+            | Instruction::Lhu { .. } | Instruction::Lw { .. } | Instruction::Ll { .. })
+    }
+
+    pub fn is_store(&self) -> bool {
+        matches!(self, Instruction::Sb { .. } | Instruction::Sh { .. } | Instruction::Sw { .. } | Instruction::Sc { .. })
+    }
+
+    // Branches and jumps.
+    pub fn is_control(&self) -> bool {
+        matches!(self,
+            Instruction::Jr { .. } | Instruction::Jalr { .. } | Instruction::J { .. } | Instruction::Jal { .. }
+            | Instruction::Beq { .. } | Instruction::Bne { .. } | Instruction::Bgtz { .. } | Instruction::Blez { .. }
+            | Instruction::Bltz { .. } | Instruction::Bgez { .. } | Instruction::Bltzal { .. } | Instruction::Bgezal { .. })
+    }
 }
 
+// Output is valid assembler input: re-assembling it at the same address yields the same word.
+// The zero word shows as nop, use {:#} to get sll $zero, $zero, 0 instead.
 impl Display for Instruction {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
+            Instruction::Sll { t: RegisterName::Zero, d: RegisterName::Zero, sham: 0 } if !f.alternate() => write!(f, "nop"),
             Instruction::Add { s, t, d } => write!(f, "add {}, {}, {}", d, s, t),
             Instruction::Addu { s, t, d } => write!(f, "addu {}, {}, {}", d, s, t),
             Instruction::And { s, t, d } => write!(f, "and {}, {}, {}", d, s, t),
@@ -529,11 +667,11 @@ impl Display for Instruction {
             Instruction::Srav { s, t, d } => write!(f, "srav {}, {}, {}", d, t, s),
             Instruction::Srl { t, d, sham } => write!(f, "srl {}, {}, {}", d, t, sham),
             Instruction::Srlv { s, t, d } => write!(f, "srlv {}, {}, {}", d, t, s),
-            Instruction::Sub { s, t, d } => write!(f, "sub {}, {}, {}", s, t, d),
-            Instruction::Subu { s, t, d } => write!(f, "subu {}, {}, {}", s, t, d),
-            Instruction::Xor { s, t, d } => write!(f, "xor {}, {}, {}", s, t, d),
-            Instruction::Slt { s, t, d } => write!(f, "slt {}, {}, {}", s, t, d),
-            Instruction::Sltu { s, t, d } => write!(f, "sltu {}, {}, {}", s, t, d),
+            Instruction::Sub { s, t, d } => write!(f, "sub {}, {}, {}", d, s, t),
+            Instruction::Subu { s, t, d } => write!(f, "subu {}, {}, {}", d, s, t),
+            Instruction::Xor { s, t, d } => write!(f, "xor {}, {}, {}", d, s, t),
+            Instruction::Slt { s, t, d } => write!(f, "slt {}, {}, {}", d, s, t),
+            Instruction::Sltu { s, t, d } => write!(f, "sltu {}, {}, {}", d, s, t),
             Instruction::Jr { s } => write!(f, "jr {}", s),
             Instruction::Jalr { s } => write!(f, "jalr {}", s),
             Instruction::Madd { s, t } => write!(f, "madd {}, {}", s, t),
@@ -543,9 +681,9 @@ impl Display for Instruction {
             Instruction::Msubu { s, t } => write!(f, "msubu {}, {}", s, t),
             Instruction::Addi { s, t, imm } => write!(f, "addi {}, {}, {}", t, s, sig(*imm)),
             Instruction::Addiu { s, t, imm } => write!(f, "addiu {}, {}, {}", t, s, sig(*imm)),
-            Instruction::Andi { s, t, imm } => write!(f, "andi {}, {}, {}", t, s, sig(*imm)),
-            Instruction::Ori { s, t, imm } => write!(f, "ori {}, {}, {}", t, s, sig(*imm)),
-            Instruction::Xori { s, t, imm } => write!(f, "xori {}, {}, {}", t, s, sig(*imm)),
+            Instruction::Andi { s, t, imm } => write!(f, "andi {}, {}, 0x{:x}", t, s, imm),
+            Instruction::Ori { s, t, imm } => write!(f, "ori {}, {}, 0x{:x}", t, s, imm),
+            Instruction::Xori { s, t, imm } => write!(f, "xori {}, {}, 0x{:x}", t, s, imm),
             Instruction::Lui { s, imm } => write!(f, "lui {}, {}", s, sig(*imm)),
             Instruction::Lhi { t, imm } => write!(f, "lhi {}, {}", t, sig(*imm)),
             Instruction::Llo { t, imm } => write!(f, "llo {}, {}", t, sig(*imm)),
@@ -569,12 +707,82 @@ impl Display for Instruction {
             Instruction::Sb { s, t, imm } => write!(f, "sb {}, {}({})", t, sig(*imm), s),
             Instruction::Sh { s, t, imm } => write!(f, "sh {}, {}({})", t, sig(*imm), s),
             Instruction::Sw { s, t, imm } => write!(f, "sw {}, {}({})", t, sig(*imm), s),
+            Instruction::Ll { s, t, imm } => write!(f, "ll {}, {}({})", t, sig(*imm), s),
+            Instruction::Sc { s, t, imm } => write!(f, "sc {}, {}({})", t, sig(*imm), s),
             Instruction::Mfhi { d } => write!(f, "mfhi {}", d),
             Instruction::Mflo { d } => write!(f, "mflo {}", d),
             Instruction::Mthi { s } => write!(f, "mthi {}", s),
             Instruction::Mtlo { s } => write!(f, "mtlo {}", s),
             Instruction::Trap => write!(f, "trap"),
             Instruction::Syscall => write!(f, "syscall"),
+            Instruction::Break { code: 0 } => write!(f, "break"),
+            Instruction::Break { code } => write!(f, "break {}", code),
+            Instruction::Sync => write!(f, "sync"),
         }
     }
 }
+
+pub struct LabeledInstruction<'a> {
+    instruction: &'a Instruction,
+    labels: &'a HashMap<u32, String>,
+}
+
+impl Display for LabeledInstruction<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let text = self.instruction.to_string();
+
+        // The target is always written last.
+        let labeled = self.instruction.address()
+            .and_then(|address| Some((text.strip_suffix(&format!("0x{address:x}"))?, self.labels.get(&address)?)));
+
+        match labeled {
+            Some((start, label)) => write!(f, "{start}{label}"),
+            None => write!(f, "{text}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::unit::instruction::InstructionParameter::{Address, Offset, Register};
+    use crate::unit::instruction::{Instruction, InstructionDecoder, SymbolizedParameter};
+    use crate::unit::register::RegisterName::{SP, T0, Zero};
+    use std::collections::HashMap;
+
+    fn labels() -> HashMap<u32, String> {
+        HashMap::from([(0x400000, "main".to_string()), (0x400010, "loop".to_string())])
+    }
+
+    #[test]
+    fn targets_with_labels_are_named() {
+        let jump = InstructionDecoder::decode(0x400020, 0x08100000).unwrap(); // j 0x400000
+
+        assert_eq!(jump.display_with(&labels()).to_string(), "j main");
+        assert_eq!(jump.parameters_with_labels(&labels()), [SymbolizedParameter::Label("main".into(), 0x400000)]);
+
+        // beq $t0, $zero, 0x400010 from 0x400000.
+        let branch = Instruction::Beq { s: T0, t: Zero, address: 0x400010 };
+
+        assert_eq!(branch.display_with(&labels()).to_string(), "beq $t0, $zero, loop");
+        assert_eq!(branch.parameters_with_labels(&labels()), [
+            SymbolizedParameter::Parameter(Register(T0)),
+            SymbolizedParameter::Parameter(Register(Zero)),
+            SymbolizedParameter::Label("loop".into(), 0x400010),
+        ]);
+    }
+
+    #[test]
+    fn other_parameters_stay_as_they_are() {
+        // Only an exact match is named.
+        let jump = Instruction::Jal { address: 0x400004 };
+
+        assert_eq!(jump.display_with(&labels()).to_string(), jump.to_string());
+        assert_eq!(jump.parameters_with_labels(&labels()), [SymbolizedParameter::Parameter(Address(0x400004))]);
+
+        // An offset that happens to equal a labeled address isn't one.
+        let load = Instruction::Lw { s: SP, t: T0, imm: 0x10 };
+
+        assert_eq!(load.display_with(&HashMap::from([(0x10, "small".to_string())])).to_string(), load.to_string());
+        assert_eq!(load.parameters_with_labels(&labels())[1], SymbolizedParameter::Parameter(Offset(0x10, SP)));
+    }
+}
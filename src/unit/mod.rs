@@ -1,4 +1,8 @@
+pub mod console;
 pub mod device;
+pub mod dialog;
 pub mod instruction;
 pub mod register;
+pub mod spec;
 pub mod suggestions;
+pub mod terminal;
@@ -14,7 +14,7 @@ pub enum RegisterName {
 }
 
 impl RegisterName {
-    fn to_str(&self) -> &str {
+    fn to_str(self) -> &'static str {
         match self {
             RegisterName::Zero => "zero",
             RegisterName::AT => "at",
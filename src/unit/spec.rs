@@ -0,0 +1,474 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use crate::assembler::lexer::lex_recovering;
+use crate::assembler::lexer::TokenKind::Comment;
+use crate::cpu::error::Error as CpuError;
+use crate::execution::executor::ExecutorMode::{Finished, Invalid};
+use crate::unit::device::{StopCondition, UnitDevice};
+use crate::unit::terminal::TerminalSyscall;
+use crate::unit::spec::Operand::{Constant, Label, Memory, Register};
+
+// Test specs for the CLI `test` command (and anything else that wants to grade a program).
+// Written as `#!` comments in the source, or one per line in a sidecar file:
+//   #! assert $v0 == 42
+//   #! assert word[result] == 7       (also half[...], byte[...], with label+offset or an address)
+//   #! assert $t0 < -1                (comparisons are signed, byte/half at their own width)
+//   #! max-steps 100000
+// The program runs until it exits (syscall 10 or 17) or runs off the end of its code,
+// then every assertion is checked.
+
+const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct SpecError {
+    pub line: usize, // 1 based
+    pub message: String,
+}
+
+impl Display for SpecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Test spec line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for SpecError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Clone, Debug)]
+pub enum Address {
+    Constant(u32),
+    Label(String, i64), // name, offset
+}
+
+#[derive(Clone, Debug)]
+pub enum Operand {
+    Register(String), // $v0, $pc, $hi, $lo, $8
+    Memory(usize, Address), // width in bytes
+    Label(String), // address of a label
+    Constant(u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct Assertion {
+    pub line: usize,
+    pub text: String,
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TestSpec {
+    pub assertions: Vec<Assertion>,
+    pub max_steps: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+    pub passed: bool,
+    pub message: String, // values seen, or why they couldn't be read
+}
+
+#[derive(Clone, Debug)]
+pub struct TestReport {
+    pub results: Vec<AssertionResult>,
+    pub output: String, // printed through syscalls
+    pub steps: u64,
+    pub exit_code: Option<u32>, // from syscall 17
+    pub error: Option<String>, // the program didn't finish properly, assertions are checked anyway
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.results.iter().all(|result| result.passed)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|result| !result.passed).count()
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+        })
+    }
+}
+
+impl Comparison {
+    fn check(&self, left: u32, right: u32, width: usize) -> bool {
+        // Narrow reads are compared at their own width, so byte[x] == 0xfd and byte[x] == -3 both work.
+        let shift = 32 - 8 * width as u32;
+        let (a, b) = ((left << shift) as i32 >> shift, (right << shift) as i32 >> shift);
+
+        match self {
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+            Comparison::Less => a < b,
+            Comparison::LessEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterEqual => a >= b,
+        }
+    }
+}
+
+fn parse_integer(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn parse_address(text: &str) -> Option<Address> {
+    if let Some(value) = parse_integer(text) {
+        return Some(Address::Constant(value as u32))
+    }
+
+    let (name, offset) = match text.find(['+', '-']) {
+        Some(index) => (&text[..index], parse_integer(text[index..].trim_start_matches('+'))?),
+        None => (text, 0),
+    };
+
+    let name = name.trim();
+
+    (!name.is_empty()).then(|| Address::Label(name.to_string(), offset))
+}
+
+fn parse_operand(text: &str) -> Option<Operand> {
+    let text = text.trim();
+
+    if let Some(name) = text.strip_prefix('$') {
+        return Some(Register(name.to_string()))
+    }
+
+    for (prefix, width) in [("word[", 4), ("half[", 2), ("byte[", 1)] {
+        if let Some(inner) = text.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(']')) {
+            return Some(Memory(width, parse_address(&inner.replace(' ', ""))?))
+        }
+    }
+
+    if let Some(value) = parse_integer(text) {
+        return Some(Constant(value as u32))
+    }
+
+    let valid = text.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+
+    (valid && !text.is_empty()).then(|| Label(text.to_string()))
+}
+
+fn parse_assertion(line: usize, text: &str) -> Result<Assertion, SpecError> {
+    let error = |message: String| SpecError { line, message };
+
+    // Two character operators first, so <= isn't read as <.
+    let operators = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessEqual),
+        (">=", Comparison::GreaterEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    let Some((index, symbol, comparison)) = operators.iter()
+        .find_map(|(symbol, comparison)| text.find(symbol).map(|index| (index, *symbol, *comparison))) else {
+        return Err(error(format!("Expected a comparison in \"{text}\"")))
+    };
+
+    let (left, right) = (&text[..index], &text[index + symbol.len()..]);
+
+    let left = parse_operand(left).ok_or_else(|| error(format!("Could not read \"{}\"", left.trim())))?;
+    let right = parse_operand(right).ok_or_else(|| error(format!("Could not read \"{}\"", right.trim())))?;
+
+    if let (Memory(left, _), Memory(right, _)) = (&left, &right) {
+        if left != right {
+            return Err(error(format!("Compares a {left} byte value with a {right} byte value in \"{text}\"")))
+        }
+    }
+
+    Ok(Assertion { line, text: text.to_string(), left, comparison, right })
+}
+
+impl Operand {
+    fn width(&self) -> usize {
+        match self {
+            Memory(width, _) => *width,
+            _ => 4,
+        }
+    }
+}
+
+impl Assertion {
+    // Both sides are read at the memory operand's width, two memory operands always have the same one.
+    fn width(&self) -> usize {
+        self.left.width().min(self.right.width())
+    }
+}
+
+impl TestSpec {
+    fn parse_directive(&mut self, line: usize, text: &str) -> Result<(), SpecError> {
+        let text = text.trim();
+        let (name, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+
+        match name {
+            "assert" => self.assertions.push(parse_assertion(line, rest.trim())?),
+            "max-steps" => {
+                let value = parse_integer(rest.trim()).filter(|value| *value > 0).ok_or_else(|| SpecError {
+                    line, message: format!("Expected a step count, found \"{}\"", rest.trim())
+                })?;
+
+                self.max_steps = Some(value as u64)
+            }
+            _ => return Err(SpecError { line, message: format!("Unknown test directive \"{name}\"") })
+        }
+
+        Ok(())
+    }
+
+    // Comments starting with `#!` in assembly source, not `#!` in a string or the middle of a comment.
+    // A `#!/` shebang on the first line is left alone.
+    pub fn from_source(source: &str) -> Result<TestSpec, SpecError> {
+        let mut spec = TestSpec::default();
+
+        // Lines that don't lex are skipped, the assembler reports them.
+        let (tokens, _) = lex_recovering(source, 0);
+
+        for token in tokens {
+            let Comment(text) = token.kind else { continue };
+            let Some(directive) = text.strip_prefix('!') else { continue };
+
+            if token.location.index == 0 && directive.starts_with('/') {
+                continue
+            }
+
+            let line = source[..token.location.index].matches('\n').count() + 1;
+
+            spec.parse_directive(line, directive)?
+        }
+
+        Ok(spec)
+    }
+
+    // A sidecar file, one directive per line. Blank lines and `#` comments are skipped, `#!` is optional.
+    pub fn parse(text: &str) -> Result<TestSpec, SpecError> {
+        let mut spec = TestSpec::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let line = line.strip_prefix("#!").unwrap_or(line);
+
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue
+            }
+
+            spec.parse_directive(index + 1, line)?
+        }
+
+        Ok(spec)
+    }
+
+    pub fn merge(&mut self, other: TestSpec) {
+        self.assertions.extend(other.assertions);
+        self.max_steps = other.max_steps.or(self.max_steps);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assertions.is_empty() && self.max_steps.is_none()
+    }
+}
+
+impl UnitDevice {
+    fn read_operand(&self, operand: &Operand) -> Result<u32, String> {
+        let registers = self.registers();
+
+        let address = |address: &Address| match address {
+            Address::Constant(value) => Ok(*value),
+            Address::Label(name, offset) => self.binary.labels.get(name)
+                .map(|value| (*value as i64 + offset) as u32)
+                .ok_or_else(|| format!("No label named {name}")),
+        };
+
+        match operand {
+            Register(name) => match name.as_str() {
+                "pc" => Ok(registers.pc),
+                "hi" => Ok(registers.hi),
+                "lo" => Ok(registers.lo),
+                _ => UnitDevice::register_named(name)
+                    .map(|register| registers.get(register))
+                    .map_err(|_| format!("No register named ${name}")),
+            },
+            Memory(width, target) => {
+                let address = address(target)?;
+                let data = self.get_data(address, *width as u32).map_err(|error| error.to_string())?;
+
+                let mut word = [0u8; 4];
+                word[..*width].copy_from_slice(&data);
+
+                Ok(u32::from_le_bytes(word))
+            }
+            Label(name) => address(&Address::Label(name.clone(), 0)),
+            Constant(value) => Ok(*value),
+        }
+    }
+
+    // Runs until the program exits, then checks every assertion.
+    pub fn run_test(&self, spec: &TestSpec) -> TestReport {
+        let max_steps = spec.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+        let start = self.executor.retired();
+
+        let mut exit_code = None;
+        let mut error = None;
+
+        loop {
+            let taken = self.executor.retired() - start;
+
+            if taken >= max_steps {
+                error = Some(format!("Program did not finish within {max_steps} steps"));
+
+                break
+            }
+
+            let result = self.execute_until_slice(&[
+                StopCondition::Steps((max_steps - taken) as usize),
+                StopCondition::SyscallInvoked(None),
+                StopCondition::Timeout(TIMEOUT),
+                StopCondition::Complete,
+            ]);
+
+            if let Err(failure) = result {
+                error = Some(failure.to_string());
+
+                break
+            }
+
+            let mode = self.executor.frame().mode;
+
+            if mode != Invalid(CpuError::CpuSyscall) {
+                // Ran off the end of the code, otherwise out of steps (checked above).
+                if mode == Finished {
+                    break
+                }
+
+                continue
+            }
+
+            match self.handle_terminal_syscall() {
+                Ok(TerminalSyscall::Handled) => {}
+                Ok(TerminalSyscall::AwaitingInput) => {
+                    error = Some("Program is waiting for input".to_string());
+
+                    break
+                }
+                Ok(TerminalSyscall::AwaitingDialog) => {
+                    error = Some("Program is waiting on a dialog".to_string());
+
+                    break
+                }
+                Ok(TerminalSyscall::Exit(code)) => {
+                    exit_code = code;
+
+                    break
+                }
+                Err(failure) => {
+                    error = Some(failure.to_string());
+
+                    break
+                }
+            }
+        }
+
+        let results = spec.assertions.iter()
+            .map(|assertion| {
+                let values = self.read_operand(&assertion.left)
+                    .and_then(|left| Ok((left, self.read_operand(&assertion.right)?)));
+
+                match values {
+                    Ok((left, right)) => AssertionResult {
+                        assertion: assertion.clone(),
+                        passed: assertion.comparison.check(left, right, assertion.width()),
+                        message: format!("{} {} {}", left as i32, assertion.comparison, right as i32),
+                    },
+                    Err(message) => AssertionResult { assertion: assertion.clone(), passed: false, message },
+                }
+            })
+            .collect();
+
+        TestReport {
+            results,
+            output: self.terminal_output(),
+            steps: self.executor.retired() - start,
+            exit_code,
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::unit::device::UnitDevice;
+    use crate::unit::spec::TestSpec;
+
+    #[test]
+    fn directives_start_a_comment() {
+        let source = "#!/usr/bin/env titan\n\
+            .data\n\
+            text: .asciiz \"#! assert $v0 == 1\"\n\
+            .text\n\
+            li $v0, 5 # not #! assert $v0 == 2\n\
+            #! assert $v0 == 5\n\
+            \t#!max-steps 10\n";
+
+        let spec = TestSpec::from_source(source).unwrap();
+
+        assert_eq!(spec.assertions.len(), 1);
+        assert_eq!(spec.assertions[0].line, 6);
+        assert_eq!(spec.max_steps, Some(10));
+
+        let report = UnitDevice::new(assemble_from(source).unwrap()).run_test(&spec);
+
+        assert!(report.passed(), "{report:?}");
+    }
+
+    #[test]
+    fn memory_widths_must_match() {
+        let error = TestSpec::parse("assert byte[a] == half[b]").unwrap_err();
+
+        assert_eq!(error.line, 1);
+        assert!(error.message.starts_with("Compares a 1 byte value with a 2 byte value"), "{}", error.message);
+
+        // Against a register or a constant, the memory operand's width is used.
+        let source = ".data\nvalue: .byte 0xfd\n.text\nli $t0, -3\n\
+            #! assert byte[value] == -3\n#! assert byte[value] == $t0\n#! assert byte[value] < 0\n";
+
+        let spec = TestSpec::from_source(source).unwrap();
+        let report = UnitDevice::new(assemble_from(source).unwrap()).run_test(&spec);
+
+        assert!(report.passed(), "{report:?}");
+    }
+}
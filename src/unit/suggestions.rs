@@ -1,7 +1,7 @@
 use std::fmt::{Display, Formatter};
 use crate::cpu::state::Registers;
 use crate::unit::instruction::{Instruction, sig, sig_u32};
-use crate::unit::instruction::Instruction::{Add, Addi, Div, Divu, Lb, Lbu, Lh, Lhu, Lw, Sb, Sh, Sub, Sw};
+use crate::unit::instruction::Instruction::{Add, Addi, Div, Divu, Lb, Lbu, Lh, Lhu, Ll, Lw, Sb, Sc, Sh, Sub, Sw};
 use crate::unit::register::RegisterName;
 use crate::unit::suggestions::TrapErrorReason::{DivByZero, OverflowAdd, OverflowOther, OverflowSub};
 
@@ -102,7 +102,9 @@ impl Instruction {
                 | Sh { s, imm, .. } =>
                 MemoryErrorDescription::new(self.clone(), reason, 2, *s, *imm, registers),
             Lw { s, imm, .. }
-                | Sw { s, imm, .. } =>
+                | Sw { s, imm, .. }
+                | Ll { s, imm, .. }
+                | Sc { s, imm, .. } =>
                 MemoryErrorDescription::new(self.clone(), reason, 4, *s, *imm, registers),
             _ => return None
         })
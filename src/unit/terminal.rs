@@ -0,0 +1,413 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::cpu::error::Error as CpuError;
+use crate::execution::executor::ExecutorMode::{Finished, Invalid};
+use crate::unit::device::{StopCondition, UnitDevice, UnitDeviceError};
+use crate::unit::dialog::DIALOG_SYSCALLS;
+use crate::unit::register::RegisterName::{A0, A1, V0};
+
+// MARS console syscalls for UnitDevice: output is captured, input comes from send_input.
+// A read with not enough input stays pending (the syscall isn't handled) until more is sent,
+// unless the input was closed (provide_stdin), then it reads what's left or fails.
+#[derive(Clone, Debug, Default)]
+pub struct Terminal {
+    pub output: String,
+    pub input: VecDeque<u8>,
+    pub input_closed: bool,
+    searched: usize, // output before this was already matched by run_until_output_contains
+    reported: Option<(u64, u32)>, // the watched syscall last returned as a Limit (retired count, pc)
+    pending: Vec<u8>, // bytes from syscall 11 that don't make up a whole UTF-8 character yet
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TerminalSyscall {
+    Handled,
+    AwaitingInput, // left pending, the program is stopped on the syscall
+    AwaitingDialog, // the same, until provide_dialog_result (see pending_dialog)
+    Exit(Option<u32>), // syscall 10, or 17 with its code
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputWait {
+    Found,
+    AwaitingInput,
+    AwaitingDialog,
+    Exited(Option<u32>),
+    Limit, // one of the limit conditions was hit first
+}
+
+impl Terminal {
+    // A line to read, or the rest of closed input.
+    fn has_line(&self) -> bool {
+        self.input.contains(&b'\n') || (self.input_closed && !self.input.is_empty())
+    }
+
+    fn awaiting_input(&self) -> Result<TerminalSyscall, UnitDeviceError> {
+        if self.input_closed {
+            Err(UnitDeviceError::ReadPastEndOfInput)
+        } else {
+            Ok(TerminalSyscall::AwaitingInput)
+        }
+    }
+
+    // Up to (and including) the next newline, or everything left.
+    fn take_line(&mut self) -> Vec<u8> {
+        let length = self.input.iter().position(|byte| *byte == b'\n')
+            .map(|index| index + 1)
+            .unwrap_or(self.input.len());
+
+        self.input.drain(..length).collect()
+    }
+
+    // Syscall 11 prints bytes, a character made of several shows up once all of them are printed.
+    // Bytes that can't be UTF-8 print as U+FFFD.
+    fn push_byte(&mut self, byte: u8) {
+        self.pending.push(byte);
+
+        loop {
+            let (end, complete) = match std::str::from_utf8(&self.pending) {
+                Ok(_) => (self.pending.len(), true),
+                Err(error) => match error.error_len() {
+                    Some(length) => (error.valid_up_to() + length, false),
+                    None => (error.valid_up_to(), true), // the rest might still be completed
+                }
+            };
+
+            let bytes: Vec<u8> = self.pending.drain(..end).collect();
+            self.output.push_str(&String::from_utf8_lossy(&bytes));
+
+            if complete {
+                break
+            }
+        }
+    }
+
+    // Anything else printed cuts off a character syscall 11 started.
+    fn push_str(&mut self, text: &str) {
+        if !self.pending.is_empty() {
+            let bytes = std::mem::take(&mut self.pending);
+            self.output.push_str(&String::from_utf8_lossy(&bytes));
+        }
+
+        self.output.push_str(text)
+    }
+
+    // Finds needle in output that hasn't been matched yet, then moves past it.
+    fn find(&mut self, needle: &str) -> bool {
+        let rest = &self.output.as_bytes()[self.searched..];
+
+        let index = if needle.is_empty() {
+            Some(0)
+        } else {
+            rest.windows(needle.len()).position(|window| window == needle.as_bytes())
+        };
+
+        if let Some(index) = index {
+            self.searched += index + needle.len()
+        }
+
+        index.is_some()
+    }
+}
+
+pub fn read_string(device: &UnitDevice, mut address: u32) -> Result<Vec<u8>, CpuError> {
+    let mut bytes = vec![];
+
+    loop {
+        let byte = device.get_data(address, 1)?[0];
+
+        if byte == 0 {
+            return Ok(bytes)
+        }
+
+        bytes.push(byte);
+        address = address.wrapping_add(1);
+    }
+}
+
+impl UnitDevice {
+    pub fn send_input(&self, text: &str) {
+        self.terminal.borrow_mut().input.extend(text.bytes())
+    }
+
+    // All of the program's input at once (ex. "5\n7\n" for two read ints). Reading past it is
+    // ReadPastEndOfInput instead of waiting for more.
+    pub fn provide_stdin(&self, text: &str) {
+        let mut terminal = self.terminal.borrow_mut();
+
+        terminal.input.extend(text.bytes());
+        terminal.input_closed = true;
+    }
+
+    pub fn terminal_output(&self) -> String {
+        self.terminal.borrow().output.clone()
+    }
+
+    // Everything printed since the start (or clear_output).
+    pub fn stdout(&self) -> String {
+        self.terminal_output()
+    }
+
+    pub fn take_terminal_output(&self) -> String {
+        let mut terminal = self.terminal.borrow_mut();
+        terminal.searched = 0;
+
+        std::mem::take(&mut terminal.output)
+    }
+
+    pub fn clear_output(&self) {
+        self.take_terminal_output();
+    }
+
+    // Runs the syscall the program is stopped on, if it's one of the console ones:
+    // print 1, 4, 11, 34, 35, 36, read 5, 8, 12 and exit 10, 17. Dialogs go to handle_dialog_syscall.
+    pub fn handle_terminal_syscall(&self) -> Result<TerminalSyscall, UnitDeviceError> {
+        if DIALOG_SYSCALLS.contains(&self.get(V0)) {
+            return self.handle_dialog_syscall()
+        }
+
+        let a0 = self.get(A0);
+        let mut terminal = self.terminal.borrow_mut();
+
+        match self.get(V0) {
+            1 => terminal.push_str(&(a0 as i32).to_string()),
+            4 => {
+                let bytes = read_string(self, a0).map_err(|error| UnitDeviceError::InvalidInstruction(error, vec![]))?;
+
+                terminal.push_str(&String::from_utf8_lossy(&bytes))
+            }
+            11 => terminal.push_byte(a0 as u8),
+            34 => terminal.push_str(&format!("0x{a0:08x}")),
+            35 => terminal.push_str(&format!("{a0:032b}")),
+            36 => terminal.push_str(&a0.to_string()),
+            5 => {
+                if !terminal.has_line() {
+                    return terminal.awaiting_input()
+                }
+
+                let line = terminal.take_line();
+                let text = String::from_utf8_lossy(&line).trim().to_string();
+
+                let value = text.parse::<i32>()
+                    .map_err(|_| UnitDeviceError::InvalidInput(text))?;
+
+                self.set(V0, value as u32)
+            }
+            8 => {
+                // Like MARS: at most a1 - 1 bytes (the newline included), then a null.
+                let capacity = self.get(A1).saturating_sub(1) as usize;
+
+                if terminal.input.len() < capacity && !terminal.has_line() {
+                    return terminal.awaiting_input()
+                }
+
+                let mut line = terminal.take_line();
+
+                if line.len() > capacity {
+                    let rest = line.split_off(capacity);
+
+                    for byte in rest.into_iter().rev() {
+                        terminal.input.push_front(byte)
+                    }
+                }
+
+                line.push(0);
+
+                self.set_data(a0, line).map_err(|error| UnitDeviceError::InvalidInstruction(error, vec![]))?
+            }
+            12 => {
+                let Some(byte) = terminal.input.pop_front() else {
+                    return terminal.awaiting_input()
+                };
+
+                self.set(V0, byte as u32)
+            }
+            10 => return Ok(TerminalSyscall::Exit(None)),
+            17 => return Ok(TerminalSyscall::Exit(Some(a0))),
+            v0 => return Err(UnitDeviceError::UnsupportedSyscall(v0)),
+        }
+
+        drop(terminal);
+
+        self.executor.syscall_handled();
+
+        Ok(TerminalSyscall::Handled)
+    }
+
+    // Runs (handling console syscalls) until the program prints needle, or waits for input, or exits.
+    // Only output after the last match is searched, so the same prompt can be waited on again.
+    // Steps and Timeout in limit count for the whole call, not every resume. The needle is checked
+    // when syscalls run, not after every instruction.
+    pub fn run_until_output_contains(
+        &self, needle: &str, limit: &[StopCondition]
+    ) -> Result<OutputWait, UnitDeviceError> {
+        self.run_terminal(Some(needle), limit)
+    }
+
+    // Runs (handling console syscalls) until the program exits, waits for input or hits a limit.
+    // A SyscallInvoked limit stops before the syscall runs, calling again runs it and goes on.
+    pub fn run_until_exit(&self, limit: &[StopCondition]) -> Result<OutputWait, UnitDeviceError> {
+        self.run_terminal(None, limit)
+    }
+
+    fn run_terminal(&self, needle: Option<&str>, limit: &[StopCondition]) -> Result<OutputWait, UnitDeviceError> {
+        let start = Instant::now();
+        let retired = self.executor.retired();
+
+        let steps = limit.iter().filter_map(|condition| match condition {
+            StopCondition::Steps(count) => Some(*count as u64),
+            _ => None,
+        }).min();
+
+        let timeout = limit.iter().filter_map(|condition| match condition {
+            StopCondition::Timeout(duration) => Some(*duration),
+            _ => None,
+        }).min();
+
+        let watched: Vec<Option<u32>> = limit.iter().filter_map(|condition| match condition {
+            StopCondition::SyscallInvoked(v0) => Some(*v0),
+            _ => None,
+        }).collect();
+
+        let mut conditions: Vec<StopCondition> = limit.iter()
+            .filter(|condition| !matches!(
+                condition, StopCondition::Steps(_) | StopCondition::Timeout(_) | StopCondition::SyscallInvoked(_)
+            ))
+            .cloned()
+            .collect();
+
+        conditions.push(StopCondition::SyscallInvoked(None));
+        conditions.push(StopCondition::Complete);
+
+        loop {
+            if needle.is_some_and(|needle| self.terminal.borrow_mut().find(needle)) {
+                return Ok(OutputWait::Found)
+            }
+
+            let frame = self.executor.frame();
+
+            if frame.mode == Invalid(CpuError::CpuSyscall) {
+                let v0 = frame.registers.get(V0);
+
+                // A watched syscall is reported once, the next call handles it.
+                if watched.iter().any(|watch| watch.map_or(true, |value| value == v0)) {
+                    let stop = Some((self.executor.retired(), frame.registers.pc));
+                    let mut terminal = self.terminal.borrow_mut();
+
+                    if terminal.reported != stop {
+                        terminal.reported = stop;
+
+                        return Ok(OutputWait::Limit)
+                    }
+                }
+
+                match self.handle_terminal_syscall()? {
+                    TerminalSyscall::Handled => continue,
+                    TerminalSyscall::AwaitingInput => return Ok(OutputWait::AwaitingInput),
+                    TerminalSyscall::AwaitingDialog => return Ok(OutputWait::AwaitingDialog),
+                    TerminalSyscall::Exit(code) => return Ok(OutputWait::Exited(code)),
+                }
+            }
+
+            if frame.mode == Finished {
+                return Ok(OutputWait::Exited(None))
+            }
+
+            let mut resume = conditions.clone();
+
+            if let Some(steps) = steps {
+                let taken = self.executor.retired() - retired;
+
+                if taken >= steps {
+                    return Ok(OutputWait::Limit)
+                }
+
+                resume.push(StopCondition::Steps((steps - taken) as usize));
+            }
+
+            if let Some(timeout) = timeout {
+                let remaining = timeout.saturating_sub(start.elapsed());
+
+                if remaining == Duration::ZERO {
+                    return Ok(OutputWait::Limit)
+                }
+
+                resume.push(StopCondition::Timeout(remaining));
+            }
+
+            match self.execute_until_slice(&resume) {
+                Err(UnitDeviceError::ExecutionTimedOut) => return Ok(OutputWait::Limit),
+                Err(error) => return Err(error),
+                Ok(()) => {}
+            }
+
+            let frame = self.executor.frame();
+
+            // Anything but a syscall or the end of the program is one of the limits.
+            if !matches!(frame.mode, Invalid(_) | Finished) {
+                return Ok(OutputWait::Limit)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::string::assemble_from;
+    use crate::unit::device::{StopCondition, UnitDevice};
+    use crate::unit::register::RegisterName::V0;
+    use crate::unit::terminal::OutputWait;
+
+    #[test]
+    fn watched_syscalls_are_reported_once() {
+        let device = UnitDevice::new(assemble_from("
+            li $v0, 1
+            li $a0, 7
+            syscall
+            li $a0, 8
+            syscall
+            li $v0, 10
+            syscall
+        ").unwrap());
+
+        let limit = [StopCondition::SyscallInvoked(Some(1))];
+
+        assert_eq!(device.run_until_exit(&limit).unwrap(), OutputWait::Limit);
+        assert_eq!(device.stdout(), "");
+        assert_eq!(device.get(V0), 1);
+
+        assert_eq!(device.run_until_exit(&limit).unwrap(), OutputWait::Limit);
+        assert_eq!(device.stdout(), "7");
+
+        assert_eq!(device.run_until_exit(&limit).unwrap(), OutputWait::Exited(None));
+        assert_eq!(device.stdout(), "78");
+    }
+
+    // é is 0xC3 0xA9 in UTF-8, printed a byte at a time it's still one character.
+    #[test]
+    fn print_char_writes_bytes() {
+        let device = UnitDevice::new(assemble_from("
+            li $v0, 11
+            li $a0, 0xC3
+            syscall
+            li $a0, 0xA9
+            syscall
+            li $a0, 0x21
+            syscall
+            li $a0, 0xFF
+            syscall
+            li $a0, 0x41
+            syscall
+            li $a0, 0xC3
+            syscall
+            li $v0, 1
+            li $a0, 5
+            syscall
+            li $v0, 10
+            syscall
+        ").unwrap());
+
+        assert_eq!(device.run_until_exit(&[]).unwrap(), OutputWait::Exited(None));
+        assert_eq!(device.stdout(), "é!\u{FFFD}A\u{FFFD}5");
+    }
+}
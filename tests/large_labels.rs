@@ -0,0 +1,48 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use titan::assembler::string::assemble_debug_from;
+
+const LABELS: usize = 40_000;
+const REFERENCES: usize = 150_000;
+
+// A lookup table of labelled words, read from unrolled code, with a table of pointers back into it.
+fn fixture() -> String {
+    let mut source = String::new();
+
+    for index in 0..REFERENCES - LABELS {
+        writeln!(source, "lw $t0, entry_{}", index * 7 % LABELS).unwrap();
+    }
+
+    source.push_str(".data\n");
+
+    for index in 0..LABELS {
+        writeln!(source, "entry_{index}: .word entry_{}", (index + 1) % LABELS).unwrap();
+    }
+
+    source
+}
+
+#[test]
+fn many_labels_resolve_quickly() {
+    let source = fixture();
+
+    let start = Instant::now();
+    let output = assemble_debug_from(&source).unwrap();
+    let elapsed = start.elapsed();
+
+    assert_eq!(output.binary.labels.len(), LABELS);
+    assert_eq!(output.binary.labels["entry_1"], 0x10010004);
+
+    // The last pointer wraps around to the first entry.
+    let data = &output.binary.regions[1].data;
+
+    assert_eq!(data[data.len() - 4..], 0x10010000u32.to_le_bytes());
+
+    // Generous, a debug build takes a few seconds.
+    assert!(elapsed < Duration::from_secs(30), "took {elapsed:?}");
+
+    let timings = output.timings;
+
+    assert!(timings.resolve > Duration::ZERO);
+    assert!(timings.lex + timings.preprocess + timings.emit + timings.resolve <= elapsed);
+}
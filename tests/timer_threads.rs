@@ -0,0 +1,28 @@
+use std::fs;
+use std::time::{Duration, Instant};
+use titan::assembler::string::assemble_from;
+use titan::unit::device::{StopCondition, UnitDevice};
+
+// Its own test binary, so no other test starts threads while this one counts them.
+fn threads() -> usize {
+    fs::read_dir("/proc/self/task").unwrap().count()
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn quick_runs_leave_no_timer_threads() {
+    let binary = assemble_from("addi $t0, $t0, 1\naddi $t0, $t0, 1\n").unwrap();
+
+    let before = threads();
+    let start = Instant::now();
+
+    for _ in 0..1000 {
+        let device = UnitDevice::new(binary.clone());
+
+        device.execute_until([StopCondition::Complete, StopCondition::Timeout(Duration::from_secs(10))]).unwrap();
+    }
+
+    // Each call joins its timer when it returns instead of leaving it asleep for the full 10s.
+    assert_eq!(threads(), before);
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
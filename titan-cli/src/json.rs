@@ -0,0 +1,97 @@
+use std::fmt::{Display, Formatter, Write};
+
+// Just enough JSON for --json results, without pulling in serde.
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(&'static str, Value)>),
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+fn write_string(f: &mut Formatter<'_>, text: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+
+    for c in text.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+
+    f.write_char('"')
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Integer(value) => write!(f, "{value}"),
+            Value::String(text) => write_string(f, text),
+            Value::Array(values) => {
+                f.write_char('[')?;
+
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?
+                    }
+
+                    write!(f, "{value}")?
+                }
+
+                f.write_char(']')
+            }
+            Value::Object(fields) => {
+                f.write_char('{')?;
+
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?
+                    }
+
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?
+                }
+
+                f.write_char('}')
+            }
+        }
+    }
+}
@@ -1,77 +1,544 @@
+mod json;
+
+use std::fmt::Display;
 use std::fs;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{self, BufRead, Cursor, Write};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use titan::elf::Elf;
+use titan::elf::header::MAGIC;
+use titan::fmt::{format, FormatOptions};
 
-use anyhow::Result;
-use titan::assembler::string::assemble_from_path;
-use titan::cpu::memory::section::{DefaultResponder, SectionMemory};
-use titan::cpu::State;
-use titan::execution::Executor;
-use titan::execution::elf::setup::create_simple_state;
-use titan::execution::trackers::empty::EmptyTracker;
+use anyhow::{bail, Result};
+use titan::assembler::source::FileProviderPool;
+use titan::assembler::core::AssembleOptions;
+use titan::assembler::flat::{FlatFormat, FlatOptions};
+use titan::assembler::project::{file_errors, AssemblerErrors};
+use titan::assembler::registers::RegisterSlot;
+use titan::assembler::string::assemble_debug_with_options;
+use titan::cpu::error::Error as CpuError;
+use titan::cpu::memory::section::InitPolicy;
+use titan::cpu::state::HazardMode;
+use titan::execution::executor::ExecutorMode::{Finished, Invalid};
+use titan::assembler::binary::Binary;
+use titan::unit::device::{StopCondition, UnitDevice, UnitDeviceError};
+use titan::unit::dialog::{DialogBehavior, DialogResponse};
+use titan::unit::spec::TestSpec;
+use titan::unit::terminal::TerminalSyscall;
+use crate::json::Value;
 
 #[derive(Subcommand, Debug)]
 enum Command {
     Build { filename: String },
-    Run { filename: String },
-    Test { filename: String }
+    Run {
+        filename: String, // assembly, or an ELF written by --emit
+
+        // Program arguments, passed after `--` (argc in $a0, argv in $a1).
+        #[arg(last = true)]
+        args: Vec<String>
+    },
+    Test {
+        filename: String,
+
+        #[arg(last = true)]
+        args: Vec<String>
+    },
+    Fmt {
+        filename: String,
+
+        // Don't write anything, fail if the file would change (for CI).
+        #[arg(long)]
+        check: bool,
+
+        #[arg(long)]
+        inline_labels: bool
+    }
 }
 
 impl Command {
     fn filename(&self) -> &str {
         match self {
             Command::Build { filename } => filename,
-            Command::Run { filename } => filename,
-            Command::Test { filename } => filename,
+            Command::Run { filename, .. } => filename,
+            Command::Test { filename, .. } => filename,
+            Command::Fmt { filename, .. } => filename,
         }
     }
 }
 
+// What --emit writes.
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum EmitFormat {
+    Elf,
+    Bin, // flat image of the text, zero filled from --base
+    Hex, // the same image, one word per line
+    Memh, // for Verilog's $readmemh
+}
+
+// 0x prefixed hex or decimal.
+fn parse_address(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+
+    parsed.map_err(|error| format!("{text} is not an address ({error})"))
+}
+
 #[derive(Parser, Debug)]
 struct Args {
     #[command(subcommand)]
     command: Command,
 
     #[arg(short, long)]
-    emit: Option<String>
+    emit: Option<String>,
+
+    #[arg(long, value_enum, default_value = "elf")]
+    format: EmitFormat, // what --emit writes
+
+    #[arg(long, value_parser = parse_address)]
+    base: Option<u32>, // address of the first byte for bin, hex and memh, the lowest text address otherwise
+
+    #[arg(long)]
+    emit_data: bool, // bin, hex and memh include the data sections too
+
+    #[arg(long)]
+    timings: bool, // print how long each assembler phase took
+
+    #[arg(short, long)]
+    quiet: bool, // no status messages, warnings and test results are still printed
+
+    #[arg(long)]
+    json: bool, // print one JSON result to stdout instead, with the program's output captured in it
+
+    #[arg(long)]
+    permit_colonless_labels: bool, // accept `count .word 0` in data sections (with a warning)
+
+    #[arg(long)]
+    allow_implicit_padding: bool, // no warning when .half or .word data is padded to its alignment
+
+    #[arg(long)]
+    relocatable: bool, // leave label references to a linker, --emit then writes an object with relocations
+
+    #[arg(long)]
+    reject_data_targets: bool, // a branch or jump into a data section is an error instead of a warning
+
+    #[arg(long)]
+    strict_commas: bool, // operands must be separated by exactly one comma, like MARS
+
+    #[arg(long, value_name = "REGISTER")]
+    temporary_register: Option<RegisterSlot>, // pseudo instructions expand through this instead of $at (ex. k1)
+
+    #[arg(short = 'O', long)]
+    optimize: bool, // shorter pseudo expansions where resolved labels allow, changes the layout
+
+    #[arg(short = 'I', long = "include-path")]
+    include_paths: Vec<PathBuf>, // directories .include looks in after the including file's own
+
+    #[arg(long = "dialog")]
+    dialogs: Vec<String>, // answers to dialog syscalls in order (yes, no, ok, cancel or text to type), then cancel
+
+    #[arg(long)]
+    memory_seed: Option<u64>, // fill fresh memory and the heap with random bytes from this seed, instead of 0xCC and zeros
+
+    #[arg(long)]
+    check_uninitialized: bool, // report the first read of memory the program never wrote
+
+    #[arg(long)]
+    hilo_hazards: bool, // warn about mfhi/mflo within two instructions of mult/div, like MIPS I
+}
+
+// How run and test set up the device.
+struct DeviceOptions {
+    dialogs: DialogBehavior,
+    init: Option<InitPolicy>,
+    check_uninitialized: bool,
+    hilo_hazards: bool,
+}
+
+impl DeviceOptions {
+    fn new(args: &Args) -> DeviceOptions {
+        DeviceOptions {
+            dialogs: dialog_behavior(&args.dialogs),
+            init: args.memory_seed.map(InitPolicy::RandomSeeded),
+            check_uninitialized: args.check_uninitialized,
+            hilo_hazards: args.hilo_hazards,
+        }
+    }
+
+    fn device(self, binary: Binary, args: &[String]) -> UnitDevice {
+        let device = UnitDevice::with_memory_init(binary, args, self.init);
+        device.dialogs.replace(self.dialogs);
+
+        if self.check_uninitialized {
+            device.track_uninitialized_reads()
+        }
+
+        if self.hilo_hazards {
+            device.check_hi_lo_hazards(HazardMode::Warn)
+        }
+
+        device
+    }
+}
+
+// Problems noticed while running that didn't stop the program.
+fn run_warnings(device: &UnitDevice) {
+    if let Some(address) = device.first_uninitialized_read() {
+        eprintln!("warning: read uninitialized memory at 0x{address:08x}");
+    }
+
+    for hazard in device.hi_lo_hazards() {
+        eprintln!("warning: {hazard}");
+    }
+}
+
+fn dialog_behavior(dialogs: &[String]) -> DialogBehavior {
+    let responses = dialogs.iter()
+        .map(|dialog| match dialog.as_str() {
+            "yes" => DialogResponse::Yes,
+            "no" => DialogResponse::No,
+            "ok" => DialogResponse::Ok,
+            "cancel" => DialogResponse::Cancel,
+            text => DialogResponse::Text(text.to_string()),
+        })
+        .collect();
+
+    DialogBehavior::Scripted(responses)
+}
+
+// stdout only carries the program's own output (or the --json result), everything titan says goes to stderr.
+#[derive(Copy, Clone)]
+struct Status {
+    quiet: bool,
+}
+
+impl Status {
+    fn print(self, message: impl Display) {
+        if !self.quiet {
+            eprintln!("{message}")
+        }
+    }
+}
+
+// Written as soon as it's printed, so it lands in order with anything the program is prompted with.
+fn write_output(text: &str) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn format_file(filename: &str, check: bool, inline_labels: bool, status: Status) -> Result<()> {
+    let text = fs::read_to_string(filename)?;
+
+    let options = FormatOptions { labels_on_own_line: !inline_labels, ..FormatOptions::default() };
+    let formatted = format(&text, &options)?;
+
+    if formatted == text {
+        return Ok(())
+    }
+
+    if check {
+        bail!("{} is not formatted", filename)
+    }
+
+    fs::write(filename, formatted)?;
+
+    status.print(format!("Formatted {}.", filename));
+
+    Ok(())
+}
+
+// Assertions come from `#!` comments in the source and from `<filename>.test` next to it, if there is one.
+fn test_binary(
+    binary: Binary, text: &str, filename: &str, args: &[String], options: DeviceOptions,
+    json: Option<Vec<Value>>, status: Status
+) -> Result<()> {
+    let mut spec = TestSpec::from_source(text)?;
+
+    let sidecar = format!("{filename}.test");
+
+    if let Ok(sidecar) = fs::read_to_string(sidecar) {
+        spec.merge(TestSpec::parse(&sidecar)?);
+    }
+
+    if spec.is_empty() {
+        bail!("{} has no test assertions (add `#! assert ...` lines or a {}.test file)", filename, filename)
+    }
+
+    let device = options.device(binary, args);
+
+    let report = device.run_test(&spec);
+
+    if let Some(warnings) = json {
+        let results = report.results.iter()
+            .map(|result| Value::Object(vec![
+                ("line", (result.assertion.line as i64).into()),
+                ("assertion", result.assertion.text.as_str().into()),
+                ("passed", result.passed.into()),
+                ("message", result.message.as_str().into()),
+            ]))
+            .collect();
+
+        println!("{}", Value::Object(vec![
+            ("file", filename.into()),
+            ("passed", report.passed().into()),
+            ("steps", (report.steps as i64).into()),
+            ("exit_code", report.exit_code.map(|code| code as i32 as i64).into()),
+            ("error", report.error.clone().into()),
+            ("uninitialized_read", device.first_uninitialized_read().map(|address| address as i64).into()),
+            ("hilo_hazards", Value::Array(device.hi_lo_hazards().iter().map(|hazard| hazard.to_string().into()).collect())),
+            ("output", report.output.as_str().into()),
+            ("warnings", Value::Array(warnings)),
+            ("results", Value::Array(results)),
+        ]));
+    } else {
+        write_output(&report.output)?;
+
+        for result in &report.results {
+            let status = if result.passed { "PASS" } else { "FAIL" };
+
+            eprintln!("{status} line {}: {} ({})", result.assertion.line, result.assertion.text, result.message);
+        }
+
+        if let Some(error) = &report.error {
+            eprintln!("ERROR: {error}");
+        }
+
+        run_warnings(&device);
+
+        status.print(format!(
+            "{} steps, {} of {} assertions passed.",
+            report.steps, report.results.len() - report.failures(), report.results.len()
+        ));
+    }
+
+    if !report.passed() {
+        bail!("{} failed", filename)
+    }
+
+    Ok(())
+}
+
+struct RunOutcome {
+    exit_code: Option<u32>, // from syscall 17
+    error: Option<String>,
+    output: String, // only if it was captured
+}
+
+// Runs until the program exits, handling console syscalls and reading stdin when the program asks for input.
+// Output goes to stdout as each syscall returns, unless capture is set.
+fn run_program(device: &UnitDevice, capture: bool) -> Result<RunOutcome> {
+    let mut output = String::new();
+    let mut stdin = io::stdin().lock();
+
+    let outcome = |exit_code, error: Option<String>, output| Ok(RunOutcome { exit_code, error, output });
+
+    loop {
+        // A syscall still waiting for input is retried without running anything.
+        if device.executor.frame().mode != Invalid(CpuError::CpuSyscall) {
+            let result = device.execute_until([StopCondition::SyscallInvoked(None), StopCondition::Complete]);
+
+            // A fault is described with where it happened and the instruction that caused it.
+            if let Err(error) = result {
+                let message = match error {
+                    UnitDeviceError::InvalidInstruction(..) => device.stop_reason().to_string(),
+                    error => error.to_string(),
+                };
+
+                return outcome(None, Some(message), output)
+            }
+
+            match device.executor.frame().mode {
+                // Ran off the end of the code.
+                Finished => return outcome(None, None, output),
+                Invalid(CpuError::CpuSyscall) => {}
+                _ => return outcome(None, Some(device.stop_reason().to_string()), output),
+            }
+        }
+
+        let syscall = device.handle_terminal_syscall();
+        let printed = device.take_terminal_output();
+
+        if capture {
+            output.push_str(&printed)
+        } else {
+            write_output(&printed)?
+        }
+
+        match syscall {
+            Ok(TerminalSyscall::Handled) => {}
+            Ok(TerminalSyscall::AwaitingInput) => {
+                let mut line = String::new();
+
+                if stdin.read_line(&mut line)? == 0 {
+                    return outcome(None, Some("Program is waiting for input, but stdin is closed".into()), output)
+                }
+
+                device.send_input(&line)
+            }
+            Ok(TerminalSyscall::AwaitingDialog) => {
+                return outcome(None, Some("Program is waiting on a dialog".into()), output)
+            }
+            Ok(TerminalSyscall::Exit(code)) => return outcome(code, None, output),
+            Err(error) => return outcome(None, Some(error.to_string()), output),
+        }
+    }
 }
 
 fn run(args: Args) -> Result<()> {
+    let status = Status { quiet: args.quiet };
+
+    if let Command::Fmt { filename, check, inline_labels } = &args.command {
+        return format_file(filename, *check, *inline_labels, status)
+    }
+
     let filename = args.command.filename();
-    println!("Building {}...", filename);
+
+    // An emitted ELF runs as is, nothing to assemble.
+    if let Command::Run { filename, args: program_args } = &args.command {
+        let bytes = fs::read(filename)?;
+
+        if bytes.starts_with(&MAGIC.to_le_bytes()) {
+            status.print(format!("Loading {}...", filename));
+
+            let binary = Elf::read(&mut Cursor::new(bytes))?.to_binary();
+            let json = args.json.then_some(vec![]);
+
+            return run_binary(binary, filename.clone(), program_args, DeviceOptions::new(&args), json, status)
+        }
+    }
+
+    status.print(format!("Building {}...", filename));
 
     let text = fs::read_to_string(filename)?;
-    let binary = assemble_from_path(text, PathBuf::from(filename))?;
 
-    println!("Binary built!");
+    let pool = FileProviderPool::with_search_paths(args.include_paths.clone());
+    let options = AssembleOptions {
+        permit_colonless_labels: args.permit_colonless_labels,
+        warn_implicit_padding: !args.allow_implicit_padding,
+        relocatable: args.relocatable,
+        reject_data_targets: args.reject_data_targets,
+        optimize: args.optimize,
+        strict_commas: args.strict_commas,
+        temporary_register: args.temporary_register.unwrap_or(RegisterSlot::AssemblerTemporary),
+        ..AssembleOptions::default()
+    };
+    let output = assemble_debug_with_options(&pool, text.clone(), PathBuf::from(filename), options)
+        .map_err(|error| AssemblerErrors { errors: file_errors(&pool, Path::new(filename), error) })?;
+
+    if args.timings {
+        let timings = output.timings;
+
+        eprintln!(
+            "Lex {:?}, preprocess {:?}, emit {:?}, resolve {:?}",
+            timings.lex, timings.preprocess, timings.emit, timings.resolve
+        );
+    }
+
+    let binary = output.binary;
+
+    let mut warnings = vec![];
 
-    if let Some(emit) = args.emit {
-        let elf: Elf = binary.create_elf();
+    for warning in &binary.warnings {
+        let place = pool.describe(warning.location).unwrap_or_else(|| filename.to_string());
 
+        if args.json {
+            warnings.push(Value::Object(vec![("place", place.into()), ("message", warning.to_string().into())]))
+        } else {
+            eprintln!("{place}: warning: {warning}");
+        }
+    }
+
+    status.print("Binary built!");
+
+    if let Some(emit) = &args.emit {
         let mut file = File::create(emit)?;
 
-        elf.write(&mut file)?;
+        let flat = match args.format {
+            EmitFormat::Elf => None,
+            EmitFormat::Bin => Some(FlatFormat::Bin),
+            EmitFormat::Hex => Some(FlatFormat::Hex),
+            EmitFormat::Memh => Some(FlatFormat::Memh),
+        };
+
+        match flat {
+            Some(format) => {
+                let options = FlatOptions { base: args.base, include_data: args.emit_data, ..FlatOptions::default() };
+
+                binary.write_flat(&mut file, format, &options)?
+            }
+            None => {
+                let elf: Elf = binary.create_elf();
+
+                elf.write(&mut file)?
+            }
+        }
     }
 
+    let json = args.json.then_some(warnings);
+    let options = DeviceOptions::new(&args);
+
     match args.command {
-        Command::Build { filename: _ } => {}
-        Command::Run { filename: _ } | Command::Test { filename: _ } => {
-            let elf: Elf = binary.create_elf();
+        Command::Fmt { .. } => {}
+        Command::Build { filename } => {
+            if let Some(warnings) = json {
+                println!("{}", Value::Object(vec![
+                    ("file", filename.into()),
+                    ("warnings", Value::Array(warnings)),
+                ]));
+            }
+        }
+        Command::Test { filename, args } => {
+            test_binary(binary, &text, &filename, &args, options, json, status)?
+        }
+        Command::Run { filename, args } => {
+            run_binary(binary, filename, &args, options, json, status)?
+        }
+    }
 
-            let instant = Instant::now();
+    Ok(())
+}
 
-            let state: State<SectionMemory<DefaultResponder>> = create_simple_state(&elf, 0x100000);
-            let debugger = Executor::new(state, EmptyTracker { });
+fn run_binary(
+    binary: Binary, filename: String, args: &[String], options: DeviceOptions,
+    json: Option<Vec<Value>>, status: Status
+) -> Result<()> {
+    let device = options.device(binary, args);
 
-            let frame = debugger.run();
+    let instant = Instant::now();
+    let outcome = run_program(&device, json.is_some())?;
+    let end = instant.elapsed();
 
-            let end = instant.elapsed();
+    let steps = device.executor.retired();
 
-            println!("Running finished in {}ms with mode: {:?}.", end.as_millis(), frame.mode);
+    if let Some(warnings) = json {
+        println!("{}", Value::Object(vec![
+            ("file", filename.into()),
+            ("exit_code", outcome.exit_code.map(|code| code as i32 as i64).into()),
+            ("error", outcome.error.into()),
+            ("uninitialized_read", device.first_uninitialized_read().map(|address| address as i64).into()),
+            ("hilo_hazards", Value::Array(device.hi_lo_hazards().iter().map(|hazard| hazard.to_string().into()).collect())),
+            ("steps", (steps as i64).into()),
+            ("elapsed_ms", (end.as_millis() as i64).into()),
+            ("output", outcome.output.into()),
+            ("warnings", Value::Array(warnings)),
+        ]));
+    } else {
+        if let Some(error) = &outcome.error {
+            eprintln!("ERROR: {error}");
         }
+
+        run_warnings(&device);
+
+        let code = outcome.exit_code.map(|code| format!(" with code {}", code as i32)).unwrap_or_default();
+
+        status.print(format!("Running finished in {}ms{code}, {steps} steps.", end.as_millis()));
     }
 
     Ok(())
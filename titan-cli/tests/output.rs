@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const PRINTER: &str = "
+.data
+greeting: .asciiz \"hello\\n\"
+.text
+main:
+    la $a0, greeting
+    li $v0, 4
+    syscall
+    li $a0, 42
+    li $v0, 1
+    syscall
+    li $v0, 10
+    syscall
+";
+
+// Each test writes its own file, they run in parallel.
+fn source(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("titan-cli-{}-{name}.asm", std::process::id()));
+
+    fs::write(&path, text).unwrap();
+
+    path
+}
+
+fn titan(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_titan-cli")).args(args).output().unwrap()
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[test]
+fn program_output_is_all_that_goes_to_stdout() {
+    let path = source("stdout", PRINTER);
+    let output = titan(&["run", path.to_str().unwrap()]);
+
+    assert!(output.status.success());
+    assert_eq!(text(&output.stdout), "hello\n42");
+
+    let status = text(&output.stderr);
+
+    assert!(status.contains("Building"), "{status}");
+    assert!(status.contains("Binary built!"), "{status}");
+    assert!(status.contains("Running finished"), "{status}");
+}
+
+#[test]
+fn quiet_leaves_stderr_empty() {
+    let path = source("quiet", PRINTER);
+    let output = titan(&["--quiet", "run", path.to_str().unwrap()]);
+
+    assert!(output.status.success());
+    assert_eq!(text(&output.stdout), "hello\n42");
+    assert_eq!(text(&output.stderr), "");
+}
+
+#[test]
+fn json_captures_the_output() {
+    let path = source("json", PRINTER);
+    let output = titan(&["--json", "--quiet", "run", path.to_str().unwrap()]);
+
+    assert!(output.status.success());
+
+    let stdout = text(&output.stdout);
+
+    // One result, with the program's output inside it instead of before it.
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+    assert!(stdout.starts_with('{'), "{stdout}");
+    assert!(stdout.contains(r#""output":"hello\n42""#), "{stdout}");
+    assert!(stdout.contains(r#""error":null"#), "{stdout}");
+}
+
+#[test]
+fn emitted_elf_files_run_like_their_source() {
+    let path = source("emit", PRINTER);
+    let elf = path.with_extension("elf");
+
+    let build = titan(&["--emit", elf.to_str().unwrap(), "build", path.to_str().unwrap()]);
+
+    assert!(build.status.success(), "{}", text(&build.stderr));
+
+    let from_source = titan(&["run", path.to_str().unwrap()]);
+    let from_elf = titan(&["run", elf.to_str().unwrap()]);
+
+    fs::remove_file(&elf).unwrap();
+
+    assert!(from_elf.status.success(), "{}", text(&from_elf.stderr));
+    assert_eq!(text(&from_elf.stdout), text(&from_source.stdout));
+    assert!(text(&from_elf.stderr).contains("Loading"));
+}
+
+#[test]
+fn faults_name_the_instruction() {
+    let path = source("fault", "li $t0, 1\nlw $t1, 16($zero)\n");
+    let output = titan(&["run", path.to_str().unwrap()]);
+
+    let status = text(&output.stderr);
+
+    assert!(
+        status.contains("ERROR: Memory access fault: unmapped address 0x00000010 at pc 0x00400004 (lw $t1, 0x10($zero))"),
+        "{status}"
+    );
+
+    let output = titan(&["--json", "--quiet", "run", path.to_str().unwrap()]);
+    let stdout = text(&output.stdout);
+
+    assert!(stdout.contains(r#""error":"Memory access fault: unmapped address 0x00000010"#), "{stdout}");
+}
+
+#[test]
+fn arguments_after_the_separator_reach_the_program() {
+    // Prints argc, then the first argument.
+    let path = source("args", "
+        move $t0, $a1
+        li $v0, 1
+        syscall
+        lw $a0, 0($t0)
+        li $v0, 4
+        syscall
+    ");
+
+    let output = titan(&["--quiet", "run", path.to_str().unwrap(), "--", "first", "second"]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    assert_eq!(text(&output.stdout), "2first");
+}